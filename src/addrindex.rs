@@ -0,0 +1,80 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Index of the wallet address use, maintained incrementally as the wallet history grows.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use amplify::Wrapper;
+use bitcoin::{Address, Script, Txid};
+
+use crate::{AddressSummary, HistoryEntry, OnchainTxid, Wallet};
+
+/// Use statistics of the wallet addresses.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct AddressIndex {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    summaries: BTreeMap<Script, AddressSummary>,
+    /// Transactions reflected in the index, with their status at the moment of indexing.
+    indexed: BTreeMap<Txid, OnchainTxid>,
+}
+
+impl AddressIndex {
+    pub fn get(&self, script: &Script) -> Option<&AddressSummary> { self.summaries.get(script) }
+
+    pub fn summaries(&self) -> impl Iterator<Item = &AddressSummary> { self.summaries.values() }
+
+    pub fn len(&self) -> usize { self.summaries.len() }
+
+    pub fn is_empty(&self) -> bool { self.summaries.is_empty() }
+
+    /// Reflects the history entry in the index, returning whether the index has changed.
+    pub(crate) fn apply(&mut self, entry: &HistoryEntry) -> bool {
+        let txid = entry.onchain.txid;
+        match self.indexed.get(&txid) {
+            Some(onchain) if *onchain == entry.onchain => return false,
+            Some(_) => {
+                for summary in entry.address_summaries() {
+                    let script = summary.addr_src.address.script_pubkey().into_inner();
+                    if let Some(known) = self.summaries.get_mut(&script) {
+                        known.update_activity(entry.onchain);
+                    }
+                }
+            }
+            None => {
+                for summary in entry.address_summaries() {
+                    let script = summary.addr_src.address.script_pubkey().into_inner();
+                    match self.summaries.entry(script) {
+                        Entry::Vacant(vacant) => {
+                            vacant.insert(summary);
+                        }
+                        Entry::Occupied(occupied) => occupied.into_mut().merge(summary),
+                    }
+                }
+            }
+        }
+        self.indexed.insert(txid, entry.onchain);
+        true
+    }
+}
+
+impl Wallet {
+    /// Use statistics of the wallet address, if it was ever used.
+    pub fn address_summary(&self, address: &Address) -> Option<AddressSummary> {
+        self.address_index().get(&address.script_pubkey()).copied()
+    }
+}
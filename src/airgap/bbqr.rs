@@ -0,0 +1,444 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! BBQr ("Better Bitcoin QR") multi-part encoding, used by Coldcard Q and compatible software.
+//!
+//! Each frame has an 8-character header `B$` + encoding + file type + total number of parts
+//! (2 base36 digits) + zero-based part index (2 base36 digits), followed by a slice of the encoded
+//! data.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::Transaction;
+use flate2::read::DeflateDecoder;
+use wallet::psbt::Psbt;
+
+use super::deflate;
+
+const HEADER_LEN: usize = 8;
+const MAX_PARTS: usize = 36 * 36 - 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE36_ALPHABET: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BbqrError {
+    /// The frame is not a BBQr frame.
+    NoHeader,
+
+    /// Unknown BBQr encoding '{0}'.
+    UnknownEncoding(char),
+
+    /// Unknown BBQr file type '{0}'.
+    UnknownFileType(char),
+
+    /// Invalid BBQr part number "{0}".
+    InvalidPartNo(String),
+
+    /// The frame is inconsistent with the previously scanned BBQr frames.
+    InconsistentPart,
+
+    /// The data are too large to be split into at most 1295 BBQr frames.
+    TooLarge,
+
+    /// Invalid {0} data in the BBQr frames.
+    InvalidData(BbqrEncoding),
+
+    /// Unable to decompress BBQr data. Details: {0}
+    Decompression(String),
+
+    /// BBQr file of type {0} was expected, but {1} was received.
+    UnexpectedFileType(BbqrFileType, BbqrFileType),
+
+    /// Invalid consensus data in BBQr file. Details: {0}
+    Consensus(String),
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum BbqrEncoding {
+    #[display("hex")]
+    Hex,
+
+    #[display("base32")]
+    Base32,
+
+    #[display("zlib-compressed base32")]
+    Zlib,
+}
+
+impl BbqrEncoding {
+    pub fn from_char(c: char) -> Option<BbqrEncoding> {
+        Some(match c {
+            'H' => BbqrEncoding::Hex,
+            '2' => BbqrEncoding::Base32,
+            'Z' => BbqrEncoding::Zlib,
+            _ => return None,
+        })
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            BbqrEncoding::Hex => 'H',
+            BbqrEncoding::Base32 => '2',
+            BbqrEncoding::Zlib => 'Z',
+        }
+    }
+
+    /// Number of characters the part length must be a multiple of, such that each part can be
+    /// decoded independently.
+    fn alignment(self) -> usize {
+        match self {
+            BbqrEncoding::Hex => 2,
+            BbqrEncoding::Base32 | BbqrEncoding::Zlib => 8,
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> String {
+        match self {
+            BbqrEncoding::Hex => data.iter().map(|byte| format!("{:02X}", byte)).collect(),
+            BbqrEncoding::Base32 => base32_encode(data),
+            BbqrEncoding::Zlib => base32_encode(&deflate::compress(data)),
+        }
+    }
+
+    fn decode(self, s: &str) -> Result<Vec<u8>, BbqrError> {
+        match self {
+            BbqrEncoding::Hex => {
+                if s.len() % 2 != 0 {
+                    return Err(BbqrError::InvalidData(self));
+                }
+                (0..s.len())
+                    .step_by(2)
+                    .map(|pos| {
+                        s.get(pos..pos + 2)
+                            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                            .ok_or(BbqrError::InvalidData(self))
+                    })
+                    .collect()
+            }
+            BbqrEncoding::Base32 => base32_decode(s).ok_or(BbqrError::InvalidData(self)),
+            BbqrEncoding::Zlib => {
+                let compressed = base32_decode(s).ok_or(BbqrError::InvalidData(self))?;
+                let mut data = vec![];
+                DeflateDecoder::new(&compressed[..])
+                    .read_to_end(&mut data)
+                    .map_err(|err| BbqrError::Decompression(err.to_string()))?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum BbqrFileType {
+    #[display("PSBT")]
+    Psbt,
+
+    #[display("transaction")]
+    Transaction,
+
+    #[display("JSON")]
+    Json,
+
+    #[display("CBOR")]
+    Cbor,
+
+    #[display("unicode text")]
+    Unicode,
+
+    #[display("binary")]
+    Binary,
+}
+
+impl BbqrFileType {
+    pub fn from_char(c: char) -> Option<BbqrFileType> {
+        Some(match c {
+            'P' => BbqrFileType::Psbt,
+            'T' => BbqrFileType::Transaction,
+            'J' => BbqrFileType::Json,
+            'C' => BbqrFileType::Cbor,
+            'U' => BbqrFileType::Unicode,
+            'B' => BbqrFileType::Binary,
+            _ => return None,
+        })
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            BbqrFileType::Psbt => 'P',
+            BbqrFileType::Transaction => 'T',
+            BbqrFileType::Json => 'J',
+            BbqrFileType::Cbor => 'C',
+            BbqrFileType::Unicode => 'U',
+            BbqrFileType::Binary => 'B',
+        }
+    }
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut s = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut acc = 0u16;
+    let mut bits = 0u8;
+    for byte in data {
+        acc = (acc << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32_ALPHABET[(acc >> bits) as usize & 0x1F] as char);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        s.push(BASE32_ALPHABET[(acc << (5 - bits)) as usize & 0x1F] as char);
+    }
+    s
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(s.len() * 5 / 8);
+    let mut acc = 0u16;
+    let mut bits = 0u8;
+    for c in s.bytes().filter(|c| *c != b'=') {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u16;
+        acc = (acc << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(data)
+}
+
+fn base36_encode(value: usize) -> String {
+    debug_assert!(value <= MAX_PARTS);
+    let high = BASE36_ALPHABET[value / 36] as char;
+    let low = BASE36_ALPHABET[value % 36] as char;
+    format!("{}{}", high, low)
+}
+
+fn base36_decode(s: &str) -> Option<usize> { usize::from_str_radix(s, 36).ok() }
+
+/// Splits data into a sequence of BBQr frames.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BbqrEncoder {
+    file_type: BbqrFileType,
+    encoding: BbqrEncoding,
+    frames: Vec<String>,
+    cursor: usize,
+}
+
+impl BbqrEncoder {
+    /// Encodes data using zlib compression if it reduces the size of the resulting frames, or
+    /// plain base32 encoding otherwise. `max_part_len` is the maximal number of characters in a
+    /// single frame, including the header.
+    pub fn new(
+        data: &[u8],
+        file_type: BbqrFileType,
+        max_part_len: usize,
+    ) -> Result<BbqrEncoder, BbqrError> {
+        let compressed = BbqrEncoding::Zlib.encode(data);
+        let plain = BbqrEncoding::Base32.encode(data);
+        let (encoding, encoded) = if compressed.len() < plain.len() {
+            (BbqrEncoding::Zlib, compressed)
+        } else {
+            (BbqrEncoding::Base32, plain)
+        };
+        BbqrEncoder::with_encoded(encoded, file_type, encoding, max_part_len)
+    }
+
+    pub fn with_encoding(
+        data: &[u8],
+        file_type: BbqrFileType,
+        encoding: BbqrEncoding,
+        max_part_len: usize,
+    ) -> Result<BbqrEncoder, BbqrError> {
+        BbqrEncoder::with_encoded(encoding.encode(data), file_type, encoding, max_part_len)
+    }
+
+    pub fn with_psbt(psbt: &Psbt, max_part_len: usize) -> Result<BbqrEncoder, BbqrError> {
+        let data = serialize(&PartiallySignedTransaction::from(psbt.clone()));
+        BbqrEncoder::new(&data, BbqrFileType::Psbt, max_part_len)
+    }
+
+    pub fn with_transaction(
+        tx: &Transaction,
+        max_part_len: usize,
+    ) -> Result<BbqrEncoder, BbqrError> {
+        BbqrEncoder::new(&serialize(tx), BbqrFileType::Transaction, max_part_len)
+    }
+
+    fn with_encoded(
+        encoded: String,
+        file_type: BbqrFileType,
+        encoding: BbqrEncoding,
+        max_part_len: usize,
+    ) -> Result<BbqrEncoder, BbqrError> {
+        let align = encoding.alignment();
+        let capacity = max_part_len.saturating_sub(HEADER_LEN) / align * align;
+        if capacity == 0 {
+            return Err(BbqrError::TooLarge);
+        }
+        let count = ((encoded.len() + capacity - 1) / capacity).max(1);
+        if count > MAX_PARTS {
+            return Err(BbqrError::TooLarge);
+        }
+        // Balancing part lengths so the last frame is not much shorter than the others
+        let part_len = (encoded.len() + count - 1) / count;
+        let part_len = ((part_len + align - 1) / align * align).max(align);
+
+        let frames = (0..count)
+            .map(|index| {
+                let start = (index * part_len).min(encoded.len());
+                let end = (start + part_len).min(encoded.len());
+                format!(
+                    "B${}{}{}{}{}",
+                    encoding.to_char(),
+                    file_type.to_char(),
+                    base36_encode(count),
+                    base36_encode(index),
+                    &encoded[start..end]
+                )
+            })
+            .collect();
+
+        Ok(BbqrEncoder {
+            file_type,
+            encoding,
+            frames,
+            cursor: 0,
+        })
+    }
+
+    pub fn file_type(&self) -> BbqrFileType { self.file_type }
+
+    pub fn encoding(&self) -> BbqrEncoding { self.encoding }
+
+    pub fn frames(&self) -> &[String] { &self.frames }
+
+    pub fn into_frames(self) -> Vec<String> { self.frames }
+
+    /// Returns next frame for displaying, cycling through all frames in a round-robin way.
+    pub fn next_frame(&mut self) -> String {
+        let frame = self.frames[self.cursor].clone();
+        self.cursor = (self.cursor + 1) % self.frames.len();
+        frame
+    }
+}
+
+/// Incremental decoder assembling scanned BBQr frames, which may arrive in any order and may be
+/// repeated.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BbqrDecoder {
+    header: Option<(BbqrEncoding, BbqrFileType, usize)>,
+    parts: BTreeMap<usize, String>,
+}
+
+impl BbqrDecoder {
+    pub fn new() -> BbqrDecoder { BbqrDecoder::default() }
+
+    pub fn file_type(&self) -> Option<BbqrFileType> { self.header.map(|(_, ty, _)| ty) }
+
+    pub fn encoding(&self) -> Option<BbqrEncoding> { self.header.map(|(enc, _, _)| enc) }
+
+    pub fn part_count(&self) -> Option<usize> { self.header.map(|(_, _, count)| count) }
+
+    pub fn received_parts(&self) -> impl Iterator<Item = usize> + '_ { self.parts.keys().copied() }
+
+    pub fn is_complete(&self) -> bool {
+        self.part_count()
+            .map(|count| self.parts.len() == count)
+            .unwrap_or_default()
+    }
+
+    /// Processes next scanned frame, returning whether all frames are already received.
+    pub fn receive(&mut self, frame: &str) -> Result<bool, BbqrError> {
+        let frame = frame.trim();
+        if frame.len() < HEADER_LEN || !frame.starts_with("B$") || !frame.is_ascii() {
+            return Err(BbqrError::NoHeader);
+        }
+        let mut header = frame[2..4].chars();
+        let encoding = header.next().expect("header length is checked");
+        let encoding =
+            BbqrEncoding::from_char(encoding).ok_or(BbqrError::UnknownEncoding(encoding))?;
+        let file_type = header.next().expect("header length is checked");
+        let file_type =
+            BbqrFileType::from_char(file_type).ok_or(BbqrError::UnknownFileType(file_type))?;
+        let count = base36_decode(&frame[4..6])
+            .filter(|count| *count > 0)
+            .ok_or_else(|| BbqrError::InvalidPartNo(frame[4..6].to_owned()))?;
+        let index = base36_decode(&frame[6..8])
+            .filter(|index| *index < count)
+            .ok_or_else(|| BbqrError::InvalidPartNo(frame[6..8].to_owned()))?;
+
+        match self.header {
+            None => self.header = Some((encoding, file_type, count)),
+            Some(header) if header != (encoding, file_type, count) => {
+                return Err(BbqrError::InconsistentPart)
+            }
+            Some(_) => {}
+        }
+        let data = &frame[HEADER_LEN..];
+        match self.parts.get(&index) {
+            Some(known) if known != data => return Err(BbqrError::InconsistentPart),
+            Some(_) => {}
+            None => {
+                self.parts.insert(index, data.to_owned());
+            }
+        }
+
+        Ok(self.is_complete())
+    }
+
+    /// Returns decoded data once all frames are received.
+    pub fn data(&self) -> Result<Option<Vec<u8>>, BbqrError> {
+        let encoding = match self.header {
+            Some((encoding, _, _)) if self.is_complete() => encoding,
+            _ => return Ok(None),
+        };
+        let encoded = self.parts.values().map(String::as_str).collect::<String>();
+        encoding.decode(&encoded).map(Some)
+    }
+
+    fn expect_file_type(&self, expected: BbqrFileType) -> Result<(), BbqrError> {
+        match self.file_type() {
+            Some(file_type) if file_type != expected => {
+                Err(BbqrError::UnexpectedFileType(expected, file_type))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn psbt(&self) -> Result<Option<Psbt>, BbqrError> {
+        self.expect_file_type(BbqrFileType::Psbt)?;
+        self.data()?
+            .map(|data| {
+                deserialize::<PartiallySignedTransaction>(&data)
+                    .map(Psbt::from)
+                    .map_err(|err| BbqrError::Consensus(err.to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn transaction(&self) -> Result<Option<Transaction>, BbqrError> {
+        self.expect_file_type(BbqrFileType::Transaction)?;
+        self.data()?
+            .map(|data| {
+                deserialize::<Transaction>(&data)
+                    .map_err(|err| BbqrError::Consensus(err.to_string()))
+            })
+            .transpose()
+    }
+}
@@ -0,0 +1,148 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Minimal subset of CBOR (RFC 8949) required by the UR-encoded data types.
+
+pub const MAJOR_UINT: u8 = 0;
+pub const MAJOR_BYTES: u8 = 2;
+pub const MAJOR_ARRAY: u8 = 4;
+pub const MAJOR_MAP: u8 = 5;
+pub const MAJOR_TAG: u8 = 6;
+pub const MAJOR_SIMPLE: u8 = 7;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CborError {
+    /// Unexpected end of CBOR data.
+    UnexpectedEnd,
+
+    /// Unexpected CBOR major type {0} (expected {1}).
+    UnexpectedType(u8, u8),
+
+    /// Unsupported CBOR additional information value {0}.
+    Unsupported(u8),
+
+    /// CBOR value {0} does not fit into the expected integer type.
+    Overflow(u64),
+}
+
+pub fn write_head(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => buf.push(major | value as u8),
+        24..=0xFF => {
+            buf.push(major | 24);
+            buf.push(value as u8);
+        }
+        0x100..=0xFFFF => {
+            buf.push(major | 25);
+            buf.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            buf.push(major | 26);
+            buf.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend(value.to_be_bytes());
+        }
+    }
+}
+
+pub fn write_uint(buf: &mut Vec<u8>, value: u64) { write_head(buf, MAJOR_UINT, value) }
+
+pub fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    write_head(buf, MAJOR_BYTES, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+pub fn write_array(buf: &mut Vec<u8>, len: usize) { write_head(buf, MAJOR_ARRAY, len as u64) }
+
+pub fn write_map(buf: &mut Vec<u8>, len: usize) { write_head(buf, MAJOR_MAP, len as u64) }
+
+pub fn write_tag(buf: &mut Vec<u8>, tag: u64) { write_head(buf, MAJOR_TAG, tag) }
+
+pub fn write_bool(buf: &mut Vec<u8>, value: bool) {
+    write_head(buf, MAJOR_SIMPLE, if value { 21 } else { 20 })
+}
+
+pub struct Reader<'data> {
+    data: &'data [u8],
+    pos: usize,
+}
+
+impl<'data> Reader<'data> {
+    pub fn new(data: &'data [u8]) -> Self { Reader { data, pos: 0 } }
+
+    pub fn is_empty(&self) -> bool { self.pos >= self.data.len() }
+
+    fn take(&mut self, len: usize) -> Result<&'data [u8], CborError> {
+        let end = self.pos.checked_add(len).ok_or(CborError::UnexpectedEnd)?;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .ok_or(CborError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn read_head(&mut self) -> Result<(u8, u64), CborError> {
+        let byte = self.take(1)?[0];
+        let major = byte >> 5;
+        let value = match byte & 0x1F {
+            info @ 0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes([self.take(1)?[0], self.take(1)?[0]]) as u64,
+            26 => {
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(self.take(4)?);
+                u32::from_be_bytes(buf) as u64
+            }
+            27 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(self.take(8)?);
+                u64::from_be_bytes(buf)
+            }
+            info => return Err(CborError::Unsupported(info)),
+        };
+        Ok((major, value))
+    }
+
+    fn read_expected(&mut self, expected: u8) -> Result<u64, CborError> {
+        match self.read_head()? {
+            (major, value) if major == expected => Ok(value),
+            (major, _) => Err(CborError::UnexpectedType(major, expected)),
+        }
+    }
+
+    pub fn read_uint(&mut self) -> Result<u64, CborError> { self.read_expected(MAJOR_UINT) }
+
+    pub fn read_u32(&mut self) -> Result<u32, CborError> {
+        let value = self.read_uint()?;
+        u32::try_from(value).map_err(|_| CborError::Overflow(value))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, CborError> {
+        let value = self.read_uint()?;
+        usize::try_from(value).map_err(|_| CborError::Overflow(value))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'data [u8], CborError> {
+        let len = self.read_expected(MAJOR_BYTES)?;
+        let len = usize::try_from(len).map_err(|_| CborError::Overflow(len))?;
+        self.take(len)
+    }
+
+    pub fn read_array(&mut self) -> Result<usize, CborError> {
+        let len = self.read_expected(MAJOR_ARRAY)?;
+        usize::try_from(len).map_err(|_| CborError::Overflow(len))
+    }
+}
@@ -0,0 +1,148 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Raw DEFLATE (RFC 1951) compressor with the window limited to 1024 bytes, which is required by
+//! BBQr: hardware signers decompress BBQr data using `wbits=10` and are unable to follow longer
+//! back-references produced by general-purpose compressors.
+
+use std::collections::HashMap;
+
+pub const WINDOW_SIZE: usize = 1 << 10;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 20] =
+    [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769];
+const DIST_EXTRA: [u8; 20] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8];
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u8) {
+        self.acc |= value << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.data.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from the most significant bit.
+    fn write_code(&mut self, code: u32, len: u8) {
+        let reversed = code.reverse_bits() >> (32 - len as u32);
+        self.write_bits(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.data.push(self.acc as u8);
+        }
+        self.data
+    }
+}
+
+fn write_symbol(w: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol as u32, 8),
+        144..=255 => w.write_code(0x190 + (symbol as u32 - 144), 9),
+        256..=279 => w.write_code(symbol as u32 - 256, 7),
+        _ => w.write_code(0xC0 + (symbol as u32 - 280), 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= len)
+        .expect("match length is always >= 3");
+    write_symbol(w, 257 + code as u16);
+    w.write_bits(
+        (len - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code],
+    );
+
+    let code = DIST_BASE
+        .iter()
+        .rposition(|base| *base as usize <= dist)
+        .expect("distance is always >= 1");
+    w.write_code(code as u32, 5);
+    w.write_bits((dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code]);
+}
+
+/// Compresses data into a single raw DEFLATE block with fixed Huffman codes.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    w.write_bits(0b011, 3);
+
+    let mut chains = HashMap::<[u8; 3], Vec<usize>>::new();
+    let insert = |chains: &mut HashMap<[u8; 3], Vec<usize>>, pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let key = [data[pos], data[pos + 1], data[pos + 2]];
+            chains.entry(key).or_default().push(pos);
+        }
+    };
+
+    let mut pos = 0usize;
+    while pos < data.len() {
+        let mut best = (0usize, 0usize);
+        if pos + MIN_MATCH <= data.len() {
+            let key = [data[pos], data[pos + 1], data[pos + 2]];
+            if let Some(chain) = chains.get(&key) {
+                for start in chain
+                    .iter()
+                    .rev()
+                    .take(MAX_CHAIN)
+                    .take_while(|start| pos - **start <= WINDOW_SIZE)
+                {
+                    let len = data[pos..]
+                        .iter()
+                        .zip(&data[*start..])
+                        .take(MAX_MATCH)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if len > best.0 {
+                        best = (len, pos - start);
+                    }
+                }
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            write_match(&mut w, best.0, best.1);
+            for p in pos..pos + best.0 {
+                insert(&mut chains, p);
+            }
+            pos += best.0;
+        } else {
+            write_symbol(&mut w, data[pos] as u16);
+            insert(&mut chains, pos);
+            pos += 1;
+        }
+    }
+
+    write_symbol(&mut w, 256);
+    w.finish()
+}
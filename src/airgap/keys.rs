@@ -0,0 +1,214 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Encoding of signer account xpubs into `crypto-hdkey` (BCR-2020-007), `crypto-output`
+//! (BCR-2020-010) and `crypto-account` (BCR-2020-015) URs, used for enrolling keys into external
+//! air-gapped coordinators.
+
+use bitcoin::util::bip32::{ChildNumber, Fingerprint};
+use wallet::hd::Bip43;
+use wallet::onchain::PublicNetwork;
+
+use super::cbor;
+use super::ur::UrEncoder;
+use crate::Signer;
+
+pub const UR_TYPE_HDKEY: &str = "crypto-hdkey";
+pub const UR_TYPE_OUTPUT: &str = "crypto-output";
+pub const UR_TYPE_ACCOUNT: &str = "crypto-account";
+
+const TAG_HDKEY: u64 = 303;
+const TAG_KEYPATH: u64 = 304;
+const TAG_COININFO: u64 = 305;
+const TAG_SH: u64 = 400;
+const TAG_WSH: u64 = 401;
+const TAG_PKH: u64 = 403;
+const TAG_WPKH: u64 = 404;
+const TAG_TR: u64 = 409;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum KeyExportError {
+    /// Master key fingerprint of signer {0} is not known, while it is required for the account
+    /// export.
+    UnknownMasterFingerprint(Fingerprint),
+
+    /// Derivation scheme {0} has no standard output descriptor representation.
+    UnsupportedScheme(Bip43),
+}
+
+fn fingerprint_u32(fingerprint: Fingerprint) -> u64 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&fingerprint[..]);
+    u32::from_be_bytes(buf) as u64
+}
+
+/// Script expression tags wrapping the key in the output descriptor for a given derivation
+/// scheme, from the outermost to the innermost.
+fn script_tags(scheme: &Bip43) -> Result<&'static [u64], KeyExportError> {
+    Ok(match scheme {
+        Bip43::Bip44 => &[TAG_PKH],
+        Bip43::Bip45 => &[TAG_SH],
+        Bip43::Bip48Nested => &[TAG_SH, TAG_WSH],
+        Bip43::Bip48Native => &[TAG_WSH],
+        Bip43::Bip49 => &[TAG_SH, TAG_WPKH],
+        Bip43::Bip84 => &[TAG_WPKH],
+        Bip43::Bip86 => &[TAG_TR],
+        Bip43::Bip87 => &[TAG_WSH],
+        _ => return Err(KeyExportError::UnsupportedScheme(*scheme)),
+    })
+}
+
+/// Writes untagged `crypto-hdkey` map for the signer account xpub, including key origin
+/// information.
+fn write_hdkey(buf: &mut Vec<u8>, signer: &Signer, network: PublicNetwork) {
+    let xpub = signer.xpub;
+    let has_parent = xpub.depth > 0;
+    let testnet = network.is_testnet();
+
+    cbor::write_map(buf, 3 + testnet as usize + has_parent as usize);
+
+    cbor::write_uint(buf, 3);
+    cbor::write_bytes(buf, &xpub.public_key.serialize());
+
+    cbor::write_uint(buf, 4);
+    cbor::write_bytes(buf, &xpub.chain_code[..]);
+
+    if testnet {
+        cbor::write_uint(buf, 5);
+        cbor::write_tag(buf, TAG_COININFO);
+        cbor::write_map(buf, 1);
+        cbor::write_uint(buf, 2);
+        cbor::write_uint(buf, 1);
+    }
+
+    cbor::write_uint(buf, 6);
+    cbor::write_tag(buf, TAG_KEYPATH);
+    let master_known = signer.is_master_known();
+    cbor::write_map(buf, 2 + master_known as usize);
+    cbor::write_uint(buf, 1);
+    cbor::write_array(buf, signer.origin.as_ref().len() * 2);
+    for child in &signer.origin {
+        let (index, hardened) = match *child {
+            ChildNumber::Normal { index } => (index, false),
+            ChildNumber::Hardened { index } => (index, true),
+        };
+        cbor::write_uint(buf, index as u64);
+        cbor::write_bool(buf, hardened);
+    }
+    if master_known {
+        cbor::write_uint(buf, 2);
+        cbor::write_uint(buf, fingerprint_u32(signer.master_fp));
+    }
+    cbor::write_uint(buf, 3);
+    cbor::write_uint(buf, xpub.depth as u64);
+
+    if has_parent {
+        cbor::write_uint(buf, 8);
+        cbor::write_uint(buf, fingerprint_u32(xpub.parent_fingerprint));
+    }
+}
+
+fn write_output(
+    buf: &mut Vec<u8>,
+    signer: &Signer,
+    scheme: &Bip43,
+    network: PublicNetwork,
+) -> Result<(), KeyExportError> {
+    for tag in script_tags(scheme)? {
+        cbor::write_tag(buf, *tag);
+    }
+    cbor::write_tag(buf, TAG_HDKEY);
+    write_hdkey(buf, signer, network);
+    Ok(())
+}
+
+/// Produces CBOR payload of `crypto-hdkey` UR for the signer account xpub.
+pub fn hdkey_cbor(signer: &Signer, network: PublicNetwork) -> Vec<u8> {
+    let mut buf = vec![];
+    write_hdkey(&mut buf, signer, network);
+    buf
+}
+
+/// Produces CBOR payload of `crypto-output` UR describing the signer account xpub used under a
+/// given derivation scheme.
+pub fn output_cbor(
+    signer: &Signer,
+    scheme: &Bip43,
+    network: PublicNetwork,
+) -> Result<Vec<u8>, KeyExportError> {
+    let mut buf = vec![];
+    write_output(&mut buf, signer, scheme, network)?;
+    Ok(buf)
+}
+
+/// Produces CBOR payload of `crypto-account` UR for the signer, which may contain multiple
+/// accounts under different derivation schemes (all of them must originate from the same master
+/// key).
+pub fn account_cbor<'signer>(
+    master_fp: Fingerprint,
+    accounts: impl IntoIterator<Item = (&'signer Signer, Bip43)>,
+    network: PublicNetwork,
+) -> Result<Vec<u8>, KeyExportError> {
+    if master_fp == zero!() {
+        return Err(KeyExportError::UnknownMasterFingerprint(master_fp));
+    }
+    let mut outputs = vec![];
+    let mut count = 0usize;
+    for (signer, scheme) in accounts {
+        if signer.master_fp != master_fp {
+            return Err(KeyExportError::UnknownMasterFingerprint(
+                signer.fingerprint(),
+            ));
+        }
+        write_output(&mut outputs, signer, &scheme, network)?;
+        count += 1;
+    }
+
+    let mut buf = vec![];
+    cbor::write_map(&mut buf, 2);
+    cbor::write_uint(&mut buf, 1);
+    cbor::write_uint(&mut buf, fingerprint_u32(master_fp));
+    cbor::write_uint(&mut buf, 2);
+    cbor::write_array(&mut buf, count);
+    buf.extend(outputs);
+    Ok(buf)
+}
+
+impl UrEncoder {
+    pub fn with_hdkey(signer: &Signer, network: PublicNetwork, max_fragment_len: usize) -> Self {
+        UrEncoder::new(
+            UR_TYPE_HDKEY,
+            &hdkey_cbor(signer, network),
+            max_fragment_len,
+        )
+    }
+
+    pub fn with_output(
+        signer: &Signer,
+        scheme: &Bip43,
+        network: PublicNetwork,
+        max_fragment_len: usize,
+    ) -> Result<Self, KeyExportError> {
+        let cbor = output_cbor(signer, scheme, network)?;
+        Ok(UrEncoder::new(UR_TYPE_OUTPUT, &cbor, max_fragment_len))
+    }
+
+    pub fn with_account<'signer>(
+        master_fp: Fingerprint,
+        accounts: impl IntoIterator<Item = (&'signer Signer, Bip43)>,
+        network: PublicNetwork,
+        max_fragment_len: usize,
+    ) -> Result<Self, KeyExportError> {
+        let cbor = account_cbor(master_fp, accounts, network)?;
+        Ok(UrEncoder::new(UR_TYPE_ACCOUNT, &cbor, max_fragment_len))
+    }
+}
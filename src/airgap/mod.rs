@@ -0,0 +1,27 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Air-gapped data transport using (animated) QR codes.
+
+pub mod bbqr;
+mod cbor;
+mod deflate;
+pub mod keys;
+mod session;
+pub mod ur;
+
+pub use bbqr::{BbqrDecoder, BbqrEncoder, BbqrEncoding, BbqrError, BbqrFileType};
+pub use cbor::CborError;
+pub use keys::{KeyExportError, UR_TYPE_ACCOUNT, UR_TYPE_HDKEY, UR_TYPE_OUTPUT};
+pub use session::{
+    AirgapError, AnyDecoder, DisplaySession, FrameDecoder, FrameEncoder, ScanSession, ScanStatus,
+};
+pub use ur::{UrDecoder, UrEncoder, UrError, UR_TYPE_PSBT};
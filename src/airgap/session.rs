@@ -0,0 +1,304 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Codec-independent multi-part QR sessions: GUI code feeds scanned frames into [`ScanSession`]
+//! and renders frames produced by [`DisplaySession`].
+
+use std::collections::{BTreeSet, HashSet};
+
+use wallet::psbt::Psbt;
+
+use super::{BbqrDecoder, BbqrEncoder, BbqrError, UrDecoder, UrEncoder, UrError};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AirgapError {
+    /// The scanned QR code is neither a UR nor a BBQr frame.
+    UnknownFormat,
+
+    /// The scanned frame uses a different encoding than the frames scanned before.
+    FormatMismatch,
+
+    /// {0}
+    #[from]
+    Ur(UrError),
+
+    /// {0}
+    #[from]
+    Bbqr(BbqrError),
+}
+
+/// Decoders of multi-part QR codes.
+pub trait FrameDecoder {
+    type Error: std::error::Error;
+
+    /// Processes next scanned frame, returning whether the data are completely received.
+    fn receive(&mut self, frame: &str) -> Result<bool, Self::Error>;
+
+    fn is_complete(&self) -> bool;
+
+    /// Number of the parts the data are split into, if already known.
+    fn part_count(&self) -> Option<usize>;
+
+    /// Zero-based indexes of the parts which were already received or recovered.
+    fn received_parts(&self) -> BTreeSet<usize>;
+}
+
+/// Encoders of multi-part QR codes.
+pub trait FrameEncoder {
+    /// Number of distinct frames required to transfer the data.
+    fn part_count(&self) -> usize;
+
+    /// Produces next frame to be displayed.
+    fn next_frame(&mut self) -> String;
+}
+
+impl FrameDecoder for UrDecoder {
+    type Error = UrError;
+
+    fn receive(&mut self, frame: &str) -> Result<bool, Self::Error> {
+        UrDecoder::receive(self, frame)
+    }
+
+    fn is_complete(&self) -> bool { UrDecoder::is_complete(self) }
+
+    fn part_count(&self) -> Option<usize> {
+        match self.is_complete() {
+            true => Some(self.fragment_count().unwrap_or(1)),
+            false => self.fragment_count(),
+        }
+    }
+
+    fn received_parts(&self) -> BTreeSet<usize> {
+        match (self.is_complete(), self.fragment_count()) {
+            (true, count) => (0..count.unwrap_or(1)).collect(),
+            (false, _) => self.recovered_fragments(),
+        }
+    }
+}
+
+impl FrameDecoder for BbqrDecoder {
+    type Error = BbqrError;
+
+    fn receive(&mut self, frame: &str) -> Result<bool, Self::Error> {
+        BbqrDecoder::receive(self, frame)
+    }
+
+    fn is_complete(&self) -> bool { BbqrDecoder::is_complete(self) }
+
+    fn part_count(&self) -> Option<usize> { BbqrDecoder::part_count(self) }
+
+    fn received_parts(&self) -> BTreeSet<usize> { BbqrDecoder::received_parts(self).collect() }
+}
+
+impl FrameEncoder for UrEncoder {
+    fn part_count(&self) -> usize { self.fragment_count() }
+
+    fn next_frame(&mut self) -> String { self.next_part() }
+}
+
+impl FrameEncoder for BbqrEncoder {
+    fn part_count(&self) -> usize { self.frames().len() }
+
+    fn next_frame(&mut self) -> String { BbqrEncoder::next_frame(self) }
+}
+
+/// Decoder detecting the format (UR or BBQr) from the first scanned frame.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum AnyDecoder {
+    #[default]
+    Unknown,
+    Ur(UrDecoder),
+    Bbqr(BbqrDecoder),
+}
+
+impl AnyDecoder {
+    /// Returns PSBT once all frames are received.
+    pub fn psbt(&self) -> Result<Option<Psbt>, AirgapError> {
+        Ok(match self {
+            AnyDecoder::Unknown => None,
+            AnyDecoder::Ur(decoder) => decoder.psbt()?,
+            AnyDecoder::Bbqr(decoder) => decoder.psbt()?,
+        })
+    }
+}
+
+impl FrameDecoder for AnyDecoder {
+    type Error = AirgapError;
+
+    fn receive(&mut self, frame: &str) -> Result<bool, Self::Error> {
+        let trimmed = frame.trim_start();
+        let is_ur = trimmed
+            .get(..3)
+            .map(|scheme| scheme.eq_ignore_ascii_case("ur:"))
+            .unwrap_or_default();
+        let is_bbqr = trimmed.starts_with("B$");
+        if let AnyDecoder::Unknown = self {
+            *self = match (is_ur, is_bbqr) {
+                (true, _) => AnyDecoder::Ur(UrDecoder::new()),
+                (_, true) => AnyDecoder::Bbqr(BbqrDecoder::new()),
+                _ => return Err(AirgapError::UnknownFormat),
+            };
+        }
+        match self {
+            AnyDecoder::Ur(decoder) if is_ur => Ok(decoder.receive(frame)?),
+            AnyDecoder::Bbqr(decoder) if is_bbqr => Ok(decoder.receive(frame)?),
+            _ if !is_ur && !is_bbqr => Err(AirgapError::UnknownFormat),
+            _ => Err(AirgapError::FormatMismatch),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self {
+            AnyDecoder::Unknown => false,
+            AnyDecoder::Ur(decoder) => FrameDecoder::is_complete(decoder),
+            AnyDecoder::Bbqr(decoder) => FrameDecoder::is_complete(decoder),
+        }
+    }
+
+    fn part_count(&self) -> Option<usize> {
+        match self {
+            AnyDecoder::Unknown => None,
+            AnyDecoder::Ur(decoder) => FrameDecoder::part_count(decoder),
+            AnyDecoder::Bbqr(decoder) => FrameDecoder::part_count(decoder),
+        }
+    }
+
+    fn received_parts(&self) -> BTreeSet<usize> {
+        match self {
+            AnyDecoder::Unknown => empty!(),
+            AnyDecoder::Ur(decoder) => FrameDecoder::received_parts(decoder),
+            AnyDecoder::Bbqr(decoder) => FrameDecoder::received_parts(decoder),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum ScanStatus {
+    /// The frame was already scanned before and was ignored.
+    #[display("duplicate")]
+    Duplicate,
+
+    /// The frame was accepted, but more frames are required.
+    #[display("in progress")]
+    InProgress,
+
+    /// All data are received.
+    #[display("complete")]
+    Complete,
+}
+
+/// Scanning session tracking the progress of multi-part QR code reception.
+///
+/// Repeated frames, which are common when scanning animated QR codes, are detected and skipped
+/// before reaching the decoder.
+#[derive(Clone, Debug)]
+pub struct ScanSession<D: FrameDecoder = AnyDecoder> {
+    decoder: D,
+    seen: HashSet<String>,
+    duplicates: usize,
+}
+
+impl<D: FrameDecoder + Default> Default for ScanSession<D> {
+    fn default() -> Self { ScanSession::with(D::default()) }
+}
+
+impl<D: FrameDecoder> ScanSession<D> {
+    pub fn with(decoder: D) -> Self {
+        ScanSession {
+            decoder,
+            seen: empty!(),
+            duplicates: 0,
+        }
+    }
+
+    pub fn feed(&mut self, frame: &str) -> Result<ScanStatus, D::Error> {
+        if self.decoder.is_complete() {
+            return Ok(ScanStatus::Complete);
+        }
+        if self.seen.contains(frame) {
+            self.duplicates += 1;
+            return Ok(ScanStatus::Duplicate);
+        }
+        let complete = self.decoder.receive(frame)?;
+        self.seen.insert(frame.to_owned());
+        Ok(match complete {
+            true => ScanStatus::Complete,
+            false => ScanStatus::InProgress,
+        })
+    }
+
+    pub fn is_complete(&self) -> bool { self.decoder.is_complete() }
+
+    /// Number of unique frames accepted by the session.
+    pub fn scanned_count(&self) -> usize { self.seen.len() }
+
+    /// Number of repeated frames which were ignored.
+    pub fn duplicate_count(&self) -> usize { self.duplicates }
+
+    pub fn part_count(&self) -> Option<usize> { self.decoder.part_count() }
+
+    /// Zero-based indexes of the parts which are still missing; empty if the total number of parts
+    /// is not yet known.
+    pub fn missing_parts(&self) -> Vec<usize> {
+        let received = self.decoder.received_parts();
+        (0..self.part_count().unwrap_or_default())
+            .filter(|index| !received.contains(index))
+            .collect()
+    }
+
+    /// Reception progress in the range 0.0 ..= 1.0.
+    pub fn progress(&self) -> f32 {
+        match (self.is_complete(), self.part_count()) {
+            (true, _) => 1.0,
+            (false, None) | (false, Some(0)) => 0.0,
+            (false, Some(count)) => {
+                (self.decoder.received_parts().len() as f32 / count as f32).min(0.99)
+            }
+        }
+    }
+
+    /// Reception progress in percents.
+    pub fn progress_percent(&self) -> u8 { (self.progress() * 100.0).floor() as u8 }
+
+    pub fn as_decoder(&self) -> &D { &self.decoder }
+
+    pub fn into_decoder(self) -> D { self.decoder }
+}
+
+/// Session producing frames to be rendered as an animated QR code.
+#[derive(Clone, Debug)]
+pub struct DisplaySession<E: FrameEncoder> {
+    encoder: E,
+    displayed: usize,
+}
+
+impl<E: FrameEncoder> DisplaySession<E> {
+    pub fn with(encoder: E) -> Self {
+        DisplaySession {
+            encoder,
+            displayed: 0,
+        }
+    }
+
+    pub fn part_count(&self) -> usize { self.encoder.part_count() }
+
+    /// Number of frames produced so far.
+    pub fn displayed_count(&self) -> usize { self.displayed }
+
+    /// Whether all distinct parts were displayed at least once.
+    pub fn is_cycle_complete(&self) -> bool { self.displayed >= self.part_count() }
+
+    pub fn next_frame(&mut self) -> String {
+        self.displayed += 1;
+        self.encoder.next_frame()
+    }
+}
@@ -0,0 +1,563 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Uniform Resources (BCR-2020-005) with fountain-coded multi-part transfer, as used by the
+//! air-gapped signing devices for moving PSBTs through the animated QR codes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::PartiallySignedTransaction;
+use wallet::psbt::Psbt;
+
+use super::cbor::{self, CborError};
+
+/// UR type used for PSBTs.
+pub const UR_TYPE_PSBT: &str = "crypto-psbt";
+
+/// Minimal fragment length used when splitting the message into the fountain code fragments.
+const MIN_FRAGMENT_LEN: usize = 10;
+
+#[rustfmt::skip]
+const BYTEWORDS: [&str; 256] = [
+    "able", "acid", "also", "apex", "aqua", "arch", "atom", "aunt", "away", "axis", "back", "bald",
+    "barn", "belt", "beta", "bias", "blue", "body", "brag", "brew", "bulb", "buzz", "calm", "cash",
+    "cats", "chef", "city", "claw", "code", "cola", "cook", "cost", "crux", "curl", "cusp", "cyan",
+    "dark", "data", "days", "deli", "dice", "diet", "door", "down", "draw", "drop", "drum", "dull",
+    "duty", "each", "easy", "echo", "edge", "epic", "even", "exam", "exit", "eyes", "fact", "fair",
+    "fern", "figs", "film", "fish", "fizz", "flap", "flew", "flux", "foxy", "free", "frog", "fuel",
+    "fund", "gala", "game", "gear", "gems", "gift", "girl", "glow", "good", "gray", "grim", "guru",
+    "gush", "gyro", "half", "hang", "hard", "hawk", "heat", "help", "high", "hill", "holy", "hope",
+    "horn", "huts", "iced", "idea", "idle", "inch", "inky", "into", "iris", "iron", "item", "jade",
+    "jazz", "join", "jolt", "jowl", "judo", "jugs", "jump", "junk", "jury", "keep", "keno", "kept",
+    "keys", "kick", "kiln", "king", "kite", "kiwi", "knob", "lamb", "lava", "lazy", "leaf", "legs",
+    "liar", "limp", "lion", "list", "logo", "loud", "love", "luau", "luck", "lung", "main", "many",
+    "math", "maze", "memo", "menu", "meow", "mild", "mint", "miss", "monk", "nail", "navy", "need",
+    "news", "next", "noon", "note", "numb", "obey", "oboe", "omit", "onyx", "open", "oval", "owls",
+    "paid", "part", "peck", "play", "plus", "poem", "pool", "pose", "puff", "puma", "purr", "quad",
+    "quiz", "race", "ramp", "real", "redo", "rich", "road", "rock", "roof", "ruby", "ruin", "runs",
+    "rust", "safe", "saga", "scar", "sets", "silk", "skew", "slot", "soap", "solo", "song", "stub",
+    "surf", "swan", "taco", "task", "taxi", "tent", "tied", "time", "tiny", "toil", "tomb", "toys",
+    "trip", "tuna", "twin", "ugly", "undo", "unit", "urge", "user", "vast", "very", "veto", "vial",
+    "vibe", "view", "visa", "void", "vows", "wall", "wand", "warm", "wasp", "wave", "waxy", "webs",
+    "what", "when", "whiz", "wolf", "work", "yank", "yawn", "yell", "yoga", "yurt", "zaps", "zero",
+    "zest", "zinc", "zone", "zoom",
+];
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum UrError {
+    /// The frame is not a UR string.
+    NoScheme,
+
+    /// UR type "{0}" contains invalid characters.
+    InvalidType(String),
+
+    /// The frame belongs to a UR of type "{found}" while a UR of type "{expected}" is being
+    /// decoded.
+    TypeMismatch { expected: String, found: String },
+
+    /// Invalid multi-part sequence information "{0}".
+    InvalidSequence(String),
+
+    /// Invalid bytewords encoding: unknown word "{0}".
+    InvalidByteword(String),
+
+    /// Bytewords checksum does not match the frame data.
+    BytewordsChecksum,
+
+    /// Multi-part frame is inconsistent with the previously received frames.
+    InconsistentPart,
+
+    /// The reassembled message does not match its checksum.
+    MessageChecksum,
+
+    /// Invalid CBOR data in UR frame. Details: {0}
+    #[from]
+    Cbor(CborError),
+
+    /// UR of type "{0}" does not contain a PSBT.
+    NotPsbt(String),
+
+    /// Invalid PSBT data. Details: {0}
+    Psbt(String),
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn bytewords_encode(data: &[u8]) -> String {
+    let checksum = crc32(data).to_be_bytes();
+    data.iter()
+        .chain(&checksum)
+        .map(|byte| {
+            let word = BYTEWORDS[*byte as usize].as_bytes();
+            [word[0] as char, word[3] as char]
+        })
+        .fold(
+            String::with_capacity((data.len() + 4) * 2),
+            |mut s, [first, last]| {
+                s.push(first);
+                s.push(last);
+                s
+            },
+        )
+}
+
+fn bytewords_decode(s: &str) -> Result<Vec<u8>, UrError> {
+    let lookup = |pair: &[u8]| -> Result<u8, UrError> {
+        BYTEWORDS
+            .iter()
+            .position(|word| {
+                let word = word.as_bytes();
+                word[0] == pair[0] && word[3] == pair[1]
+            })
+            .map(|index| index as u8)
+            .ok_or_else(|| UrError::InvalidByteword(String::from_utf8_lossy(pair).into_owned()))
+    };
+
+    let s = s.as_bytes();
+    if s.len() % 2 != 0 || s.len() < 10 {
+        return Err(UrError::BytewordsChecksum);
+    }
+    let mut data = s.chunks(2).map(lookup).collect::<Result<Vec<_>, _>>()?;
+    let checksum = data.split_off(data.len() - 4);
+    if crc32(&data).to_be_bytes()[..] != checksum[..] {
+        return Err(UrError::BytewordsChecksum);
+    }
+    Ok(data)
+}
+
+/// Xoshiro256** PRNG seeded with SHA256 digest, as required by the UR fountain codes.
+struct Xoshiro256([u64; 4]);
+
+impl Xoshiro256 {
+    fn with_seed(seed: &[u8]) -> Xoshiro256 {
+        let digest = sha256::Hash::hash(seed).into_inner();
+        let mut state = [0u64; 4];
+        for (s, chunk) in state.iter_mut().zip(digest.chunks(8)) {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(chunk);
+            *s = u64::from_be_bytes(buf);
+        }
+        Xoshiro256(state)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn next_double(&mut self) -> f64 { self.next_u64() as f64 / (u64::MAX as f64 + 1.0) }
+
+    fn next_int(&mut self, low: u64, high: u64) -> u64 {
+        (self.next_double() * (high - low + 1) as f64) as u64 + low
+    }
+}
+
+/// Walker-Vose alias method sampler used to pick the degree of the mixed fountain parts.
+struct AliasSampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl AliasSampler {
+    fn with(weights: &[f64]) -> AliasSampler {
+        let count = weights.len();
+        let sum = weights.iter().sum::<f64>();
+        let mut scaled = weights
+            .iter()
+            .map(|w| w * count as f64 / sum)
+            .collect::<Vec<_>>();
+
+        let mut small = vec![];
+        let mut large = vec![];
+        for (index, p) in scaled.iter().enumerate().rev() {
+            if *p < 1.0 {
+                small.push(index);
+            } else {
+                large.push(index);
+            }
+        }
+
+        let mut probs = vec![0.0; count];
+        let mut aliases = vec![0usize; count];
+        while !small.is_empty() && !large.is_empty() {
+            let less = small.pop().expect("checked to be non-empty");
+            let more = large.pop().expect("checked to be non-empty");
+            probs[less] = scaled[less];
+            aliases[less] = more;
+            scaled[more] += scaled[less] - 1.0;
+            if scaled[more] < 1.0 {
+                small.push(more);
+            } else {
+                large.push(more);
+            }
+        }
+        for index in large.into_iter().chain(small) {
+            probs[index] = 1.0;
+        }
+
+        AliasSampler { probs, aliases }
+    }
+
+    fn next(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let index = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[index] {
+            index
+        } else {
+            self.aliases[index]
+        }
+    }
+}
+
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return bset![seq_num as usize - 1];
+    }
+
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend(checksum.to_be_bytes());
+    let mut rng = Xoshiro256::with_seed(&seed);
+
+    let weights = (1..=seq_len).map(|i| 1.0 / i as f64).collect::<Vec<_>>();
+    let degree = AliasSampler::with(&weights).next(&mut rng) + 1;
+
+    let mut remaining = (0..seq_len).collect::<Vec<_>>();
+    let mut shuffled = Vec::with_capacity(seq_len);
+    while !remaining.is_empty() {
+        let index = rng.next_int(0, remaining.len() as u64 - 1) as usize;
+        shuffled.push(remaining.remove(index));
+    }
+    shuffled.into_iter().take(degree).collect()
+}
+
+fn fragment_len(message_len: usize, max_fragment_len: usize) -> usize {
+    let max_fragment_len = max_fragment_len.max(MIN_FRAGMENT_LEN);
+    let max_count = (message_len / MIN_FRAGMENT_LEN).max(1);
+    let mut len = message_len;
+    for count in 1..=max_count {
+        len = (message_len + count - 1) / count;
+        if len <= max_fragment_len {
+            break;
+        }
+    }
+    len.max(1)
+}
+
+fn xor_into(target: &mut [u8], source: &[u8]) {
+    target.iter_mut().zip(source).for_each(|(t, s)| *t ^= *s);
+}
+
+fn check_type(ur_type: &str) -> Result<(), UrError> {
+    if ur_type.is_empty()
+        || !ur_type
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(UrError::InvalidType(ur_type.to_owned()));
+    }
+    Ok(())
+}
+
+/// Wraps consensus-serialized PSBT into the CBOR byte string required by `crypto-psbt` UR type.
+pub fn psbt_to_cbor(psbt: &Psbt) -> Vec<u8> {
+    let data = serialize(&PartiallySignedTransaction::from(psbt.clone()));
+    let mut cbor = Vec::with_capacity(data.len() + 9);
+    cbor::write_bytes(&mut cbor, &data);
+    cbor
+}
+
+/// Extracts PSBT from the CBOR-encoded `crypto-psbt` UR payload.
+pub fn psbt_from_cbor(cbor: &[u8]) -> Result<Psbt, UrError> {
+    let mut reader = cbor::Reader::new(cbor);
+    let data = reader.read_bytes()?;
+    if !reader.is_empty() {
+        return Err(UrError::Psbt(s!("extra data after the end of PSBT")));
+    }
+    deserialize::<PartiallySignedTransaction>(data)
+        .map(Psbt::from)
+        .map_err(|err| UrError::Psbt(err.to_string()))
+}
+
+/// Fountain encoder producing an infinite sequence of UR frames for a given message.
+///
+/// The first [`UrEncoder::fragment_count`] frames contain the original message fragments; all
+/// further frames are mixed fragments, allowing the receiver to recover the message from any
+/// sufficiently large subset of the frames it was able to scan. Messages fitting into a single
+/// fragment are produced as a single-part UR, which is repeated by the iterator.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct UrEncoder {
+    ur_type: String,
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Vec<u8>>,
+    single: Option<String>,
+    seq_num: u32,
+}
+
+impl UrEncoder {
+    /// # Panics
+    ///
+    /// If the UR type contains characters other than lowercase latin letters, digits and `-`.
+    pub fn new(ur_type: &str, message: &[u8], max_fragment_len: usize) -> UrEncoder {
+        check_type(ur_type).expect("invalid UR type");
+
+        let len = fragment_len(message.len(), max_fragment_len);
+        let single = if message.len() <= len {
+            Some(format!("ur:{}/{}", ur_type, bytewords_encode(message)))
+        } else {
+            None
+        };
+        let mut padded = message.to_vec();
+        let count = (message.len() + len - 1) / len;
+        padded.resize(count.max(1) * len, 0u8);
+
+        UrEncoder {
+            ur_type: ur_type.to_owned(),
+            message_len: message.len(),
+            checksum: crc32(message),
+            fragments: padded.chunks(len).map(<[u8]>::to_vec).collect(),
+            single,
+            seq_num: 0,
+        }
+    }
+
+    pub fn with_psbt(psbt: &Psbt, max_fragment_len: usize) -> UrEncoder {
+        UrEncoder::new(UR_TYPE_PSBT, &psbt_to_cbor(psbt), max_fragment_len)
+    }
+
+    pub fn ur_type(&self) -> &str { &self.ur_type }
+
+    pub fn is_single_part(&self) -> bool { self.single.is_some() }
+
+    /// Number of the pure (non-mixed) fragments required to transfer the message.
+    pub fn fragment_count(&self) -> usize { self.fragments.len() }
+
+    /// Sequence number of the last produced frame.
+    pub fn seq_num(&self) -> u32 { self.seq_num }
+
+    pub fn next_part(&mut self) -> String {
+        if let Some(single) = &self.single {
+            return single.clone();
+        }
+
+        self.seq_num = self.seq_num.wrapping_add(1).max(1);
+        let seq_len = self.fragments.len();
+        let mut fragment = vec![0u8; self.fragments[0].len()];
+        for index in choose_fragments(self.seq_num, seq_len, self.checksum) {
+            xor_into(&mut fragment, &self.fragments[index]);
+        }
+
+        let mut part = Vec::with_capacity(fragment.len() + 24);
+        cbor::write_array(&mut part, 5);
+        cbor::write_uint(&mut part, self.seq_num as u64);
+        cbor::write_uint(&mut part, seq_len as u64);
+        cbor::write_uint(&mut part, self.message_len as u64);
+        cbor::write_uint(&mut part, self.checksum as u64);
+        cbor::write_bytes(&mut part, &fragment);
+
+        format!(
+            "ur:{}/{}-{}/{}",
+            self.ur_type,
+            self.seq_num,
+            seq_len,
+            bytewords_encode(&part)
+        )
+    }
+}
+
+impl Iterator for UrEncoder {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> { Some(self.next_part()) }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct PartParams {
+    seq_len: usize,
+    message_len: usize,
+    checksum: u32,
+    fragment_len: usize,
+}
+
+/// Incremental decoder assembling UR frames (single- or multi-part) into the original message.
+///
+/// Frames may be provided in any order; duplicated and redundant frames are ignored.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct UrDecoder {
+    ur_type: Option<String>,
+    params: Option<PartParams>,
+    simple: BTreeMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+    message: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    pub fn new() -> UrDecoder { UrDecoder::default() }
+
+    pub fn ur_type(&self) -> Option<&str> { self.ur_type.as_deref() }
+
+    pub fn is_complete(&self) -> bool { self.message.is_some() }
+
+    /// Number of the original message fragments, known after the first multi-part frame is
+    /// received.
+    pub fn fragment_count(&self) -> Option<usize> { self.params.map(|params| params.seq_len) }
+
+    /// Indexes of the original message fragments which are already recovered.
+    pub fn recovered_fragments(&self) -> BTreeSet<usize> { self.simple.keys().copied().collect() }
+
+    pub fn message(&self) -> Option<&[u8]> { self.message.as_deref() }
+
+    pub fn into_message(self) -> Option<Vec<u8>> { self.message }
+
+    /// Processes next scanned frame, returning whether the message is completely decoded.
+    pub fn receive(&mut self, frame: &str) -> Result<bool, UrError> {
+        if self.is_complete() {
+            return Ok(true);
+        }
+
+        let frame = frame.trim().to_ascii_lowercase();
+        let frame = frame.strip_prefix("ur:").ok_or(UrError::NoScheme)?;
+        let components = frame.split('/').collect::<Vec<_>>();
+        let (ur_type, seq, payload) = match components[..] {
+            [ur_type, payload] => (ur_type, None, payload),
+            [ur_type, seq, payload] => (ur_type, Some(seq), payload),
+            _ => return Err(UrError::NoScheme),
+        };
+        check_type(ur_type)?;
+        match &self.ur_type {
+            Some(expected) if expected != ur_type => {
+                return Err(UrError::TypeMismatch {
+                    expected: expected.clone(),
+                    found: ur_type.to_owned(),
+                })
+            }
+            _ => {}
+        }
+
+        let data = bytewords_decode(payload)?;
+        self.ur_type = Some(ur_type.to_owned());
+        let seq = match seq {
+            None => {
+                self.message = Some(data);
+                return Ok(true);
+            }
+            Some(seq) => seq,
+        };
+
+        let (seq_num, seq_len) = seq
+            .split_once('-')
+            .and_then(|(num, len)| Some((num.parse::<u32>().ok()?, len.parse::<usize>().ok()?)))
+            .filter(|(num, len)| *num > 0 && *len > 0)
+            .ok_or_else(|| UrError::InvalidSequence(seq.to_owned()))?;
+
+        let mut reader = cbor::Reader::new(&data);
+        if reader.read_array()? != 5 {
+            return Err(UrError::InconsistentPart);
+        }
+        let part_seq_num = reader.read_u32()?;
+        let part_seq_len = reader.read_usize()?;
+        let message_len = reader.read_usize()?;
+        let checksum = reader.read_u32()?;
+        let fragment = reader.read_bytes()?.to_vec();
+        if part_seq_num != seq_num || part_seq_len != seq_len || !reader.is_empty() {
+            return Err(UrError::InconsistentPart);
+        }
+
+        let params = PartParams {
+            seq_len,
+            message_len,
+            checksum,
+            fragment_len: fragment.len(),
+        };
+        match self.params {
+            None if fragment.len() * seq_len < message_len => {
+                return Err(UrError::InconsistentPart)
+            }
+            None => self.params = Some(params),
+            Some(known) if known != params => return Err(UrError::InconsistentPart),
+            Some(_) => {}
+        }
+
+        self.process_part(choose_fragments(seq_num, seq_len, checksum), fragment);
+
+        if self.simple.len() == seq_len {
+            let mut message = self.simple.values().flatten().copied().collect::<Vec<_>>();
+            message.truncate(message_len);
+            if crc32(&message) != checksum {
+                return Err(UrError::MessageChecksum);
+            }
+            self.message = Some(message);
+        }
+
+        Ok(self.is_complete())
+    }
+
+    fn process_part(&mut self, indexes: BTreeSet<usize>, fragment: Vec<u8>) {
+        let mut queue = vec![(indexes, fragment)];
+        while let Some((mut indexes, mut fragment)) = queue.pop() {
+            for (index, simple) in &self.simple {
+                if indexes.remove(index) {
+                    xor_into(&mut fragment, simple);
+                }
+            }
+            match indexes.len() {
+                0 => {}
+                1 => {
+                    let index = *indexes.iter().next().expect("length is checked");
+                    self.simple.insert(index, fragment);
+                    let (reducible, rest) = self
+                        .mixed
+                        .drain(..)
+                        .partition::<Vec<_>, _>(|(set, _)| set.contains(&index));
+                    self.mixed = rest;
+                    queue.extend(reducible);
+                }
+                _ if self.mixed.iter().any(|(set, _)| set == &indexes) => {}
+                _ => self.mixed.push((indexes, fragment)),
+            }
+        }
+    }
+
+    /// Extracts PSBT from the completely decoded `crypto-psbt` UR.
+    ///
+    /// Returns `Ok(None)` if the decoding is not yet complete.
+    pub fn psbt(&self) -> Result<Option<Psbt>, UrError> {
+        match (&self.ur_type, &self.message) {
+            (_, None) => Ok(None),
+            (Some(ur_type), Some(message)) if ur_type == UR_TYPE_PSBT || ur_type == "psbt" => {
+                psbt_from_cbor(message).map(Some)
+            }
+            (ur_type, Some(_)) => Err(UrError::NotPsbt(ur_type.clone().unwrap_or_default())),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Coin age and UTXO analytics for treasury reporting and consolidation planning.
+
+use bitcoin::OutPoint;
+
+use crate::{OnchainStatus, Wallet, WalletState};
+
+/// Average number of blocks mined per day.
+pub const BLOCKS_PER_DAY: u32 = 144;
+
+/// Analytics of a single unspent wallet coin.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct UtxoAnalytics {
+    pub outpoint: OutPoint,
+    /// Value of the coin, in sats.
+    pub value: u64,
+    /// Number of blocks since the coin was mined, or `None` for unconfirmed coins.
+    pub age: Option<u32>,
+    /// Fee required to spend the coin at the current fee rate, in sats.
+    pub spend_fee: u64,
+    /// Whether spending the coin at the current fee rate costs more than its value.
+    pub is_dust: bool,
+}
+
+impl UtxoAnalytics {
+    /// Coin days accumulated by the coin, which will be destroyed when it gets spent.
+    pub fn days_unrealized(&self) -> f64 { coin_days(self.value, self.age.unwrap_or_default()) }
+}
+
+/// Analytics of a wallet coin which was already spent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SpentAnalytics {
+    pub outpoint: OutPoint,
+    /// Value of the coin, in sats.
+    pub value: u64,
+    /// Number of blocks between the coin creation and spending.
+    pub age: u32,
+    /// Coin days destroyed by spending the coin.
+    pub days_destroyed: f64,
+}
+
+/// Report over the wallet state with the analytics of individual coins.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CoinAgeReport {
+    pub state: WalletState,
+    /// Wallet height the ages are computed at.
+    pub height: u32,
+    /// Fee rate used for the dust detection, in sats per vbyte.
+    pub fee_rate: f32,
+    pub utxos: Vec<UtxoAnalytics>,
+    pub spent: Vec<SpentAnalytics>,
+}
+
+impl CoinAgeReport {
+    /// Total value of the coins which are not worth spending at the current fee rate.
+    pub fn dust_value(&self) -> u64 {
+        self.utxos
+            .iter()
+            .filter(|utxo| utxo.is_dust)
+            .map(|utxo| utxo.value)
+            .sum()
+    }
+
+    /// Average age of the confirmed coins weighted by their value, in blocks.
+    pub fn average_age(&self) -> Option<f64> {
+        let (value, weighted) = self
+            .utxos
+            .iter()
+            .filter_map(|utxo| {
+                utxo.age
+                    .map(|age| (utxo.value, utxo.value as f64 * age as f64))
+            })
+            .fold((0u64, 0f64), |(value, weighted), (v, w)| {
+                (value + v, weighted + w)
+            });
+        (value > 0).then(|| weighted / value as f64)
+    }
+
+    /// Total coin days accumulated by the unspent coins.
+    pub fn days_unrealized(&self) -> f64 {
+        self.utxos.iter().map(UtxoAnalytics::days_unrealized).sum()
+    }
+
+    /// Total coin days destroyed by spending wallet coins.
+    pub fn days_destroyed(&self) -> f64 {
+        self.spent.iter().map(|spent| spent.days_destroyed).sum()
+    }
+
+    /// Coins which cost more than 1% of their value to spend at the current fee rate, and thus
+    /// should be consolidated while the fees are low.
+    pub fn consolidation_candidates(&self) -> impl Iterator<Item = &UtxoAnalytics> {
+        self.utxos
+            .iter()
+            .filter(|utxo| !utxo.is_dust && utxo.spend_fee * 100 > utxo.value)
+    }
+}
+
+fn coin_days(value: u64, age: u32) -> f64 {
+    value as f64 / 100_000_000.0 * age as f64 / BLOCKS_PER_DAY as f64
+}
+
+impl Wallet {
+    /// Computes coin age analytics, using the fastest fee estimate for the dust detection.
+    pub fn coin_age_report(&self) -> Result<CoinAgeReport, miniscript::Error> {
+        let fee_rate = self.ephemerals().fees.0;
+        let spend_fee = self.input_spend_fee()?;
+        let height = self.height();
+
+        let utxos = self
+            .utxos()
+            .iter()
+            .map(|utxo| UtxoAnalytics {
+                outpoint: utxo.outpoint(),
+                value: utxo.value,
+                age: match utxo.onchain.status {
+                    OnchainStatus::Blockchain(mined) => Some(height.saturating_sub(mined)),
+                    OnchainStatus::Mempool => None,
+                },
+                spend_fee,
+                is_dust: spend_fee >= utxo.value,
+            })
+            .collect();
+
+        let mut spent = vec![];
+        for entry in self.history() {
+            let spent_at = match entry.onchain.status {
+                OnchainStatus::Blockchain(height) => height,
+                OnchainStatus::Mempool => continue,
+            };
+            for txin in &entry.tx.input {
+                let outpoint = txin.previous_output;
+                let prev = self.history().iter().find(|prev| {
+                    prev.onchain.txid == outpoint.txid && prev.debit.contains_key(&outpoint.vout)
+                });
+                let (prev, mined) = match prev.map(|prev| (prev, prev.onchain.status)) {
+                    Some((prev, OnchainStatus::Blockchain(height))) => (prev, height),
+                    _ => continue,
+                };
+                let value = prev.tx.output[outpoint.vout as usize].value;
+                let age = spent_at.saturating_sub(mined);
+                spent.push(SpentAnalytics {
+                    outpoint,
+                    value,
+                    age,
+                    days_destroyed: coin_days(value, age),
+                });
+            }
+        }
+
+        Ok(CoinAgeReport {
+            state: self.state(),
+            height,
+            fee_rate,
+            utxos,
+            spent,
+        })
+    }
+}
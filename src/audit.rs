@@ -0,0 +1,223 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+use std::collections::BTreeSet;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::Txid;
+use chrono::{DateTime, Utc};
+use strict_encoding::StrictEncode;
+use wallet::hd::HardenedIndex;
+
+use crate::WalletDescriptor;
+
+/// Tag used for computing hashes of the audit log records.
+const AUDIT_RECORD_TAG: &[u8] = b"bpro:audit-record:v1";
+
+/// Security-relevant event recorded into the wallet audit log.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub enum AuditEvent {
+    #[display("signer {0} added")]
+    SignerAdded(Fingerprint),
+
+    #[display("signer {0} removed")]
+    SignerRemoved(Fingerprint),
+
+    #[display("signer {0} updated")]
+    SignerUpdated(Fingerprint),
+
+    /// Wallet descriptor has changed; contains hash of the new descriptor.
+    #[display("wallet descriptor changed to {0}")]
+    DescriptorChanged(sha256::Hash),
+
+    #[display("account {0} added")]
+    AccountAdded(HardenedIndex),
+
+    #[display("account {0} removed")]
+    AccountRemoved(HardenedIndex),
+
+    #[display("PSBT for transaction {txid} signed")]
+    PsbtSigned {
+        txid: Txid,
+        signers: BTreeSet<Fingerprint>,
+    },
+
+    #[display("{0} settings changed")]
+    SettingsChanged(String),
+
+    #[display("spending policy changed")]
+    SpendingPolicyChanged,
+
+    /// Spending policy was lifted with an override token; contains the token id.
+    #[display("spending policy overridden with token {0}")]
+    PolicyOverridden(sha256::Hash),
+
+    /// Migration to a successor wallet was planned; contains hash of the successor descriptor.
+    #[display("migration to wallet descriptor {0} planned")]
+    MigrationPlanned(sha256::Hash),
+
+    #[display("wallet exported as {0}")]
+    Exported(String),
+}
+
+impl AuditEvent {
+    /// Constructs [`AuditEvent::DescriptorChanged`] event for a given wallet descriptor.
+    pub fn descriptor_changed(descriptor: &WalletDescriptor) -> AuditEvent {
+        AuditEvent::DescriptorChanged(descriptor.id())
+    }
+
+    /// Signers affected by the event.
+    pub fn signers(&self) -> BTreeSet<Fingerprint> {
+        match self {
+            AuditEvent::SignerAdded(fp)
+            | AuditEvent::SignerRemoved(fp)
+            | AuditEvent::SignerUpdated(fp) => bset![*fp],
+            AuditEvent::PsbtSigned { signers, .. } => signers.clone(),
+            _ => bset![],
+        }
+    }
+}
+
+/// Audit log record, committing to the hash of the previous record.
+#[derive(Getters, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct AuditRecord {
+    #[getter(as_copy)]
+    timestamp: DateTime<Utc>,
+    event: AuditEvent,
+    #[getter(as_copy)]
+    prev: sha256::Hash,
+}
+
+impl AuditRecord {
+    /// Hash of the record, which is committed to by the next record in the log.
+    pub fn record_hash(&self) -> sha256::Hash {
+        let tag = sha256::Hash::hash(AUDIT_RECORD_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag[..]);
+        engine.input(&tag[..]);
+        engine.input(&self.prev[..]);
+        engine.input(&self.timestamp.timestamp().to_le_bytes());
+        engine.input(&self.timestamp.timestamp_subsec_nanos().to_le_bytes());
+        engine.input(&self.event.strict_serialize().expect("audit event encoding"));
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AuditError {
+    /// Audit log record {0} does not commit to the previous record; the log was tampered with.
+    BrokenChain(usize),
+
+    /// Audit log record {0} has timestamp preceding the previous record.
+    TimestampOrder(usize),
+}
+
+/// Filter for the audit log queries.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct AuditFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub till: Option<DateTime<Utc>>,
+    /// Return only events affecting a specific signer.
+    pub signer: Option<Fingerprint>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.from
+            .map(|from| record.timestamp >= from)
+            .unwrap_or(true)
+            && self
+                .till
+                .map(|till| record.timestamp <= till)
+                .unwrap_or(true)
+            && self
+                .signer
+                .map(|fp| record.event.signers().contains(&fp))
+                .unwrap_or(true)
+    }
+}
+
+/// Append-only hash-chained log of the security-relevant wallet events.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct AuditLog(Vec<AuditRecord>);
+
+impl AuditLog {
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, AuditRecord> { self.0.iter() }
+
+    /// Hash of the last record, or zero hash for an empty log.
+    pub fn head(&self) -> sha256::Hash {
+        self.0
+            .last()
+            .map(AuditRecord::record_hash)
+            .unwrap_or_else(sha256::Hash::all_zeros)
+    }
+
+    pub(crate) fn append(&mut self, event: AuditEvent) {
+        let prev = self.head();
+        let timestamp = self
+            .0
+            .last()
+            .map(|record| record.timestamp.max(Utc::now()))
+            .unwrap_or_else(Utc::now);
+        self.0.push(AuditRecord {
+            timestamp,
+            event,
+            prev,
+        });
+    }
+
+    /// Verifies integrity of the log hash chain.
+    pub fn verify(&self) -> Result<(), AuditError> {
+        let mut prev = sha256::Hash::all_zeros();
+        let mut prev_time = None;
+        for (no, record) in self.0.iter().enumerate() {
+            if record.prev != prev {
+                return Err(AuditError::BrokenChain(no));
+            }
+            if prev_time
+                .map(|time| record.timestamp < time)
+                .unwrap_or_default()
+            {
+                return Err(AuditError::TimestampOrder(no));
+            }
+            prev = record.record_hash();
+            prev_time = Some(record.timestamp);
+        }
+        Ok(())
+    }
+
+    /// Returns records matching the filter, in the order of their addition.
+    pub fn query<'log>(
+        &'log self,
+        filter: &'log AuditFilter,
+    ) -> impl Iterator<Item = &'log AuditRecord> + 'log {
+        self.0.iter().filter(|record| filter.matches(record))
+    }
+}
+
+impl<'log> IntoIterator for &'log AuditLog {
+    type Item = &'log AuditRecord;
+    type IntoIter = std::slice::Iter<'log, AuditRecord>;
+
+    fn into_iter(self) -> Self::IntoIter { self.0.iter() }
+}
@@ -0,0 +1,197 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Decoding of arbitrary transactions in the context of the wallet, for reviewing transactions
+//! produced externally before they are signed or broadcast.
+
+use amplify::Wrapper;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use wallet::hd::{DerivationSubpath, UnhardenedIndex};
+
+use crate::onchain::is_change_terminal;
+use crate::{TimelockReq, TimelockedSigs, TxDirection, Wallet};
+
+/// Transaction input in the context of the wallet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ClassifiedInput {
+    pub vin: u32,
+    pub outpoint: OutPoint,
+    /// Output spent by the input, if known to the wallet.
+    pub prevout: Option<TxOut>,
+    /// Derivation terminal of the wallet address the input spends from.
+    pub terminal: Option<DerivationSubpath<UnhardenedIndex>>,
+    /// Depth and branch of the wallet spending condition which the input satisfies.
+    pub condition: Option<(u8, TimelockedSigs)>,
+}
+
+impl ClassifiedInput {
+    pub fn is_mine(&self) -> bool { self.terminal.is_some() }
+}
+
+/// Transaction output in the context of the wallet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ClassifiedOutput {
+    pub vout: u32,
+    pub txout: TxOut,
+    /// Derivation terminal of the wallet address receiving the output.
+    pub terminal: Option<DerivationSubpath<UnhardenedIndex>>,
+}
+
+impl ClassifiedOutput {
+    pub fn is_mine(&self) -> bool { self.terminal.is_some() }
+
+    pub fn is_change(&self) -> bool {
+        self.terminal
+            .as_ref()
+            .map(|terminal| is_change_terminal(terminal))
+            .unwrap_or_default()
+    }
+}
+
+/// Effect of a transaction on the wallet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxClassification {
+    pub txid: Txid,
+    pub inputs: Vec<ClassifiedInput>,
+    pub outputs: Vec<ClassifiedOutput>,
+    /// Transaction fee, if all spent outputs are known.
+    pub fee: Option<u64>,
+}
+
+impl TxClassification {
+    /// Value of the wallet outputs spent by the transaction.
+    pub fn spent(&self) -> u64 {
+        self.inputs
+            .iter()
+            .filter(|input| input.is_mine())
+            .filter_map(|input| input.prevout.as_ref())
+            .map(|txout| txout.value)
+            .sum()
+    }
+
+    /// Value of the transaction outputs paying to the wallet.
+    pub fn received(&self) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| output.is_mine())
+            .map(|output| output.txout.value)
+            .sum()
+    }
+
+    /// Net change of the wallet balance.
+    pub fn balance(&self) -> i64 { self.received() as i64 - self.spent() as i64 }
+
+    pub fn direction(&self) -> TxDirection {
+        match self.balance() {
+            x if x > 0 => TxDirection::Incoming,
+            x if x < 0 => TxDirection::Outgoing,
+            _ => TxDirection::Internal,
+        }
+    }
+
+    /// Value leaving the wallet to the outputs of other parties.
+    pub fn sent_out(&self) -> u64 {
+        self.outputs
+            .iter()
+            .filter(|output| !output.is_mine())
+            .map(|output| output.txout.value)
+            .sum()
+    }
+
+    /// Whether outputs spent by all transaction inputs are known, such that the effect on the
+    /// wallet is complete.
+    pub fn is_complete(&self) -> bool { self.inputs.iter().all(|input| input.prevout.is_some()) }
+}
+
+impl Wallet {
+    /// Attributes inputs and outputs of an arbitrary transaction to the wallet addresses and
+    /// detects the spending conditions used by the wallet inputs.
+    pub fn classify_tx(&self, tx: &Transaction) -> TxClassification {
+        let inputs = tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(vin, txin)| {
+                let outpoint = txin.previous_output;
+                let prevout = self
+                    .utxos()
+                    .iter()
+                    .find(|utxo| utxo.outpoint() == outpoint)
+                    .map(|utxo| TxOut {
+                        value: utxo.value,
+                        script_pubkey: utxo.addr_src.address.script_pubkey().into_inner(),
+                    })
+                    .or_else(|| self.known_prevout(outpoint));
+                let terminal = prevout
+                    .as_ref()
+                    .and_then(|txout| self.is_mine(&txout.script_pubkey));
+                let condition = terminal
+                    .as_ref()
+                    .and_then(|_| self.exercised_condition(tx, vin));
+                ClassifiedInput {
+                    vin: vin as u32,
+                    outpoint,
+                    prevout,
+                    terminal,
+                    condition,
+                }
+            })
+            .collect::<Vec<_>>();
+        let outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, txout)| ClassifiedOutput {
+                vout: vout as u32,
+                txout: txout.clone(),
+                terminal: self.is_mine(&txout.script_pubkey),
+            })
+            .collect::<Vec<_>>();
+        let fee = if tx.is_coin_base() {
+            Some(0)
+        } else {
+            inputs
+                .iter()
+                .map(|input| input.prevout.as_ref().map(|txout| txout.value))
+                .sum::<Option<u64>>()
+                .and_then(|spent| {
+                    spent.checked_sub(tx.output.iter().map(|txout| txout.value).sum())
+                })
+        };
+        TxClassification {
+            txid: tx.txid(),
+            inputs,
+            outputs,
+            fee,
+        }
+    }
+
+    /// Spending condition branch enabled by the lock time and sequence of the transaction
+    /// input. Timelocked branches are usable only when the transaction enables them, so the
+    /// most restrictive of the enabled branches is reported.
+    fn exercised_condition(&self, tx: &Transaction, vin: usize) -> Option<(u8, TimelockedSigs)> {
+        self.as_settings()
+            .spending_conditions()
+            .iter()
+            .flat_map(|(depth, condition)| {
+                condition
+                    .branches()
+                    .into_iter()
+                    .map(move |branch| (*depth, branch))
+            })
+            .filter(|(_, branch)| match branch.timelock.script_timelock() {
+                Ok(None) => true,
+                Ok(Some(timelock)) => timelock.is_enabled_by(tx, vin),
+                Err(_) => false,
+            })
+            .max_by_key(|(depth, branch)| (branch.timelock != TimelockReq::Anytime, *depth))
+    }
+}
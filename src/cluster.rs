@@ -0,0 +1,121 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Clustering of wallet coins which are already linked onchain or by their labels, and tracking
+//! of "toxic" change known to the identified counterparties.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::{OutPoint, Txid};
+
+use crate::Wallet;
+
+/// Group of wallet coins which external observers may already consider as belonging to the
+/// same owner.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct CoinCluster {
+    pub coins: BTreeSet<OutPoint>,
+    /// Labels of the transactions which created the cluster coins.
+    pub labels: BTreeSet<String>,
+    /// Total value of the cluster coins, in sats.
+    pub value: u64,
+    /// Cluster coins which are change of the payments to identified counterparties.
+    pub toxic: BTreeSet<OutPoint>,
+}
+
+/// Disjoint sets of the wallet transactions.
+#[derive(Default)]
+struct TxSets(BTreeMap<Txid, Txid>);
+
+impl TxSets {
+    fn find(&mut self, txid: Txid) -> Txid {
+        let mut root = txid;
+        while let Some(parent) = self.0.get(&root).copied().filter(|parent| *parent != root) {
+            root = parent;
+        }
+        self.0.insert(txid, root);
+        root
+    }
+
+    fn union(&mut self, a: Txid, b: Txid) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.0.insert(a, b);
+        }
+    }
+}
+
+impl Wallet {
+    /// Detects whether the wallet coin is a change of a payment to an identified counterparty,
+    /// such that spending it together with other coins reveals them to that counterparty.
+    pub fn is_toxic(&self, outpoint: OutPoint) -> bool {
+        self.history().iter().any(|entry| {
+            entry.onchain.txid == outpoint.txid
+                && !entry.credit.is_empty()
+                && !entry.beneficiaries.is_empty()
+                && entry.debit.contains_key(&outpoint.vout)
+        })
+    }
+
+    /// Unspent wallet coins which are change of the payments to identified counterparties.
+    pub fn toxic_coins(&self) -> BTreeSet<OutPoint> {
+        self.utxos()
+            .iter()
+            .map(|utxo| utxo.outpoint())
+            .filter(|outpoint| self.is_toxic(*outpoint))
+            .collect()
+    }
+
+    /// Groups unspent wallet coins into clusters. Coins get into the same cluster if they were
+    /// created by the same transaction, by transactions spending coins of the same cluster, or by
+    /// transactions having the same label.
+    pub fn coin_clusters(&self) -> Vec<CoinCluster> {
+        let mut sets = TxSets::default();
+        let mut labelled = BTreeMap::<String, Txid>::new();
+        for entry in self.history() {
+            let txid = entry.onchain.txid;
+            sets.find(txid);
+            for txin in &entry.tx.input {
+                let prevout = txin.previous_output;
+                let is_ours = self.history().iter().any(|prev| {
+                    prev.onchain.txid == prevout.txid && prev.debit.contains_key(&prevout.vout)
+                });
+                if is_ours {
+                    sets.union(txid, prevout.txid);
+                }
+            }
+            if let Some(label) = self.coin_label(OutPoint::new(txid, 0)) {
+                let first = *labelled.entry(label).or_insert(txid);
+                sets.union(txid, first);
+            }
+        }
+
+        let mut clusters = BTreeMap::<Txid, CoinCluster>::new();
+        for utxo in self.utxos() {
+            let outpoint = utxo.outpoint();
+            let cluster = clusters.entry(sets.find(outpoint.txid)).or_default();
+            cluster.coins.insert(outpoint);
+            cluster.value += utxo.value;
+            cluster.labels.extend(self.coin_label(outpoint));
+            if self.is_toxic(outpoint) {
+                cluster.toxic.insert(outpoint);
+            }
+        }
+        clusters.into_values().collect()
+    }
+
+    /// Cluster containing the given wallet coin.
+    pub fn coin_cluster(&self, outpoint: OutPoint) -> Option<CoinCluster> {
+        self.coin_clusters()
+            .into_iter()
+            .find(|cluster| cluster.coins.contains(&outpoint))
+    }
+}
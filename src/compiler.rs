@@ -0,0 +1,678 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::{LockTime, Network};
+use miniscript::descriptor::{Sh, TapTree, Wsh};
+use miniscript::policy::compiler::CompilerError;
+use miniscript::policy::concrete::{Policy, PolicyError};
+use miniscript::policy::{semantic, Liftable};
+use miniscript::{Descriptor, Legacy, Segwitv0, Tap};
+use wallet::descriptors::DescriptorClass;
+use wallet::hd::{DerivationAccount, DerivationSubpath, TerminalStep, UnsatisfiableKey};
+
+use crate::{
+    ScriptTimelock, Signer, SignerRef, SigsReq, SpendingCondition, TimelockError, TimelockReq,
+    TimelockedSigs, ToTapTree,
+};
+
+/// Maximal difference in depth of spending conditions which is still reflected in the
+/// satisfaction probability weights.
+const MAX_WEIGHT_SHIFT: u8 = 16;
+
+/// Errors lifting descriptor into a set of spending conditions.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum LiftError {
+    /// Unable to lift descriptor into a semantic policy. Details: {0}
+    Lift(String),
+
+    /// Descriptor can't be satisfied.
+    Unsatisfiable,
+
+    /// Descriptor contains spending path without signatures, which is not supported.
+    NoSignatures,
+
+    /// Descriptor contains hash locks, which can't be represented with spending conditions.
+    HashLock,
+
+    /// Spending path "{0}" can't be represented as a spending condition.
+    Unsupported(String),
+
+    #[display(inner)]
+    #[from]
+    Timelock(TimelockError),
+}
+
+/// Strategy for packing a set of spending conditions into a script structure.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub enum ScriptLayout {
+    /// Conditions are packed one or two per tree level following their depth. This is the
+    /// layout used by all existing wallets; it must be kept for them to produce the same
+    /// addresses.
+    #[default]
+    #[display("depth-ordered")]
+    DepthOrdered,
+
+    /// Conditions are weighted by their assigned spending probabilities or, if none are
+    /// assigned, by their depth (conditions with lower depth are treated as more likely spending
+    /// paths) and packed such that the expected satisfaction cost is minimal.
+    /// For taproot, a timelock-free single-key condition is moved to the key path.
+    #[display("cost-optimized")]
+    CostOptimized,
+}
+
+/// Ordering of keys in multi-signature scripts.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum MultisigOrder {
+    /// Keys are sorted (`sortedmulti`) for segwit descriptors and kept in the signer order for
+    /// pre-segwit ones, as it was done by wallets created before the order became configurable.
+    #[default]
+    #[display("auto")]
+    Auto,
+
+    /// Keys are lexicographically sorted after derivation (`sortedmulti`), such that the order
+    /// in which cosigners were added does not matter across coordinators.
+    #[display("sorted")]
+    Sorted,
+
+    /// Keys are used in the order of the wallet signers (`multi`).
+    #[display("ordered")]
+    Ordered,
+}
+
+impl MultisigOrder {
+    /// Detects whether `sortedmulti` is used for a given descriptor class. Taproot multi-sig
+    /// scripts are always ordered, since miniscript does not define sorted `multi_a`.
+    pub fn is_sorted(self, class: DescriptorClass) -> bool {
+        match (self, class) {
+            (_, DescriptorClass::TaprootC0) => false,
+            (MultisigOrder::Auto, class) => class.is_segwit_v0(),
+            (MultisigOrder::Sorted, _) => true,
+            (MultisigOrder::Ordered, _) => false,
+        }
+    }
+}
+
+/// Compiler of the wallet spending conditions into miniscript descriptors.
+#[derive(Clone, Debug)]
+pub struct ConditionCompiler<'signers> {
+    signers: &'signers [Signer],
+    terminal: &'signers DerivationSubpath<TerminalStep>,
+    testnet: bool,
+    layout: ScriptLayout,
+    multisig_order: MultisigOrder,
+    spend_weights: Option<&'signers BTreeMap<SpendingCondition, u16>>,
+    nums_internal_key: bool,
+    musig_key_path: bool,
+}
+
+impl<'signers> ConditionCompiler<'signers> {
+    pub fn new(
+        signers: &'signers [Signer],
+        terminal: &'signers DerivationSubpath<TerminalStep>,
+        testnet: bool,
+    ) -> Self {
+        ConditionCompiler {
+            signers,
+            terminal,
+            testnet,
+            layout: ScriptLayout::DepthOrdered,
+            multisig_order: MultisigOrder::Auto,
+            spend_weights: None,
+            nums_internal_key: false,
+            musig_key_path: false,
+        }
+    }
+
+    pub fn with_layout(mut self, layout: ScriptLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> ScriptLayout { self.layout }
+
+    pub fn with_multisig_order(mut self, order: MultisigOrder) -> Self {
+        self.multisig_order = order;
+        self
+    }
+
+    pub fn multisig_order(&self) -> MultisigOrder { self.multisig_order }
+
+    /// Forces use of the provably unspendable (NUMS) taproot internal key, such that taproot
+    /// outputs can be spent only with script paths, even for a single-signer wallet.
+    pub fn with_nums_internal_key(mut self, nums: bool) -> Self {
+        self.nums_internal_key = nums;
+        self
+    }
+
+    pub fn nums_internal_key(&self) -> bool { self.nums_internal_key }
+
+    /// Moves timelock-free N-of-N condition (see [`ConditionCompiler::musig_signers`]) out of
+    /// the taproot script tree, such that it is satisfied by a key-path spending with the
+    /// MuSig2-aggregated key of the signers. Miniscript descriptors can't express aggregated
+    /// keys, so the compiled descriptor uses unsatisfiable internal key, which must be replaced
+    /// with the aggregated key after derivation (see [`crate::WalletSettings::derive`]).
+    pub fn with_musig_key_path(mut self, musig: bool) -> Self {
+        self.musig_key_path = musig;
+        self
+    }
+
+    pub fn musig_key_path(&self) -> bool { self.musig_key_path }
+
+    /// Assigns relative spending probabilities to the conditions, used by
+    /// [`ScriptLayout::CostOptimized`] layout instead of the ones derived from the condition
+    /// depths. Conditions without assigned probability get the lowest weight of 1.
+    pub fn with_spend_weights(
+        mut self,
+        weights: &'signers BTreeMap<SpendingCondition, u16>,
+    ) -> Self {
+        self.spend_weights = Some(weights).filter(|weights| !weights.is_empty());
+        self
+    }
+
+    /// Satisfaction probability weights of the conditions: explicitly assigned, if any, or
+    /// derived from the condition depths.
+    fn weighted<T>(&self, items: Vec<(u8, &SpendingCondition, T)>) -> Vec<(usize, T)> {
+        match self.spend_weights {
+            Some(weights) => items
+                .into_iter()
+                .map(|(_, cond, item)| {
+                    (
+                        weights.get(cond).copied().unwrap_or(1).max(1) as usize,
+                        item,
+                    )
+                })
+                .collect(),
+            None => depth_weighted(
+                items
+                    .into_iter()
+                    .map(|(depth, _, item)| (depth, item))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Compiles spending conditions into a descriptor of a given class.
+    pub fn compile(
+        &self,
+        class: DescriptorClass,
+        conditions: &BTreeSet<(u8, SpendingCondition)>,
+    ) -> Result<Descriptor<DerivationAccount>, miniscript::Error> {
+        let script_only = self.nums_internal_key && class == DescriptorClass::TaprootC0;
+        if self.signers.len() <= 1 && !script_only {
+            let first_key = self
+                .signers
+                .first()
+                .ok_or_else(|| {
+                    miniscript::Error::Unexpected(s!("wallet core does not contain any signers"))
+                })?
+                .to_tracking_account(self.terminal.clone());
+
+            return Ok(match class {
+                DescriptorClass::PreSegwit => Descriptor::new_pk(first_key),
+                DescriptorClass::SegwitV0 => Descriptor::new_wpkh(first_key)?,
+                DescriptorClass::NestedV0 => Descriptor::new_sh_wpkh(first_key)?,
+                DescriptorClass::TaprootC0 => Descriptor::new_tr(first_key, None)?,
+            });
+        }
+
+        if class == DescriptorClass::TaprootC0 {
+            let (internal_key, tree) = self.tap_tree(conditions)?;
+            let internal_key = internal_key.unwrap_or_else(|| {
+                DerivationAccount::unsatisfiable_key((self.testnet, self.terminal.clone()))
+            });
+            return Descriptor::new_tr(internal_key, tree);
+        }
+
+        let policy = self.policy(conditions)?;
+
+        let err_mapper = |err| match err {
+            CompilerError::PolicyError(PolicyError::DuplicatePubKeys) => {
+                miniscript::Error::Unexpected(s!(
+                    "Multiple spending conditions re-using the same keys require Taproot"
+                ))
+            }
+            err => miniscript::Error::CompilerError(err),
+        };
+
+        let sorted_multi =
+            if self.multisig_order.is_sorted(class) { sorted_multi_keys(&policy) } else { None };
+
+        if class.is_segwit_v0() {
+            let descr = match sorted_multi {
+                Some((min_sigs, keys)) => Wsh::new_sortedmulti(min_sigs, keys)?,
+                None => {
+                    let ms_witscript = policy.compile::<Segwitv0>().map_err(err_mapper)?;
+                    Wsh::new(ms_witscript)?
+                }
+            };
+            return Ok(match class {
+                DescriptorClass::SegwitV0 => Descriptor::Wsh(descr),
+                DescriptorClass::NestedV0 => Descriptor::Sh(Sh::new_with_wsh(descr)),
+                _ => unreachable!(),
+            });
+        }
+
+        if let Some((min_sigs, keys)) = sorted_multi {
+            return Ok(Descriptor::Sh(Sh::new_sortedmulti(min_sigs, keys)?));
+        }
+        let ms = policy.compile::<Legacy>().map_err(err_mapper)?;
+        Ok(Descriptor::Sh(Sh::new(ms)?))
+    }
+
+    /// Constructs a single concrete policy covering all of the spending conditions.
+    pub fn policy(
+        &self,
+        conditions: &BTreeSet<(u8, SpendingCondition)>,
+    ) -> Result<Policy<DerivationAccount>, miniscript::Error> {
+        let dfs_tree = conditions
+            .iter()
+            .map(|(depth, cond)| (*depth, cond.policy(self.signers, self.terminal)));
+
+        let policy = match self.layout {
+            ScriptLayout::DepthOrdered => {
+                // Pack the tree into a linear structure
+                let (policy, remnant) = dfs_tree.rfold(
+                    (None, None)
+                        as (
+                            Option<Policy<DerivationAccount>>,
+                            Option<Policy<DerivationAccount>>,
+                        ),
+                    |(acc, prev), (index, pol)| match (acc, prev) {
+                        (None, None) if index % 2 == 1 => (None, Some(pol)),
+                        (None, None) => (Some(pol), None),
+                        (None, Some(prev)) => (
+                            Some(Policy::Or(vec![
+                                (index as usize, pol),
+                                (index as usize + 1, prev),
+                            ])),
+                            None,
+                        ),
+                        (Some(acc), None) => (
+                            Some(Policy::Or(vec![
+                                (index as usize, pol),
+                                (index as usize + 1, acc),
+                            ])),
+                            None,
+                        ),
+                        _ => unreachable!(),
+                    },
+                );
+                policy.or(remnant)
+            }
+            ScriptLayout::CostOptimized => {
+                let nodes = self.weighted(
+                    conditions
+                        .iter()
+                        .map(|(depth, cond)| {
+                            (*depth, cond, cond.policy(self.signers, self.terminal))
+                        })
+                        .collect(),
+                );
+                huffman(nodes, |(w1, p1), (w2, p2)| {
+                    Policy::Or(vec![(w1, p1), (w2, p2)])
+                })
+            }
+        };
+
+        policy.ok_or_else(|| {
+            miniscript::Error::Unexpected(s!("zero signing accounts must be filtered"))
+        })
+    }
+
+    /// Finds timelock-free N-of-N condition with at least two signers, which can be satisfied
+    /// with a MuSig2 key-path spending instead of a tapscript multi-signature.
+    pub fn musig_signers(
+        &self,
+        conditions: &BTreeSet<(u8, SpendingCondition)>,
+    ) -> Option<Vec<&'signers Signer>> {
+        self.musig_condition(conditions).map(|(_, signers)| signers)
+    }
+
+    fn musig_condition<'conditions>(
+        &self,
+        conditions: &'conditions BTreeSet<(u8, SpendingCondition)>,
+    ) -> Option<(&'conditions SpendingCondition, Vec<&'signers Signer>)> {
+        conditions
+            .iter()
+            .filter(|(_, cond)| {
+                matches!(
+                    cond,
+                    SpendingCondition::Sigs(TimelockedSigs {
+                        timelock: TimelockReq::Anytime,
+                        ..
+                    })
+                )
+            })
+            .find_map(|(_, cond)| {
+                let (k, keys) = sorted_multi_keys(&cond.policy(self.signers, self.terminal))?;
+                if k != keys.len() || k < 2 {
+                    return None;
+                }
+                let signers = keys
+                    .iter()
+                    .map(|key| {
+                        self.signers
+                            .iter()
+                            .find(|signer| signer.fingerprint() == key.account_xpub.fingerprint())
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some((cond, signers))
+            })
+    }
+
+    /// Constructs taproot script tree from the spending conditions, optionally extracting a
+    /// condition which can be satisfied with a key path spending.
+    #[allow(clippy::type_complexity)]
+    pub fn tap_tree(
+        &self,
+        conditions: &BTreeSet<(u8, SpendingCondition)>,
+    ) -> Result<
+        (
+            Option<DerivationAccount>,
+            Option<TapTree<DerivationAccount>>,
+        ),
+        miniscript::Error,
+    > {
+        let musig = self
+            .musig_condition(conditions)
+            .map(|(cond, _)| cond)
+            .filter(|_| self.musig_key_path && !self.nums_internal_key);
+        let mut policies = conditions
+            .iter()
+            .filter(|(_, cond)| Some(cond) != musig)
+            .map(|(depth, cond)| (*depth, cond, cond.policy(self.signers, self.terminal)))
+            .collect::<Vec<_>>();
+
+        if self.layout == ScriptLayout::DepthOrdered {
+            if policies.is_empty() {
+                return Ok((None, None));
+            }
+            let tree = policies
+                .into_iter()
+                .try_fold::<_, _, Result<_, miniscript::Error>>(
+                    Vec::new(),
+                    |mut acc, (depth, _, policy)| {
+                        acc.push((depth, policy.compile::<Tap>()?));
+                        Ok(acc)
+                    },
+                )?;
+            return Ok((None, Some(tree.to_tap_tree()?)));
+        }
+
+        let key_path = policies
+            .iter()
+            .position(|(_, cond, policy)| {
+                matches!(
+                    cond,
+                    SpendingCondition::Sigs(TimelockedSigs {
+                        timelock: TimelockReq::Anytime,
+                        ..
+                    })
+                ) && single_key(policy).is_some()
+            })
+            .filter(|_| !self.nums_internal_key && musig.is_none());
+        let internal_key = key_path
+            .map(|pos| policies.remove(pos))
+            .and_then(|(_, _, policy)| single_key(&policy).cloned());
+
+        let leaves = policies
+            .into_iter()
+            .map(|(depth, cond, policy)| {
+                Ok((
+                    depth,
+                    cond,
+                    TapTree::Leaf(Arc::new(policy.compile::<Tap>()?)),
+                ))
+            })
+            .collect::<Result<Vec<_>, miniscript::Error>>()?;
+        let tree = huffman(self.weighted(leaves), |(_, left), (_, right)| {
+            TapTree::Tree(Arc::new(left), Arc::new(right))
+        });
+
+        Ok((internal_key, tree))
+    }
+}
+
+/// Reconstructs spending conditions from a descriptor, which may be imported from other
+/// software, for the cases when its script structure allows that. Conditions of non-taproot
+/// descriptors are numbered according to their order in the script; taproot conditions use the
+/// depth of the script leaf, with the key path spending having zero depth.
+pub fn lift_conditions(
+    descriptor: &Descriptor<DerivationAccount>,
+) -> Result<BTreeSet<(u8, SpendingCondition)>, LiftError> {
+    let lift_err = |err: miniscript::Error| LiftError::Lift(err.to_string());
+
+    let mut alternatives = Vec::<(u8, semantic::Policy<DerivationAccount>)>::new();
+    match descriptor {
+        Descriptor::Tr(tr) => {
+            let internal_key = tr.internal_key();
+            let unspendable = DerivationAccount::unsatisfiable_key((
+                internal_key.account_xpub.network != Network::Bitcoin,
+                internal_key.terminal_path.clone(),
+            ));
+            if *internal_key != unspendable {
+                alternatives.push((0, semantic::Policy::Key(internal_key.clone())));
+            }
+            for (depth, ms) in tr.iter_scripts() {
+                alternatives.push((depth, ms.lift().map_err(lift_err)?.normalized()));
+            }
+        }
+        _ => {
+            let policy = descriptor.lift().map_err(lift_err)?.normalized();
+            let subs = split_alternatives(policy);
+            let single = subs.len() == 1;
+            alternatives.extend(subs.into_iter().enumerate().map(|(no, policy)| {
+                let depth = if single { 0 } else { no as u8 + 1 };
+                (depth, policy)
+            }));
+        }
+    }
+
+    let mut all_keys = bset![];
+    for (_, policy) in &alternatives {
+        collect_keys(policy, &mut all_keys);
+    }
+
+    alternatives
+        .into_iter()
+        .map(|(depth, policy)| Ok((depth, lift_condition(&policy, &all_keys)?)))
+        .collect()
+}
+
+fn split_alternatives(
+    policy: semantic::Policy<DerivationAccount>,
+) -> Vec<semantic::Policy<DerivationAccount>> {
+    match policy {
+        semantic::Policy::Threshold(1, subs)
+            if !subs
+                .iter()
+                .all(|sub| matches!(sub, semantic::Policy::Key(_))) =>
+        {
+            subs.into_iter().flat_map(split_alternatives).collect()
+        }
+        policy => vec![policy],
+    }
+}
+
+fn collect_keys(policy: &semantic::Policy<DerivationAccount>, keys: &mut BTreeSet<Fingerprint>) {
+    match policy {
+        semantic::Policy::Key(key) => {
+            keys.insert(key.account_xpub.fingerprint());
+        }
+        semantic::Policy::Threshold(_, subs) => {
+            subs.iter().for_each(|sub| collect_keys(sub, keys));
+        }
+        _ => {}
+    }
+}
+
+/// Extracts threshold and signer fingerprints from a policy consisting only of keys.
+fn key_threshold(
+    policy: &semantic::Policy<DerivationAccount>,
+) -> Option<(usize, Vec<Fingerprint>)> {
+    match policy {
+        semantic::Policy::Key(key) => Some((1, vec![key.account_xpub.fingerprint()])),
+        semantic::Policy::Threshold(k, subs) => subs
+            .iter()
+            .map(|sub| match sub {
+                semantic::Policy::Key(key) => Some(key.account_xpub.fingerprint()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|fingerprints| (*k, fingerprints)),
+        _ => None,
+    }
+}
+
+fn lift_condition(
+    policy: &semantic::Policy<DerivationAccount>,
+    all_keys: &BTreeSet<Fingerprint>,
+) -> Result<SpendingCondition, LiftError> {
+    let unsupported = || LiftError::Unsupported(policy.to_string());
+
+    let mut timelocks = vec![];
+    let (k, fingerprints) = match policy {
+        semantic::Policy::Unsatisfiable => return Err(LiftError::Unsatisfiable),
+        semantic::Policy::Trivial => return Err(LiftError::NoSignatures),
+        semantic::Policy::After(_) | semantic::Policy::Older(_) => {
+            return Err(LiftError::NoSignatures)
+        }
+        semantic::Policy::Sha256(_)
+        | semantic::Policy::Hash256(_)
+        | semantic::Policy::Ripemd160(_)
+        | semantic::Policy::Hash160(_) => return Err(LiftError::HashLock),
+        policy if key_threshold(policy).is_some() => {
+            key_threshold(policy).expect("checked in the guard")
+        }
+        semantic::Policy::Threshold(n, subs) if *n == subs.len() => {
+            let mut keys = vec![];
+            let mut threshold = None;
+            for sub in subs {
+                match sub {
+                    semantic::Policy::Key(key) => keys.push(key.account_xpub.fingerprint()),
+                    semantic::Policy::After(_) | semantic::Policy::Older(_) => timelocks.push(sub),
+                    semantic::Policy::Sha256(_)
+                    | semantic::Policy::Hash256(_)
+                    | semantic::Policy::Ripemd160(_)
+                    | semantic::Policy::Hash160(_) => return Err(LiftError::HashLock),
+                    sub => match (key_threshold(sub), &threshold) {
+                        (Some(thresh), None) => threshold = Some(thresh),
+                        _ => return Err(unsupported()),
+                    },
+                }
+            }
+            match (threshold, keys.is_empty()) {
+                (Some(threshold), true) => threshold,
+                (None, false) => (keys.len(), keys),
+                (None, true) => return Err(LiftError::NoSignatures),
+                (Some(_), false) => return Err(unsupported()),
+            }
+        }
+        _ => return Err(unsupported()),
+    };
+
+    let fingerprints = fingerprints.into_iter().collect::<BTreeSet<_>>();
+    let k = u16::try_from(k).map_err(|_| unsupported())?;
+    let sigs = if &fingerprints == all_keys {
+        match k {
+            k if k as usize == all_keys.len() => SigsReq::All,
+            1 => SigsReq::Any,
+            k => SigsReq::AtLeast(k),
+        }
+    } else {
+        SigsReq::Specific(k, fingerprints.into_iter().map(SignerRef::from).collect())
+    };
+
+    let timelock = match timelocks.as_slice() {
+        [] => TimelockReq::Anytime,
+        [semantic::Policy::After(lock_time)] => {
+            TimelockReq::try_from(ScriptTimelock::Absolute(LockTime::from(*lock_time)))?
+        }
+        [semantic::Policy::Older(sequence)] => {
+            TimelockReq::try_from(ScriptTimelock::Relative(*sequence))?
+        }
+        _ => return Err(unsupported()),
+    };
+
+    Ok(SpendingCondition::Sigs(TimelockedSigs { sigs, timelock }))
+}
+
+/// Extracts threshold and keys from a policy which is a plain threshold over keys, which can
+/// be represented with `sortedmulti`.
+fn sorted_multi_keys(
+    policy: &Policy<DerivationAccount>,
+) -> Option<(usize, Vec<DerivationAccount>)> {
+    let (k, thresh) = match policy {
+        Policy::Threshold(k, thresh) => (*k, thresh),
+        _ => return None,
+    };
+    let keys = thresh
+        .iter()
+        .filter_map(|pol| match pol {
+            Policy::Key(key) => Some(key.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    (keys.len() == thresh.len()).then_some((k, keys))
+}
+
+fn single_key(policy: &Policy<DerivationAccount>) -> Option<&DerivationAccount> {
+    match policy {
+        Policy::Key(key) => Some(key),
+        Policy::Threshold(1, subs) if subs.len() == 1 => single_key(&subs[0]),
+        _ => None,
+    }
+}
+
+/// Converts condition depths into satisfaction probability weights, such that each next depth
+/// level is twice less likely to be used than the previous one.
+fn depth_weighted<T>(items: Vec<(u8, T)>) -> Vec<(usize, T)> {
+    let max_depth = items
+        .iter()
+        .map(|(depth, _)| *depth)
+        .max()
+        .unwrap_or_default();
+    items
+        .into_iter()
+        .map(|(depth, item)| (1usize << (max_depth - depth).min(MAX_WEIGHT_SHIFT), item))
+        .collect()
+}
+
+/// Packs weighted items into a binary tree by repeatedly merging two least likely nodes, which
+/// minimizes the expected depth of the item used for the satisfaction.
+pub(crate) fn huffman<T>(
+    mut nodes: Vec<(usize, T)>,
+    mut merge: impl FnMut((usize, T), (usize, T)) -> T,
+) -> Option<T> {
+    while nodes.len() > 1 {
+        // Stable sort keeps the original order for equally weighted nodes
+        nodes.sort_by_key(|(weight, _)| Reverse(*weight));
+        let second = nodes.pop().expect("at least two nodes");
+        let first = nodes.pop().expect("at least two nodes");
+        let weight = first.0 + second.0;
+        nodes.push((weight, merge(first, second)));
+    }
+    nodes.pop().map(|(_, node)| node)
+}
@@ -0,0 +1,243 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Policy-enforcing co-signing services, which hold one of the wallet keys and sign only after
+//! an additional confirmation (like 2FA code) from the user.
+
+use std::error::Error as StdError;
+
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::Txid;
+use chrono::{DateTime, Utc};
+use wallet::hd::HardenedIndex;
+use wallet::onchain::PublicNetwork;
+use wallet::psbt::Psbt;
+
+use crate::{AuditEvent, Ownership, Signer, SpendingPolicy, Wallet};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CosignerError {
+    /// Co-signer key {0} is not one of the wallet signers.
+    UnknownSigner(Fingerprint),
+
+    /// Co-signer key {0} is already registered.
+    AlreadyRegistered(Fingerprint),
+
+    /// Co-signer enrollment is for {0} network, while the wallet uses {1}.
+    NetworkMismatch(bitcoin::Network, PublicNetwork),
+
+    /// Co-signing service returned PSBT for a different transaction.
+    TxMismatch,
+
+    /// Co-signing service has not provided any signatures.
+    NoSignatures,
+
+    /// Co-signing service session has expired at {0}.
+    SessionExpired(DateTime<Utc>),
+
+    /// Co-signing service error. Details: {0}
+    Service(String),
+}
+
+/// Request for enrollment of the wallet with a co-signing service.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct EnrollmentRequest {
+    pub network: PublicNetwork,
+    /// Account index under which the service should derive its key.
+    pub account: HardenedIndex,
+    /// Policy which must be enforced by the service before co-signing.
+    pub policy: SpendingPolicy,
+    /// User contact for the second-factor confirmations (like email or phone number).
+    pub contact: String,
+}
+
+impl EnrollmentRequest {
+    /// Constructs request asking the service to enforce the wallet spending policy.
+    pub fn with(wallet: &Wallet, account: HardenedIndex, contact: String) -> Self {
+        EnrollmentRequest {
+            network: wallet.as_settings().network(),
+            account,
+            policy: wallet.spending_policy().clone(),
+            contact,
+        }
+    }
+}
+
+/// Enrollment payload returned by a co-signing service, containing the service key.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct CosignerEnrollment {
+    /// Service name shown to the user.
+    pub service: String,
+    /// Service endpoint.
+    pub endpoint: String,
+    /// Identifier of the user account within the service.
+    pub account_id: String,
+    pub master_fp: Fingerprint,
+    pub origin: DerivationPath,
+    pub xpub: ExtendedPubKey,
+    pub enrolled_at: DateTime<Utc>,
+}
+
+impl CosignerEnrollment {
+    /// Constructs wallet signer for the service key, which can be used in spending conditions
+    /// as any other signer.
+    pub fn to_signer(&self) -> Signer {
+        Signer {
+            master_fp: self.master_fp,
+            origin: self.origin.clone(),
+            account: self
+                .origin
+                .as_ref()
+                .last()
+                .copied()
+                .and_then(|child| HardenedIndex::try_from(child).ok()),
+            xpub: self.xpub,
+            device: None,
+            name: self.service.clone(),
+            ownership: Ownership::External,
+        }
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint { self.xpub.fingerprint() }
+}
+
+/// Pending co-signing session awaiting the second-factor confirmation.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct CosigningSession {
+    pub id: String,
+    pub txid: Txid,
+    /// Confirmation method used by the service (like "totp", "sms" or "email").
+    pub method: String,
+    /// Message to be shown to the user.
+    pub message: Option<String>,
+    pub expires: DateTime<Utc>,
+}
+
+/// Co-signing service which signs PSBTs after enforcing its policy and receiving the
+/// second-factor confirmation from the user.
+pub trait CosigningService {
+    type Error: StdError;
+
+    /// Enrolls the wallet with the service, returning the service key.
+    fn enroll(&self, request: &EnrollmentRequest) -> Result<CosignerEnrollment, Self::Error>;
+
+    /// Submits PSBT for co-signing, starting the confirmation session.
+    fn request_signature(
+        &self,
+        enrollment: &CosignerEnrollment,
+        psbt: &Psbt,
+    ) -> Result<CosigningSession, Self::Error>;
+
+    /// Completes session with the confirmation code, returning PSBT signed by the service.
+    fn confirm(&self, session: &CosigningSession, code: &str) -> Result<Psbt, Self::Error>;
+}
+
+impl Wallet {
+    /// Registers co-signing service for one of the wallet signers, which must be created from
+    /// the same enrollment with [`CosignerEnrollment::to_signer`].
+    pub fn register_cosigner(
+        &mut self,
+        enrollment: CosignerEnrollment,
+    ) -> Result<(), CosignerError> {
+        let fingerprint = enrollment.fingerprint();
+        let network = self.as_settings().network();
+        if (enrollment.xpub.network == bitcoin::Network::Bitcoin)
+            != (network == PublicNetwork::Mainnet)
+        {
+            return Err(CosignerError::NetworkMismatch(
+                enrollment.xpub.network,
+                network,
+            ));
+        }
+        if !self
+            .as_settings()
+            .signers()
+            .iter()
+            .any(|signer| signer.fingerprint() == fingerprint)
+        {
+            return Err(CosignerError::UnknownSigner(fingerprint));
+        }
+        if self.cosigner(fingerprint).is_some() {
+            return Err(CosignerError::AlreadyRegistered(fingerprint));
+        }
+        self.insert_cosigner(enrollment);
+        self.record_audit(AuditEvent::SettingsChanged(format!(
+            "co-signer {}",
+            fingerprint
+        )));
+        Ok(())
+    }
+
+    pub fn cosigner(&self, fingerprint: Fingerprint) -> Option<&CosignerEnrollment> {
+        self.cosigners().get(&fingerprint)
+    }
+
+    /// Runs co-signing round-trip with a service: submits PSBT, obtains confirmation code with
+    /// the provided callback and combines signatures returned by the service into the PSBT.
+    pub fn cosign<S: CosigningService>(
+        &mut self,
+        service: &S,
+        fingerprint: Fingerprint,
+        psbt: &mut Psbt,
+        confirm: impl FnOnce(&CosigningSession) -> String,
+    ) -> Result<(), CosignerError> {
+        let enrollment = self
+            .cosigner(fingerprint)
+            .ok_or(CosignerError::UnknownSigner(fingerprint))?;
+        let session = service
+            .request_signature(enrollment, psbt)
+            .map_err(|err| CosignerError::Service(err.to_string()))?;
+        let code = confirm(&session);
+        if session.expires < Utc::now() {
+            return Err(CosignerError::SessionExpired(session.expires));
+        }
+        let signed = service
+            .confirm(&session, &code)
+            .map_err(|err| CosignerError::Service(err.to_string()))?;
+
+        let mut combined = PartiallySignedTransaction::from(psbt.clone());
+        let signed = PartiallySignedTransaction::from(signed);
+        if combined.unsigned_tx.txid() != signed.unsigned_tx.txid() {
+            return Err(CosignerError::TxMismatch);
+        }
+        let sig_count = |psbt: &PartiallySignedTransaction| -> usize {
+            psbt.inputs
+                .iter()
+                .map(|input| {
+                    input.partial_sigs.len()
+                        + input.tap_script_sigs.len()
+                        + input.tap_key_sig.iter().count()
+                })
+                .sum()
+        };
+        let before = sig_count(&combined);
+        combined
+            .combine(signed)
+            .map_err(|_| CosignerError::TxMismatch)?;
+        if sig_count(&combined) <= before {
+            return Err(CosignerError::NoSignatures);
+        }
+        let txid = combined.unsigned_tx.txid();
+        *psbt = Psbt::from(combined);
+
+        self.record_audit(AuditEvent::PsbtSigned {
+            txid,
+            signers: bset![fingerprint],
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Identification of the transaction payers and beneficiaries from the address book, labels
+//! used before and other wallets of the user.
+
+use std::collections::BTreeMap;
+
+use amplify::Wrapper;
+use bitcoin::{Address, Script};
+
+use crate::{Wallet, WalletHub};
+
+impl Wallet {
+    /// Adds address to the address book, returning the name it had before.
+    pub fn add_contact(&mut self, address: &Address, name: impl ToString) -> Option<String> {
+        self.address_book_mut()
+            .insert(address.script_pubkey(), name.to_string())
+    }
+
+    pub fn remove_contact(&mut self, address: &Address) -> Option<String> {
+        self.address_book_mut().remove(&address.script_pubkey())
+    }
+
+    /// Names of the counterparties known by the wallet: entries of the address book and
+    /// beneficiary labels assigned to the external outputs of the wallet transactions.
+    pub fn known_counterparties(&self) -> BTreeMap<Script, String> {
+        let mut known = self
+            .history()
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .beneficiaries
+                    .iter()
+                    .filter(|(vout, _)| !entry.debit.contains_key(vout))
+                    .filter_map(|(vout, name)| {
+                        let txout = entry.tx.output.get(*vout as usize)?;
+                        Some((txout.script_pubkey.clone(), name.clone()))
+                    })
+            })
+            .collect::<BTreeMap<_, _>>();
+        known.extend(self.address_book().clone());
+        known
+    }
+
+    /// Scripts used by the wallet, for identifying it as a counterparty by other wallets.
+    pub fn used_scripts(&self) -> impl Iterator<Item = Script> + '_ {
+        self.history()
+            .iter()
+            .flat_map(|entry| entry.debit.values())
+            .chain(self.utxos().iter().map(|utxo| &utxo.addr_src))
+            .map(|addr_src| addr_src.address.script_pubkey().into_inner())
+    }
+
+    /// Fills empty payers and beneficiaries of the wallet transactions from the counterparties
+    /// known to the wallet and the provided ones. Payers are identified when an input spends an
+    /// output of a known transaction paying to a known counterparty. Returns the number of
+    /// updated transactions.
+    pub fn populate_counterparties(&mut self, others: &BTreeMap<Script, String>) -> usize {
+        let mut known = self.known_counterparties();
+        for (script, name) in others {
+            known.entry(script.clone()).or_insert_with(|| name.clone());
+        }
+        let outputs =
+            self.history()
+                .iter()
+                .flat_map(|entry| {
+                    entry.tx.output.iter().enumerate().map(|(vout, txout)| {
+                        ((entry.onchain.txid, vout as u32), &txout.script_pubkey)
+                    })
+                })
+                .filter_map(|(outpoint, script)| Some((outpoint, known.get(script)?.clone())))
+                .collect::<BTreeMap<_, _>>();
+
+        let mut count = 0usize;
+        self.map_history(|entry| {
+            let mut updated = false;
+            for (vout, txout) in entry.tx.output.iter().enumerate() {
+                let vout = vout as u32;
+                if entry.debit.contains_key(&vout) || entry.beneficiaries.contains_key(&vout) {
+                    continue;
+                }
+                if let Some(name) = known.get(&txout.script_pubkey) {
+                    entry.beneficiaries.insert(vout, name.clone());
+                    updated = true;
+                }
+            }
+            for (vin, txin) in entry.tx.input.iter().enumerate() {
+                let vin = vin as u32;
+                if entry.credit.contains_key(&vin) || entry.payers.contains_key(&vin) {
+                    continue;
+                }
+                let prevout = txin.previous_output;
+                if let Some(name) = outputs.get(&(prevout.txid, prevout.vout)) {
+                    entry.payers.insert(vin, (Some(name.clone()), None));
+                    updated = true;
+                }
+            }
+            count += updated as usize;
+        });
+        count
+    }
+}
+
+impl WalletHub {
+    /// Fills empty payers and beneficiaries of all opened wallets, identifying other opened
+    /// wallets as counterparties by their names. Returns the number of updated transactions.
+    pub fn populate_counterparties(&mut self) -> usize {
+        let scripts = self
+            .wallets()
+            .map(|(path, wallet)| {
+                let name = match wallet.meta().name.as_str() {
+                    "" => path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    name => name.to_owned(),
+                };
+                let scripts = wallet
+                    .used_scripts()
+                    .map(|script| (script, name.clone()))
+                    .collect::<BTreeMap<_, _>>();
+                (path.to_path_buf(), scripts)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let paths = scripts.keys().cloned().collect::<Vec<_>>();
+        paths
+            .into_iter()
+            .map(|path| {
+                let others = scripts
+                    .iter()
+                    .filter(|(other, _)| **other != path)
+                    .flat_map(|(_, scripts)| scripts.clone())
+                    .collect();
+                self.wallet_mut(&path)
+                    .map(|wallet| wallet.populate_counterparties(&others))
+                    .unwrap_or_default()
+            })
+            .sum()
+    }
+}
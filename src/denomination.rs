@@ -0,0 +1,307 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Denominations of bitcoin amounts and their locale-aware formatting and parsing.
+
+use std::io::{Read, Write};
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::Wallet;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AmountError {
+    /// Amount is not provided.
+    Empty,
+
+    /// Amount `{0}` contains invalid characters.
+    InvalidFormat(String),
+
+    /// Amount `{0}` has more decimal digits than the denomination allows.
+    TooPrecise(String),
+
+    /// Amount `{0}` exceeds the maximum value.
+    Overflow(String),
+
+    /// Amount format uses `{0}` both as the decimal and the grouping separator.
+    AmbiguousSeparator(char),
+}
+
+/// Denomination in which bitcoin amounts are displayed and entered.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum Denomination {
+    #[default]
+    #[display("BTC")]
+    Btc,
+
+    #[display("mBTC")]
+    MilliBtc,
+
+    /// Micro-bitcoins (100 sats).
+    #[display("bits")]
+    Bits,
+
+    #[display("sats")]
+    Sats,
+}
+
+impl Denomination {
+    pub fn all() -> &'static [Denomination] {
+        &[Denomination::Btc, Denomination::MilliBtc, Denomination::Bits, Denomination::Sats]
+    }
+
+    /// Number of decimal digits of the amounts in this denomination.
+    pub fn precision(self) -> usize {
+        match self {
+            Denomination::Btc => 8,
+            Denomination::MilliBtc => 5,
+            Denomination::Bits => 2,
+            Denomination::Sats => 0,
+        }
+    }
+
+    /// Formats amount given in sats, omitting the denomination name.
+    pub fn format(self, sats: u64, format: AmountFormat) -> String {
+        let precision = self.precision();
+        let unit = 10u64.pow(precision as u32);
+        let integer = group_digits(&(sats / unit).to_string(), format.grouping);
+        if precision == 0 {
+            return integer;
+        }
+        let mut fraction = format!("{:0width$}", sats % unit, width = precision);
+        while fraction.len() > format.min_decimals as usize && fraction.ends_with('0') {
+            fraction.pop();
+        }
+        if fraction.is_empty() {
+            integer
+        } else {
+            format!("{}{}{}", integer, format.decimal, fraction)
+        }
+    }
+
+    /// Formats signed amount given in sats, like wallet balance change.
+    pub fn format_signed(self, sats: i64, format: AmountFormat) -> String {
+        let sign = if sats < 0 { "-" } else { "" };
+        format!("{}{}", sign, self.format(sats.unsigned_abs(), format))
+    }
+
+    /// Parses amount in this denomination into sats. Grouping separators are accepted only
+    /// between the groups of three integer digits; amounts with more decimal digits than the
+    /// denomination precision are rejected instead of being rounded.
+    pub fn parse(self, s: &str, format: AmountFormat) -> Result<u64, AmountError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AmountError::Empty);
+        }
+        let invalid = || AmountError::InvalidFormat(s.to_owned());
+        let (integer, fraction) = s.split_once(format.decimal).unwrap_or((s, ""));
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        // Whitespace grouping separators are hard to type, so any whitespace is accepted
+        // in their place
+        let is_separator = |c: char| match format.grouping {
+            Some(grouping) if grouping.is_whitespace() => c.is_whitespace(),
+            Some(grouping) => c == grouping,
+            None => false,
+        };
+        let integer = if integer.contains(is_separator) {
+            let mut groups = integer.split(is_separator);
+            let first = groups.next().unwrap_or_default();
+            if !(1..=3).contains(&first.len()) || groups.any(|group| group.len() != 3) {
+                return Err(invalid());
+            }
+            integer.chars().filter(|c| !is_separator(*c)).collect()
+        } else {
+            integer.to_owned()
+        };
+        if !is_digits(&integer)
+            || !is_digits(fraction)
+            || (integer.is_empty() && fraction.is_empty())
+        {
+            return Err(invalid());
+        }
+        let fraction = fraction.trim_end_matches('0');
+        let precision = self.precision();
+        if fraction.len() > precision {
+            return Err(AmountError::TooPrecise(s.to_owned()));
+        }
+        let overflow = || AmountError::Overflow(s.to_owned());
+        let integer = match integer.as_str() {
+            "" => 0,
+            integer => integer.parse::<u64>().map_err(|_| overflow())?,
+        };
+        let fraction = format!("{:0<width$}", fraction, width = precision);
+        let fraction = match fraction.as_str() {
+            "" => 0,
+            fraction => fraction.parse::<u64>().map_err(|_| overflow())?,
+        };
+        integer
+            .checked_mul(10u64.pow(precision as u32))
+            .and_then(|sats| sats.checked_add(fraction))
+            .ok_or_else(overflow)
+    }
+}
+
+/// Locale-specific rules for the number formatting.
+#[derive(Getters, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", try_from = "AmountFormatUnchecked")
+)]
+pub struct AmountFormat {
+    #[getter(as_copy)]
+    decimal: char,
+    /// Separator of the groups of three integer digits, if any.
+    #[getter(as_copy)]
+    grouping: Option<char>,
+    /// Minimal number of the decimal digits to show; trailing zeros beyond it are dropped.
+    #[getter(as_copy)]
+    min_decimals: u8,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct AmountFormatUnchecked {
+    decimal: char,
+    grouping: Option<char>,
+    min_decimals: u8,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<AmountFormatUnchecked> for AmountFormat {
+    type Error = AmountError;
+
+    fn try_from(format: AmountFormatUnchecked) -> Result<Self, Self::Error> {
+        AmountFormat::new(format.decimal, format.grouping, format.min_decimals)
+    }
+}
+
+impl StrictEncode for AmountFormat {
+    fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        Ok(strict_encode_list!(e;
+            self.decimal as u32,
+            self.grouping.map(u32::from),
+            self.min_decimals
+        ))
+    }
+}
+
+impl StrictDecode for AmountFormat {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let char = |code: u32| {
+            char::from_u32(code).ok_or_else(|| {
+                strict_encoding::Error::DataIntegrityError(format!(
+                    "invalid separator character code {}",
+                    code
+                ))
+            })
+        };
+        AmountFormat::new(
+            char(u32::strict_decode(&mut d)?)?,
+            Option::<u32>::strict_decode(&mut d)?
+                .map(char)
+                .transpose()?,
+            u8::strict_decode(&mut d)?,
+        )
+        .map_err(|err| strict_encoding::Error::DataIntegrityError(err.to_string()))
+    }
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self { AmountFormat::ENGLISH }
+}
+
+impl AmountFormat {
+    /// Constructs amount format, failing if the decimal and grouping separators are the same,
+    /// which makes amounts ambiguous.
+    pub fn new(
+        decimal: char,
+        grouping: Option<char>,
+        min_decimals: u8,
+    ) -> Result<AmountFormat, AmountError> {
+        if grouping == Some(decimal) {
+            return Err(AmountError::AmbiguousSeparator(decimal));
+        }
+        Ok(AmountFormat {
+            decimal,
+            grouping,
+            min_decimals,
+        })
+    }
+
+    /// `1,234.5678`
+    pub const ENGLISH: AmountFormat = AmountFormat {
+        decimal: '.',
+        grouping: Some(','),
+        min_decimals: 0,
+    };
+
+    /// `1.234,5678`
+    pub const CONTINENTAL: AmountFormat = AmountFormat {
+        decimal: ',',
+        grouping: Some('.'),
+        min_decimals: 0,
+    };
+
+    /// `1 234,5678`, using narrow no-break space
+    pub const SI: AmountFormat = AmountFormat {
+        decimal: ',',
+        grouping: Some('\u{202F}'),
+        min_decimals: 0,
+    };
+
+    /// `1234.5678`
+    pub const PLAIN: AmountFormat = AmountFormat {
+        decimal: '.',
+        grouping: None,
+        min_decimals: 0,
+    };
+}
+
+fn group_digits(digits: &str, separator: Option<char>) -> String {
+    let separator = match separator {
+        Some(separator) => separator,
+        None => return digits.to_owned(),
+    };
+    let mut grouped = String::with_capacity(digits.len() * 4 / 3);
+    for (no, c) in digits.chars().enumerate() {
+        if no > 0 && (digits.len() - no) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+impl Wallet {
+    /// Formats amount in sats according to the wallet display preferences, including the
+    /// denomination name.
+    pub fn format_amount(&self, sats: u64) -> String {
+        format!(
+            "{} {}",
+            self.denomination().format(sats, self.amount_format()),
+            self.denomination()
+        )
+    }
+
+    /// Parses amount entered in the wallet denomination into sats.
+    pub fn parse_amount(&self, s: &str) -> Result<u64, AmountError> {
+        self.denomination().parse(s, self.amount_format())
+    }
+}
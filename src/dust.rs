@@ -0,0 +1,86 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Wallet policy on dust: tiny outputs which are not worth creating or spending.
+
+use crate::migration::TXIN_BASE_WEIGHT;
+use crate::sweep::DUST_LIMIT;
+use crate::{UtxoTxid, Wallet};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DustError {
+    /// Output value of {0} sats is below the wallet minimum of {1} sats.
+    OutputBelowMinimum(u64, u64),
+}
+
+/// Wallet-level policy on dust outputs.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct DustPolicy {
+    /// Change below this value (in sats) is not created and goes to the fee instead.
+    pub min_change: u64,
+    /// Minimum value (in sats) of the payment outputs accepted by the transaction composer.
+    pub min_output: u64,
+    /// Whether UTXOs which cost more to spend at the current fee rate than their value are
+    /// excluded from the spendable balance and coin selection.
+    pub exclude_uneconomic: bool,
+}
+
+impl Default for DustPolicy {
+    fn default() -> Self {
+        DustPolicy {
+            min_change: DUST_LIMIT,
+            min_output: DUST_LIMIT,
+            exclude_uneconomic: false,
+        }
+    }
+}
+
+impl DustPolicy {
+    /// Checks that a payment output value is accepted by the policy.
+    pub fn check_output(&self, value: u64) -> Result<(), DustError> {
+        let min = self.min_output.max(DUST_LIMIT);
+        if value < min {
+            return Err(DustError::OutputBelowMinimum(value, min));
+        }
+        Ok(())
+    }
+
+    /// Detects whether change of the given value must be created; otherwise it goes to the fee.
+    pub fn keeps_change(&self, value: u64) -> bool { value >= self.min_change.max(DUST_LIMIT) }
+}
+
+impl Wallet {
+    /// Fee required to spend a single wallet input at the fastest fee estimate, in sats.
+    pub fn input_spend_fee(&self) -> Result<u64, miniscript::Error> {
+        let (descriptor, _) = self.as_settings().descriptors_all()?;
+        let input_weight = TXIN_BASE_WEIGHT + descriptor.max_satisfaction_weight()?;
+        Ok((input_weight as f32 / 4.0 * self.ephemerals().fees.0).ceil() as u64)
+    }
+
+    /// Minimal value of the UTXOs accounted as spendable, if uneconomic UTXOs are excluded by
+    /// the dust policy.
+    pub(crate) fn economic_threshold(&self) -> Option<u64> {
+        if !self.dust_policy().exclude_uneconomic {
+            return None;
+        }
+        self.input_spend_fee().ok()
+    }
+
+    /// Detects whether the UTXO is excluded from spending as uneconomic by the dust policy.
+    pub fn is_uneconomic(&self, utxo: &UtxoTxid) -> bool {
+        self.economic_threshold()
+            .map(|threshold| utxo.value <= threshold)
+            .unwrap_or_default()
+    }
+}
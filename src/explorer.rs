@@ -0,0 +1,122 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Links to the block explorers for transactions, outputs and addresses.
+
+use std::fmt::Display;
+
+use bitcoin::Txid;
+use wallet::onchain::PublicNetwork;
+
+use crate::{AddressSource, HistoryEntry, UtxoTxid};
+
+/// URL templates of a block explorer. Templates may use `{txid}`, `{vout}` and `{address}`
+/// placeholders.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct ExplorerTemplate {
+    pub tx: String,
+    pub output: String,
+    pub address: String,
+}
+
+impl ExplorerTemplate {
+    fn with_base(base: &str, output_suffix: &str) -> ExplorerTemplate {
+        ExplorerTemplate {
+            tx: format!("{}/tx/{{txid}}", base),
+            output: format!("{}/tx/{{txid}}{}", base, output_suffix),
+            address: format!("{}/address/{{address}}", base),
+        }
+    }
+}
+
+/// Block explorer used for the links.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum ExplorerLinks {
+    #[default]
+    #[display("mempool.space")]
+    MempoolSpace,
+
+    #[display("blockstream.info")]
+    Blockstream,
+
+    /// Custom explorer, for instance self-hosted one; the templates are used for the wallet
+    /// network.
+    #[display("custom")]
+    Custom(ExplorerTemplate),
+}
+
+impl ExplorerLinks {
+    /// URL templates for the given network, if the explorer supports it.
+    pub fn template(&self, network: PublicNetwork) -> Option<ExplorerTemplate> {
+        let base = match (self, network) {
+            (ExplorerLinks::Custom(template), _) => return Some(template.clone()),
+            (ExplorerLinks::MempoolSpace, PublicNetwork::Mainnet) => "https://mempool.space",
+            (ExplorerLinks::MempoolSpace, PublicNetwork::Testnet) => {
+                "https://mempool.space/testnet"
+            }
+            (ExplorerLinks::MempoolSpace, PublicNetwork::Signet) => "https://mempool.space/signet",
+            (ExplorerLinks::Blockstream, PublicNetwork::Mainnet) => "https://blockstream.info",
+            (ExplorerLinks::Blockstream, PublicNetwork::Testnet) => {
+                "https://blockstream.info/testnet"
+            }
+            (ExplorerLinks::Blockstream, PublicNetwork::Signet) => return None,
+        };
+        Some(match self {
+            ExplorerLinks::Blockstream => ExplorerTemplate::with_base(base, "?output:{vout}"),
+            _ => ExplorerTemplate::with_base(base, "#vout={vout}"),
+        })
+    }
+
+    pub fn tx_url(&self, network: PublicNetwork, txid: Txid) -> Option<String> {
+        self.template(network)
+            .map(|template| template.tx.replace("{txid}", &txid.to_string()))
+    }
+
+    pub fn output_url(&self, network: PublicNetwork, txid: Txid, vout: u32) -> Option<String> {
+        self.template(network).map(|template| {
+            template
+                .output
+                .replace("{txid}", &txid.to_string())
+                .replace("{vout}", &vout.to_string())
+        })
+    }
+
+    pub fn address_url(&self, network: PublicNetwork, address: impl Display) -> Option<String> {
+        self.template(network)
+            .map(|template| template.address.replace("{address}", &address.to_string()))
+    }
+}
+
+impl HistoryEntry {
+    pub fn explorer_url(&self, links: &ExplorerLinks, network: PublicNetwork) -> Option<String> {
+        links.tx_url(network, self.onchain.txid)
+    }
+}
+
+impl UtxoTxid {
+    pub fn explorer_url(&self, links: &ExplorerLinks, network: PublicNetwork) -> Option<String> {
+        links.output_url(network, self.onchain.txid, self.vout)
+    }
+}
+
+impl AddressSource {
+    pub fn explorer_url(&self, links: &ExplorerLinks, network: PublicNetwork) -> Option<String> {
+        links.address_url(network, self.address)
+    }
+}
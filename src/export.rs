@@ -0,0 +1,221 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Export of the wallet transaction history for accountants and auditors.
+
+use std::io::{self, Write};
+
+use bitcoin::{Address, Network};
+
+use crate::{AmountFormat, HistoryEntry, Wallet};
+
+/// Column of the exported history.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum HistoryColumn {
+    #[display("date")]
+    Date,
+
+    #[display("txid")]
+    Txid,
+
+    #[display("direction")]
+    Direction,
+
+    /// Change of the wallet balance.
+    #[display("amount")]
+    Amount,
+
+    #[display("fee")]
+    Fee,
+
+    /// Change of the wallet balance in the wallet fiat currency, at the current exchange rate.
+    #[display("fiat_value")]
+    FiatValue,
+
+    #[display("label")]
+    Label,
+
+    /// Names of the payers and beneficiaries.
+    #[display("counterparties")]
+    Counterparties,
+
+    /// Addresses receiving funds in the transaction.
+    #[display("addresses")]
+    Addresses,
+}
+
+impl HistoryColumn {
+    pub fn all() -> &'static [HistoryColumn] {
+        &[
+            HistoryColumn::Date,
+            HistoryColumn::Txid,
+            HistoryColumn::Direction,
+            HistoryColumn::Amount,
+            HistoryColumn::Fee,
+            HistoryColumn::FiatValue,
+            HistoryColumn::Label,
+            HistoryColumn::Counterparties,
+            HistoryColumn::Addresses,
+        ]
+    }
+}
+
+/// Value of an exported cell.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Cell {
+    Text(String),
+    Number(String),
+    Null,
+}
+
+impl Cell {
+    fn csv(&self) -> String {
+        match self {
+            Cell::Text(text) if text.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            }
+            Cell::Text(text) | Cell::Number(text) => text.clone(),
+            Cell::Null => s!(""),
+        }
+    }
+
+    fn json(&self) -> String {
+        match self {
+            Cell::Text(text) => json_string(text),
+            Cell::Number(number) => number.clone(),
+            Cell::Null => s!("null"),
+        }
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Amounts are exported in the denomination without grouping, such that they remain parseable
+/// numbers.
+const EXPORT_FORMAT: AmountFormat = AmountFormat::PLAIN;
+
+impl Wallet {
+    fn history_cell(&self, entry: &HistoryEntry, column: HistoryColumn) -> Cell {
+        let denomination = self.denomination();
+        let network = Network::from(self.as_settings().network());
+        match column {
+            HistoryColumn::Date => Cell::Text(entry.date_time_est().to_rfc3339()),
+            HistoryColumn::Txid => Cell::Text(entry.onchain.txid.to_string()),
+            HistoryColumn::Direction => Cell::Text(entry.direction().to_string()),
+            HistoryColumn::Amount => {
+                Cell::Number(denomination.format_signed(entry.balance(), EXPORT_FORMAT))
+            }
+            HistoryColumn::Fee => entry
+                .fee
+                .map(|fee| Cell::Number(denomination.format(fee, EXPORT_FORMAT)))
+                .unwrap_or(Cell::Null),
+            HistoryColumn::FiatValue => self
+                .fiat_value(entry)
+                .map(|value| Cell::Number(format!("{:.2}", value)))
+                .unwrap_or(Cell::Null),
+            HistoryColumn::Label => entry
+                .comment
+                .as_ref()
+                .map(|comment| Cell::Text(comment.label.clone()))
+                .unwrap_or(Cell::Null),
+            HistoryColumn::Counterparties => {
+                let mut names = entry
+                    .payers
+                    .values()
+                    .filter_map(|(name, _)| name.clone())
+                    .chain(entry.beneficiaries.values().cloned())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                Cell::Text(names.join("; "))
+            }
+            HistoryColumn::Addresses => Cell::Text(
+                entry
+                    .tx
+                    .output
+                    .iter()
+                    .filter_map(|txout| Address::from_script(&txout.script_pubkey, network).ok())
+                    .map(|address| address.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+        }
+    }
+
+    fn history_rows(&self, columns: &[HistoryColumn]) -> Vec<Vec<Cell>> {
+        self.history()
+            .iter()
+            .map(|entry| {
+                columns
+                    .iter()
+                    .map(|column| self.history_cell(entry, *column))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes wallet history as CSV with a header line. Amounts use the wallet denomination.
+    pub fn export_history_csv(
+        &self,
+        columns: &[HistoryColumn],
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let header = columns
+            .iter()
+            .map(HistoryColumn::to_string)
+            .collect::<Vec<_>>();
+        writeln!(writer, "{}", header.join(","))?;
+        for row in self.history_rows(columns) {
+            let line = row.iter().map(Cell::csv).collect::<Vec<_>>();
+            writeln!(writer, "{}", line.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Writes wallet history as JSON array of objects keyed by the column names. Amounts use the
+    /// wallet denomination.
+    pub fn export_history_json(
+        &self,
+        columns: &[HistoryColumn],
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        writeln!(writer, "[")?;
+        let rows = self.history_rows(columns);
+        for (no, row) in rows.iter().enumerate() {
+            let fields = columns
+                .iter()
+                .zip(row)
+                .map(|(column, cell)| {
+                    format!("{}: {}", json_string(&column.to_string()), cell.json())
+                })
+                .collect::<Vec<_>>();
+            let sep = if no + 1 < rows.len() { "," } else { "" };
+            writeln!(writer, "  {{{}}}{}", fields.join(", "), sep)?;
+        }
+        writeln!(writer, "]")
+    }
+}
@@ -0,0 +1,103 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Computation of the transaction fees from the values of the spent outputs.
+
+use std::collections::BTreeSet;
+
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use wallet::onchain::{ResolveTx, TxResolverError};
+
+use crate::{HistoryEntry, Wallet};
+
+impl HistoryEntry {
+    /// Transaction fee rate, in sats per vbyte.
+    pub fn fee_rate(&self) -> Option<f32> {
+        self.fee.map(|fee| fee as f32 / self.tx.vsize() as f32)
+    }
+}
+
+impl Wallet {
+    /// Output spent by the transaction input, if known from the wallet history or the cache of
+    /// previously resolved outputs.
+    pub fn known_prevout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        self.prevouts().get(&outpoint).cloned().or_else(|| {
+            self.history()
+                .iter()
+                .find(|entry| entry.onchain.txid == outpoint.txid)
+                .and_then(|entry| entry.tx.output.get(outpoint.vout as usize))
+                .cloned()
+        })
+    }
+
+    fn compute_fee(&self, tx: &Transaction) -> Option<u64> {
+        if tx.is_coin_base() {
+            return Some(0);
+        }
+        let spent = tx
+            .input
+            .iter()
+            .map(|txin| {
+                self.known_prevout(txin.previous_output)
+                    .map(|txout| txout.value)
+            })
+            .sum::<Option<u64>>()?;
+        let created = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        spent.checked_sub(created)
+    }
+
+    /// Fills missing fees of the history entries from the already known outputs, returning the
+    /// number of updated entries. Called after each wallet sync.
+    pub(crate) fn refresh_fees(&mut self) -> usize {
+        let fees = self
+            .history()
+            .iter()
+            .filter(|entry| entry.fee.is_none())
+            .filter_map(|entry| Some((entry.onchain.txid, self.compute_fee(&entry.tx)?)))
+            .collect::<Vec<_>>();
+        if fees.is_empty() {
+            return 0;
+        }
+        self.map_history(|entry| {
+            if let Some((_, fee)) = fees.iter().find(|(txid, _)| *txid == entry.onchain.txid) {
+                entry.fee = Some(*fee);
+            }
+        });
+        fees.len()
+    }
+
+    /// Resolves transactions spent by the history entries with unknown fees, caching the spent
+    /// outputs, and computes the fees. Returns the number of updated entries.
+    pub fn resolve_fees(&mut self, resolver: &impl ResolveTx) -> Result<usize, TxResolverError> {
+        let missing = self
+            .history()
+            .iter()
+            .filter(|entry| entry.fee.is_none() && !entry.tx.is_coin_base())
+            .flat_map(|entry| entry.tx.input.iter().map(|txin| txin.previous_output))
+            .filter(|outpoint| self.known_prevout(*outpoint).is_none())
+            .collect::<BTreeSet<_>>();
+        let txids = missing
+            .iter()
+            .map(|outpoint| outpoint.txid)
+            .collect::<BTreeSet<Txid>>();
+        for txid in txids {
+            let tx = resolver.resolve_tx(txid)?;
+            let outputs = missing
+                .iter()
+                .filter(|outpoint| outpoint.txid == txid)
+                .filter_map(|outpoint| Some((*outpoint, tx.output.get(outpoint.vout as usize)?)))
+                .map(|(outpoint, txout)| (outpoint, txout.clone()))
+                .collect::<Vec<_>>();
+            self.prevouts_mut().extend(outputs);
+        }
+        Ok(self.refresh_fees())
+    }
+}
@@ -9,18 +9,69 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
-use std::io::{Seek, Write};
+mod autosave;
+mod backup;
+mod journal;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+use std::io::{Cursor, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+use argon2::{Algorithm, Argon2, Params, Version};
+use bitcoin::hashes::{sha256, Hash};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use strict_encoding::{StrictDecode, StrictEncode};
 
-use crate::{Wallet, WalletSettings};
+pub use self::autosave::{AutosaveController, AutosavePolicy};
+pub use self::backup::{backup, list_backups, restore_backup, Backup, BACKUP_DIR};
+pub use self::journal::{recover_journal, write_journaled, JournalRecovery};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{SqliteError, SqliteStore};
+use crate::wallet::{WalletSettingsV1, WalletV1};
+use crate::{TemplateCatalog, Wallet, WalletSettings};
+
+/// Equals to first 4 bytes of SHA256("mycitadel:wallet:v2")
+/// = 4c3addac53394ea8a8f0375cca41bf1b018c394328f5079bda4778afc9b9eb84
+/// Check with `echo -n "mycitadel:wallet:v2" | shasum -a 256`
+const WALLET_DOC_MAGIC: [u8; 4] = [0x4c, 0x3a, 0xdd, 0xac];
 
+/// Magic number of the first version of the wallet documents, which are migrated when read.
+///
 /// Equals to first 4 bytes of SHA256("mycitadel:wallet:v1")
 /// = a4546a8ef3a51f1faf2dab1517346e9d84b249f7f52d29339b4ee53fe870d14f
 /// Check with `echo -n "mycitadel:wallet:v1" | shasum -a 256`
-const WALLET_DOC_MAGIC: [u8; 4] = [0xa4, 0x54, 0x6a, 0x8e];
+const WALLET_DOC_MAGIC_V1: [u8; 4] = [0xa4, 0x54, 0x6a, 0x8e];
+
+/// Equals to first 4 bytes of SHA256("mycitadel:templates:v1")
+/// = b7f9e2f08b2ddec37ed4ad57e9cc7684b18dd0b3bfc19f6592bf92be749fbe0d
+/// Check with `echo -n "mycitadel:templates:v1" | shasum -a 256`
+const TEMPLATES_DOC_MAGIC: [u8; 4] = [0xb7, 0xf9, 0xe2, 0xf0];
+
+/// Equals to first 4 bytes of SHA256("mycitadel:checksum:v1")
+/// = 89e35a08cee09c492db91e2c005f5b4231eed534dce19ead9680108f73f5236e
+/// Check with `echo -n "mycitadel:checksum:v1" | shasum -a 256`
+const CHECKSUM_MAGIC: [u8; 4] = [0x89, 0xe3, 0x5a, 0x08];
+const CHECKSUM_TRAILER_LEN: usize = 4 + sha256::Hash::LEN;
+
+/// Equals to first 4 bytes of SHA256("mycitadel:encrypted:v1")
+/// = b357f8da30f95c60626ae71565d2181d5268fbe9e7fb4c4d68759271ea4eb7fa
+/// Check with `echo -n "mycitadel:encrypted:v1" | shasum -a 256`
+const ENCRYPTED_DOC_MAGIC: [u8; 4] = [0xb3, 0x57, 0xf8, 0xda];
+
+/// Magic number of zstd frames (RFC 8878).
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+/// Magic, three KDF parameters, salt and nonce.
+const ENCRYPTED_HEADER_LEN: usize = 4 + 4 * 3 + SALT_LEN + NONCE_LEN;
 
 pub struct RefWrap<'doc, T>(pub(self) &'doc T)
 where T: StrictEncode;
@@ -79,8 +130,412 @@ pub enum Error {
     Magic { expected: u32, actual: u32 },
     #[display("extra data after the end of file")]
     DataNotEntirelyConsumed,
+    #[display("document checksum does not match its content; the file is corrupted")]
+    Checksum,
+    #[display("the document is compressed, but zstd support is not compiled in")]
+    CompressionUnsupported,
+    #[display("the file is encrypted and requires a password to be opened")]
+    Encrypted,
+    #[display("the file is not encrypted")]
+    NotEncrypted,
+    #[display("wrong password or corrupted encrypted file")]
+    WrongPassword,
+    #[display("invalid key derivation parameters: {0}")]
+    Kdf(String),
+    #[display("the file is opened by another wallet handle or process")]
+    Locked,
+}
+
+/// Appends checksum trailer to the serialized document.
+fn append_checksum(data: &mut Vec<u8>) {
+    let hash = sha256::Hash::hash(data);
+    data.extend(CHECKSUM_MAGIC);
+    data.extend(&hash[..]);
+}
+
+/// Verifies and removes checksum trailer from the serialized document. Documents written before
+/// checksums were introduced are returned as is.
+fn strip_checksum(data: &[u8]) -> Result<&[u8], Error> {
+    let len = match data.len().checked_sub(CHECKSUM_TRAILER_LEN) {
+        Some(len) if data[len..].starts_with(&CHECKSUM_MAGIC) => len,
+        _ => return Ok(data),
+    };
+    if sha256::Hash::hash(&data[..len])[..] != data[len + 4..] {
+        return Err(Error::Checksum);
+    }
+    Ok(&data[..len])
+}
+
+/// Decodes strict-encoded data, failing if they are not entirely consumed.
+fn decode_exact<T: StrictDecode>(data: &[u8]) -> Result<T, Error> {
+    let mut cursor = Cursor::new(data);
+    let value = T::strict_decode(&mut cursor)?;
+    if data.len() as u64 != cursor.position() {
+        return Err(Error::DataNotEntirelyConsumed);
+    }
+    Ok(value)
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Writes data to a temporary file next to `path`, flushes it to disk and atomically renames it
+/// into `path`, such that the file is never left in a partially-written state.
+pub fn write_atomic(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = sibling_path(path, ".tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            err
+        })
+}
+
+/// Advisory lock preventing several wallet handles or processes from working with the same
+/// document file at the same time.
+///
+/// The lock is an operating system advisory lock (`flock` on Unix, file sharing mode on Windows)
+/// taken on a `.lock` file next to the document, so it is released by the operating system even
+/// if the process terminates abnormally; the lock file itself is left on the disk. Each lock value
+/// is a separate handle, so two values locking the same document conflict even within a single
+/// process. The lock is released when the value is dropped.
+///
+/// Document reads and writes with [`FileDocument`] methods take the lock (shared for reads and
+/// exclusive for writes) for the duration of the operation. A handle keeping the lock for a longer
+/// time (like [`crate::WalletHub`] for its opened wallets) has to perform them inside
+/// [`FileLock::scope`].
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    exclusive: bool,
+    // Kept open to hold the OS lock
+    _file: fs::File,
+}
+
+impl FileLock {
+    /// Acquires exclusive lock for the document at `path`. Fails with [`Error::Locked`] if the
+    /// document is locked by another handle or process.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<FileLock, Error> {
+        Self::acquire_with(path, true)
+    }
+
+    /// Acquires shared lock for the document at `path`, which can be held by several readers at
+    /// once. Fails with [`Error::Locked`] if the document is exclusively locked by another handle
+    /// or process.
+    pub fn acquire_shared(path: impl AsRef<Path>) -> Result<FileLock, Error> {
+        Self::acquire_with(path, false)
+    }
+
+    fn acquire_with(path: impl AsRef<Path>, exclusive: bool) -> Result<FileLock, Error> {
+        let path = sibling_path(path.as_ref(), ".lock");
+        let file = os_lock(&path, exclusive)?.ok_or(Error::Locked)?;
+        Ok(FileLock {
+            path,
+            exclusive,
+            _file: file,
+        })
+    }
+
+    pub fn lock_path(&self) -> &Path { &self.path }
+
+    pub fn is_exclusive(&self) -> bool { self.exclusive }
+
+    /// Runs `f` reading or writing the locked document under this lock, such that
+    /// [`FileDocument`] methods called from it on the current thread do not try to take the lock
+    /// again. Writes require the lock to be exclusive.
+    pub fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _scope = IoScope::enter(self.path.clone(), self.exclusive);
+        f()
+    }
+
+    /// Acquires lock for a single read or write of the document, unless the current thread
+    /// already performs it in the scope of a lock held for the document.
+    fn acquire_io(
+        path: impl AsRef<Path>,
+        exclusive: bool,
+    ) -> Result<Option<(IoScope, FileLock)>, Error> {
+        let lock_path = sibling_path(path.as_ref(), ".lock");
+        if IoScope::covers(&lock_path, exclusive) {
+            return Ok(None);
+        }
+        let lock = Self::acquire_with(path, exclusive)?;
+        Ok(Some((IoScope::enter(lock_path, exclusive), lock)))
+    }
+}
+
+thread_local! {
+    /// Lock files of the documents read or written by the current thread under a held lock,
+    /// together with the flag whether the lock is exclusive.
+    static IO_SCOPES: RefCell<Vec<(PathBuf, bool)>> = const { RefCell::new(vec![]) };
+}
+
+/// Marks the current thread as reading or writing a document under a held lock until dropped.
+struct IoScope;
+
+impl IoScope {
+    fn enter(lock_path: PathBuf, exclusive: bool) -> IoScope {
+        IO_SCOPES.with(|scopes| scopes.borrow_mut().push((lock_path, exclusive)));
+        IoScope
+    }
+
+    fn covers(lock_path: &Path, exclusive: bool) -> bool {
+        IO_SCOPES.with(|scopes| {
+            scopes
+                .borrow()
+                .iter()
+                .any(|(path, held)| path == lock_path && (*held || !exclusive))
+        })
+    }
+}
+
+impl Drop for IoScope {
+    fn drop(&mut self) { IO_SCOPES.with(|scopes| scopes.borrow_mut().pop()); }
+}
+
+/// Opens lock file and locks it without blocking, returning `None` if the lock is held by
+/// another handle.
+#[cfg(unix)]
+fn os_lock(path: &Path, exclusive: bool) -> io::Result<Option<fs::File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    // SAFETY: `flock` is called with a valid descriptor owned by `file`
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match io::Error::last_os_error() {
+        err if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        err => Err(err),
+    }
+}
+
+/// Opens lock file and locks it without blocking, returning `None` if the lock is held by
+/// another handle.
+#[cfg(windows)]
+fn os_lock(path: &Path, exclusive: bool) -> io::Result<Option<fs::File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    // The file can't be opened while another handle is open with an incompatible sharing mode:
+    // readers share the file with each other, while the writer does not share it at all
+    let share_mode = if exclusive { 0 } else { FILE_SHARE_READ | FILE_SHARE_WRITE };
+    match fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(share_mode)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Opens lock file; locking is not supported on this platform.
+#[cfg(not(any(unix, windows)))]
+fn os_lock(path: &Path, _exclusive: bool) -> io::Result<Option<fs::File>> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map(Some)
+}
+
+/// Argon2id key derivation parameters used for the file encryption.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KdfParams {
+    /// Memory size in KiB.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Maximal parameters used for the key derivation (1 GiB of memory, 16 iterations and 16
+    /// lanes), such that a crafted file can't exhaust memory or CPU of the device opening it.
+    pub const MAX: KdfParams = KdfParams {
+        m_cost: 1024 * 1024,
+        t_cost: 16,
+        p_cost: 16,
+    };
+
+    /// Parameters limited by [`KdfParams::MAX`].
+    pub fn clamped(self) -> KdfParams {
+        KdfParams {
+            m_cost: self.m_cost.min(Self::MAX.m_cost),
+            t_cost: self.t_cost.min(Self::MAX.t_cost),
+            p_cost: self.p_cost.min(Self::MAX.p_cost),
+        }
+    }
+
+    fn derive_key(self, password: &str, salt: &[u8]) -> Result<Key, Error> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+            .map_err(|err| Error::Kdf(err.to_string()))?;
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .map_err(|err| Error::Kdf(err.to_string()))?;
+        Ok(key.into())
+    }
+}
+
+/// Password and key derivation parameters of an encrypted document, kept by the owner of an
+/// opened document to encrypt it again when saving.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct FileEncryption {
+    pub password: String,
+    pub kdf: KdfParams,
+}
+
+impl Debug for FileEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileEncryption")
+            .field("password", &"<redacted>")
+            .field("kdf", &self.kdf)
+            .finish()
+    }
+}
+
+impl FileEncryption {
+    pub fn with(password: impl ToString, kdf: KdfParams) -> FileEncryption {
+        FileEncryption {
+            password: password.to_string(),
+            kdf,
+        }
+    }
+
+    /// Takes key derivation parameters from the header of the encrypted file, such that the file
+    /// is saved with the same parameters it was encrypted with.
+    pub fn from_file(path: impl AsRef<Path>, password: impl ToString) -> Result<Self, Error> {
+        let mut header = [0u8; ENCRYPTED_HEADER_LEN];
+        let mut file = fs::File::open(path)?;
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::NotEncrypted)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let kdf = kdf_params(&header).ok_or(Error::NotEncrypted)?;
+        Ok(FileEncryption::with(password, kdf))
+    }
+}
+
+/// Reads key derivation parameters from the header of the data produced by [`encrypt`].
+pub fn kdf_params(data: &[u8]) -> Option<KdfParams> {
+    if !is_encrypted(data) || data.len() < ENCRYPTED_HEADER_LEN {
+        return None;
+    }
+    let u32_at = |pos: usize| {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&data[pos..pos + 4]);
+        u32::from_be_bytes(buf)
+    };
+    Some(KdfParams {
+        m_cost: u32_at(4),
+        t_cost: u32_at(8),
+        p_cost: u32_at(12),
+    })
+}
+
+/// Encrypts serialized document with ChaCha20-Poly1305 using key derived from the password with
+/// Argon2id. The resulting data contain all information required for decryption except the
+/// password. Key derivation parameters are limited by [`KdfParams::MAX`].
+pub fn encrypt(data: &[u8], password: &str, kdf: KdfParams) -> Result<Vec<u8>, Error> {
+    let kdf = kdf.clamped();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let key = kdf.derive_key(password, &salt)?;
+    let ciphertext = ChaCha20Poly1305::new(&key)
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .expect("in-memory encryption can't fail");
+
+    let mut buf = Vec::with_capacity(ENCRYPTED_HEADER_LEN + ciphertext.len());
+    buf.extend(ENCRYPTED_DOC_MAGIC);
+    buf.extend(kdf.m_cost.to_be_bytes());
+    buf.extend(kdf.t_cost.to_be_bytes());
+    buf.extend(kdf.p_cost.to_be_bytes());
+    buf.extend(salt);
+    buf.extend(nonce);
+    buf.extend(ciphertext);
+    Ok(buf)
 }
 
+/// Decrypts data produced by [`encrypt`]. Key derivation parameters from the data are limited by
+/// [`KdfParams::MAX`] before deriving the key.
+pub fn decrypt(data: &[u8], password: &str) -> Result<Vec<u8>, Error> {
+    if !is_encrypted(data) {
+        return Err(Error::NotEncrypted);
+    }
+    let kdf = kdf_params(data).ok_or(Error::WrongPassword)?.clamped();
+    let (salt, rest) = data[16..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let key = kdf.derive_key(password, salt)?;
+    ChaCha20Poly1305::new(&key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::WrongPassword)
+}
+
+/// Detects whether the data are zstd-compressed.
+pub fn is_compressed(data: &[u8]) -> bool { data.starts_with(&ZSTD_MAGIC) }
+
+/// Compresses serialized document with zstd using a given compression level (1..=22).
+#[cfg(feature = "zstd")]
+pub fn compress(data: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+    Ok(zstd::stream::encode_all(data, level)?)
+}
+
+/// Decompresses serialized document produced by `compress` (available with the `zstd` feature).
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    #[cfg(feature = "zstd")]
+    {
+        Ok(zstd::stream::decode_all(data)?)
+    }
+    #[cfg(not(feature = "zstd"))]
+    {
+        let _ = data;
+        Err(Error::CompressionUnsupported)
+    }
+}
+
+/// Detects whether the data are produced by [`encrypt`].
+pub fn is_encrypted(data: &[u8]) -> bool { data.starts_with(&ENCRYPTED_DOC_MAGIC) }
+
 pub trait FileDocument
 where Self: From<Self::FallbackDocType>
 {
@@ -92,30 +547,53 @@ where Self: From<Self::FallbackDocType>
 
     fn magic_u32() -> u32 { u32::from_be_bytes(Self::DOC_MAGIC) }
 
+    /// Decodes data following the magic number of a previous document version, migrating them
+    /// into the current version. Returns `None` if the magic number is not known.
+    fn decode_legacy(magic: [u8; 4], data: &[u8]) -> Option<Result<Self, Error>>
+    where Self: Sized {
+        let _ = (magic, data);
+        None
+    }
+
     fn file_name(base: &str, order_no: usize) -> String {
         let mut path = PathBuf::from(format!("{}-{}", base, order_no));
         path.set_extension(Self::FILE_EXT);
         path.display().to_string()
     }
 
-    fn read_file(path: impl AsRef<Path>) -> Result<Self, Error>
+    /// Deserializes document from its binary representation, including magic number.
+    fn from_doc_bytes(data: &[u8]) -> Result<Self, Error>
     where Self: StrictDecode {
-        let mut file = fs::OpenOptions::new()
-            .create(false)
-            .write(false)
-            .read(true)
-            .open(&path)?;
-        let doc = DocReader::<Self>::strict_decode(&mut file)
+        if is_encrypted(data) {
+            return Err(Error::Encrypted);
+        }
+        let decompressed;
+        let data = if is_compressed(data) {
+            decompressed = decompress(data)?;
+            &decompressed
+        } else {
+            data
+        };
+        let data = strip_checksum(data)?;
+        if data.len() >= 4 && data[..4] != Self::DOC_MAGIC {
+            let mut magic = [0u8; 4];
+            magic.copy_from_slice(&data[..4]);
+            if let Some(doc) = Self::decode_legacy(magic, &data[4..]) {
+                return doc;
+            }
+        }
+        let mut cursor = Cursor::new(data);
+        let doc = DocReader::<Self>::strict_decode(&mut cursor)
             .map_err(Error::from)
             .and_then(|doc| {
-                if fs::metadata(path)?.len() != file.stream_position()? {
+                if data.len() as u64 != cursor.stream_position()? {
                     return Err(Error::DataNotEntirelyConsumed);
                 }
                 Ok(doc)
             })
             .or_else(|_| {
-                file.rewind()?;
-                DocReader::<Self::FallbackDocType>::strict_decode(&mut file).map(|r| DocReader::<
+                cursor.rewind()?;
+                DocReader::<Self::FallbackDocType>::strict_decode(&mut cursor).map(|r| DocReader::<
                     Self,
                 > {
                     magic: r.magic,
@@ -131,11 +609,282 @@ where Self: From<Self::FallbackDocType>
         Ok(doc.data)
     }
 
+    /// Serializes document into its binary representation, including magic number and checksum
+    /// trailer.
+    fn to_doc_bytes(&self) -> Result<Vec<u8>, Error>
+    where Self: Sized + StrictEncode {
+        let mut buf = vec![];
+        DocWriter::with(Self::DOC_MAGIC, self).strict_encode(&mut buf)?;
+        append_checksum(&mut buf);
+        Ok(buf)
+    }
+
+    /// Reads document from an arbitrary data source until its end.
+    fn read_from(mut reader: impl Read) -> Result<Self, Error>
+    where Self: StrictDecode {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_doc_bytes(&data)
+    }
+
+    /// Writes document into an arbitrary data sink, returning number of bytes written.
+    fn write_to(&self, mut writer: impl Write) -> Result<usize, Error>
+    where Self: Sized + StrictEncode {
+        let data = self.to_doc_bytes()?;
+        writer.write_all(&data)?;
+        Ok(data.len())
+    }
+
+    fn read_from_encrypted(mut reader: impl Read, password: &str) -> Result<Self, Error>
+    where Self: StrictDecode {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_doc_bytes(&decrypt(&data, password)?)
+    }
+
+    fn write_to_encrypted(
+        &self,
+        mut writer: impl Write,
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<usize, Error>
+    where
+        Self: Sized + StrictEncode,
+    {
+        let data = encrypt(&self.to_doc_bytes()?, password, kdf)?;
+        writer.write_all(&data)?;
+        Ok(data.len())
+    }
+
+    /// Reads document from the file, holding its [`FileLock`] while reading.
+    fn read_file(path: impl AsRef<Path>) -> Result<Self, Error>
+    where Self: StrictDecode {
+        let _lock = FileLock::acquire_io(&path, false)?;
+        recover_journal(&path)?;
+        Self::from_doc_bytes(&fs::read(path)?)
+    }
+
+    /// Writes document to the file, holding its [`FileLock`] while writing. Fails with
+    /// [`Error::Encrypted`] instead of replacing an encrypted file with a plaintext one; use
+    /// [`FileDocument::write_file_encrypted`] or [`FileDocument::remove_password`] for such
+    /// files.
     fn write_file(&self, path: impl AsRef<Path>) -> Result<usize, Error>
     where Self: Sized + StrictEncode {
-        let doc = DocWriter::with(Self::DOC_MAGIC, self);
-        let file = fs::File::create(path)?;
-        doc.strict_encode(file).map_err(Error::Encoding)
+        let _lock = FileLock::acquire_io(&path, true)?;
+        ensure_plaintext(&path)?;
+        let data = self.to_doc_bytes()?;
+        write_journaled(path, &data)?;
+        Ok(data.len())
+    }
+
+    /// Reads document from the file, decrypting it if `encryption` is given.
+    fn read_file_with(
+        path: impl AsRef<Path>,
+        encryption: Option<&FileEncryption>,
+    ) -> Result<Self, Error>
+    where
+        Self: StrictDecode,
+    {
+        match encryption {
+            Some(encryption) => Self::read_file_encrypted(path, &encryption.password),
+            None => Self::read_file(path),
+        }
+    }
+
+    /// Writes document to the file, encrypting it if `encryption` is given.
+    fn write_file_with(
+        &self,
+        path: impl AsRef<Path>,
+        encryption: Option<&FileEncryption>,
+    ) -> Result<usize, Error>
+    where
+        Self: Sized + StrictEncode,
+    {
+        match encryption {
+            Some(encryption) => {
+                self.write_file_encrypted(path, &encryption.password, encryption.kdf)
+            }
+            None => self.write_file(path),
+        }
+    }
+
+    /// Writes zstd-compressed document to the file, encrypting it after the compression if
+    /// `encryption` is given. Compressed documents are detected automatically when read.
+    #[cfg(feature = "zstd")]
+    fn write_file_compressed(
+        &self,
+        path: impl AsRef<Path>,
+        level: i32,
+        encryption: Option<&FileEncryption>,
+    ) -> Result<usize, Error>
+    where
+        Self: Sized + StrictEncode,
+    {
+        let _lock = FileLock::acquire_io(&path, true)?;
+        let mut data = compress(&self.to_doc_bytes()?, level)?;
+        match encryption {
+            Some(encryption) => data = encrypt(&data, &encryption.password, encryption.kdf)?,
+            None => ensure_plaintext(&path)?,
+        }
+        write_journaled(path, &data)?;
+        Ok(data.len())
+    }
+
+    /// Writes document to the file, encrypting it if `encryption` is given and preserving its
+    /// previous version among at most `keep` timestamped backups (see [`backup`]).
+    fn write_file_with_backup(
+        &self,
+        path: impl AsRef<Path>,
+        keep: usize,
+        encryption: Option<&FileEncryption>,
+    ) -> Result<usize, Error>
+    where
+        Self: Sized + StrictEncode,
+    {
+        let _lock = FileLock::acquire_io(&path, true)?;
+        if encryption.is_none() {
+            ensure_plaintext(&path)?;
+        }
+        backup(&path, keep)?;
+        self.write_file_with(path, encryption)
+    }
+
+    /// Detects whether the file is password-encrypted.
+    fn is_encrypted_file(path: impl AsRef<Path>) -> Result<bool, Error> {
+        recover_journal(&path)?;
+        let mut magic = [0u8; 4];
+        let mut file = fs::File::open(path)?;
+        match file.read_exact(&mut magic) {
+            Ok(()) => Ok(is_encrypted(&magic)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn read_file_encrypted(path: impl AsRef<Path>, password: &str) -> Result<Self, Error>
+    where Self: StrictDecode {
+        let _lock = FileLock::acquire_io(&path, false)?;
+        recover_journal(&path)?;
+        Self::from_doc_bytes(&decrypt(&fs::read(path)?, password)?)
+    }
+
+    fn write_file_encrypted(
+        &self,
+        path: impl AsRef<Path>,
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<usize, Error>
+    where
+        Self: Sized + StrictEncode,
+    {
+        let _lock = FileLock::acquire_io(&path, true)?;
+        let data = encrypt(&self.to_doc_bytes()?, password, kdf)?;
+        write_journaled(path, &data)?;
+        Ok(data.len())
+    }
+
+    /// Re-encrypts the file with a new password. If the file was not encrypted, `old_password` is
+    /// ignored and the file gets encrypted.
+    fn change_password(
+        path: impl AsRef<Path>,
+        old_password: &str,
+        new_password: &str,
+        kdf: KdfParams,
+    ) -> Result<(), Error>
+    where
+        Self: Sized + StrictEncode + StrictDecode,
+    {
+        let _lock = FileLock::acquire_io(&path, true)?;
+        recover_journal(&path)?;
+        let data = fs::read(&path)?;
+        let doc = match is_encrypted(&data) {
+            true => Self::from_doc_bytes(&decrypt(&data, old_password)?)?,
+            false => Self::from_doc_bytes(&data)?,
+        };
+        doc.write_file_encrypted(path, new_password, kdf)?;
+        Ok(())
+    }
+
+    /// Decrypts the file, storing it without a password.
+    fn remove_password(path: impl AsRef<Path>, password: &str) -> Result<(), Error>
+    where Self: Sized + StrictEncode + StrictDecode {
+        let _lock = FileLock::acquire_io(&path, true)?;
+        let doc = Self::read_file_encrypted(&path, password)?;
+        write_journaled(path, &doc.to_doc_bytes()?)?;
+        Ok(())
+    }
+}
+
+/// Fails with [`Error::Encrypted`] if the file exists and is encrypted.
+fn ensure_plaintext(path: impl AsRef<Path>) -> Result<(), Error> {
+    recover_journal(&path)?;
+    let mut magic = [0u8; 4];
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    match file.read_exact(&mut magic) {
+        Ok(()) if is_encrypted(&magic) => Err(Error::Encrypted),
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Persistence backend for wallets.
+pub trait WalletStore {
+    type Error: std::error::Error;
+
+    fn load(&mut self) -> Result<Wallet, Self::Error>;
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Self::Error>;
+}
+
+/// Wallet storage in a single file document, optionally password-encrypted.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    encryption: Option<FileEncryption>,
+}
+
+impl FileStore {
+    pub fn with(path: impl AsRef<Path>) -> FileStore {
+        FileStore {
+            path: path.as_ref().to_owned(),
+            encryption: None,
+        }
+    }
+
+    /// Storage in an encrypted file, which is encrypted again on each save.
+    pub fn with_encryption(path: impl AsRef<Path>, encryption: FileEncryption) -> FileStore {
+        FileStore {
+            path: path.as_ref().to_owned(),
+            encryption: Some(encryption),
+        }
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    pub fn encryption(&self) -> Option<&FileEncryption> { self.encryption.as_ref() }
+
+    /// Changes encryption used for the next saves, for instance after the password change.
+    pub fn set_encryption(&mut self, encryption: Option<FileEncryption>) {
+        self.encryption = encryption;
+    }
+}
+
+impl WalletStore for FileStore {
+    type Error = Error;
+
+    fn load(&mut self) -> Result<Wallet, Self::Error> {
+        Wallet::read_file_with(&self.path, self.encryption.as_ref())
+    }
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Self::Error> {
+        wallet
+            .write_file_with(&self.path, self.encryption.as_ref())
+            .map(|_| ())
     }
 }
 
@@ -143,4 +892,230 @@ impl FileDocument for Wallet {
     const DOC_MAGIC: [u8; 4] = WALLET_DOC_MAGIC;
     const FILE_EXT: &'static str = "mcw";
     type FallbackDocType = WalletSettings;
+
+    fn decode_legacy(magic: [u8; 4], data: &[u8]) -> Option<Result<Self, Error>> {
+        if magic != WALLET_DOC_MAGIC_V1 {
+            return None;
+        }
+        let wallet = decode_exact::<WalletV1>(data)
+            .map(Wallet::from)
+            .or_else(|_| {
+                decode_exact::<WalletSettingsV1>(data)
+                    .map(|settings| Wallet::from(WalletSettings::from(settings)))
+            });
+        Some(wallet)
+    }
+}
+
+impl FileDocument for TemplateCatalog {
+    const DOC_MAGIC: [u8; 4] = TEMPLATES_DOC_MAGIC;
+    const FILE_EXT: &'static str = "mct";
+    type FallbackDocType = TemplateCatalog;
+}
+
+impl Wallet {
+    /// Writes watch-only copy of the wallet (see [`Wallet::export_watch_only`]) to a file.
+    pub fn write_watch_only(&self, path: impl AsRef<Path>) -> Result<usize, Error> {
+        self.export_watch_only().write_file(path)
+    }
+
+    /// Reads wallet from a file, ensuring that all of its signers are treated as watch-only, even
+    /// if the file was produced by a signing wallet.
+    pub fn read_watch_only(path: impl AsRef<Path>) -> Result<Wallet, Error> {
+        Wallet::read_file(path).map(|wallet| wallet.export_watch_only())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::FromHex;
+    use wallet::hd::UnhardenedIndex;
+    use wallet::onchain::PublicNetwork;
+
+    use super::*;
+
+    /// Wallet document written by the version using the `a4546a8e` magic: single-sig P2WPKH
+    /// mainnet wallet with a single UTXO on the first change address.
+    const WALLET_V1: &str = concat!(
+        "a4546a8e00000100010200010100000000000100000002010003774c910fcf07fa96886ea794f0d5caed9afe30b44b83",
+        "f7e213bb92930e7df4bd3da4bc190a2680111d31fadfdc905f2a7f6ce77c6f109919116f253d43445219010000000000",
+        "01000000000002000100000000010000000001000000000488b21e03155bca59800000003da4bc190a2680111d31fadf",
+        "dc905f2a7f6ce77c6f109919116f253d4344521903774c910fcf07fa96886ea794f0d5caed9afe30b44b83f7e213bb92",
+        "930e7df4bd00000001011000626c6f636b73747265616d2e696e666fbc02010001000000010000000000000000000000",
+        "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000100169e1e83e930853391bc6f35f605c6754cfead57cf8387639d3b4096",
+        "c54f18f400aa0000000050c30000000000000100000002d986ed01b7a22225a70edbf2ba7cfb63a15cb3aa0000000000",
+        "000000000000",
+    );
+
+    const TEST_KDF: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bpro-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let path = temp_path("round-trip.mct");
+        let catalog = TemplateCatalog::new("test");
+        catalog
+            .write_file_encrypted(&path, "secret", TEST_KDF)
+            .unwrap();
+        assert!(TemplateCatalog::is_encrypted_file(&path).unwrap());
+        assert_eq!(
+            TemplateCatalog::read_file_encrypted(&path, "secret").unwrap(),
+            catalog
+        );
+
+        let encryption = FileEncryption::from_file(&path, "secret").unwrap();
+        assert_eq!(encryption.kdf, TEST_KDF);
+        catalog.write_file_with(&path, Some(&encryption)).unwrap();
+        assert_eq!(
+            TemplateCatalog::read_file_with(&path, Some(&encryption)).unwrap(),
+            catalog
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wrong_password() {
+        let path = temp_path("wrong-password.mct");
+        TemplateCatalog::new("test")
+            .write_file_encrypted(&path, "secret", TEST_KDF)
+            .unwrap();
+        assert!(matches!(
+            TemplateCatalog::read_file_encrypted(&path, "wrong"),
+            Err(Error::WrongPassword)
+        ));
+        assert!(matches!(
+            TemplateCatalog::read_file(&path),
+            Err(Error::Encrypted)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn plaintext_does_not_replace_encrypted() {
+        let path = temp_path("plaintext.mct");
+        let catalog = TemplateCatalog::new("test");
+        catalog
+            .write_file_encrypted(&path, "secret", TEST_KDF)
+            .unwrap();
+        assert!(matches!(catalog.write_file(&path), Err(Error::Encrypted)));
+        assert!(matches!(
+            catalog.write_file_with_backup(&path, 1, None),
+            Err(Error::Encrypted)
+        ));
+        assert_eq!(
+            TemplateCatalog::read_file_encrypted(&path, "secret").unwrap(),
+            catalog
+        );
+
+        TemplateCatalog::remove_password(&path, "secret").unwrap();
+        assert_eq!(TemplateCatalog::read_file(&path).unwrap(), catalog);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_encrypted_round_trip() {
+        let path = temp_path("compressed.mct");
+        let catalog = TemplateCatalog::new("test");
+        let encryption = FileEncryption::with("secret", TEST_KDF);
+        catalog
+            .write_file_compressed(&path, 3, Some(&encryption))
+            .unwrap();
+        assert_eq!(
+            TemplateCatalog::read_file_with(&path, Some(&encryption)).unwrap(),
+            catalog
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wallet_v1_decoded() {
+        let path = temp_path("wallet-v1.mcw");
+        fs::write(&path, Vec::<u8>::from_hex(WALLET_V1).unwrap()).unwrap();
+        let wallet = Wallet::read_file(&path).unwrap();
+        assert_eq!(wallet.as_settings().network(), PublicNetwork::Mainnet);
+        assert_eq!(wallet.as_settings().signers().len(), 1);
+        assert_eq!(wallet.next_change_index(), UnhardenedIndex::from(2u8));
+        assert_eq!(wallet.utxos().len(), 1);
+        let utxo = wallet.utxos().iter().next().unwrap();
+        assert_eq!(utxo.value, 50_000);
+        assert_eq!(utxo.vout, 1);
+        assert_eq!(
+            utxo.addr_src.address.to_string(),
+            "bc1qmxrw6qdh5g3ztfcwm0et5l8mvws4eva24kmp8m"
+        );
+
+        // The migrated wallet is written in the current document version
+        wallet.write_file(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[..4], WALLET_DOC_MAGIC);
+        assert_eq!(Wallet::read_file(&path).unwrap().utxos(), wallet.utxos());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lock_conflicts_within_process() {
+        let path = temp_path("locked.mct");
+        let lock = FileLock::acquire(&path).unwrap();
+        assert!(matches!(FileLock::acquire(&path), Err(Error::Locked)));
+        assert!(matches!(
+            FileLock::acquire_shared(&path),
+            Err(Error::Locked)
+        ));
+        drop(lock);
+
+        let reader = FileLock::acquire_shared(&path).unwrap();
+        let other = FileLock::acquire_shared(&path).unwrap();
+        assert!(matches!(FileLock::acquire(&path), Err(Error::Locked)));
+        drop((reader, other));
+        FileLock::acquire(&path).unwrap();
+    }
+
+    #[test]
+    fn locked_document_io() {
+        let path = temp_path("locked-io.mct");
+        let catalog = TemplateCatalog::new("test");
+        catalog.write_file(&path).unwrap();
+
+        let lock = FileLock::acquire(&path).unwrap();
+        assert!(matches!(catalog.write_file(&path), Err(Error::Locked)));
+        assert!(matches!(
+            TemplateCatalog::read_file(&path),
+            Err(Error::Locked)
+        ));
+        lock.scope(|| {
+            catalog.write_file(&path).unwrap();
+            assert_eq!(TemplateCatalog::read_file(&path).unwrap(), catalog);
+        });
+        drop(lock);
+
+        let reader = FileLock::acquire_shared(&path).unwrap();
+        assert_eq!(TemplateCatalog::read_file(&path).unwrap(), catalog);
+        assert!(matches!(catalog.write_file(&path), Err(Error::Locked)));
+        drop(reader);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn kdf_clamped() {
+        let kdf = KdfParams {
+            m_cost: u32::MAX,
+            t_cost: 2,
+            p_cost: u32::MAX,
+        };
+        assert_eq!(kdf.clamped(), KdfParams {
+            m_cost: KdfParams::MAX.m_cost,
+            t_cost: 2,
+            p_cost: KdfParams::MAX.p_cost,
+        });
+    }
 }
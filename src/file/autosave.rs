@@ -0,0 +1,121 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+use std::time::{Duration, Instant};
+
+use super::WalletStore;
+use crate::Wallet;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AutosavePolicy {
+    /// Time without wallet mutations after which the wallet is saved.
+    pub quiet_period: Duration,
+    /// Maximal time the wallet may stay unsaved while being continuously mutated.
+    pub max_delay: Duration,
+}
+
+impl Default for AutosavePolicy {
+    fn default() -> Self {
+        AutosavePolicy {
+            quiet_period: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Wallet together with its storage, saving the wallet after it was mutated.
+///
+/// All mutations must go through [`AutosaveController::wallet_mut`], which marks the wallet as
+/// dirty. The owner must call [`AutosaveController::poll`] periodically (for instance from a GUI
+/// timer); it saves the wallet once no mutations happened for the quiet period, or when the wallet
+/// has been dirty for longer than the maximal delay.
+///
+/// Encrypted wallets must use a store keeping their encryption, like
+/// [`super::FileStore::with_encryption`], so they are not saved as plaintext.
+#[derive(Debug)]
+pub struct AutosaveController<S: WalletStore> {
+    wallet: Wallet,
+    store: S,
+    policy: AutosavePolicy,
+    dirty_since: Option<Instant>,
+    last_mutation: Option<Instant>,
+}
+
+impl<S: WalletStore> AutosaveController<S> {
+    pub fn with(wallet: Wallet, store: S, policy: AutosavePolicy) -> Self {
+        AutosaveController {
+            wallet,
+            store,
+            policy,
+            dirty_since: None,
+            last_mutation: None,
+        }
+    }
+
+    pub fn load(mut store: S, policy: AutosavePolicy) -> Result<Self, S::Error> {
+        let wallet = store.load()?;
+        Ok(AutosaveController::with(wallet, store, policy))
+    }
+
+    pub fn wallet(&self) -> &Wallet { &self.wallet }
+
+    pub fn store(&self) -> &S { &self.store }
+
+    /// Provides mutable access to the storage, for instance for changing the password of the
+    /// [`super::FileStore`] used for the next saves.
+    pub fn store_mut(&mut self) -> &mut S { &mut self.store }
+
+    /// Provides mutable access to the wallet, marking it as requiring save.
+    pub fn wallet_mut(&mut self) -> &mut Wallet {
+        self.mark_dirty();
+        &mut self.wallet
+    }
+
+    pub fn mark_dirty(&mut self) {
+        let now = Instant::now();
+        self.dirty_since.get_or_insert(now);
+        self.last_mutation = Some(now);
+    }
+
+    pub fn is_dirty(&self) -> bool { self.dirty_since.is_some() }
+
+    /// Time remaining until the next save, if the wallet is dirty.
+    pub fn save_due_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let quiet_due = self.last_mutation? + self.policy.quiet_period;
+        let max_due = self.dirty_since? + self.policy.max_delay;
+        Some(quiet_due.min(max_due).saturating_duration_since(now))
+    }
+
+    /// Saves the wallet if it is due, returning whether the save happened.
+    pub fn poll(&mut self) -> Result<bool, S::Error> {
+        match self.save_due_in() {
+            Some(due) if due.is_zero() => self.flush().map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Saves the wallet immediately if it is dirty.
+    pub fn flush(&mut self) -> Result<(), S::Error> {
+        if self.is_dirty() {
+            self.store.save(&self.wallet)?;
+            self.dirty_since = None;
+            self.last_mutation = None;
+        }
+        Ok(())
+    }
+
+    /// Saves pending changes and releases the wallet and its storage.
+    pub fn into_inner(mut self) -> Result<(Wallet, S), S::Error> {
+        self.flush()?;
+        Ok((self.wallet, self.store))
+    }
+}
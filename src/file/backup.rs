@@ -0,0 +1,108 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Rotation of timestamped document backups kept in `backups/` subdirectory next to the document.
+
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+
+use super::write_atomic;
+
+pub const BACKUP_DIR: &str = "backups";
+const BACKUP_EXT: &str = "bak";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Document backup file.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub created_at: DateTime<Utc>,
+}
+
+fn backup_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(BACKUP_DIR)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Copies existing document file into the backup directory and removes the oldest backups, such
+/// that at most `keep` backups are left. Does nothing if the document file does not exist yet or
+/// `keep` is zero.
+pub fn backup(path: impl AsRef<Path>, keep: usize) -> io::Result<Option<Backup>> {
+    let path = path.as_ref();
+    if keep == 0 || !path.exists() {
+        return Ok(None);
+    }
+    let dir = backup_dir(path);
+    fs::create_dir_all(&dir)?;
+
+    let created_at = Utc::now();
+    let backup_path = dir.join(format!(
+        "{}.{}.{BACKUP_EXT}",
+        file_name(path),
+        created_at.format(TIMESTAMP_FORMAT)
+    ));
+    fs::copy(path, &backup_path)?;
+
+    for outdated in list_backups(path)?.into_iter().skip(keep) {
+        fs::remove_file(outdated.path)?;
+    }
+
+    Ok(Some(Backup {
+        path: backup_path,
+        created_at,
+    }))
+}
+
+/// Lists backups of the document, starting from the most recent one.
+pub fn list_backups(path: impl AsRef<Path>) -> io::Result<Vec<Backup>> {
+    let path = path.as_ref();
+    let dir = backup_dir(path);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let prefix = format!("{}.", file_name(path));
+    let suffix = format!(".{BACKUP_EXT}");
+
+    let mut backups = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let created_at = name
+            .strip_prefix(&prefix)
+            .and_then(|name| name.strip_suffix(&suffix))
+            .and_then(|timestamp| NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok())
+            .map(|timestamp| Utc.from_utc_datetime(&timestamp));
+        if let Some(created_at) = created_at {
+            backups.push(Backup {
+                path: entry.path(),
+                created_at,
+            });
+        }
+    }
+    backups.sort_by_key(|backup| Reverse(backup.created_at));
+    Ok(backups)
+}
+
+/// Replaces document file with the content of the backup.
+pub fn restore_backup(path: impl AsRef<Path>, backup: &Backup) -> io::Result<()> {
+    write_atomic(path, &fs::read(&backup.path)?)
+}
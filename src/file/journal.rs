@@ -0,0 +1,78 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Write-ahead journal for document saves.
+//!
+//! New document content is first written to a `.journal` file next to the document together with
+//! its hash, and only then moved into place. If the process crashes during the save, the journal
+//! is found on the next open: a complete journal is applied, and a partially-written one is
+//! discarded, leaving the previous document version intact.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bitcoin::hashes::{sha256, Hash};
+
+use super::{sibling_path, write_atomic};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum JournalRecovery {
+    /// No interrupted save was detected.
+    #[display("clean")]
+    Clean,
+
+    /// Interrupted save was completed from the journal.
+    #[display("completed")]
+    Completed,
+
+    /// Incomplete journal was discarded, keeping the previous document version.
+    #[display("rolled back")]
+    RolledBack,
+}
+
+/// Saves data into `path` via the journal.
+pub fn write_journaled(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let journal_path = sibling_path(path, ".journal");
+
+    let mut journal = fs::File::create(&journal_path)?;
+    journal.write_all(data)?;
+    journal.write_all(&sha256::Hash::hash(data)[..])?;
+    journal.sync_all()?;
+
+    write_atomic(path, data)?;
+    fs::remove_file(journal_path)
+}
+
+/// Completes or rolls back save interrupted by a crash. Must be called before reading the
+/// document file.
+pub fn recover_journal(path: impl AsRef<Path>) -> io::Result<JournalRecovery> {
+    let path = path.as_ref();
+    let journal_path = sibling_path(path, ".journal");
+    let _ = fs::remove_file(sibling_path(path, ".tmp"));
+
+    let journal = match fs::read(&journal_path) {
+        Ok(journal) => journal,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(JournalRecovery::Clean),
+        Err(err) => return Err(err),
+    };
+
+    let recovery = match journal.len().checked_sub(sha256::Hash::LEN) {
+        Some(len) if sha256::Hash::hash(&journal[..len])[..] == journal[len..] => {
+            write_atomic(path, &journal[..len])?;
+            JournalRecovery::Completed
+        }
+        _ => JournalRecovery::RolledBack,
+    };
+    fs::remove_file(journal_path)?;
+    Ok(recovery)
+}
@@ -0,0 +1,264 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! SQLite wallet storage, persisting transaction history and UTXO set incrementally.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Txid;
+use rusqlite::{params, Connection, OptionalExtension};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::WalletStore;
+use crate::{HistoryEntry, UtxoTxid, Wallet};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS wallet (id INTEGER PRIMARY KEY CHECK (id = 0), data BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS history (txid BLOB PRIMARY KEY, data BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS utxos (data BLOB PRIMARY KEY);
+";
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SqliteError {
+    /// Database error. Details: {0}
+    #[from]
+    Sqlite(rusqlite::Error),
+
+    /// Invalid wallet data in the database. Details: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// The database does not contain a wallet.
+    NoWallet,
+}
+
+/// Wallet storage in SQLite database.
+///
+/// Unlike file documents, which are re-written completely on each save, only the history entries
+/// and UTXOs changed since the last load or save are written to the database.
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Connection,
+    history: BTreeMap<Txid, sha256::Hash>,
+    utxos: BTreeSet<UtxoTxid>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteStore, SqliteError> {
+        SqliteStore::with(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<SqliteStore, SqliteError> {
+        SqliteStore::with(Connection::open_in_memory()?)
+    }
+
+    pub fn with(conn: Connection) -> Result<SqliteStore, SqliteError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore {
+            conn,
+            history: empty!(),
+            utxos: empty!(),
+        })
+    }
+}
+
+impl WalletStore for SqliteStore {
+    type Error = SqliteError;
+
+    fn load(&mut self) -> Result<Wallet, Self::Error> {
+        let data: Vec<u8> = self
+            .conn
+            .query_row("SELECT data FROM wallet WHERE id = 0", [], |row| row.get(0))
+            .optional()?
+            .ok_or(SqliteError::NoWallet)?;
+        let mut wallet = Wallet::strict_deserialize(data)?;
+
+        let mut history = bset![];
+        self.history = empty!();
+        let mut stmt = self.conn.prepare("SELECT data FROM history")?;
+        for data in stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))? {
+            let data = data?;
+            let entry = HistoryEntry::strict_deserialize(&data)?;
+            self.history
+                .insert(entry.tx.txid(), sha256::Hash::hash(&data));
+            history.insert(entry);
+        }
+
+        let mut utxos = bset![];
+        let mut stmt = self.conn.prepare("SELECT data FROM utxos")?;
+        for data in stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))? {
+            utxos.insert(UtxoTxid::strict_deserialize(data?)?);
+        }
+        self.utxos = utxos.clone();
+
+        wallet.set_records(utxos, history);
+        Ok(wallet)
+    }
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Self::Error> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO wallet (id, data) VALUES (0, ?1)",
+            params![wallet.to_core().strict_serialize()?],
+        )?;
+
+        let mut history = BTreeMap::new();
+        for entry in wallet.history() {
+            let txid = entry.tx.txid();
+            let data = entry.strict_serialize()?;
+            let hash = sha256::Hash::hash(&data);
+            if self.history.get(&txid) != Some(&hash) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO history (txid, data) VALUES (?1, ?2)",
+                    params![&txid[..], data],
+                )?;
+            }
+            history.insert(txid, hash);
+        }
+        for txid in self
+            .history
+            .keys()
+            .filter(|txid| !history.contains_key(*txid))
+        {
+            tx.execute("DELETE FROM history WHERE txid = ?1", params![&txid[..]])?;
+        }
+
+        for utxo in wallet.utxos().difference(&self.utxos) {
+            tx.execute("INSERT OR REPLACE INTO utxos (data) VALUES (?1)", params![
+                utxo.strict_serialize()?
+            ])?;
+        }
+        for utxo in self.utxos.difference(wallet.utxos()) {
+            tx.execute("DELETE FROM utxos WHERE data = ?1", params![
+                utxo.strict_serialize()?
+            ])?;
+        }
+
+        tx.commit()?;
+        self.history = history;
+        self.utxos = wallet.utxos().clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use bitcoin::{OutPoint, PackedLockTime, Script, Transaction, TxIn, TxOut};
+    use bitcoin_scripts::address::AddressCompat;
+    use wallet::descriptors::DescriptorClass;
+    use wallet::hd::{Bip43, SegmentIndexes, UnhardenedIndex};
+    use wallet::onchain::PublicNetwork;
+
+    use super::*;
+    use crate::{
+        AddressSource, ElectrumPreset, ElectrumServer, OnchainStatus, OnchainTxid, Signer,
+        SpendingCondition, WalletSettings,
+    };
+
+    const XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+
+    fn wallet() -> Wallet {
+        let network = PublicNetwork::Mainnet;
+        let xpub = ExtendedPubKey::from_str(XPUB).unwrap();
+        let settings = WalletSettings::new_btc(
+            [Signer::with_xpub(xpub, &Bip43::Bip84, network)],
+            [(0, SpendingCondition::all())],
+            DescriptorClass::SegwitV0,
+            network,
+            ElectrumServer::tls(ElectrumPreset::Blockstream, network),
+        )
+        .unwrap();
+        Wallet::from(settings)
+    }
+
+    fn entry(lock_time: u32) -> HistoryEntry {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..default!()
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        HistoryEntry {
+            onchain: OnchainTxid {
+                txid: tx.txid(),
+                status: OnchainStatus::Blockchain(100),
+                date_time: None,
+            },
+            tx,
+            credit: empty!(),
+            debit: empty!(),
+            payers: empty!(),
+            beneficiaries: empty!(),
+            fee: None,
+            comment: None,
+        }
+    }
+
+    fn utxo(wallet: &Wallet, entry: &HistoryEntry) -> UtxoTxid {
+        let address = wallet.indexed_address(UnhardenedIndex::zero());
+        UtxoTxid {
+            onchain: entry.onchain,
+            value: 1000,
+            vout: 0,
+            addr_src: AddressSource {
+                address: AddressCompat::try_from(address).unwrap(),
+                change: UnhardenedIndex::zero(),
+                index: UnhardenedIndex::zero(),
+            },
+            coinbase: false,
+        }
+    }
+
+    fn assert_stored(store: &mut SqliteStore, wallet: &Wallet) {
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.as_settings(), wallet.as_settings());
+        assert_eq!(loaded.utxos(), wallet.utxos());
+        let txids = |wallet: &Wallet| {
+            wallet
+                .history()
+                .iter()
+                .map(|entry| entry.tx.txid())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(txids(&loaded), txids(wallet));
+    }
+
+    #[test]
+    fn incremental_save() {
+        let mut store = SqliteStore::in_memory().unwrap();
+        assert!(matches!(store.load(), Err(SqliteError::NoWallet)));
+
+        let mut wallet = wallet();
+        let (first, second) = (entry(1), entry(2));
+        let utxos = bset![utxo(&wallet, &first), utxo(&wallet, &second)];
+        wallet.set_records(utxos, bset![first, second.clone()]);
+        store.save(&wallet).unwrap();
+        assert_stored(&mut store, &wallet);
+
+        // Records which are no longer present in the wallet are deleted from the database
+        wallet.set_records(bset![utxo(&wallet, &second)], bset![second]);
+        store.save(&wallet).unwrap();
+        assert_stored(&mut store, &wallet);
+    }
+}
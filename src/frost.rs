@@ -0,0 +1,942 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! FROST threshold key generation and signing producing BIP-340 signatures for the t-of-n
+//! taproot key-path spending. All round messages and participant states are strict-encodable,
+//! such that participants can exchange them asynchronously through files or a coordinator.
+//! Public data are additionally serde-serializable; secret data (key shares, polynomial
+//! coefficients and nonces) are not, have redacted debug output and are wiped when dropped.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Formatter};
+use std::hint::black_box;
+
+use bitcoin::secp256k1::{self, schnorr, PublicKey, Scalar, SecretKey, SECP256K1};
+use bitcoin::util::bip32::ChildNumber;
+use bitcoin::util::taproot::{TapBranchHash, TapTweakHash};
+use bitcoin::XOnlyPublicKey;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use wallet::hd::UnhardenedIndex;
+
+use crate::sign::{hash_scalar, is_odd, x_bytes};
+
+/// Curve order minus two, used for computing scalar inverses.
+const ORDER_MINUS_TWO: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE,
+    0xBA, 0xAE, 0xDC, 0xE6, 0xAF, 0x48, 0xA0, 0x3B, 0xBF, 0xD2, 0x5E, 0x8C, 0xD0, 0x36, 0x41, 0x3F,
+];
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum FrostError {
+    /// Threshold {0} must be at least 1 and must not exceed the number of participants {1}.
+    InvalidThreshold(u16, u16),
+
+    /// Participant index {0} is out of range.
+    UnknownParticipant(u16),
+
+    /// Message from participant {0} was already received.
+    DuplicateMessage(u16),
+
+    /// Messages from {0} participants are still missing.
+    MissingMessages(usize),
+
+    /// Participant {0} has provided invalid proof of knowledge of its secret.
+    InvalidProof(u16),
+
+    /// Key share sent by participant {0} does not match its commitments.
+    InvalidShare(u16),
+
+    /// Signing package contains {0} signers, while at least {1} are required.
+    NotEnoughSigners(usize, u16),
+
+    /// Participant {0} is not part of the signing package.
+    NotSigner(u16),
+
+    /// Secret nonce does not match the commitment of participant {0} in the signing package.
+    NonceMismatch(u16),
+
+    /// Signature share of participant {0} is invalid.
+    InvalidSignatureShare(u16),
+
+    /// Cryptographic operation produced an invalid value; the round must be restarted.
+    InvalidValue,
+}
+
+impl From<secp256k1::Error> for FrostError {
+    fn from(_: secp256k1::Error) -> Self { FrostError::InvalidValue }
+}
+
+fn random_scalar() -> SecretKey {
+    loop {
+        let mut data = [0u8; 32];
+        OsRng.fill_bytes(&mut data);
+        if let Ok(sk) = SecretKey::from_slice(&data) {
+            return sk;
+        }
+    }
+}
+
+/// Overwrites secret data with zeros once it is not used anymore.
+fn wipe(data: &mut [u8; 32]) {
+    *data = [0u8; 32];
+    black_box(data);
+}
+
+fn scalar(data: &[u8; 32]) -> Result<SecretKey, FrostError> { Ok(SecretKey::from_slice(data)?) }
+
+fn index_scalar(index: u16) -> SecretKey {
+    let mut data = [0u8; 32];
+    data[30..].copy_from_slice(&index.to_be_bytes());
+    SecretKey::from_slice(&data).expect("participant indexes are non-zero")
+}
+
+fn add(a: SecretKey, b: SecretKey) -> Result<SecretKey, FrostError> {
+    Ok(a.add_tweak(&Scalar::from(b))?)
+}
+
+fn mul(a: SecretKey, b: SecretKey) -> Result<SecretKey, FrostError> {
+    Ok(a.mul_tweak(&Scalar::from(b))?)
+}
+
+fn inverse(a: SecretKey) -> Result<SecretKey, FrostError> {
+    let mut acc: Option<SecretKey> = None;
+    for byte in ORDER_MINUS_TWO {
+        for bit in (0..8).rev() {
+            acc = acc.map(|acc| mul(acc, acc)).transpose()?;
+            if byte >> bit & 1 == 1 {
+                acc = Some(match acc {
+                    Some(acc) => mul(acc, a)?,
+                    None => a,
+                });
+            }
+        }
+    }
+    acc.ok_or(FrostError::InvalidValue)
+}
+
+/// Evaluates the polynomial with the given coefficients at a participant index.
+fn evaluate(coefficients: &[SecretKey], index: u16) -> Result<SecretKey, FrostError> {
+    let x = index_scalar(index);
+    let mut acc: Option<SecretKey> = None;
+    for coef in coefficients.iter().rev() {
+        acc = Some(match acc {
+            Some(acc) => add(mul(acc, x)?, *coef)?,
+            None => *coef,
+        });
+    }
+    acc.ok_or(FrostError::InvalidValue)
+}
+
+/// Evaluates the polynomial committed to with the given points at a participant index.
+fn evaluate_commitments(commitments: &[PublicKey], index: u16) -> Result<PublicKey, FrostError> {
+    let x = Scalar::from(index_scalar(index));
+    let mut acc: Option<PublicKey> = None;
+    for point in commitments.iter().rev() {
+        acc = Some(match acc {
+            Some(acc) => acc.mul_tweak(SECP256K1, &x)?.combine(point)?,
+            None => *point,
+        });
+    }
+    acc.ok_or(FrostError::InvalidValue)
+}
+
+/// Lagrange coefficient of a signer for interpolation at zero over the set of signers.
+fn lagrange(index: u16, signers: &BTreeSet<u16>) -> Result<SecretKey, FrostError> {
+    let mut num = index_scalar(1);
+    let mut den = index_scalar(1);
+    for other in signers.iter().filter(|other| **other != index) {
+        num = mul(num, index_scalar(*other))?;
+        den = mul(
+            den,
+            add(index_scalar(*other), index_scalar(index).negate())?,
+        )?;
+    }
+    mul(num, inverse(den)?)
+}
+
+fn sum_points<'a>(
+    points: impl IntoIterator<Item = &'a PublicKey>,
+) -> Result<PublicKey, FrostError> {
+    Ok(PublicKey::combine_keys(
+        &points.into_iter().collect::<Vec<_>>(),
+    )?)
+}
+
+/// First-round DKG message broadcast to all other participants.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct FrostDkgCommitment {
+    pub sender: u16,
+    /// Commitments to the sender polynomial coefficients.
+    pub commitments: Vec<PublicKey>,
+    /// Proof of knowledge of the secret (the constant polynomial term).
+    pub proof_r: PublicKey,
+    pub proof_mu: [u8; 32],
+}
+
+impl FrostDkgCommitment {
+    fn challenge(
+        context: &[u8],
+        sender: u16,
+        secret: PublicKey,
+        r: PublicKey,
+    ) -> Result<SecretKey, FrostError> {
+        Ok(hash_scalar(b"FROST/dkg-proof", &[
+            &(context.len() as u32).to_be_bytes(),
+            context,
+            &sender.to_be_bytes(),
+            &secret.serialize(),
+            &r.serialize(),
+        ])?)
+    }
+
+    fn verify(&self, threshold: u16, context: &[u8]) -> Result<(), FrostError> {
+        let secret = self
+            .commitments
+            .first()
+            .copied()
+            .ok_or(FrostError::InvalidProof(self.sender))?;
+        if self.commitments.len() != threshold as usize {
+            return Err(FrostError::InvalidProof(self.sender));
+        }
+        let c = FrostDkgCommitment::challenge(context, self.sender, secret, self.proof_r)?;
+        let expected = self
+            .proof_r
+            .combine(&secret.mul_tweak(SECP256K1, &Scalar::from(c))?)?;
+        if scalar(&self.proof_mu)?.public_key(SECP256K1) != expected {
+            return Err(FrostError::InvalidProof(self.sender));
+        }
+        Ok(())
+    }
+}
+
+/// Second-round DKG message with a secret key share, which must be delivered to the receiver
+/// over a private channel. Being secret, it is serializable only with strict encoding, for
+/// putting into encrypted messages or files.
+#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct FrostDkgShare {
+    pub sender: u16,
+    pub receiver: u16,
+    pub share: [u8; 32],
+}
+
+impl Debug for FrostDkgShare {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrostDkgShare")
+            .field("sender", &self.sender)
+            .field("receiver", &self.receiver)
+            .field("share", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Drop for FrostDkgShare {
+    fn drop(&mut self) { wipe(&mut self.share); }
+}
+
+/// Distributed key generation state of a single participant, which must be kept private and
+/// stored encrypted.
+#[derive(Clone, PartialEq, Eq)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct FrostDkg {
+    index: u16,
+    threshold: u16,
+    participants: u16,
+    /// Context string of the key generation session, which is committed to by the proofs of
+    /// knowledge, preventing their replay in other sessions.
+    context: Vec<u8>,
+    coefficients: Vec<[u8; 32]>,
+    commitments: BTreeMap<u16, FrostDkgCommitment>,
+    shares: BTreeMap<u16, [u8; 32]>,
+}
+
+impl Debug for FrostDkg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrostDkg")
+            .field("index", &self.index)
+            .field("threshold", &self.threshold)
+            .field("participants", &self.participants)
+            .field("context", &self.context)
+            .field("coefficients", &"<redacted>")
+            .field("commitments", &self.commitments)
+            .field("shares", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Drop for FrostDkg {
+    fn drop(&mut self) {
+        self.coefficients.iter_mut().for_each(wipe);
+        self.shares.values_mut().for_each(wipe);
+    }
+}
+
+impl FrostDkg {
+    /// Starts key generation for the participant with a given index (starting from 1),
+    /// returning the state and the first-round message. All participants must use the same
+    /// context string, unique for the key generation session (like a random session id agreed
+    /// by the participants, followed by the wallet name).
+    pub fn new(
+        index: u16,
+        threshold: u16,
+        participants: u16,
+        context: impl AsRef<[u8]>,
+    ) -> Result<(FrostDkg, FrostDkgCommitment), FrostError> {
+        let context = context.as_ref().to_vec();
+        if threshold == 0 || threshold > participants {
+            return Err(FrostError::InvalidThreshold(threshold, participants));
+        }
+        if index == 0 || index > participants {
+            return Err(FrostError::UnknownParticipant(index));
+        }
+        let coefficients = (0..threshold).map(|_| random_scalar()).collect::<Vec<_>>();
+        let commitments = coefficients
+            .iter()
+            .map(|coef| coef.public_key(SECP256K1))
+            .collect::<Vec<_>>();
+        let k = random_scalar();
+        let proof_r = k.public_key(SECP256K1);
+        let c = FrostDkgCommitment::challenge(&context, index, commitments[0], proof_r)?;
+        let proof_mu = add(k, mul(coefficients[0], c)?)?;
+        let message = FrostDkgCommitment {
+            sender: index,
+            commitments,
+            proof_r,
+            proof_mu: proof_mu.secret_bytes(),
+        };
+        let mut dkg = FrostDkg {
+            index,
+            threshold,
+            participants,
+            context,
+            coefficients: coefficients.iter().map(SecretKey::secret_bytes).collect(),
+            commitments: empty!(),
+            shares: empty!(),
+        };
+        dkg.add_commitment(message.clone())?;
+        Ok((dkg, message))
+    }
+
+    pub fn index(&self) -> u16 { self.index }
+
+    pub fn threshold(&self) -> u16 { self.threshold }
+
+    pub fn participants(&self) -> u16 { self.participants }
+
+    pub fn context(&self) -> &[u8] { &self.context }
+
+    fn check_sender(&self, sender: u16) -> Result<(), FrostError> {
+        if sender == 0 || sender > self.participants {
+            return Err(FrostError::UnknownParticipant(sender));
+        }
+        Ok(())
+    }
+
+    /// Registers first-round message of another participant.
+    pub fn add_commitment(&mut self, message: FrostDkgCommitment) -> Result<(), FrostError> {
+        self.check_sender(message.sender)?;
+        if self.commitments.contains_key(&message.sender) {
+            return Err(FrostError::DuplicateMessage(message.sender));
+        }
+        message.verify(self.threshold, &self.context)?;
+        self.commitments.insert(message.sender, message);
+        Ok(())
+    }
+
+    /// Produces second-round messages for all other participants once all first-round messages
+    /// are received.
+    pub fn shares(&mut self) -> Result<Vec<FrostDkgShare>, FrostError> {
+        let missing = self.participants as usize - self.commitments.len();
+        if missing > 0 {
+            return Err(FrostError::MissingMessages(missing));
+        }
+        let coefficients = self
+            .coefficients
+            .iter()
+            .map(scalar)
+            .collect::<Result<Vec<_>, _>>()?;
+        let own = evaluate(&coefficients, self.index)?;
+        self.shares.insert(self.index, own.secret_bytes());
+        (1..=self.participants)
+            .filter(|receiver| *receiver != self.index)
+            .map(|receiver| {
+                Ok(FrostDkgShare {
+                    sender: self.index,
+                    receiver,
+                    share: evaluate(&coefficients, receiver)?.secret_bytes(),
+                })
+            })
+            .collect()
+    }
+
+    /// Registers second-round message of another participant, verifying it against the
+    /// participant commitments.
+    pub fn add_share(&mut self, message: FrostDkgShare) -> Result<(), FrostError> {
+        self.check_sender(message.sender)?;
+        if message.receiver != self.index {
+            return Err(FrostError::UnknownParticipant(message.receiver));
+        }
+        if self.shares.contains_key(&message.sender) {
+            return Err(FrostError::DuplicateMessage(message.sender));
+        }
+        let commitment = self
+            .commitments
+            .get(&message.sender)
+            .ok_or(FrostError::MissingMessages(1))?;
+        let expected = evaluate_commitments(&commitment.commitments, self.index)?;
+        if scalar(&message.share)?.public_key(SECP256K1) != expected {
+            return Err(FrostError::InvalidShare(message.sender));
+        }
+        self.shares.insert(message.sender, message.share);
+        Ok(())
+    }
+
+    /// Completes key generation once shares from all participants are received.
+    pub fn finalize(&self) -> Result<FrostKeyShare, FrostError> {
+        let missing = self.participants as usize - self.shares.len();
+        if missing > 0 {
+            return Err(FrostError::MissingMessages(missing));
+        }
+        let mut secret: Option<SecretKey> = None;
+        for share in self.shares.values() {
+            let share = scalar(share)?;
+            secret = Some(match secret {
+                Some(acc) => add(acc, share)?,
+                None => share,
+            });
+        }
+        let secret = secret.ok_or(FrostError::InvalidValue)?;
+        let group_key = sum_points(self.commitments.values().map(|msg| &msg.commitments[0]))?;
+        let verification_shares = (1..=self.participants)
+            .map(|index| {
+                let points = self
+                    .commitments
+                    .values()
+                    .map(|msg| evaluate_commitments(&msg.commitments, index))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((index, sum_points(&points)?))
+            })
+            .collect::<Result<BTreeMap<_, _>, FrostError>>()?;
+        Ok(FrostKeyShare {
+            index: self.index,
+            secret: secret.secret_bytes(),
+            group: FrostGroup {
+                threshold: self.threshold,
+                group_key,
+                verification_shares,
+            },
+        })
+    }
+}
+
+/// Public information about the FROST group, used by coordinator to verify signature shares
+/// and aggregate them.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct FrostGroup {
+    pub threshold: u16,
+    pub group_key: PublicKey,
+    /// Public keys corresponding to the secret shares of each participant.
+    pub verification_shares: BTreeMap<u16, PublicKey>,
+}
+
+/// Output key tweaking details.
+struct FrostTweak {
+    output_key: PublicKey,
+    /// Whether the secret shares have to be negated.
+    negate: bool,
+    /// Tweak added to the aggregated signature, already adjusted for the output key parity.
+    tweak: SecretKey,
+}
+
+/// Tweak shifting the group key and all key shares for a given terminal, such that each
+/// wallet address gets its own group key.
+fn terminal_tweak(
+    group_key: PublicKey,
+    change: UnhardenedIndex,
+    index: UnhardenedIndex,
+) -> Result<SecretKey, FrostError> {
+    Ok(hash_scalar(b"FROST/derive", &[
+        &group_key.serialize(),
+        &u32::from(ChildNumber::from(change)).to_be_bytes(),
+        &u32::from(ChildNumber::from(index)).to_be_bytes(),
+    ])?)
+}
+
+impl FrostGroup {
+    /// Taproot internal key.
+    pub fn internal_key(&self) -> XOnlyPublicKey { self.group_key.x_only_public_key().0 }
+
+    /// Derives group for a given terminal (change and address index). The group key of the
+    /// derived group is used as the internal key of the wallet taproot descriptors (see
+    /// [`crate::WalletSettings::derive`]).
+    pub fn derive(
+        &self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Result<FrostGroup, FrostError> {
+        let tweak = Scalar::from(terminal_tweak(self.group_key, change, index)?);
+        Ok(FrostGroup {
+            threshold: self.threshold,
+            group_key: self.group_key.add_exp_tweak(SECP256K1, &tweak)?,
+            verification_shares: self
+                .verification_shares
+                .iter()
+                .map(|(index, key)| Ok((*index, key.add_exp_tweak(SECP256K1, &tweak)?)))
+                .collect::<Result<_, FrostError>>()?,
+        })
+    }
+
+    /// Taproot output key for a given script tree root.
+    pub fn output_key(
+        &self,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<XOnlyPublicKey, FrostError> {
+        Ok(self.tweak(merkle_root)?.output_key.x_only_public_key().0)
+    }
+
+    fn tweak(&self, merkle_root: Option<TapBranchHash>) -> Result<FrostTweak, FrostError> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.internal_key(), merkle_root);
+        let tweak = SecretKey::from_slice(&tweak[..])?;
+        let internal_odd = is_odd(self.group_key);
+        let internal = if internal_odd { self.group_key.negate(SECP256K1) } else { self.group_key };
+        let output_key = internal.add_exp_tweak(SECP256K1, &Scalar::from(tweak))?;
+        let output_odd = is_odd(output_key);
+        Ok(FrostTweak {
+            output_key,
+            negate: internal_odd ^ output_odd,
+            tweak: if output_odd { tweak.negate() } else { tweak },
+        })
+    }
+
+    fn context(&self, package: &FrostSigningPackage) -> Result<FrostContext, FrostError> {
+        if package.commitments.len() < self.threshold as usize {
+            return Err(FrostError::NotEnoughSigners(
+                package.commitments.len(),
+                self.threshold,
+            ));
+        }
+        if let Some(index) = package
+            .commitments
+            .keys()
+            .find(|index| !self.verification_shares.contains_key(index))
+        {
+            return Err(FrostError::UnknownParticipant(*index));
+        }
+        let tweak = self.tweak(package.merkle_root)?;
+        let output_key = x_bytes(tweak.output_key);
+        let encoded = package
+            .commitments
+            .iter()
+            .flat_map(|(index, commitment)| {
+                let mut data = index.to_be_bytes().to_vec();
+                data.extend(commitment.hiding.serialize());
+                data.extend(commitment.binding.serialize());
+                data
+            })
+            .collect::<Vec<_>>();
+        let mut binding_factors = BTreeMap::new();
+        let mut nonces = BTreeMap::new();
+        for (index, commitment) in &package.commitments {
+            let rho = hash_scalar(b"FROST/rho", &[
+                &index.to_be_bytes(),
+                &package.msg,
+                &encoded,
+                &output_key,
+            ])?;
+            let nonce = commitment.hiding.combine(
+                &commitment
+                    .binding
+                    .mul_tweak(SECP256K1, &Scalar::from(rho))?,
+            )?;
+            binding_factors.insert(*index, rho);
+            nonces.insert(*index, nonce);
+        }
+        let r = sum_points(nonces.values())?;
+        let challenge = hash_scalar(b"BIP0340/challenge", &[
+            &x_bytes(r),
+            &output_key,
+            &package.msg,
+        ])?;
+        Ok(FrostContext {
+            tweak,
+            r,
+            challenge,
+            binding_factors,
+            nonces,
+            signers: package.commitments.keys().copied().collect(),
+        })
+    }
+
+    /// Verifies signature share of a participant.
+    pub fn verify_share(
+        &self,
+        package: &FrostSigningPackage,
+        share: &FrostSignatureShare,
+    ) -> Result<(), FrostError> {
+        let ctx = self.context(package)?;
+        self.verify_share_with(&ctx, share)
+    }
+
+    fn verify_share_with(
+        &self,
+        ctx: &FrostContext,
+        share: &FrostSignatureShare,
+    ) -> Result<(), FrostError> {
+        let index = share.signer;
+        let nonce = *ctx.nonces.get(&index).ok_or(FrostError::NotSigner(index))?;
+        let nonce = if is_odd(ctx.r) { nonce.negate(SECP256K1) } else { nonce };
+        let key = *self
+            .verification_shares
+            .get(&index)
+            .ok_or(FrostError::UnknownParticipant(index))?;
+        let key = if ctx.tweak.negate { key.negate(SECP256K1) } else { key };
+        let factor = mul(lagrange(index, &ctx.signers)?, ctx.challenge)?;
+        let expected = nonce.combine(&key.mul_tweak(SECP256K1, &Scalar::from(factor))?)?;
+        if scalar(&share.share)?.public_key(SECP256K1) != expected {
+            return Err(FrostError::InvalidSignatureShare(index));
+        }
+        Ok(())
+    }
+
+    /// Verifies signature shares of all signers from the package and aggregates them into the
+    /// final BIP-340 signature valid for the taproot output key.
+    pub fn aggregate(
+        &self,
+        package: &FrostSigningPackage,
+        shares: &[FrostSignatureShare],
+    ) -> Result<schnorr::Signature, FrostError> {
+        let ctx = self.context(package)?;
+        let received = shares
+            .iter()
+            .map(|share| share.signer)
+            .collect::<BTreeSet<_>>();
+        if received.len() != shares.len() {
+            let mut seen = BTreeSet::new();
+            let duplicate = shares
+                .iter()
+                .find(|share| !seen.insert(share.signer))
+                .map(|share| share.signer)
+                .unwrap_or_default();
+            return Err(FrostError::DuplicateMessage(duplicate));
+        }
+        let missing = ctx.signers.difference(&received).count();
+        if missing > 0 {
+            return Err(FrostError::MissingMessages(missing));
+        }
+        let mut z = mul(ctx.tweak.tweak, ctx.challenge)?;
+        for share in shares {
+            self.verify_share_with(&ctx, share)?;
+            z = add(z, scalar(&share.share)?)?;
+        }
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(&x_bytes(ctx.r));
+        data[32..].copy_from_slice(&z.secret_bytes());
+        let sig = schnorr::Signature::from_slice(&data)?;
+        let msg = secp256k1::Message::from_slice(&package.msg)?;
+        SECP256K1
+            .verify_schnorr(&sig, &msg, &ctx.tweak.output_key.x_only_public_key().0)
+            .map_err(|_| FrostError::InvalidValue)?;
+        Ok(sig)
+    }
+}
+
+/// Values shared by all signers once the signing package is known.
+struct FrostContext {
+    tweak: FrostTweak,
+    r: PublicKey,
+    challenge: SecretKey,
+    binding_factors: BTreeMap<u16, SecretKey>,
+    nonces: BTreeMap<u16, PublicKey>,
+    signers: BTreeSet<u16>,
+}
+
+/// Key share of a FROST participant, produced by the distributed key generation. Contains
+/// secret data and must be stored encrypted.
+#[derive(Clone, PartialEq, Eq)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct FrostKeyShare {
+    pub index: u16,
+    secret: [u8; 32],
+    pub group: FrostGroup,
+}
+
+impl Debug for FrostKeyShare {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrostKeyShare")
+            .field("index", &self.index)
+            .field("secret", &"<redacted>")
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+impl Drop for FrostKeyShare {
+    fn drop(&mut self) { wipe(&mut self.secret); }
+}
+
+/// Secret nonces of a signer for a single signing session. They must be persisted (encrypted)
+/// until the signature share is produced and never reused afterwards.
+#[derive(StrictEncode, StrictDecode)]
+pub struct FrostSecNonce {
+    signer: u16,
+    hiding: [u8; 32],
+    binding: [u8; 32],
+}
+
+impl Debug for FrostSecNonce {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrostSecNonce")
+            .field("signer", &self.signer)
+            .field("hiding", &"<redacted>")
+            .field("binding", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Drop for FrostSecNonce {
+    fn drop(&mut self) {
+        wipe(&mut self.hiding);
+        wipe(&mut self.binding);
+    }
+}
+
+/// Public nonce commitments of a signer, sent to the coordinator in the first signing round.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct FrostNonceCommitment {
+    pub hiding: PublicKey,
+    pub binding: PublicKey,
+}
+
+/// Signing package distributed by the coordinator in the second signing round.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct FrostSigningPackage {
+    /// Message to sign (like taproot key-path sighash).
+    pub msg: [u8; 32],
+    /// Root of the taproot script tree the output key commits to.
+    pub merkle_root: Option<TapBranchHash>,
+    /// Nonce commitments of the signers taking part in the session.
+    pub commitments: BTreeMap<u16, FrostNonceCommitment>,
+}
+
+/// Signature share of a signer, sent to the coordinator.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct FrostSignatureShare {
+    pub signer: u16,
+    pub share: [u8; 32],
+}
+
+impl FrostKeyShare {
+    pub fn public_key(&self) -> Result<PublicKey, FrostError> {
+        Ok(scalar(&self.secret)?.public_key(SECP256K1))
+    }
+
+    /// Derives key share for signing spendings of a given terminal (change and address index),
+    /// matching the group derived with [`FrostGroup::derive`].
+    pub fn derive(
+        &self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Result<FrostKeyShare, FrostError> {
+        let tweak = terminal_tweak(self.group.group_key, change, index)?;
+        Ok(FrostKeyShare {
+            index: self.index,
+            secret: add(scalar(&self.secret)?, tweak)?.secret_bytes(),
+            group: self.group.derive(change, index)?,
+        })
+    }
+
+    /// Generates nonces for a new signing session, returning the secret part to be persisted
+    /// and the commitment to be sent to the coordinator.
+    pub fn commit(&self) -> (FrostSecNonce, FrostNonceCommitment) {
+        let (hiding, binding) = (random_scalar(), random_scalar());
+        let commitment = FrostNonceCommitment {
+            hiding: hiding.public_key(SECP256K1),
+            binding: binding.public_key(SECP256K1),
+        };
+        let secnonce = FrostSecNonce {
+            signer: self.index,
+            hiding: hiding.secret_bytes(),
+            binding: binding.secret_bytes(),
+        };
+        (secnonce, commitment)
+    }
+
+    /// Produces signature share for the signing package, consuming the secret nonce.
+    pub fn sign(
+        &self,
+        package: &FrostSigningPackage,
+        secnonce: FrostSecNonce,
+    ) -> Result<FrostSignatureShare, FrostError> {
+        let index = self.index;
+        let commitment = package
+            .commitments
+            .get(&index)
+            .ok_or(FrostError::NotSigner(index))?;
+        let (hiding, binding) = (scalar(&secnonce.hiding)?, scalar(&secnonce.binding)?);
+        if secnonce.signer != index
+            || commitment.hiding != hiding.public_key(SECP256K1)
+            || commitment.binding != binding.public_key(SECP256K1)
+        {
+            return Err(FrostError::NonceMismatch(index));
+        }
+        let ctx = self.group.context(package)?;
+        let rho = ctx.binding_factors[&index];
+        let nonce = add(hiding, mul(binding, rho)?)?;
+        let nonce = if is_odd(ctx.r) { nonce.negate() } else { nonce };
+        let secret = scalar(&self.secret)?;
+        let secret = if ctx.tweak.negate { secret.negate() } else { secret };
+        let factor = mul(lagrange(index, &ctx.signers)?, ctx.challenge)?;
+        let share = add(nonce, mul(secret, factor)?)?;
+        Ok(FrostSignatureShare {
+            signer: index,
+            share: share.secret_bytes(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use wallet::hd::SegmentIndexes;
+
+    use super::*;
+
+    const CONTEXT: &[u8] = b"bpro test session";
+
+    fn keygen(threshold: u16, participants: u16) -> Vec<FrostKeyShare> {
+        let (mut states, commitments): (Vec<_>, Vec<_>) = (1..=participants)
+            .map(|index| FrostDkg::new(index, threshold, participants, CONTEXT).unwrap())
+            .unzip();
+        for state in &mut states {
+            for commitment in &commitments {
+                if commitment.sender != state.index() {
+                    state.add_commitment(commitment.clone()).unwrap();
+                }
+            }
+        }
+        let shares = states
+            .iter_mut()
+            .flat_map(|state| state.shares().unwrap())
+            .collect::<Vec<_>>();
+        for share in shares {
+            let receiver = share.receiver as usize - 1;
+            states[receiver].add_share(share).unwrap();
+        }
+        states
+            .iter()
+            .map(|state| state.finalize().unwrap())
+            .collect()
+    }
+
+    fn sign(
+        signers: &[&FrostKeyShare],
+        group: &FrostGroup,
+        merkle_root: Option<TapBranchHash>,
+    ) -> schnorr::Signature {
+        let msg = [0x5Au8; 32];
+        let (secnonces, commitments): (Vec<_>, BTreeMap<_, _>) = signers
+            .iter()
+            .map(|signer| {
+                let (secnonce, commitment) = signer.commit();
+                (secnonce, (signer.index, commitment))
+            })
+            .unzip();
+        let package = FrostSigningPackage {
+            msg,
+            merkle_root,
+            commitments,
+        };
+        let shares = signers
+            .iter()
+            .zip(secnonces)
+            .map(|(signer, secnonce)| signer.sign(&package, secnonce).unwrap())
+            .collect::<Vec<_>>();
+        let sig = group.aggregate(&package, &shares).unwrap();
+        let msg = secp256k1::Message::from_slice(&msg).unwrap();
+        SECP256K1
+            .verify_schnorr(&sig, &msg, &group.output_key(merkle_root).unwrap())
+            .unwrap();
+        sig
+    }
+
+    #[test]
+    fn threshold_sign() {
+        let shares = keygen(2, 3);
+        let group = shares[0].group.clone();
+        assert!(shares.iter().all(|share| share.group == group));
+        sign(&[&shares[0], &shares[2]], &group, None);
+        sign(&[&shares[0], &shares[1], &shares[2]], &group, None);
+    }
+
+    #[test]
+    fn derived_sign() {
+        let shares = keygen(2, 3);
+        let (change, index) = (UnhardenedIndex::zero(), UnhardenedIndex::from(5u8));
+        let group = shares[0].group.derive(change, index).unwrap();
+        assert_ne!(group.group_key, shares[0].group.group_key);
+        assert_ne!(
+            group,
+            shares[0]
+                .group
+                .derive(change, UnhardenedIndex::from(6u8))
+                .unwrap()
+        );
+
+        let derived = shares
+            .iter()
+            .map(|share| share.derive(change, index).unwrap())
+            .collect::<Vec<_>>();
+        for share in &derived {
+            assert_eq!(share.group, group);
+            assert_eq!(
+                group.verification_shares[&share.index],
+                share.public_key().unwrap()
+            );
+        }
+        let merkle_root = Some(TapBranchHash::from_inner([0x11; 32]));
+        sign(&[&derived[1], &derived[2]], &group, merkle_root);
+    }
+
+    #[test]
+    fn dkg_context() {
+        let (_, commitment) = FrostDkg::new(1, 2, 2, b"session a").unwrap();
+        let (mut dkg, _) = FrostDkg::new(2, 2, 2, b"session b").unwrap();
+        assert_eq!(
+            dkg.add_commitment(commitment),
+            Err(FrostError::InvalidProof(1))
+        );
+    }
+
+    #[test]
+    fn redacted_debug() {
+        let shares = keygen(2, 2);
+        let debug = format!("{:?}", shares[0]);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&format!("{:?}", shares[0].secret)));
+
+        let (secnonce, _) = shares[0].commit();
+        let debug = format!("{:?}", secnonce);
+        assert!(!debug.contains(&format!("{:?}", secnonce.hiding)));
+        assert!(!debug.contains(&format!("{:?}", secnonce.binding)));
+    }
+}
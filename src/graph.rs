@@ -0,0 +1,129 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Ancestry graph of the wallet transactions.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::{OutPoint, Txid};
+
+use crate::Wallet;
+
+/// Funding relation between two wallet transactions: output `outpoint` of the parent transaction
+/// is spent by input `vin` of the child transaction.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct TxEdge {
+    pub outpoint: OutPoint,
+    pub child: Txid,
+    pub vin: u32,
+}
+
+impl TxEdge {
+    pub fn parent(&self) -> Txid { self.outpoint.txid }
+}
+
+/// Directed acyclic graph of the wallet transactions, where edges connect transactions with the
+/// transactions spending their outputs.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TxGraph {
+    nodes: BTreeSet<Txid>,
+    parents: BTreeMap<Txid, BTreeSet<TxEdge>>,
+    children: BTreeMap<Txid, BTreeSet<TxEdge>>,
+}
+
+impl TxGraph {
+    pub fn contains(&self, txid: Txid) -> bool { self.nodes.contains(&txid) }
+
+    pub fn txids(&self) -> impl Iterator<Item = Txid> + '_ { self.nodes.iter().copied() }
+
+    pub fn edges(&self) -> impl Iterator<Item = &TxEdge> {
+        self.parents.values().flat_map(BTreeSet::iter)
+    }
+
+    /// Edges to the wallet transactions funding a given transaction.
+    pub fn parents(&self, txid: Txid) -> impl Iterator<Item = &TxEdge> {
+        self.parents.get(&txid).into_iter().flat_map(BTreeSet::iter)
+    }
+
+    /// Edges to the wallet transactions spending outputs of a given transaction.
+    pub fn children(&self, txid: Txid) -> impl Iterator<Item = &TxEdge> {
+        self.children
+            .get(&txid)
+            .into_iter()
+            .flat_map(BTreeSet::iter)
+    }
+
+    /// Transactions which are not funded by other wallet transactions, i.e. which bring funds
+    /// into the wallet from outside.
+    pub fn roots(&self) -> impl Iterator<Item = Txid> + '_ {
+        self.txids().filter(|txid| !self.parents.contains_key(txid))
+    }
+
+    /// All wallet transactions from which funds of a given transaction originate.
+    pub fn ancestors(&self, txid: Txid) -> BTreeSet<Txid> {
+        self.walk(txid, |txid| {
+            self.parents(txid).map(TxEdge::parent).collect()
+        })
+    }
+
+    /// All wallet transactions which spend funds originating from a given transaction.
+    pub fn descendants(&self, txid: Txid) -> BTreeSet<Txid> {
+        self.walk(txid, |txid| {
+            self.children(txid).map(|edge| edge.child).collect()
+        })
+    }
+
+    fn walk(&self, txid: Txid, next: impl Fn(Txid) -> Vec<Txid>) -> BTreeSet<Txid> {
+        let mut visited = bset![];
+        let mut queue = next(txid);
+        while let Some(txid) = queue.pop() {
+            if visited.insert(txid) {
+                queue.extend(next(txid));
+            }
+        }
+        visited
+    }
+}
+
+impl Wallet {
+    /// Builds ancestry graph of the wallet transactions.
+    pub fn tx_graph(&self) -> TxGraph {
+        let mut graph = TxGraph {
+            nodes: self
+                .history()
+                .iter()
+                .map(|entry| entry.onchain.txid)
+                .collect(),
+            ..default!()
+        };
+        for entry in self.history() {
+            let child = entry.onchain.txid;
+            for (vin, txin) in entry.tx.input.iter().enumerate() {
+                let outpoint = txin.previous_output;
+                if !graph.nodes.contains(&outpoint.txid) {
+                    continue;
+                }
+                let edge = TxEdge {
+                    outpoint,
+                    child,
+                    vin: vin as u32,
+                };
+                graph.parents.entry(child).or_default().insert(edge);
+                graph
+                    .children
+                    .entry(outpoint.txid)
+                    .or_default()
+                    .insert(edge);
+            }
+        }
+        graph
+    }
+}
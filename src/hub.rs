@@ -0,0 +1,323 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Management of multiple wallets opened by an application.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "electrum")]
+use std::sync::Arc;
+
+use wallet::onchain::PublicNetwork;
+
+use crate::file::{self, FileEncryption, FileLock};
+#[cfg(feature = "electrum")]
+use crate::ElectrumSec;
+use crate::{ElectrumServer, FileDocument, Wallet};
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum HubError {
+    /// {0}
+    #[from]
+    File(file::Error),
+
+    /// Wallet {0} is already opened.
+    AlreadyOpen(String),
+
+    /// Wallet {0} is not opened.
+    NotOpen(String),
+
+    /// Unable to connect to Electrum server {0}. Details: {1}
+    Electrum(ElectrumServer, String),
+
+    /// Electrum server {0} is a Tor service, while no Tor SOCKS5 proxy is configured.
+    NoTorProxy(ElectrumServer),
+}
+
+#[derive(Debug)]
+struct HubEntry {
+    wallet: Wallet,
+    /// Encryption of the wallet file, re-applied on each save.
+    encryption: Option<FileEncryption>,
+    /// Exclusive lock of the wallet file held while the wallet is opened.
+    lock: FileLock,
+}
+
+/// Collection of the wallets opened by an application, each one locked for exclusive use by the
+/// hub while open (see [`FileLock`]).
+///
+/// Wallets are identified by the path of their files. Electrum connections are shared between
+/// wallets using the same server.
+#[derive(Default)]
+pub struct WalletHub {
+    wallets: BTreeMap<PathBuf, HubEntry>,
+    #[cfg(feature = "electrum")]
+    connections: BTreeMap<ElectrumServer, Arc<electrum_client::Client>>,
+    /// Address of the SOCKS5 proxy used to connect to the Tor Electrum servers.
+    #[cfg(feature = "electrum")]
+    tor_proxy: Option<String>,
+}
+
+impl Debug for WalletHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Electrum clients do not implement `Debug`, so the connections are omitted
+        let mut debug = f.debug_struct("WalletHub");
+        debug.field("wallets", &self.wallets);
+        #[cfg(feature = "electrum")]
+        debug.field("tor_proxy", &self.tor_proxy);
+        debug.finish_non_exhaustive()
+    }
+}
+
+impl WalletHub {
+    pub fn new() -> WalletHub { WalletHub::default() }
+
+    fn entry(&self, path: &Path) -> Result<&HubEntry, HubError> {
+        self.wallets
+            .get(path)
+            .ok_or_else(|| HubError::NotOpen(path.display().to_string()))
+    }
+
+    fn insert(
+        &mut self,
+        path: PathBuf,
+        wallet: Wallet,
+        encryption: Option<FileEncryption>,
+        lock: FileLock,
+    ) -> &mut Wallet {
+        &mut self
+            .wallets
+            .entry(path)
+            .or_insert(HubEntry {
+                wallet,
+                encryption,
+                lock,
+            })
+            .wallet
+    }
+
+    /// Opens wallet from the file, locking it. Encrypted wallets must be opened with
+    /// [`WalletHub::open_encrypted`].
+    pub fn open(&mut self, path: impl AsRef<Path>) -> Result<&mut Wallet, HubError> {
+        self.open_with(path, None)
+    }
+
+    /// Opens password-encrypted wallet from the file, locking it. The wallet is encrypted with
+    /// the same password and key derivation parameters when saved.
+    pub fn open_encrypted(
+        &mut self,
+        path: impl AsRef<Path>,
+        password: &str,
+    ) -> Result<&mut Wallet, HubError> {
+        let encryption = FileEncryption::from_file(&path, password)?;
+        self.open_with(path, Some(encryption))
+    }
+
+    fn open_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        encryption: Option<FileEncryption>,
+    ) -> Result<&mut Wallet, HubError> {
+        let path = path.as_ref().to_owned();
+        if self.wallets.contains_key(&path) {
+            return Err(HubError::AlreadyOpen(path.display().to_string()));
+        }
+        let lock = FileLock::acquire(&path)?;
+        let wallet = lock.scope(|| Wallet::read_file_with(&path, encryption.as_ref()))?;
+        Ok(self.insert(path, wallet, encryption, lock))
+    }
+
+    /// Adds newly created wallet to the hub, saving it to the file, which is encrypted if
+    /// `encryption` is given.
+    pub fn create(
+        &mut self,
+        path: impl AsRef<Path>,
+        wallet: Wallet,
+        encryption: Option<FileEncryption>,
+    ) -> Result<&mut Wallet, HubError> {
+        let path = path.as_ref().to_owned();
+        if self.wallets.contains_key(&path) {
+            return Err(HubError::AlreadyOpen(path.display().to_string()));
+        }
+        let lock = FileLock::acquire(&path)?;
+        lock.scope(|| wallet.write_file_with(&path, encryption.as_ref()))?;
+        Ok(self.insert(path, wallet, encryption, lock))
+    }
+
+    /// Changes encryption of the opened wallet, saving it with the new password (or without a
+    /// password if `encryption` is `None`).
+    pub fn set_encryption(
+        &mut self,
+        path: impl AsRef<Path>,
+        encryption: Option<FileEncryption>,
+    ) -> Result<(), HubError> {
+        let path = path.as_ref();
+        let entry = self
+            .wallets
+            .get_mut(path)
+            .ok_or_else(|| HubError::NotOpen(path.display().to_string()))?;
+        entry.lock.scope(|| match &encryption {
+            Some(encryption) => entry
+                .wallet
+                .write_file_encrypted(path, &encryption.password, encryption.kdf)
+                .map(|_| ()),
+            None => {
+                if let Some(old) = &entry.encryption {
+                    Wallet::remove_password(path, &old.password)?;
+                }
+                entry.wallet.write_file(path).map(|_| ())
+            }
+        })?;
+        entry.encryption = encryption;
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self, path: impl AsRef<Path>) -> bool {
+        self.wallets
+            .get(path.as_ref())
+            .map(|entry| entry.encryption.is_some())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HubError> {
+        let path = path.as_ref();
+        let entry = self.entry(path)?;
+        entry.lock.scope(|| {
+            entry
+                .wallet
+                .write_file_with(path, entry.encryption.as_ref())
+        })?;
+        Ok(())
+    }
+
+    pub fn save_all(&self) -> Result<(), HubError> {
+        for (path, entry) in &self.wallets {
+            entry.lock.scope(|| {
+                entry
+                    .wallet
+                    .write_file_with(path, entry.encryption.as_ref())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Saves and closes the wallet, releasing its file lock.
+    pub fn close(&mut self, path: impl AsRef<Path>) -> Result<Wallet, HubError> {
+        let path = path.as_ref();
+        self.save(path)?;
+        let entry = self.wallets.remove(path).expect("presence checked by save");
+        Ok(entry.wallet)
+    }
+
+    /// Saves and closes all wallets.
+    pub fn close_all(&mut self) -> Result<(), HubError> {
+        self.save_all()?;
+        self.wallets.clear();
+        #[cfg(feature = "electrum")]
+        self.connections.clear();
+        Ok(())
+    }
+
+    pub fn is_open(&self, path: impl AsRef<Path>) -> bool {
+        self.wallets.contains_key(path.as_ref())
+    }
+
+    pub fn wallet(&self, path: impl AsRef<Path>) -> Option<&Wallet> {
+        self.wallets.get(path.as_ref()).map(|entry| &entry.wallet)
+    }
+
+    pub fn wallet_mut(&mut self, path: impl AsRef<Path>) -> Option<&mut Wallet> {
+        self.wallets
+            .get_mut(path.as_ref())
+            .map(|entry| &mut entry.wallet)
+    }
+
+    pub fn len(&self) -> usize { self.wallets.len() }
+
+    pub fn is_empty(&self) -> bool { self.wallets.is_empty() }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> { self.wallets.keys().map(PathBuf::as_path) }
+
+    pub fn wallets(&self) -> impl Iterator<Item = (&Path, &Wallet)> {
+        self.wallets
+            .iter()
+            .map(|(path, entry)| (path.as_path(), &entry.wallet))
+    }
+
+    /// Total balance of all opened wallets for a given network, in satoshis.
+    pub fn total_balance(&self, network: PublicNetwork) -> u64 {
+        self.wallets
+            .values()
+            .map(|entry| &entry.wallet)
+            .filter(|wallet| wallet.as_settings().network() == network)
+            .map(|wallet| wallet.state().balance)
+            .sum()
+    }
+
+    /// Sets address of the Tor SOCKS5 proxy (like `127.0.0.1:9050`) used to connect to the Tor
+    /// Electrum servers, dropping the existing connections to them.
+    #[cfg(feature = "electrum")]
+    pub fn set_tor_proxy(&mut self, proxy: Option<String>) {
+        self.connections
+            .retain(|server, _| server.sec != ElectrumSec::Tor);
+        self.tor_proxy = proxy;
+    }
+
+    /// Returns Electrum client for the server used by the wallet, reusing connection if some
+    /// other wallet uses the same server. Tor servers are connected through the proxy set with
+    /// [`WalletHub::set_tor_proxy`].
+    #[cfg(feature = "electrum")]
+    pub fn electrum(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Arc<electrum_client::Client>, HubError> {
+        let server = self
+            .entry(path.as_ref())?
+            .wallet
+            .as_settings()
+            .electrum()
+            .clone();
+        if let Some(client) = self.connections.get(&server) {
+            return Ok(client.clone());
+        }
+        let electrum_err =
+            |err: electrum_client::Error| HubError::Electrum(server.clone(), err.to_string());
+        let (url, socks5) = match server.sec {
+            ElectrumSec::Tls => (format!("ssl://{}:{}", server.server, server.port), None),
+            ElectrumSec::Tor => {
+                let proxy = self
+                    .tor_proxy
+                    .as_ref()
+                    .ok_or_else(|| HubError::NoTorProxy(server.clone()))?;
+                (
+                    format!("tcp://{}:{}", server.server, server.port),
+                    Some(electrum_client::Socks5Config::new(proxy)),
+                )
+            }
+            ElectrumSec::None => (format!("tcp://{}:{}", server.server, server.port), None),
+        };
+        let config = electrum_client::ConfigBuilder::new().socks5(socks5).build();
+        let client = electrum_client::Client::from_config(&url, config)
+            .map(Arc::new)
+            .map_err(electrum_err)?;
+        self.connections.insert(server, client.clone());
+        Ok(client)
+    }
+
+    /// Drops Electrum connection to the server, for instance after a connection failure, such
+    /// that the next request will reconnect.
+    #[cfg(feature = "electrum")]
+    pub fn disconnect(&mut self, server: &ElectrumServer) -> bool {
+        self.connections.remove(server).is_some()
+    }
+}
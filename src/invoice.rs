@@ -0,0 +1,190 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Expected payments (invoices) to the wallet receive addresses.
+
+use std::collections::BTreeMap;
+
+use amplify::Wrapper;
+use bitcoin::{Address, OutPoint};
+use bitcoin_scripts::address::AddressCompat;
+use chrono::{DateTime, Utc};
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+use wallet::onchain::PublicNetwork;
+
+use crate::{OnchainStatus, PaymentUri, Wallet};
+
+/// Status of an expected payment.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum InvoiceStatus {
+    /// No payment received yet.
+    #[display("pending")]
+    Pending,
+
+    /// Received payments are below the expected amount.
+    #[display("underpaid")]
+    Underpaid,
+
+    /// Expected amount is received, but some of the payments are not yet mined.
+    #[display("unconfirmed")]
+    Unconfirmed,
+
+    /// Expected amount is received and mined.
+    #[display("paid")]
+    Paid,
+
+    /// No payment was received before the expiry.
+    #[display("expired")]
+    Expired,
+}
+
+/// Payment expected to a dedicated wallet receive address.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct Invoice {
+    /// Receive address index.
+    pub index: UnhardenedIndex,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<::serde_with::DisplayFromStr>")
+    )]
+    pub address: AddressCompat,
+    /// Expected amount, in sats.
+    pub amount: u64,
+    pub memo: String,
+    pub created: DateTime<Utc>,
+    pub expiry: Option<DateTime<Utc>>,
+    /// Outputs paying to the invoice address, with their values and mining status.
+    pub payments: BTreeMap<OutPoint, (u64, OnchainStatus)>,
+    pub status: InvoiceStatus,
+}
+
+impl Invoice {
+    /// Total amount received to the invoice address, in sats.
+    pub fn received(&self) -> u64 { self.payments.values().map(|(value, _)| value).sum() }
+
+    /// Amount left to be paid, in sats.
+    pub fn due(&self) -> u64 { self.amount.saturating_sub(self.received()) }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expiry.map(|expiry| expiry <= now).unwrap_or_default()
+    }
+
+    /// Payment request for the invoice.
+    pub fn to_uri(&self, network: PublicNetwork) -> PaymentUri {
+        let address = Address::from_script(self.address.script_pubkey().as_inner(), network.into())
+            .expect("invoice address script");
+        let mut uri = PaymentUri::with(address);
+        uri.amount = Some(self.amount);
+        if !self.memo.is_empty() {
+            uri.message = Some(self.memo.clone());
+        }
+        uri
+    }
+
+    fn status_at(&self, now: DateTime<Utc>) -> InvoiceStatus {
+        let received = self.received();
+        if received >= self.amount {
+            let mined = self.payments.values().all(|(_, status)| status.is_mined());
+            if mined {
+                InvoiceStatus::Paid
+            } else {
+                InvoiceStatus::Unconfirmed
+            }
+        } else if received > 0 {
+            InvoiceStatus::Underpaid
+        } else if self.is_expired(now) {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Pending
+        }
+    }
+}
+
+impl Wallet {
+    /// Issues invoice for a fresh receive address, returning its identifier.
+    pub fn issue_invoice(
+        &mut self,
+        amount: u64,
+        memo: impl ToString,
+        expiry: Option<DateTime<Utc>>,
+    ) -> u32 {
+        let (index, address) = self.fresh_address();
+        let network = bitcoin::Network::from(self.as_settings().network());
+        let invoice = Invoice {
+            index,
+            address: AddressCompat::from_script(&address.script_pubkey().into(), network.into())
+                .expect("wallet address script"),
+            amount,
+            memo: memo.to_string(),
+            created: Utc::now(),
+            expiry,
+            payments: empty!(),
+            status: InvoiceStatus::Pending,
+        };
+        let invoices = self.invoices_mut();
+        let id = invoices.keys().last().map(|id| id + 1).unwrap_or_default();
+        invoices.insert(id, invoice);
+        id
+    }
+
+    pub fn remove_invoice(&mut self, id: u32) -> Option<Invoice> { self.invoices_mut().remove(&id) }
+
+    /// Invoices with the given status.
+    pub fn invoices_by_status(
+        &self,
+        status: InvoiceStatus,
+    ) -> impl Iterator<Item = (u32, &Invoice)> {
+        self.invoices()
+            .iter()
+            .filter(move |(_, invoice)| invoice.status == status)
+            .map(|(id, invoice)| (*id, invoice))
+    }
+
+    /// Matches wallet transactions against the invoices and updates their status. Called after
+    /// each wallet sync.
+    pub(crate) fn refresh_invoices(&mut self) {
+        let now = Utc::now();
+        let payments = self
+            .history()
+            .iter()
+            .flat_map(|entry| {
+                entry.debit.iter().map(|(vout, addr_src)| {
+                    let outpoint = OutPoint::new(entry.onchain.txid, *vout);
+                    let value = entry.tx.output[*vout as usize].value;
+                    (
+                        addr_src.index,
+                        addr_src.change,
+                        outpoint,
+                        value,
+                        entry.onchain.status,
+                    )
+                })
+            })
+            .filter(|(_, change, ..)| *change == UnhardenedIndex::zero())
+            .collect::<Vec<_>>();
+        for invoice in self.invoices_mut().values_mut() {
+            invoice.payments = payments
+                .iter()
+                .filter(|(index, ..)| *index == invoice.index)
+                .map(|(_, _, outpoint, value, status)| (*outpoint, (*value, *status)))
+                .collect();
+            invoice.status = invoice.status_at(now);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Labels of the wallet addresses and coins and their export in BIP-329 format.
+
+use std::io::{self, Write};
+
+use bitcoin::{Address, Network, OutPoint};
+use chrono::{DateTime, Utc};
+
+use crate::export::json_string;
+use crate::{Comment, Wallet};
+
+/// Label and memo assigned by the user to a wallet coin.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct CoinLabel {
+    /// Short label, like the source of the funds, used for filtering coins.
+    pub label: String,
+    /// Free-form notes.
+    pub memo: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Wallet {
+    pub fn address_label(&self, address: &Address) -> Option<&Comment> {
+        self.address_labels().get(&address.script_pubkey())
+    }
+
+    /// Assigns label to the address, returning the previous one. Empty label removes it.
+    pub fn set_address_label(&mut self, address: &Address, label: String) -> Option<Comment> {
+        let script = address.script_pubkey();
+        if label.is_empty() {
+            return self.address_labels_mut().remove(&script);
+        }
+        self.address_labels_mut().insert(script, Comment {
+            label,
+            timestamp: Utc::now(),
+        })
+    }
+
+    /// Addresses whose labels contain the query, ignoring case.
+    pub fn search_address_labels(&self, query: &str) -> Vec<(Address, &Comment)> {
+        let query = query.to_lowercase();
+        let network = Network::from(self.as_settings().network());
+        self.address_labels()
+            .iter()
+            .filter(|(_, comment)| comment.label.to_lowercase().contains(&query))
+            .filter_map(|(script, comment)| {
+                Some((Address::from_script(script, network).ok()?, comment))
+            })
+            .collect()
+    }
+
+    /// Label and memo assigned to the coin, not including labels inherited from the transaction.
+    pub fn coin_note(&self, outpoint: OutPoint) -> Option<&CoinLabel> {
+        self.coin_labels().get(&outpoint)
+    }
+
+    /// Label of the coin: the one assigned to it or, if none, inherited from the comment or
+    /// payer names of the transaction which created it.
+    pub fn coin_label(&self, outpoint: OutPoint) -> Option<String> {
+        if let Some(note) = self.coin_labels().get(&outpoint) {
+            if !note.label.is_empty() {
+                return Some(note.label.clone());
+            }
+        }
+        let entry = self
+            .history()
+            .iter()
+            .find(|entry| entry.onchain.txid == outpoint.txid)?;
+        entry
+            .comment
+            .as_ref()
+            .map(|comment| comment.label.clone())
+            .or_else(|| entry.payers.values().find_map(|(name, _)| name.clone()))
+            .filter(|label| !label.is_empty())
+    }
+
+    /// Assigns label and memo to the coin, returning the previous ones. Empty label without a
+    /// memo removes them, such that the coin inherits the transaction label again.
+    pub fn set_coin_label(
+        &mut self,
+        outpoint: OutPoint,
+        label: String,
+        memo: Option<String>,
+    ) -> Option<CoinLabel> {
+        let memo = memo.filter(|memo| !memo.is_empty());
+        if label.is_empty() && memo.is_none() {
+            return self.coin_labels_mut().remove(&outpoint);
+        }
+        self.coin_labels_mut().insert(outpoint, CoinLabel {
+            label,
+            memo,
+            timestamp: Utc::now(),
+        })
+    }
+
+    /// Whether the coin label, own or inherited, matches the given one ignoring case.
+    pub fn is_coin_labeled(&self, outpoint: OutPoint, label: &str) -> bool {
+        self.coin_label(outpoint)
+            .map(|coin_label| coin_label.to_lowercase() == label.to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Writes transaction, address and coin labels as BIP-329 JSON lines.
+    pub fn export_bip329(&self, mut writer: impl Write) -> io::Result<()> {
+        let network = Network::from(self.as_settings().network());
+        for entry in self.history() {
+            if let Some(comment) = &entry.comment {
+                writeln!(
+                    writer,
+                    r#"{{"type": "tx", "ref": "{}", "label": {}}}"#,
+                    entry.onchain.txid,
+                    json_string(&comment.label)
+                )?;
+            }
+        }
+        for (script, comment) in self.address_labels() {
+            if let Ok(address) = Address::from_script(script, network) {
+                writeln!(
+                    writer,
+                    r#"{{"type": "addr", "ref": "{}", "label": {}}}"#,
+                    address,
+                    json_string(&comment.label)
+                )?;
+            }
+        }
+        for (outpoint, note) in self.coin_labels() {
+            if !note.label.is_empty() {
+                writeln!(
+                    writer,
+                    r#"{{"type": "output", "ref": "{}", "label": {}}}"#,
+                    outpoint,
+                    json_string(&note.label)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -20,31 +20,126 @@ extern crate bitcoin_hwi as hwi;
 #[cfg(feature = "serde")]
 extern crate serde_with;
 
+mod addrindex;
+pub mod airgap;
+mod analytics;
+mod audit;
+mod classify;
+mod cluster;
+mod compiler;
+mod cosigner;
+mod counterparty;
+mod denomination;
+mod dust;
 mod electrum;
+mod explorer;
+mod export;
+mod fees;
 pub mod file;
+mod frost;
+mod graph;
+mod hub;
+mod invoice;
+mod labels;
+mod migration;
 mod onchain;
+mod ordering;
+mod payjoin;
+mod payment;
+mod policy;
+mod privacy;
 pub mod psbt;
+mod rates;
+mod recipient;
 mod sign;
+mod signet;
+mod silent;
+pub mod sweep;
+mod tapret;
+mod tapspend;
 mod taptree;
+mod tax;
 mod template;
+mod timeline;
 mod types;
+mod uri;
+mod vault;
 mod wallet;
 
+pub use addrindex::AddressIndex;
+pub use analytics::{CoinAgeReport, SpentAnalytics, UtxoAnalytics, BLOCKS_PER_DAY};
+pub use audit::{AuditError, AuditEvent, AuditFilter, AuditLog, AuditRecord};
+pub use classify::{ClassifiedInput, ClassifiedOutput, TxClassification};
+pub use cluster::CoinCluster;
+pub use compiler::{lift_conditions, ConditionCompiler, LiftError, MultisigOrder, ScriptLayout};
+pub use cosigner::{
+    CosignerEnrollment, CosignerError, CosigningService, CosigningSession, EnrollmentRequest,
+};
+pub use denomination::{AmountError, AmountFormat, Denomination};
+pub use dust::{DustError, DustPolicy};
 pub use electrum::{ElectrumPreset, ElectrumSec, ElectrumServer};
-pub use file::FileDocument;
+pub use explorer::{ExplorerLinks, ExplorerTemplate};
+pub use export::HistoryColumn;
+pub use file::{FileDocument, FileLock, FileStore, WalletStore};
+pub use frost::{
+    FrostDkg, FrostDkgCommitment, FrostDkgShare, FrostError, FrostGroup, FrostKeyShare,
+    FrostNonceCommitment, FrostSecNonce, FrostSignatureShare, FrostSigningPackage,
+};
+pub use graph::{TxEdge, TxGraph};
+pub use hub::{HubError, WalletHub};
+pub use invoice::{Invoice, InvoiceStatus};
+pub use labels::CoinLabel;
+pub use migration::{
+    BatchStatus, MigrationBatch, MigrationError, MigrationPlan, MAX_STANDARD_TX_WEIGHT,
+};
 pub use onchain::{
-    AddressSource, AddressSummary, AddressValue, HistoryEntry, OnchainStatus, OnchainTxid, Prevout,
-    TxidMeta, UtxoTxid,
+    AddressDetails, AddressKind, AddressSource, AddressSummary, AddressValue, Comment,
+    CursorDirection, HistoryCursor, HistoryEntry, HistoryPage, OnchainStatus, OnchainTxid, Prevout,
+    TxDirection, TxFilter, TxOrder, TxPage, TxidMeta, UtxoTxid,
+};
+pub use ordering::TxOrdering;
+pub use payjoin::{PayjoinError, PayjoinParams, PayjoinReceiver, PayjoinSender, PayjoinTransport};
+pub use payment::{PaymentDraft, PaymentTemplate, Schedule, SchedulePeriod};
+pub use policy::{OverrideToken, PolicyViolation, SpendingPolicy};
+pub use privacy::{PrivacyIssue, PrivacyReport, PrivacySeverity, ScriptType, ROUND_AMOUNT_UNIT};
+#[cfg(feature = "serde")]
+pub use rates::{BlockchainInfo, CoinGecko};
+pub use rates::{
+    ExchangeRate, HistoricalRateProvider, HttpGet, PriceHistory, RateError, RateProvider,
+};
+pub use recipient::{RecipientCheck, RecipientError, RecipientWarning};
+pub use sign::{
+    MuSigError, MuSigKeyAgg, MuSigPartialSig, MuSigPubNonce, MuSigRound, MuSigSecNonce,
+    MuSigSession, XprivSigner,
+};
+pub use signet::{CustomSignet, SignetError};
+pub use silent::{
+    silent_payment_outputs, SilentPaymentAddress, SilentPaymentError, SilentPaymentKeys,
+    SilentPaymentOutput, SILENT_PAYMENT_BRANCH,
+};
+pub use sweep::{SweepError, SweepKey, SweepScript, SweepUtxo};
+pub use tapret::{TapretError, TapretTweak};
+pub use tapspend::{
+    fill_tap_output, fill_tap_scripts, plan_tap_spend, tap_key_origins, TapFinalize, TapSpendError,
+    TapSpendPath, TapSpendPlan,
+};
+pub use taptree::{tap_tree_builder, RawTapLeaf, TapLeaf, TapLeafError, ToTapTree};
+pub use tax::{CostBasisMethod, Disposal, TaxError, TaxFormat, TaxLot, TaxReport};
+pub use template::{
+    Requirement, RoleCondition, RoleError, RoleSigs, RoleTemplate, TemplateCatalog, TemplateError,
+    TemplateViolation, WalletTemplate, WalletTemplateBuilder, MAX_ROLE_COMBINATIONS,
 };
-pub use sign::XprivSigner;
-pub use taptree::ToTapTree;
-pub use template::{Requirement, WalletTemplate};
+pub use timeline::{BalancePoint, TimelineResolution};
 pub use types::{
-    Error, HardwareDevice, HardwareList, OriginFormat, Ownership, Signer, SigsReq,
-    TimelockDuration, TimelockReq, TimelockedSigs,
+    DegradingSigs, Error, HardwareDevice, HardwareList, KeyOriginError, NamedSigsReq, OriginFormat,
+    OriginParseError, Ownership, ScriptTimelock, Signer, SignerRef, SigsReq, TimelockDuration,
+    TimelockError, TimelockReq, TimelockedSigs,
 };
+pub use uri::{PaymentUri, UriError};
+pub use vault::{Vault, VaultAlert, VaultError, VaultState};
 
 pub use self::wallet::{
-    DerivationStandardExt, DerivationType, DescriptorError, SpendingCondition, Wallet,
-    WalletDescriptor, WalletEphemerals, WalletSettings, WalletState,
+    Balance, ConditionBalance, DerivationStandardExt, DerivationType, DescriptorError,
+    SpendingCondition, Wallet, WalletDescriptor, WalletEphemerals, WalletMeta, WalletSettings,
+    WalletState, ADDRESS_GAP_LIMIT, COINBASE_MATURITY,
 };
@@ -0,0 +1,597 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Migration of the wallet funds to a successor wallet descriptor, used when a signer gets
+//! replaced or the wallet descriptor is upgraded.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+
+use amplify::Wrapper;
+use bitcoin::psbt::{self, PartiallySignedTransaction};
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, KeySource};
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{
+    OutPoint, PackedLockTime, PublicKey, Script, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    XOnlyPublicKey,
+};
+use chrono::{DateTime, Utc};
+use miniscript::descriptor::ShInner;
+use miniscript::{Descriptor, ToPublicKey};
+use wallet::descriptors::DescriptorClass;
+use wallet::hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use wallet::onchain::PublicNetwork;
+
+use crate::sweep::DUST_LIMIT;
+use crate::tapspend::{fill_tap_output, fill_tap_scripts, tap_key_origins};
+use crate::{AuditEvent, MuSigKeyAgg, OnchainStatus, Signer, UtxoTxid, Wallet, WalletSettings};
+
+/// Weight of the transaction fields not depending on inputs and outputs, including segwit
+/// marker and flag.
+pub(crate) const TX_OVERHEAD_WEIGHT: usize = (4 + 4 + 1 + 1) * 4 + 2;
+
+/// Weight of the transaction input fields not depending on the input satisfaction.
+pub(crate) const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4) * 4;
+
+/// Maximal weight of a transaction relayed by the network nodes.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MigrationError {
+    /// Successor wallet operates on {1} network, while the wallet uses {0}.
+    NetworkMismatch(PublicNetwork, PublicNetwork),
+
+    /// Successor wallet descriptor is the same as the current one.
+    SameDescriptor,
+
+    /// Wallet already has a migration in progress.
+    InProgress,
+
+    /// Wallet has no migration in progress.
+    NoMigration,
+
+    /// Wallet has no spendable funds to migrate.
+    NoFunds,
+
+    /// Wallet funds ({0} sats) are insufficient to pay migration fees ({1} sats).
+    InsufficientFunds(u64, u64),
+
+    /// Migration plan has no batch number {0}.
+    UnknownBatch(usize),
+
+    /// Output {0} spent by the migration plan is not among the wallet UTXOs.
+    UtxoSpent(OutPoint),
+
+    /// Transaction {0} spent by the migration plan is not known to the wallet.
+    UnknownTx(Txid),
+
+    /// Unable to construct wallet descriptor. Details: {0}
+    Descriptor(String),
+}
+
+impl From<miniscript::Error> for MigrationError {
+    fn from(err: miniscript::Error) -> Self { MigrationError::Descriptor(err.to_string()) }
+}
+
+/// Progress of a single migration transaction.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "lowercase")
+)]
+pub enum BatchStatus {
+    #[display("planned")]
+    Planned,
+
+    #[display("broadcast as {0}")]
+    Broadcast(Txid),
+
+    #[display("confirmed as {0}")]
+    Confirmed(Txid),
+}
+
+/// Single migration transaction sweeping a batch of UTXOs into the successor wallet.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct MigrationBatch {
+    pub inputs: BTreeSet<OutPoint>,
+    /// Total amount of the batch inputs.
+    pub amount: u64,
+    pub fee: u64,
+    /// Index of the successor wallet receiving address.
+    pub index: UnhardenedIndex,
+    pub status: BatchStatus,
+}
+
+impl MigrationBatch {
+    /// Amount received by the successor wallet.
+    pub fn migrated(&self) -> u64 { self.amount - self.fee }
+}
+
+/// Plan for moving all wallet funds to a successor wallet descriptor, persisted in the wallet
+/// file to track the migration progress.
+#[derive(Getters, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct MigrationPlan {
+    successor: WalletSettings,
+    #[getter(as_copy)]
+    created_at: DateTime<Utc>,
+    batches: Vec<MigrationBatch>,
+}
+
+impl MigrationPlan {
+    /// Detects whether all migration transactions were mined.
+    pub fn is_complete(&self) -> bool {
+        self.batches
+            .iter()
+            .all(|batch| matches!(batch.status, BatchStatus::Confirmed(_)))
+    }
+
+    /// Batches which still have to be signed and broadcast.
+    pub fn pending(&self) -> impl Iterator<Item = (usize, &MigrationBatch)> {
+        self.batches
+            .iter()
+            .enumerate()
+            .filter(|(_, batch)| batch.status == BatchStatus::Planned)
+    }
+
+    /// Amount already moved to the successor wallet with confirmed transactions.
+    pub fn migrated_amount(&self) -> u64 {
+        self.batches
+            .iter()
+            .filter(|batch| matches!(batch.status, BatchStatus::Confirmed(_)))
+            .map(MigrationBatch::migrated)
+            .sum()
+    }
+
+    /// Total amount of the fees paid by the migration transactions.
+    pub fn total_fee(&self) -> u64 { self.batches.iter().map(|batch| batch.fee).sum() }
+
+    /// Constructs successor wallet, which should be saved as a new wallet file.
+    pub fn to_successor_wallet(&self) -> Wallet { Wallet::from(self.successor.clone()) }
+}
+
+impl Wallet {
+    /// Plans migration of all spendable wallet funds to the successor wallet, splitting them
+    /// into batches of at most `max_inputs` UTXOs (further limited by the transaction
+    /// standardness rules). Each batch pays to a fresh successor wallet
+    /// address with a fee computed from the given fee rate (in sats per vbyte).
+    ///
+    /// The plan gets stored in the wallet; frozen and immature UTXOs are not migrated, as well
+    /// as UTXOs which can't pay for their own inclusion into the batch.
+    pub fn plan_migration(
+        &mut self,
+        successor: WalletSettings,
+        max_inputs: usize,
+        fee_rate: f32,
+    ) -> Result<&MigrationPlan, MigrationError> {
+        if self.migration().is_some() {
+            return Err(MigrationError::InProgress);
+        }
+        let settings = self.as_settings();
+        if successor.network() != settings.network() {
+            return Err(MigrationError::NetworkMismatch(
+                settings.network(),
+                successor.network(),
+            ));
+        }
+        if successor.core() == settings.core() {
+            return Err(MigrationError::SameDescriptor);
+        }
+
+        let (descriptor, others) = settings.descriptors_all()?;
+        let (successor_descriptor, _) = successor.descriptors_all()?;
+        let input_weight = TXIN_BASE_WEIGHT
+            + iter::once(descriptor)
+                .chain(others)
+                .map(|descriptor| descriptor.max_satisfaction_weight())
+                .try_fold(0usize, |max, weight| weight.map(|weight| max.max(weight)))?;
+        let output_weight = successor_output_weight(&successor, &successor_descriptor)?;
+
+        let mut utxos = self
+            .utxos()
+            .iter()
+            .filter(|utxo| !self.is_frozen(utxo.outpoint()) && !self.is_immature(utxo))
+            .collect::<Vec<_>>();
+        if utxos.is_empty() {
+            return Err(MigrationError::NoFunds);
+        }
+        utxos.sort_by_key(|utxo| Reverse(utxo.value));
+
+        let max_inputs = max_inputs
+            .min((MAX_STANDARD_TX_WEIGHT - TX_OVERHEAD_WEIGHT - output_weight) / input_weight)
+            .max(1);
+        let mut batches = vec![];
+        let mut index = UnhardenedIndex::zero();
+        for chunk in utxos.chunks(max_inputs) {
+            let weight = TX_OVERHEAD_WEIGHT + input_weight * chunk.len() + output_weight;
+            let fee = (weight as f32 / 4.0 * fee_rate).ceil() as u64;
+            let amount = chunk.iter().map(|utxo| utxo.value).sum::<u64>();
+            if amount < fee + DUST_LIMIT {
+                continue;
+            }
+            batches.push(MigrationBatch {
+                inputs: chunk.iter().map(|utxo| utxo.outpoint()).collect(),
+                amount,
+                fee,
+                index,
+                status: BatchStatus::Planned,
+            });
+            index = index.checked_inc().unwrap_or(index);
+        }
+        if batches.is_empty() {
+            let amount = utxos.iter().map(|utxo| utxo.value).sum();
+            let weight = TX_OVERHEAD_WEIGHT + input_weight + output_weight;
+            let fee = (weight as f32 / 4.0 * fee_rate).ceil() as u64;
+            return Err(MigrationError::InsufficientFunds(amount, fee + DUST_LIMIT));
+        }
+
+        self.record_audit(AuditEvent::MigrationPlanned(successor.core().id()));
+        self.set_migration(Some(MigrationPlan {
+            successor,
+            created_at: Utc::now(),
+            batches,
+        }));
+        Ok(self
+            .migration()
+            .as_ref()
+            .expect("migration plan is just set"))
+    }
+
+    /// Plans upgrade of the wallet to a taproot descriptor with the same signers and spending
+    /// conditions, sweeping the funds with as few transactions as possible.
+    ///
+    /// The wallet itself stays unchanged and must be kept watched, together with the successor
+    /// wallet, until [`Wallet::is_drained`] reports that all funds were moved.
+    pub fn plan_taproot_upgrade(
+        &mut self,
+        fee_rate: f32,
+    ) -> Result<&MigrationPlan, MigrationError> {
+        let successor = self
+            .as_settings()
+            .with_descriptor_class(DescriptorClass::TaprootC0);
+        self.plan_migration(successor, usize::MAX, fee_rate)
+    }
+
+    /// Detects whether the wallet migration is complete and no funds are left under the wallet
+    /// descriptor, such that the wallet no longer needs to be watched.
+    pub fn is_drained(&self) -> bool {
+        self.migration()
+            .as_ref()
+            .map(MigrationPlan::is_complete)
+            .unwrap_or_default()
+            && self.utxos().is_empty()
+    }
+
+    /// Constructs unsigned PSBTs for all pending migration batches, returning them together with
+    /// the batch numbers.
+    pub fn migration_psbts(
+        &self,
+    ) -> Result<Vec<(usize, PartiallySignedTransaction)>, MigrationError> {
+        let plan = self
+            .migration()
+            .as_ref()
+            .ok_or(MigrationError::NoMigration)?;
+        let (successor_descriptor, _) = plan.successor.descriptors_all()?;
+
+        plan.pending()
+            .map(|(no, batch)| {
+                let utxos = batch
+                    .inputs
+                    .iter()
+                    .map(|outpoint| {
+                        self.utxos()
+                            .iter()
+                            .find(|utxo| utxo.outpoint() == *outpoint)
+                            .ok_or(MigrationError::UtxoSpent(*outpoint))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let dest = derive(
+                    &plan.successor,
+                    &successor_descriptor,
+                    UnhardenedIndex::zero(),
+                    batch.index,
+                )?;
+                let tx = Transaction {
+                    version: 2,
+                    lock_time: PackedLockTime::ZERO,
+                    input: utxos
+                        .iter()
+                        .map(|utxo| TxIn {
+                            previous_output: utxo.outpoint(),
+                            script_sig: Script::new(),
+                            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                            witness: Witness::new(),
+                        })
+                        .collect(),
+                    output: vec![TxOut {
+                        value: batch.migrated(),
+                        script_pubkey: dest.script_pubkey(),
+                    }],
+                };
+                let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+                    .expect("transaction without signatures");
+
+                for (input, utxo) in psbt.inputs.iter_mut().zip(&utxos) {
+                    self.fill_wallet_input(input, utxo)?;
+                }
+                self.tx_ordering().apply(&mut psbt, &[]);
+                fill_wallet_output(
+                    &mut psbt.outputs[0],
+                    &dest,
+                    &plan.successor,
+                    UnhardenedIndex::zero(),
+                    batch.index,
+                );
+
+                Ok((no, psbt))
+            })
+            .collect()
+    }
+
+    /// Registers broadcast of the migration batch transaction.
+    pub fn mark_migration_broadcast(
+        &mut self,
+        batch: usize,
+        txid: Txid,
+    ) -> Result<(), MigrationError> {
+        let plan = self.migration_mut().ok_or(MigrationError::NoMigration)?;
+        let batch = plan
+            .batches
+            .get_mut(batch)
+            .ok_or(MigrationError::UnknownBatch(batch))?;
+        batch.status = BatchStatus::Broadcast(txid);
+        Ok(())
+    }
+
+    /// Removes migration plan from the wallet, returning it.
+    pub fn cancel_migration(&mut self) -> Option<MigrationPlan> { self.set_migration(None) }
+
+    /// Updates migration progress from the wallet history: batches whose inputs were spent by a
+    /// known transaction are marked as broadcast, and mined transactions as confirmed.
+    pub(crate) fn refresh_migration(&mut self) {
+        let history = self
+            .history()
+            .iter()
+            .map(|entry| (entry.onchain.txid, (entry.onchain.status, &entry.tx)))
+            .collect::<BTreeMap<_, _>>();
+        let mut plan = match self.migration().clone() {
+            Some(plan) => plan,
+            None => return,
+        };
+        for batch in &mut plan.batches {
+            if batch.status == BatchStatus::Planned {
+                let spending = history.iter().find(|(_, (_, tx))| {
+                    tx.input
+                        .iter()
+                        .any(|txin| batch.inputs.contains(&txin.previous_output))
+                });
+                if let Some((txid, _)) = spending {
+                    batch.status = BatchStatus::Broadcast(*txid);
+                }
+            }
+            if let BatchStatus::Broadcast(txid) = batch.status {
+                if let Some((OnchainStatus::Blockchain(_), _)) = history.get(&txid) {
+                    batch.status = BatchStatus::Confirmed(txid);
+                }
+            }
+        }
+        self.set_migration(Some(plan));
+    }
+
+    /// Fills PSBT input spending wallet UTXO with the information required by the signers.
+    pub(crate) fn fill_wallet_input(
+        &self,
+        input: &mut psbt::Input,
+        utxo: &UtxoTxid,
+    ) -> Result<(), MigrationError> {
+        let (change, index) = (utxo.addr_src.change, utxo.addr_src.index);
+        let tapret = self.tapret_tweak(change, index).and_then(|tweak| {
+            let tr = self.derive_tr(change, index).ok()?;
+            let spend_info = tweak.spend_info(&tr).ok()?;
+            (Script::new_v1_p2tr_tweaked(spend_info.output_key())
+                == *utxo.addr_src.address.script_pubkey().as_inner())
+            .then_some((tr, spend_info))
+        });
+        let derived = match tapret {
+            Some((ref tr, _)) => Descriptor::Tr(tr.clone()),
+            None => {
+                let descriptor = self
+                    .as_settings()
+                    .descriptor_for_script(
+                        change,
+                        index,
+                        utxo.addr_src.address.script_pubkey().as_inner(),
+                    )
+                    .ok_or_else(|| {
+                        MigrationError::Descriptor(s!("UTXO does not match wallet descriptors"))
+                    })?;
+                derive(self.as_settings(), &descriptor, change, index)?
+            }
+        };
+        let prev_tx = self
+            .history()
+            .iter()
+            .find(|entry| entry.onchain.txid == utxo.onchain.txid)
+            .map(|entry| entry.tx.clone());
+        let origins = key_origins(
+            self.as_settings().signers(),
+            utxo.addr_src.change,
+            utxo.addr_src.index,
+        );
+
+        input.witness_utxo = Some(TxOut {
+            value: utxo.value,
+            script_pubkey: utxo.addr_src.address.script_pubkey().into_inner(),
+        });
+        if let Descriptor::Tr(tr) = &derived {
+            input.tap_internal_key = Some(tr.internal_key().to_x_only_pubkey());
+            // Tapret-tweaked outputs commit to the extended tree, so the key-path signer must
+            // tweak with its merkle root
+            let spend_info = match &tapret {
+                Some((_, spend_info)) => spend_info.clone(),
+                None => (*tr.spend_info()).clone(),
+            };
+            input.tap_merkle_root = spend_info.merkle_root();
+            fill_tap_scripts(input, tr, &spend_info);
+            input.tap_key_origins = tap_key_origins(tr, origins);
+            add_musig_origins(
+                &mut input.tap_key_origins,
+                self.as_settings(),
+                change,
+                index,
+            );
+            return Ok(());
+        }
+
+        match (prev_tx, derived.desc_type().segwit_version()) {
+            (Some(tx), _) => input.non_witness_utxo = Some(tx),
+            (None, None) => return Err(MigrationError::UnknownTx(utxo.onchain.txid)),
+            (None, Some(_)) => {}
+        }
+        if derived.desc_type().segwit_version().is_none() {
+            input.witness_utxo = None;
+        }
+        input.bip32_derivation = origins
+            .into_iter()
+            .map(|(pk, source)| (pk.inner, source))
+            .collect();
+        let (redeem_script, witness_script) = scripts(&derived);
+        input.redeem_script = redeem_script;
+        input.witness_script = witness_script;
+        Ok(())
+    }
+}
+
+pub(crate) fn derive(
+    settings: &WalletSettings,
+    descriptor: &Descriptor<DerivationAccount>,
+    change: UnhardenedIndex,
+    index: UnhardenedIndex,
+) -> Result<Descriptor<PublicKey>, MigrationError> {
+    settings
+        .derive(descriptor, change, index)
+        .map_err(|_| MigrationError::Descriptor(s!("unable to derive descriptor")))
+}
+
+fn successor_output_weight(
+    successor: &WalletSettings,
+    descriptor: &Descriptor<DerivationAccount>,
+) -> Result<usize, MigrationError> {
+    let script_len = derive(
+        successor,
+        descriptor,
+        UnhardenedIndex::zero(),
+        UnhardenedIndex::zero(),
+    )?
+    .script_pubkey()
+    .len();
+    Ok((8 + 1 + script_len) * 4)
+}
+
+/// Fills PSBT output paying to a derived wallet descriptor with the scripts and key origins.
+pub(crate) fn fill_wallet_output(
+    output: &mut psbt::Output,
+    descriptor: &Descriptor<PublicKey>,
+    settings: &WalletSettings,
+    change: UnhardenedIndex,
+    index: UnhardenedIndex,
+) {
+    let origins = key_origins(settings.signers(), change, index);
+    match descriptor {
+        Descriptor::Tr(tr) => {
+            fill_tap_output(output, tr, origins);
+            add_musig_origins(&mut output.tap_key_origins, settings, change, index);
+        }
+        _ => {
+            output.bip32_derivation = origins
+                .into_iter()
+                .map(|(pk, source)| (pk.inner, source))
+                .collect();
+            let (redeem_script, witness_script) = scripts(descriptor);
+            output.redeem_script = redeem_script;
+            output.witness_script = witness_script;
+        }
+    }
+}
+
+/// Adds origins of the keys of MuSig2 participants, which are aggregated into the taproot
+/// internal key and are not present in the descriptor, such that the signers can recognize
+/// their keys when producing partial signatures.
+pub(crate) fn add_musig_origins(
+    origins: &mut BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    settings: &WalletSettings,
+    change: UnhardenedIndex,
+    index: UnhardenedIndex,
+) {
+    let keys = match settings
+        .musig_signers()
+        .and_then(|signers| MuSigKeyAgg::derive(&signers, change, index, None).ok())
+    {
+        Some(key_agg) => key_agg.keys().to_vec(),
+        None => return,
+    };
+    for (pk, source) in key_origins(settings.signers(), change, index) {
+        if keys.contains(&pk.inner) {
+            origins
+                .entry(pk.to_x_only_pubkey())
+                .or_insert_with(|| (vec![], source));
+        }
+    }
+}
+
+/// Derives public keys of all signers for a given terminal, together with their origins.
+pub(crate) fn key_origins(
+    signers: &[Signer],
+    change: UnhardenedIndex,
+    index: UnhardenedIndex,
+) -> Vec<(PublicKey, KeySource)> {
+    let terminal = [ChildNumber::from(change), ChildNumber::from(index)];
+    signers
+        .iter()
+        .filter_map(|signer| {
+            let xpub = signer.xpub.derive_pub(SECP256K1, &terminal).ok()?;
+            let (fingerprint, origin) = if signer.is_master_known() {
+                (signer.master_fp, signer.origin.clone())
+            } else {
+                (signer.fingerprint(), DerivationPath::master())
+            };
+            Some((
+                PublicKey::new(xpub.public_key),
+                (fingerprint, origin.extend(terminal)),
+            ))
+        })
+        .collect()
+}
+
+/// Returns redeem and witness scripts required for spending P2SH and P2WSH outputs.
+fn scripts(descriptor: &Descriptor<PublicKey>) -> (Option<Script>, Option<Script>) {
+    match descriptor {
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wsh(wsh) => {
+                let witness_script = wsh.inner_script();
+                (Some(witness_script.to_v0_p2wsh()), Some(witness_script))
+            }
+            ShInner::Wpkh(wpkh) => (Some(wpkh.script_pubkey()), None),
+            _ => (Some(sh.inner_script()), None),
+        },
+        Descriptor::Wsh(wsh) => (None, Some(wsh.inner_script())),
+        _ => (None, None),
+    }
+}
@@ -9,6 +9,7 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
@@ -22,27 +23,89 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use electrum_client::{GetHistoryRes, ListUnspentRes};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub struct AddressSummary {
     pub addr_src: AddressSource,
+    /// Funds received on the address and not spent yet.
     pub balance: u64,
-    pub volume: u64,
+    /// Total amount received on the address.
+    pub received: u64,
+    /// Total amount spent from the address.
+    pub sent: u64,
     pub tx_count: u32,
+    /// Transaction which first used the address.
+    pub first_activity: Option<OnchainTxid>,
+    /// Transaction which last used the address (receiving or spending funds).
+    pub last_activity: Option<OnchainTxid>,
 }
 
 impl AddressSummary {
+    /// Total amount moved through the address.
+    #[deprecated(since = "0.6.0", note = "use `received` and `sent` fields instead")]
+    pub fn volume(self) -> u64 { self.received + self.sent }
+
     pub fn merge(&mut self, other: AddressSummary) {
-        self.balance += other.balance;
-        self.volume += other.volume;
-        self.tx_count += 1;
+        self.received += other.received;
+        self.sent += other.sent;
+        self.balance = self.received.saturating_sub(self.sent);
+        self.tx_count += other.tx_count;
+        self.first_activity = earliest(self.first_activity, other.first_activity);
+        self.last_activity = self.last_activity.max(other.last_activity);
+    }
+
+    /// Updates activity after the transaction got mined or reorged.
+    pub(crate) fn update_activity(&mut self, onchain: OnchainTxid) {
+        let txid = onchain.txid;
+        for activity in [&mut self.first_activity, &mut self.last_activity] {
+            if activity
+                .map(|activity| activity.txid == txid)
+                .unwrap_or_default()
+            {
+                *activity = Some(onchain);
+            }
+        }
+        self.first_activity = earliest(self.first_activity, Some(onchain));
+        self.last_activity = self.last_activity.max(Some(onchain));
+    }
+}
+
+fn earliest(a: Option<OnchainTxid>, b: Option<OnchainTxid>) -> Option<OnchainTxid> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
     }
 }
 
 impl AddressSummary {
+    pub fn kind(self) -> AddressKind { self.addr_src.kind() }
+
     pub fn icon_name(self) -> Option<&'static str> { self.addr_src.icon_name() }
 
     pub fn terminal_string(self) -> String { self.addr_src.terminal_string() }
 }
 
+/// Detects whether derivation terminal of a wallet address belongs to the change addresses.
+pub(crate) fn is_change_terminal(terminal: &[UnhardenedIndex]) -> bool {
+    terminal.first() == Some(&UnhardenedIndex::one())
+}
+
+/// Detailed information about use of a wallet address.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AddressDetails {
+    pub terminal: DerivationSubpath<UnhardenedIndex>,
+    pub is_change: bool,
+    /// Transaction which first used the address.
+    pub first_use: Option<OnchainTxid>,
+    /// Transaction which last used the address (receiving or spending funds).
+    pub last_use: Option<OnchainTxid>,
+    pub tx_count: u32,
+    pub total_received: u64,
+    pub balance: u64,
+    /// Label assigned to the address or, if none, name of the beneficiary it was given to.
+    pub label: Option<String>,
+}
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 #[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
@@ -56,6 +119,18 @@ pub struct AddressSource {
     pub index: UnhardenedIndex,
 }
 
+/// Purpose of the wallet address, defined by its derivation path.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum AddressKind {
+    /// Address given out for receiving payments.
+    #[display("receive")]
+    Receive,
+
+    /// Address receiving change of the wallet transactions.
+    #[display("change")]
+    Change,
+}
+
 impl AddressSource {
     /// # Panics
     ///
@@ -73,10 +148,18 @@ impl AddressSource {
         }
     }
 
-    pub fn icon_name(self) -> Option<&'static str> {
+    pub fn kind(self) -> AddressKind {
         match self.change.first_index() {
-            1 => Some("view-refresh-symbolic"),
-            _ => None,
+            1 => AddressKind::Change,
+            _ => AddressKind::Receive,
+        }
+    }
+
+    /// GTK symbolic icon name for the address kind.
+    pub fn icon_name(self) -> Option<&'static str> {
+        match self.kind() {
+            AddressKind::Change => Some("view-refresh-symbolic"),
+            AddressKind::Receive => None,
         }
     }
 
@@ -94,6 +177,8 @@ pub struct AddressValue {
 }
 
 impl AddressValue {
+    pub fn kind(self) -> AddressKind { self.addr_src.kind() }
+
     pub fn icon_name(self) -> Option<&'static str> { self.addr_src.icon_name() }
 
     pub fn terminal_string(self) -> String { self.addr_src.terminal_string() }
@@ -180,13 +265,7 @@ impl Ord for OnchainTxid {
 }
 
 impl PartialOrd for OnchainTxid {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.txid == other.txid && self.status != other.status {
-            None
-        } else {
-            Some(self.cmp(other))
-        }
-    }
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
 }
 
 impl OnchainTxid {
@@ -199,6 +278,14 @@ impl OnchainTxid {
         self.date_time.map(DateTime::<chrono::Local>::from)
     }
 
+    /// Number of confirmations at the given chain tip height; zero for mempool transactions.
+    pub fn confirmations(self, tip: u32) -> u32 {
+        match self.status {
+            OnchainStatus::Blockchain(height) if tip >= height => tip - height + 1,
+            _ => 0,
+        }
+    }
+
     pub fn mining_info(self) -> String {
         match self.status {
             OnchainStatus::Mempool => s!("pending"),
@@ -245,18 +332,141 @@ impl Ord for HistoryEntry {
 }
 
 impl PartialOrd for HistoryEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.onchain.partial_cmp(&other.onchain)
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum TxDirection {
+    /// Transaction increases wallet balance.
+    #[display("incoming")]
+    Incoming,
+
+    /// Transaction decreases wallet balance.
+    #[display("outgoing")]
+    Outgoing,
+
+    /// Transaction moves funds between wallet addresses, leaving balance unchanged.
+    #[display("internal")]
+    Internal,
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+pub enum TxOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    LargestFirst,
+    SmallestFirst,
+}
+
+/// Filter selecting wallet transactions. Unset criteria match all transactions.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct TxFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub till: Option<DateTime<Utc>>,
+    /// Minimal absolute change of wallet balance, in satoshis.
+    pub min_amount: Option<u64>,
+    /// Maximal absolute change of wallet balance, in satoshis.
+    pub max_amount: Option<u64>,
+    pub direction: Option<TxDirection>,
+    /// Case-insensitive substring of the transaction label.
+    pub label: Option<String>,
+    /// Whether the transaction must be mined (`Some(true)`) or in mempool (`Some(false)`).
+    pub confirmed: Option<bool>,
+}
+
+impl TxFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        let date_time = entry.date_time_est().with_timezone(&Utc);
+        let amount = entry.balance().unsigned_abs();
+        self.from.map(|from| date_time >= from).unwrap_or(true)
+            && self.till.map(|till| date_time <= till).unwrap_or(true)
+            && self.min_amount.map(|min| amount >= min).unwrap_or(true)
+            && self.max_amount.map(|max| amount <= max).unwrap_or(true)
+            && self
+                .direction
+                .map(|direction| entry.direction() == direction)
+                .unwrap_or(true)
+            && self
+                .confirmed
+                .map(|confirmed| entry.onchain.status.is_mined() == confirmed)
+                .unwrap_or(true)
+            && self
+                .label
+                .as_ref()
+                .map(|needle| {
+                    entry
+                        .comment
+                        .as_ref()
+                        .map(|comment| {
+                            comment
+                                .label
+                                .to_lowercase()
+                                .contains(&needle.to_lowercase())
+                        })
+                        .unwrap_or_default()
+                })
+                .unwrap_or(true)
     }
 }
 
+/// Page of the wallet transactions matching a filter.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TxPage<'wallet> {
+    pub entries: Vec<&'wallet HistoryEntry>,
+    /// Total number of transactions matching the filter.
+    pub total: usize,
+    pub offset: usize,
+}
+
+impl<'wallet> TxPage<'wallet> {
+    pub fn has_more(&self) -> bool { self.offset + self.entries.len() < self.total }
+}
+
+impl Borrow<OnchainTxid> for HistoryEntry {
+    // History entries are ordered by their onchain information only, so the borrowed form has
+    // the same ordering
+    fn borrow(&self) -> &OnchainTxid { &self.onchain }
+}
+
+/// Position in the wallet history, which remains valid when new entries are added to the history.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HistoryCursor(pub(crate) OnchainTxid);
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+pub enum CursorDirection {
+    /// From the most recent (including unconfirmed) transactions to the oldest ones.
+    #[default]
+    Backward,
+    /// From the oldest transactions to the most recent ones.
+    Forward,
+}
+
+/// Page of the wallet history retrieved with a cursor.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct HistoryPage<'wallet> {
+    pub entries: Vec<&'wallet HistoryEntry>,
+    /// Cursor for retrieving the next page, if there are more entries.
+    pub next: Option<HistoryCursor>,
+}
+
 impl HistoryEntry {
-    pub fn icon_name(&self) -> &'static str {
+    pub fn cursor(&self) -> HistoryCursor { HistoryCursor(self.onchain) }
+
+    pub fn direction(&self) -> TxDirection {
         match self.balance() {
-            x if x > 0 => "media-playlist-consecutive-symbolic",
-            x if x < 0 => "mail-send-symbolic",
-            0 => "view-refresh-symbolic",
-            _ => unreachable!(),
+            x if x > 0 => TxDirection::Incoming,
+            x if x < 0 => TxDirection::Outgoing,
+            _ => TxDirection::Internal,
+        }
+    }
+
+    /// GTK symbolic icon name for the transaction direction.
+    pub fn icon_name(&self) -> &'static str {
+        match self.direction() {
+            TxDirection::Incoming => "media-playlist-consecutive-symbolic",
+            TxDirection::Outgoing => "mail-send-symbolic",
+            TxDirection::Internal => "view-refresh-symbolic",
         }
     }
 
@@ -266,6 +476,8 @@ impl HistoryEntry {
 
     pub fn mining_info(&self) -> String { self.onchain.mining_info() }
 
+    pub fn confirmations(&self, tip: u32) -> u32 { self.onchain.confirmations(tip) }
+
     pub fn value_credited(&self) -> u64 { self.credit.values().map(|addr| addr.value).sum() }
 
     pub fn value_debited(&self) -> u64 {
@@ -278,28 +490,41 @@ impl HistoryEntry {
 
     pub fn balance(&self) -> i64 { self.value_debited() as i64 - self.value_credited() as i64 }
 
+    /// Use of each of the wallet addresses by the transaction.
     pub fn address_summaries(&self) -> Vec<AddressSummary> {
-        self.credit
+        let spent = self
+            .credit
             .values()
-            .map(|a| AddressSummary {
-                addr_src: a.addr_src,
+            .map(|addr| (addr.addr_src, 0, addr.value));
+        let received = self.debit.iter().map(|(vout, addr_src)| {
+            let value = self
+                .tx
+                .output
+                .get(*vout as usize)
+                .map(|txout| txout.value)
+                .unwrap_or_default();
+            (*addr_src, value, 0)
+        });
+        let mut summaries = BTreeMap::<AddressSource, AddressSummary>::new();
+        for (addr_src, received, sent) in spent.chain(received) {
+            let summary = summaries.entry(addr_src).or_insert(AddressSummary {
+                addr_src,
                 balance: 0,
-                volume: 0,
+                received: 0,
+                sent: 0,
                 tx_count: 1,
+                first_activity: Some(self.onchain),
+                last_activity: Some(self.onchain),
+            });
+            summary.received += received;
+            summary.sent += sent;
+        }
+        summaries
+            .into_values()
+            .map(|summary| AddressSummary {
+                balance: summary.received.saturating_sub(summary.sent),
+                ..summary
             })
-            .chain(self.debit.iter().map(|(vout, a)| {
-                AddressSummary {
-                    addr_src: *a,
-                    balance: 0,
-                    volume: self
-                        .tx
-                        .output
-                        .get(*vout as usize)
-                        .map(|txout| txout.value)
-                        .unwrap_or_default(),
-                    tx_count: 1,
-                }
-            }))
             .collect()
     }
 
@@ -319,6 +544,8 @@ pub struct UtxoTxid {
     pub value: u64,
     pub vout: u32,
     pub addr_src: AddressSource,
+    /// Whether the output is created by a coinbase transaction.
+    pub coinbase: bool,
 }
 
 impl UtxoTxid {
@@ -408,6 +635,7 @@ impl UtxoTxid {
             vout: res.tx_pos as u32,
             value: res.value,
             addr_src,
+            coinbase: false,
         }
     }
 }
@@ -0,0 +1,94 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Ordering of inputs and outputs in the transactions composed by the wallet.
+
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::Script;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+
+/// Strategy for ordering inputs and outputs of the composed transactions.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum TxOrdering {
+    /// Lexicographic ordering of inputs and outputs defined by BIP-69.
+    #[display("BIP-69")]
+    Bip69,
+
+    /// Uniformly random ordering of inputs and outputs.
+    #[default]
+    #[display("random")]
+    Random,
+
+    /// Random ordering, with the payment outputs placed first in their original order.
+    #[display("payment first")]
+    PaymentFirst,
+}
+
+impl TxOrdering {
+    /// Reorders inputs and outputs of an unsigned PSBT, together with their PSBT data.
+    /// `payments` are the scriptPubkeys of the payment outputs.
+    pub fn apply(self, psbt: &mut PartiallySignedTransaction, payments: &[Script]) {
+        let tx = &psbt.unsigned_tx;
+        let mut inputs = (0..tx.input.len()).collect::<Vec<_>>();
+        let mut outputs = (0..tx.output.len()).collect::<Vec<_>>();
+        match self {
+            TxOrdering::Bip69 => {
+                inputs.sort_by_key(|no| {
+                    let prevout = tx.input[*no].previous_output;
+                    let mut txid = prevout.txid.into_inner();
+                    txid.reverse();
+                    (txid, prevout.vout)
+                });
+                outputs.sort_by(|a, b| {
+                    let (a, b) = (&tx.output[*a], &tx.output[*b]);
+                    a.value
+                        .cmp(&b.value)
+                        .then_with(|| a.script_pubkey.as_bytes().cmp(b.script_pubkey.as_bytes()))
+                });
+            }
+            TxOrdering::Random => {
+                shuffle(&mut inputs);
+                shuffle(&mut outputs);
+            }
+            TxOrdering::PaymentFirst => {
+                shuffle(&mut inputs);
+                let (mut first, mut rest): (Vec<_>, Vec<_>) = outputs
+                    .into_iter()
+                    .partition(|no| payments.contains(&tx.output[*no].script_pubkey));
+                shuffle(&mut rest);
+                first.extend(rest);
+                outputs = first;
+            }
+        }
+
+        let tx = &mut psbt.unsigned_tx;
+        tx.input = inputs.iter().map(|no| tx.input[*no].clone()).collect();
+        psbt.inputs = inputs.iter().map(|no| psbt.inputs[*no].clone()).collect();
+        tx.output = outputs.iter().map(|no| tx.output[*no].clone()).collect();
+        psbt.outputs = outputs.iter().map(|no| psbt.outputs[*no].clone()).collect();
+    }
+}
+
+/// Fisher-Yates shuffle using the OS randomness.
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = (OsRng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
@@ -0,0 +1,776 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Payjoin (BIP-78) protocol, where the payment receiver contributes its own inputs into the
+//! payment transaction, breaking the common-input-ownership heuristic.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+
+use amplify::Wrapper;
+use bitcoin::psbt::{self, PartiallySignedTransaction};
+use bitcoin::{OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness};
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+use crate::migration::{derive, fill_wallet_output, TXIN_BASE_WEIGHT, TX_OVERHEAD_WEIGHT};
+use crate::sweep::DUST_LIMIT;
+use crate::{DustError, PaymentUri, ScriptType, Wallet};
+
+#[derive(Clone, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PayjoinError {
+    /// Payment request does not contain payjoin endpoint.
+    NoEndpoint,
+
+    /// Payjoin endpoint {0} must use HTTPS or be a Tor onion service.
+    InsecureEndpoint(String),
+
+    /// Payment request does not specify the amount.
+    NoAmount,
+
+    /// Payment request address is for a different network than the wallet.
+    NetworkMismatch,
+
+    /// Wallet funds are insufficient to pay {0} sats.
+    InsufficientFunds(u64),
+
+    /// {0}
+    #[from]
+    Dust(DustError),
+
+    /// Unable to construct the payment transaction. Details: {0}
+    Construction(String),
+
+    /// Original PSBT must be fully signed and finalized.
+    NotFinalized,
+
+    /// Original transaction does not pay to the payment request address.
+    NoPayment,
+
+    /// Payjoin proposal changes transaction version or lock time.
+    TxFieldsChanged,
+
+    /// Payjoin proposal does not spend original input {0}.
+    MissingInput(OutPoint),
+
+    /// Payjoin proposal changes sequence number of the input {0}.
+    SequenceChanged(OutPoint),
+
+    /// Payjoin proposal contains signatures or finalized data for the sender input {0}.
+    SenderInputSigned(OutPoint),
+
+    /// Receiver input {0} is not finalized.
+    ReceiverInputNotFinalized(OutPoint),
+
+    /// Receiver input {0} has no information about the spent output.
+    ReceiverInputUnknownUtxo(OutPoint),
+
+    /// Receiver input {0} spends output belonging to the sender wallet.
+    ReceiverInputOwned(OutPoint),
+
+    /// Receiver input {0} has script type different from the sender inputs.
+    ScriptTypeMismatch(OutPoint),
+
+    /// Payjoin proposal does not contain sender output {0}.
+    MissingOutput(Script),
+
+    /// Payjoin proposal decreases sender output {0}.
+    OutputDecreased(Script),
+
+    /// Payjoin proposal substitutes or decreases the payment output, while this was not
+    /// allowed.
+    PaymentChanged,
+
+    /// Payjoin proposal requires sender to pay {contribution} sats of additional fees, while at
+    /// most {max} sats were allowed.
+    FeeContribution { contribution: u64, max: u64 },
+
+    /// Payjoin proposal fee rate {0:.2} sat/vbyte is below the original fee rate.
+    FeeRateTooLow(f32),
+
+    /// Sum of the transaction input or output values overflows.
+    ValueOverflow,
+
+    /// Payjoin endpoint has failed to return the proposal. Details: {0}
+    Transport(String),
+
+    /// Invalid payjoin request parameters. Details: {0}
+    InvalidParams(String),
+
+    /// Payjoin protocol version {0} is not supported.
+    UnsupportedVersion(String),
+
+    /// Original transaction spends wallet output {0}.
+    OriginalInputOwned(OutPoint),
+
+    /// Original input {0} has no information about the spent output.
+    OriginalUnknownUtxo(OutPoint),
+
+    /// Wallet has no UTXO suitable for contributing to the payjoin.
+    NoContribution,
+}
+
+/// Parameters of the payjoin request sent by the sender to the receiver endpoint.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PayjoinParams {
+    /// Index of the sender output from which the additional fee may be subtracted.
+    pub additional_fee_output_index: Option<usize>,
+    /// Maximal additional fee, in sats, which may be subtracted from the sender output.
+    pub max_additional_fee_contribution: u64,
+    /// Whether the receiver must not substitute the payment output.
+    pub disable_output_substitution: bool,
+    /// Minimal fee rate of the proposal, in sats per vbyte.
+    pub min_fee_rate: Option<f32>,
+}
+
+impl PayjoinParams {
+    /// Parses parameters from the endpoint URL query received by the payjoin receiver.
+    pub fn from_query(query: &str) -> Result<PayjoinParams, PayjoinError> {
+        let mut params = PayjoinParams {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: 0,
+            disable_output_substitution: false,
+            min_fee_rate: None,
+        };
+        let mut version = None;
+        let invalid = |key: &str| PayjoinError::InvalidParams(format!("invalid `{}` value", key));
+        for param in query.trim_start_matches('?').split('&') {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "v" => version = Some(value.to_owned()),
+                "additionalfeeoutputindex" => {
+                    params.additional_fee_output_index =
+                        Some(value.parse().map_err(|_| invalid(key))?)
+                }
+                "maxadditionalfeecontribution" => {
+                    params.max_additional_fee_contribution =
+                        value.parse().map_err(|_| invalid(key))?
+                }
+                "disableoutputsubstitution" => params.disable_output_substitution = value == "true",
+                "minfeerate" => {
+                    params.min_fee_rate = Some(value.parse().map_err(|_| invalid(key))?)
+                }
+                _ => {}
+            }
+        }
+        match version.as_deref() {
+            Some("1") => Ok(params),
+            Some(version) => Err(PayjoinError::UnsupportedVersion(version.to_owned())),
+            None => Err(PayjoinError::InvalidParams(s!("missing protocol version"))),
+        }
+    }
+
+    /// Encodes parameters as the endpoint URL query.
+    pub fn to_query(&self) -> String {
+        let mut params = vec![s!("v=1")];
+        if let Some(index) = self.additional_fee_output_index {
+            params.push(format!("additionalfeeoutputindex={}", index));
+            params.push(format!(
+                "maxadditionalfeecontribution={}",
+                self.max_additional_fee_contribution
+            ));
+        }
+        if self.disable_output_substitution {
+            params.push(s!("disableoutputsubstitution=true"));
+        }
+        if let Some(fee_rate) = self.min_fee_rate {
+            params.push(format!("minfeerate={}", fee_rate));
+        }
+        params.join("&")
+    }
+}
+
+/// Transport delivering payjoin requests to the receiver endpoint, usually over HTTPS or Tor.
+pub trait PayjoinTransport {
+    type Error: StdError;
+
+    /// Posts signed original PSBT to the endpoint URL (which includes request parameters),
+    /// returning the receiver proposal.
+    fn post(
+        &self,
+        url: &str,
+        original: &PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, Self::Error>;
+}
+
+/// Sender side of the payjoin protocol.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PayjoinSender {
+    original: PartiallySignedTransaction,
+    endpoint: String,
+    payee: Script,
+    params: PayjoinParams,
+}
+
+impl PayjoinSender {
+    /// Prepares payjoin request from a signed and finalized original PSBT. The original
+    /// transaction should be broadcasted if the payjoin fails.
+    pub fn new(
+        original: PartiallySignedTransaction,
+        uri: &PaymentUri,
+        params: PayjoinParams,
+    ) -> Result<PayjoinSender, PayjoinError> {
+        let endpoint = uri.payjoin.clone().ok_or(PayjoinError::NoEndpoint)?;
+        let is_onion = endpoint
+            .strip_prefix("http://")
+            .and_then(|rest| rest.split(&['/', ':'][..]).next())
+            .map(|host| host.ends_with(".onion"))
+            .unwrap_or_default();
+        if !endpoint.starts_with("https://") && !is_onion {
+            return Err(PayjoinError::InsecureEndpoint(endpoint));
+        }
+        if original
+            .inputs
+            .iter()
+            .any(|input| input.final_script_sig.is_none() && input.final_script_witness.is_none())
+        {
+            return Err(PayjoinError::NotFinalized);
+        }
+        let payee = uri.address.script_pubkey();
+        if !original
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|txout| txout.script_pubkey == payee)
+        {
+            return Err(PayjoinError::NoPayment);
+        }
+        let params = PayjoinParams {
+            disable_output_substitution: params.disable_output_substitution
+                || !uri.output_substitution,
+            ..params
+        };
+        Ok(PayjoinSender {
+            original,
+            endpoint,
+            payee,
+            params,
+        })
+    }
+
+    pub fn original(&self) -> &PartiallySignedTransaction { &self.original }
+
+    pub fn params(&self) -> &PayjoinParams { &self.params }
+
+    /// Endpoint URL including the request parameters.
+    pub fn request_url(&self) -> String {
+        let sep = if self.endpoint.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.endpoint, sep, self.params.to_query())
+    }
+
+    /// Original transaction, which should be broadcasted if the payjoin fails.
+    pub fn fallback_tx(&self) -> Transaction { self.original.clone().extract_tx() }
+}
+
+/// Receiver side of the payjoin protocol.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PayjoinReceiver {
+    original: PartiallySignedTransaction,
+    proposal: PartiallySignedTransaction,
+    /// Index of the input contributed by the receiver in the proposal.
+    input_index: usize,
+}
+
+impl PayjoinReceiver {
+    pub fn original(&self) -> &PartiallySignedTransaction { &self.original }
+
+    /// Unsigned proposal, in which the receiver input must be signed and finalized by the
+    /// wallet signers before calling [`PayjoinReceiver::finalize`].
+    pub fn proposal(&self) -> &PartiallySignedTransaction { &self.proposal }
+
+    /// Original transaction, which should be broadcasted by the receiver if the sender does
+    /// not broadcast the payjoin transaction in a reasonable time.
+    pub fn fallback_tx(&self) -> Transaction { self.original.clone().extract_tx() }
+
+    /// Completes proposal with the signed receiver input, returning PSBT which must be sent
+    /// back to the sender. Information which may leak the receiver wallet structure is removed.
+    pub fn finalize(
+        &self,
+        signed: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, PayjoinError> {
+        if signed.unsigned_tx != self.proposal.unsigned_tx {
+            return Err(PayjoinError::Construction(s!(
+                "signed PSBT is for a different transaction"
+            )));
+        }
+        let input = &signed.inputs[self.input_index];
+        if !is_finalized(input) {
+            let outpoint = signed.unsigned_tx.input[self.input_index].previous_output;
+            return Err(PayjoinError::ReceiverInputNotFinalized(outpoint));
+        }
+        let mut proposal = self.proposal.clone();
+        proposal.inputs[self.input_index] = psbt::Input {
+            witness_utxo: input.witness_utxo.clone(),
+            non_witness_utxo: input.non_witness_utxo.clone(),
+            final_script_sig: input.final_script_sig.clone(),
+            final_script_witness: input.final_script_witness.clone(),
+            ..default!()
+        };
+        for output in &mut proposal.outputs {
+            *output = psbt::Output::default();
+        }
+        proposal.xpub.clear();
+        Ok(proposal)
+    }
+}
+
+/// Sums transaction values, failing on overflow instead of wrapping around.
+fn checked_sum(values: impl IntoIterator<Item = u64>) -> Result<u64, PayjoinError> {
+    values.into_iter().try_fold(0u64, |sum, value| {
+        sum.checked_add(value).ok_or(PayjoinError::ValueOverflow)
+    })
+}
+
+fn spent_output(input: &psbt::Input, txin: &TxIn) -> Option<TxOut> {
+    input.witness_utxo.clone().or_else(|| {
+        input.non_witness_utxo.as_ref().and_then(|prev_tx| {
+            prev_tx
+                .output
+                .get(txin.previous_output.vout as usize)
+                .cloned()
+        })
+    })
+}
+
+fn is_finalized(input: &psbt::Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+impl Wallet {
+    /// Generates payment request for a fresh wallet address, advertising payjoin endpoint.
+    pub fn payjoin_uri(
+        &mut self,
+        endpoint: impl ToString,
+        amount: Option<u64>,
+        label: Option<String>,
+    ) -> PaymentUri {
+        let (_, address) = self.fresh_address();
+        PaymentUri {
+            amount,
+            label,
+            payjoin: Some(endpoint.to_string()),
+            ..PaymentUri::with(address)
+        }
+    }
+
+    /// Validates original PSBT received by the payjoin endpoint and contributes one of the
+    /// wallet UTXOs into it, returning receiver state with the unsigned proposal. `query` is
+    /// the request URL query with the sender parameters.
+    ///
+    /// The caller must make sure that the original PSBT inputs were not seen by the endpoint
+    /// before, preventing probing attacks on the wallet UTXOs.
+    pub fn payjoin_receive(
+        &self,
+        original: PartiallySignedTransaction,
+        query: &str,
+    ) -> Result<PayjoinReceiver, PayjoinError> {
+        let params = PayjoinParams::from_query(query)?;
+        let orig_tx = &original.unsigned_tx;
+        if original.inputs.iter().any(|input| !is_finalized(input)) {
+            return Err(PayjoinError::NotFinalized);
+        }
+
+        let mut spent = 0u64;
+        let mut sender_types = BTreeSet::new();
+        for (txin, input) in orig_tx.input.iter().zip(&original.inputs) {
+            let outpoint = txin.previous_output;
+            let txout =
+                spent_output(input, txin).ok_or(PayjoinError::OriginalUnknownUtxo(outpoint))?;
+            if self.is_mine(&txout.script_pubkey).is_some() {
+                return Err(PayjoinError::OriginalInputOwned(outpoint));
+            }
+            spent = spent
+                .checked_add(txout.value)
+                .ok_or(PayjoinError::ValueOverflow)?;
+            sender_types.insert(ScriptType::from(&txout.script_pubkey));
+        }
+        let payment_index = orig_tx
+            .output
+            .iter()
+            .position(|txout| self.is_mine(&txout.script_pubkey).is_some())
+            .ok_or(PayjoinError::NoPayment)?;
+        let fee =
+            spent.saturating_sub(checked_sum(orig_tx.output.iter().map(|txout| txout.value))?);
+        let fee_rate = (fee as f32 / original.clone().extract_tx().vsize() as f32)
+            .max(params.min_fee_rate.unwrap_or_default());
+
+        let construction_err = |err: miniscript::Error| PayjoinError::Construction(err.to_string());
+        let (descriptor, _) = self
+            .as_settings()
+            .descriptors_all()
+            .map_err(construction_err)?;
+        let input_weight = TXIN_BASE_WEIGHT
+            + descriptor
+                .max_satisfaction_weight()
+                .map_err(construction_err)?;
+        let input_fee = (input_weight as f32 / 4.0 * fee_rate).ceil() as u64;
+
+        let utxo = self
+            .utxos()
+            .iter()
+            .filter(|utxo| {
+                !self.is_frozen(utxo.outpoint())
+                    && !self.is_immature(utxo)
+                    && !self.silent_payments().contains_key(&utxo.outpoint())
+                    && utxo.value > input_fee
+            })
+            .filter(|utxo| {
+                let script = utxo.addr_src.address.script_pubkey();
+                sender_types.len() != 1
+                    || sender_types.contains(&ScriptType::from(script.as_inner()))
+            })
+            .min_by_key(|utxo| utxo.value)
+            .ok_or(PayjoinError::NoContribution)?;
+
+        // Fee for the contributed input is paid by the sender up to the allowed contribution,
+        // and the rest is paid from the receiver output
+        let mut proposal_tx = orig_tx.clone();
+        let sender_contribution = params
+            .additional_fee_output_index
+            .filter(|index| *index != payment_index)
+            .and_then(|index| proposal_tx.output.get_mut(index))
+            .map(|txout| {
+                let contribution = params
+                    .max_additional_fee_contribution
+                    .min(input_fee)
+                    .min(txout.value.saturating_sub(DUST_LIMIT));
+                txout.value -= contribution;
+                contribution
+            })
+            .unwrap_or_default();
+        let payment = &mut proposal_tx.output[payment_index].value;
+        *payment = payment
+            .checked_add(utxo.value - (input_fee - sender_contribution))
+            .ok_or(PayjoinError::ValueOverflow)?;
+        let input_index = proposal_tx.input.len();
+        proposal_tx.input.push(TxIn {
+            previous_output: utxo.outpoint(),
+            script_sig: Script::new(),
+            sequence: orig_tx
+                .input
+                .first()
+                .map(|txin| txin.sequence)
+                .unwrap_or(Sequence::ENABLE_RBF_NO_LOCKTIME),
+            witness: Witness::new(),
+        });
+
+        let mut proposal = PartiallySignedTransaction::from_unsigned_tx(proposal_tx)
+            .expect("transaction without signatures");
+        self.fill_wallet_input(&mut proposal.inputs[input_index], utxo)
+            .map_err(|err| PayjoinError::Construction(err.to_string()))?;
+
+        Ok(PayjoinReceiver {
+            original,
+            proposal,
+            input_index,
+        })
+    }
+
+    /// Constructs unsigned original PSBT for the payment request with payjoin endpoint, paying
+    /// change (if above the dust limit) to the next change address. The fee is computed from
+    /// `fee_rate` (in sats per vbyte). Returns PSBT together with the request parameters.
+    pub fn payjoin_original(
+        &self,
+        uri: &PaymentUri,
+        fee_rate: f32,
+    ) -> Result<(PartiallySignedTransaction, PayjoinParams), PayjoinError> {
+        if uri.payjoin.is_none() {
+            return Err(PayjoinError::NoEndpoint);
+        }
+        if uri.address.network != bitcoin::Network::from(self.as_settings().network()) {
+            return Err(PayjoinError::NetworkMismatch);
+        }
+        let amount = uri.amount.ok_or(PayjoinError::NoAmount)?;
+        self.dust_policy().check_output(amount)?;
+        let construction_err = |err: miniscript::Error| PayjoinError::Construction(err.to_string());
+
+        let (descriptor, _) = self
+            .as_settings()
+            .descriptors_all()
+            .map_err(construction_err)?;
+        let input_weight = TXIN_BASE_WEIGHT
+            + descriptor
+                .max_satisfaction_weight()
+                .map_err(construction_err)?;
+        let payee = uri.address.script_pubkey();
+        let change_descriptor = self
+            .change_descriptor(std::slice::from_ref(&payee))
+            .map_err(construction_err)?;
+        let change_index = self.next_change_index();
+        let change = derive(
+            self.as_settings(),
+            &change_descriptor,
+            UnhardenedIndex::one(),
+            change_index,
+        )
+        .map_err(|err| PayjoinError::Construction(err.to_string()))?;
+        let outputs_weight = (8 + 1 + payee.len()) * 4 + (8 + 1 + change.script_pubkey().len()) * 4;
+
+        let mut fee = 0u64;
+        let (prevouts, total) = loop {
+            let (prevouts, total) = self
+                .coinselect(amount + fee)
+                .ok_or(PayjoinError::InsufficientFunds(amount + fee))?;
+            let weight = TX_OVERHEAD_WEIGHT + input_weight * prevouts.len() + outputs_weight;
+            let required = (weight as f32 / 4.0 * fee_rate).ceil() as u64;
+            if total >= amount + required {
+                break (prevouts, total);
+            }
+            fee = required;
+        };
+        let weight = TX_OVERHEAD_WEIGHT + input_weight * prevouts.len() + outputs_weight;
+        let fee = (weight as f32 / 4.0 * fee_rate).ceil() as u64;
+
+        let mut output = vec![TxOut {
+            value: amount,
+            script_pubkey: payee,
+        }];
+        let change_value = total - amount - fee;
+        let has_change = self.dust_policy().keeps_change(change_value);
+        if has_change {
+            output.push(TxOut {
+                value: change_value,
+                script_pubkey: change.script_pubkey(),
+            });
+        }
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: prevouts
+                .iter()
+                .map(|prevout| TxIn {
+                    previous_output: prevout.outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)
+            .expect("transaction without signatures");
+        for (input, prevout) in psbt.inputs.iter_mut().zip(&prevouts) {
+            let utxo = self
+                .utxos()
+                .iter()
+                .find(|utxo| utxo.outpoint() == prevout.outpoint)
+                .expect("coinselect returns wallet UTXOs");
+            self.fill_wallet_input(input, utxo)
+                .map_err(|err| PayjoinError::Construction(err.to_string()))?;
+        }
+        if has_change {
+            fill_wallet_output(
+                &mut psbt.outputs[1],
+                &change,
+                self.as_settings(),
+                UnhardenedIndex::one(),
+                change_index,
+            );
+        }
+        self.tx_ordering()
+            .apply(&mut psbt, &[uri.address.script_pubkey()]);
+        let change_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| has_change && txout.script_pubkey == change.script_pubkey());
+
+        let params = PayjoinParams {
+            additional_fee_output_index: change_output,
+            max_additional_fee_contribution: if has_change {
+                (input_weight as f32 / 4.0 * fee_rate).ceil() as u64
+            } else {
+                0
+            },
+            disable_output_substitution: !uri.output_substitution,
+            min_fee_rate: Some(fee_rate),
+        };
+        Ok((psbt, params))
+    }
+
+    /// Sends payjoin request to the receiver, validates returned proposal and prepares it for
+    /// re-signing by the wallet signers. The signed proposal must be finalized and broadcasted
+    /// by the caller; if any step fails, [`PayjoinSender::fallback_tx`] should be broadcasted
+    /// instead.
+    pub fn payjoin_request<T: PayjoinTransport>(
+        &self,
+        sender: &PayjoinSender,
+        transport: &T,
+    ) -> Result<PartiallySignedTransaction, PayjoinError> {
+        let proposal = transport
+            .post(&sender.request_url(), sender.original())
+            .map_err(|err| PayjoinError::Transport(err.to_string()))?;
+        self.process_payjoin_proposal(sender, proposal)
+    }
+
+    /// Validates payjoin proposal against the BIP-78 sender checks and prepares it for
+    /// re-signing: sender inputs and outputs get the wallet information required by signers,
+    /// while receiver inputs stay finalized.
+    pub fn process_payjoin_proposal(
+        &self,
+        sender: &PayjoinSender,
+        mut proposal: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, PayjoinError> {
+        let original = &sender.original;
+        let orig_tx = &original.unsigned_tx;
+        let tx = &proposal.unsigned_tx;
+        if tx.version != orig_tx.version || tx.lock_time != orig_tx.lock_time {
+            return Err(PayjoinError::TxFieldsChanged);
+        }
+
+        // Inputs
+        let orig_inputs = orig_tx
+            .input
+            .iter()
+            .zip(&original.inputs)
+            .map(|(txin, input)| (txin.previous_output, (txin, input)))
+            .collect::<BTreeMap<_, _>>();
+        let sender_types = orig_tx
+            .input
+            .iter()
+            .zip(&original.inputs)
+            .filter_map(|(txin, input)| spent_output(input, txin))
+            .map(|txout| ScriptType::from(&txout.script_pubkey))
+            .collect::<BTreeSet<_>>();
+        let sender_sequence = orig_tx.input.first().map(|txin| txin.sequence);
+        let mut seen = BTreeSet::new();
+        for (txin, input) in tx.input.iter().zip(&proposal.inputs) {
+            let outpoint = txin.previous_output;
+            if let Some((orig_txin, _)) = orig_inputs.get(&outpoint) {
+                if txin.sequence != orig_txin.sequence {
+                    return Err(PayjoinError::SequenceChanged(outpoint));
+                }
+                if is_finalized(input)
+                    || !input.partial_sigs.is_empty()
+                    || input.tap_key_sig.is_some()
+                    || !input.tap_script_sigs.is_empty()
+                {
+                    return Err(PayjoinError::SenderInputSigned(outpoint));
+                }
+                seen.insert(outpoint);
+                continue;
+            }
+            if !is_finalized(input) {
+                return Err(PayjoinError::ReceiverInputNotFinalized(outpoint));
+            }
+            let spent = spent_output(input, txin)
+                .ok_or(PayjoinError::ReceiverInputUnknownUtxo(outpoint))?;
+            if self.is_mine(&spent.script_pubkey).is_some() {
+                return Err(PayjoinError::ReceiverInputOwned(outpoint));
+            }
+            if sender_types.len() == 1
+                && !sender_types.contains(&ScriptType::from(&spent.script_pubkey))
+            {
+                return Err(PayjoinError::ScriptTypeMismatch(outpoint));
+            }
+            if Some(txin.sequence) != sender_sequence {
+                return Err(PayjoinError::SequenceChanged(outpoint));
+            }
+        }
+        if let Some(outpoint) = orig_inputs.keys().find(|outpoint| !seen.contains(outpoint)) {
+            return Err(PayjoinError::MissingInput(*outpoint));
+        }
+
+        // Outputs
+        let params = &sender.params;
+        let fee_output = params
+            .additional_fee_output_index
+            .and_then(|index| orig_tx.output.get(index))
+            .map(|txout| txout.script_pubkey.clone());
+        let mut contribution = 0u64;
+        for orig_out in &orig_tx.output {
+            let script = &orig_out.script_pubkey;
+            let value = tx
+                .output
+                .iter()
+                .find(|txout| &txout.script_pubkey == script)
+                .map(|txout| txout.value);
+            if *script == sender.payee {
+                if params.disable_output_substitution && value.unwrap_or_default() < orig_out.value
+                {
+                    return Err(PayjoinError::PaymentChanged);
+                }
+                continue;
+            }
+            let value = value.ok_or_else(|| PayjoinError::MissingOutput(script.clone()))?;
+            if value < orig_out.value {
+                if Some(script) != fee_output.as_ref() {
+                    return Err(PayjoinError::OutputDecreased(script.clone()));
+                }
+                contribution = orig_out.value - value;
+            }
+        }
+        if contribution > params.max_additional_fee_contribution {
+            return Err(PayjoinError::FeeContribution {
+                contribution,
+                max: params.max_additional_fee_contribution,
+            });
+        }
+
+        // Fee rate, estimated from the finalized original and receiver inputs
+        if let Some(min_fee_rate) = params.min_fee_rate {
+            let mut signed = tx.clone();
+            for (txin, input) in signed.input.iter_mut().zip(&proposal.inputs) {
+                let source = orig_inputs
+                    .get(&txin.previous_output)
+                    .map(|(_, input)| *input)
+                    .unwrap_or(input);
+                txin.script_sig = source.final_script_sig.clone().unwrap_or_default();
+                txin.witness = source.final_script_witness.clone().unwrap_or_default();
+            }
+            let spent = checked_sum(tx.input.iter().zip(&proposal.inputs).map(|(txin, input)| {
+                orig_inputs
+                    .get(&txin.previous_output)
+                    .and_then(|(txin, input)| spent_output(input, txin))
+                    .or_else(|| spent_output(input, txin))
+                    .map(|txout| txout.value)
+                    .unwrap_or_default()
+            }))?;
+            let fee = spent.saturating_sub(checked_sum(tx.output.iter().map(|txout| txout.value))?);
+            let fee_rate = fee as f32 / signed.vsize() as f32;
+            if fee_rate < min_fee_rate {
+                return Err(PayjoinError::FeeRateTooLow(fee_rate));
+            }
+        }
+
+        // Restoring information for the sender signers
+        for (txin, input) in proposal.unsigned_tx.input.iter().zip(&mut proposal.inputs) {
+            if !orig_inputs.contains_key(&txin.previous_output) {
+                continue;
+            }
+            let utxo = self
+                .utxos()
+                .iter()
+                .find(|utxo| utxo.outpoint() == txin.previous_output)
+                .ok_or(PayjoinError::MissingInput(txin.previous_output))?;
+            *input = psbt::Input::default();
+            self.fill_wallet_input(input, utxo)
+                .map_err(|err| PayjoinError::Construction(err.to_string()))?;
+        }
+        for (txout, output) in proposal
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(&mut proposal.outputs)
+        {
+            if let Some(orig_output) = orig_tx
+                .output
+                .iter()
+                .position(|orig| orig.script_pubkey == txout.script_pubkey)
+                .filter(|_| txout.script_pubkey != sender.payee)
+                .and_then(|index| original.outputs.get(index))
+            {
+                *output = orig_output.clone();
+            }
+        }
+        Ok(proposal)
+    }
+}
@@ -0,0 +1,199 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Recurring and scheduled payments, materialized into drafts for the transaction composer.
+
+use bitcoin_scripts::address::AddressCompat;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::Wallet;
+
+/// Period between the recurring payments.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum SchedulePeriod {
+    #[display("every {0} day(s)")]
+    Days(u16),
+
+    #[display("every {0} week(s)")]
+    Weeks(u16),
+
+    /// Monthly payments; if the month is shorter than the day of the first payment, the payment
+    /// happens on the last day of the month.
+    #[display("every {0} month(s)")]
+    Months(u16),
+}
+
+/// Payment schedule.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct Schedule {
+    /// Date of the first payment.
+    pub start: DateTime<Utc>,
+    /// Period for recurring payments; `None` for one-time scheduled payment.
+    pub period: Option<SchedulePeriod>,
+    /// Date after which no more payments happen.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    pub fn once(date: DateTime<Utc>) -> Schedule {
+        Schedule {
+            start: date,
+            period: None,
+            end: None,
+        }
+    }
+
+    pub fn recurring(start: DateTime<Utc>, period: SchedulePeriod) -> Schedule {
+        Schedule {
+            start,
+            period: Some(period),
+            end: None,
+        }
+    }
+
+    /// Date of the payment with a given number, starting from zero.
+    pub fn occurrence(&self, no: u32) -> Option<DateTime<Utc>> {
+        let date = match (self.period, no) {
+            (_, 0) => Some(self.start),
+            (None, _) => None,
+            (Some(SchedulePeriod::Days(days)), no) => self
+                .start
+                .checked_add_signed(Duration::days(days as i64 * no as i64)),
+            (Some(SchedulePeriod::Weeks(weeks)), no) => self
+                .start
+                .checked_add_signed(Duration::weeks(weeks as i64 * no as i64)),
+            (Some(SchedulePeriod::Months(months)), no) => {
+                add_months(self.start, months as u32 * no)
+            }
+        }?;
+        match self.end {
+            Some(end) if date > end => None,
+            _ => Some(date),
+        }
+    }
+}
+
+fn add_months(date: DateTime<Utc>, months: u32) -> Option<DateTime<Utc>> {
+    let total = date.month0() + months;
+    let year = date.year().checked_add((total / 12) as i32)?;
+    let month = total % 12 + 1;
+    let day = (1..=date.day())
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))?;
+    let naive = day.and_time(date.time());
+    Some(DateTime::<Utc>::from_utc(naive, Utc))
+}
+
+/// Template for recurring or scheduled payment.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct PaymentTemplate {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<::serde_with::DisplayFromStr>")
+    )]
+    pub payee: AddressCompat,
+    pub amount: u64,
+    pub schedule: Schedule,
+    pub label: String,
+    /// Number of payments already materialized into drafts.
+    pub materialized: u32,
+}
+
+impl PaymentTemplate {
+    pub fn with(payee: AddressCompat, amount: u64, schedule: Schedule, label: String) -> Self {
+        PaymentTemplate {
+            payee,
+            amount,
+            schedule,
+            label,
+            materialized: 0,
+        }
+    }
+
+    /// Date of the next payment, if any.
+    pub fn next_due(&self) -> Option<DateTime<Utc>> { self.schedule.occurrence(self.materialized) }
+
+    /// Detects whether all scheduled payments were materialized.
+    pub fn is_finished(&self) -> bool { self.next_due().is_none() }
+
+    fn due(&self, id: u32, now: DateTime<Utc>) -> impl Iterator<Item = PaymentDraft> + '_ {
+        (self.materialized..)
+            .map_while(|no| self.schedule.occurrence(no))
+            .take_while(move |date| *date <= now)
+            .map(move |due| PaymentDraft {
+                template: id,
+                payee: self.payee,
+                amount: self.amount,
+                label: self.label.clone(),
+                due,
+            })
+    }
+}
+
+/// Payment which is due, to be used by the transaction composer.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct PaymentDraft {
+    /// Identifier of the template which produced the draft.
+    pub template: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<::serde_with::DisplayFromStr>")
+    )]
+    pub payee: AddressCompat,
+    pub amount: u64,
+    pub label: String,
+    pub due: DateTime<Utc>,
+}
+
+impl Wallet {
+    /// Adds payment template, returning its identifier.
+    pub fn add_payment_template(&mut self, template: PaymentTemplate) -> u32 {
+        let templates = self.payment_templates_mut();
+        let id = templates.keys().last().map(|id| id + 1).unwrap_or_default();
+        templates.insert(id, template);
+        id
+    }
+
+    pub fn remove_payment_template(&mut self, id: u32) -> Option<PaymentTemplate> {
+        self.payment_templates_mut().remove(&id)
+    }
+
+    /// Lists payments which are due at the given moment, without marking them as materialized.
+    pub fn due_payments(&self, now: DateTime<Utc>) -> Vec<PaymentDraft> {
+        self.payment_templates()
+            .iter()
+            .flat_map(|(id, template)| template.due(*id, now))
+            .collect()
+    }
+
+    /// Materializes payments which are due at the given moment into drafts, such that they are
+    /// not returned again.
+    pub fn materialize_due_payments(&mut self, now: DateTime<Utc>) -> Vec<PaymentDraft> {
+        let drafts = self.due_payments(now);
+        for draft in &drafts {
+            if let Some(template) = self.payment_templates_mut().get_mut(&draft.template) {
+                template.materialized += 1;
+            }
+        }
+        drafts
+    }
+}
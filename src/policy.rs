@@ -0,0 +1,248 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+use std::collections::BTreeSet;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{ecdsa, Message, PublicKey, SecretKey, SECP256K1};
+use bitcoin::Script;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::Wallet;
+
+/// Tag used for computing message signed by the override tokens.
+const OVERRIDE_TOKEN_TAG: &[u8] = b"bpro:override-token:v1";
+
+/// Operational limits enforced on outgoing payments before a PSBT is constructed.
+///
+/// Limits apply to the amounts paid to external scripts; payments back to the wallet are not
+/// counted. Policy violations can be lifted for a single spending with an [`OverrideToken`]
+/// signed by one of [`SpendingPolicy::override_keys`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct SpendingPolicy {
+    /// Maximal amount paid by a single transaction.
+    pub max_per_tx: Option<u64>,
+    /// Maximal amount paid within any 24-hour period, including the proposed transaction.
+    pub max_per_day: Option<u64>,
+    /// If not empty, payments are allowed only to these scriptPubkeys.
+    pub whitelist: BTreeSet<Script>,
+    /// Keys which can issue override tokens.
+    pub override_keys: BTreeSet<PublicKey>,
+}
+
+impl SpendingPolicy {
+    /// Detects whether the policy does not restrict spendings in any way.
+    pub fn is_unrestricted(&self) -> bool {
+        self.max_per_tx.is_none() && self.max_per_day.is_none() && self.whitelist.is_empty()
+    }
+}
+
+/// Violation of the wallet [`SpendingPolicy`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PolicyViolation {
+    /// Transaction pays {amount} sats, exceeding per-transaction limit of {limit} sats.
+    TxLimit { amount: u64, limit: u64 },
+
+    /// Transaction pays {amount} sats, which together with {spent} sats paid during the last 24
+    /// hours exceeds daily limit of {limit} sats.
+    DailyLimit { spent: u64, amount: u64, limit: u64 },
+
+    /// Destination {0} is not in the whitelist.
+    NotWhitelisted(Script),
+
+    /// Override token is signed by a key not authorized by the spending policy.
+    OverrideUnauthorized,
+
+    /// Override token has invalid signature.
+    OverrideSignature,
+
+    /// Override token has expired at {0}.
+    OverrideExpired(DateTime<Utc>),
+
+    /// Override token was already used.
+    OverrideReused,
+
+    /// Override token allows spending up to {limit} sats, while transaction pays {amount} sats.
+    OverrideAmount { amount: u64, limit: u64 },
+}
+
+/// One-time authorization to spend funds despite the wallet spending policy violations.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct OverrideToken {
+    pub nonce: u64,
+    pub expires: DateTime<Utc>,
+    /// Maximal amount which can be paid by the transaction using the token.
+    pub max_amount: u64,
+    pub key: PublicKey,
+    pub signature: ecdsa::Signature,
+}
+
+impl OverrideToken {
+    /// Issues token signed with a secret key, which must correspond to one of the policy
+    /// override keys.
+    pub fn issue(
+        secret_key: &SecretKey,
+        nonce: u64,
+        expires: DateTime<Utc>,
+        max_amount: u64,
+    ) -> OverrideToken {
+        let msg = Self::message(nonce, expires, max_amount);
+        OverrideToken {
+            nonce,
+            expires,
+            max_amount,
+            key: PublicKey::from_secret_key(SECP256K1, secret_key),
+            signature: SECP256K1.sign_ecdsa(&msg, secret_key),
+        }
+    }
+
+    /// Unique token identifier, used to prevent token reuse.
+    pub fn id(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&Self::message(self.nonce, self.expires, self.max_amount)[..]);
+        engine.input(&self.key.serialize());
+        sha256::Hash::from_engine(engine)
+    }
+
+    pub fn verify(&self) -> bool {
+        let msg = Self::message(self.nonce, self.expires, self.max_amount);
+        SECP256K1
+            .verify_ecdsa(&msg, &self.signature, &self.key)
+            .is_ok()
+    }
+
+    fn message(nonce: u64, expires: DateTime<Utc>, max_amount: u64) -> Message {
+        let tag = sha256::Hash::hash(OVERRIDE_TOKEN_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag[..]);
+        engine.input(&tag[..]);
+        engine.input(&nonce.to_le_bytes());
+        engine.input(&expires.timestamp().to_le_bytes());
+        engine.input(&max_amount.to_le_bytes());
+        Message::from_slice(&sha256::Hash::from_engine(engine)[..])
+            .expect("hash is always a valid message")
+    }
+}
+
+impl Wallet {
+    /// Amount paid by the wallet to external destinations since a given moment, including
+    /// unconfirmed transactions.
+    pub fn spent_since(&self, since: DateTime<Utc>) -> u64 {
+        self.history()
+            .iter()
+            .filter(|entry| {
+                entry
+                    .onchain
+                    .date_time
+                    .map(|time| time >= since)
+                    .unwrap_or(true)
+            })
+            .filter(|entry| entry.balance() < 0)
+            .map(|entry| entry.balance().unsigned_abs())
+            .sum()
+    }
+
+    /// Checks payments of a proposed transaction against the wallet spending policy, returning
+    /// all detected violations. Payments to the wallet own scripts are not counted.
+    pub fn check_spending(
+        &self,
+        payments: &[(Script, u64)],
+        now: DateTime<Utc>,
+    ) -> Result<(), Vec<PolicyViolation>> {
+        let policy = self.spending_policy();
+        let mut violations = vec![];
+
+        let external = payments
+            .iter()
+            .filter(|(script, _)| self.is_mine(script).is_none())
+            .collect::<Vec<_>>();
+        let amount = external.iter().map(|(_, value)| *value).sum::<u64>();
+
+        if let Some(limit) = policy.max_per_tx {
+            if amount > limit {
+                violations.push(PolicyViolation::TxLimit { amount, limit });
+            }
+        }
+        if let Some(limit) = policy.max_per_day {
+            let spent = self.spent_since(now - Duration::days(1));
+            if spent.saturating_add(amount) > limit {
+                violations.push(PolicyViolation::DailyLimit {
+                    spent,
+                    amount,
+                    limit,
+                });
+            }
+        }
+        if !policy.whitelist.is_empty() {
+            violations.extend(
+                external
+                    .iter()
+                    .filter(|(script, _)| !policy.whitelist.contains(script))
+                    .map(|(script, _)| PolicyViolation::NotWhitelisted(script.clone())),
+            );
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Authorizes payments of a proposed transaction, which must be done before constructing
+    /// PSBT. If the spending policy is violated, the payments are authorized only with a valid
+    /// override token, which gets consumed and can't be used again.
+    pub fn authorize_spending(
+        &mut self,
+        payments: &[(Script, u64)],
+        now: DateTime<Utc>,
+        token: Option<&OverrideToken>,
+    ) -> Result<(), Vec<PolicyViolation>> {
+        let mut violations = match self.check_spending(payments, now) {
+            Ok(()) => return Ok(()),
+            Err(violations) => violations,
+        };
+        let token = match token {
+            Some(token) => token,
+            None => return Err(violations),
+        };
+
+        let amount = payments
+            .iter()
+            .filter(|(script, _)| self.is_mine(script).is_none())
+            .map(|(_, value)| *value)
+            .sum::<u64>();
+        let err = if !self.spending_policy().override_keys.contains(&token.key) {
+            PolicyViolation::OverrideUnauthorized
+        } else if !token.verify() {
+            PolicyViolation::OverrideSignature
+        } else if token.expires < now {
+            PolicyViolation::OverrideExpired(token.expires)
+        } else if self.used_overrides().contains(&token.id()) {
+            PolicyViolation::OverrideReused
+        } else if amount > token.max_amount {
+            PolicyViolation::OverrideAmount {
+                amount,
+                limit: token.max_amount,
+            }
+        } else {
+            self.mark_override_used(token.id());
+            return Ok(());
+        };
+
+        violations.push(err);
+        Err(violations)
+    }
+}
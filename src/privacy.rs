@@ -0,0 +1,248 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Privacy analysis of transaction drafts, detecting heuristics which chain analysis may use to
+//! link wallet coins or identify change, such that they can be fixed before signing.
+
+use std::collections::BTreeSet;
+
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Script, TxOut};
+use wallet::descriptors::DescriptorClass;
+
+use crate::Wallet;
+
+/// Payment amounts which are multiples of this value (in sats) are considered round.
+pub const ROUND_AMOUNT_UNIT: u64 = 10_000;
+
+/// Type of the scriptPubkey, as seen by the external observers.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum ScriptType {
+    #[display("P2PKH")]
+    P2pkh,
+
+    #[display("P2SH")]
+    P2sh,
+
+    #[display("P2WPKH")]
+    P2wpkh,
+
+    #[display("P2WSH")]
+    P2wsh,
+
+    #[display("P2TR")]
+    P2tr,
+
+    #[display("non-standard")]
+    Other,
+}
+
+impl From<&Script> for ScriptType {
+    fn from(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            ScriptType::P2pkh
+        } else if script.is_p2sh() {
+            ScriptType::P2sh
+        } else if script.is_v0_p2wpkh() {
+            ScriptType::P2wpkh
+        } else if script.is_v0_p2wsh() {
+            ScriptType::P2wsh
+        } else if script.is_v1_p2tr() {
+            ScriptType::P2tr
+        } else {
+            ScriptType::Other
+        }
+    }
+}
+
+impl ScriptType {
+    /// Descriptor class producing scripts of this type, if any.
+    pub fn descriptor_class(self) -> Option<DescriptorClass> {
+        match self {
+            ScriptType::P2pkh => Some(DescriptorClass::PreSegwit),
+            ScriptType::P2sh => Some(DescriptorClass::NestedV0),
+            ScriptType::P2wpkh | ScriptType::P2wsh => Some(DescriptorClass::SegwitV0),
+            ScriptType::P2tr => Some(DescriptorClass::TaprootC0),
+            ScriptType::Other => None,
+        }
+    }
+}
+
+/// Severity of a privacy issue.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum PrivacySeverity {
+    #[display("low")]
+    Low,
+
+    #[display("medium")]
+    Medium,
+
+    #[display("high")]
+    High,
+}
+
+/// Privacy issue detected in a transaction draft.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum PrivacyIssue {
+    /// Output {0} pays to a wallet address which was already used.
+    OwnAddressReuse(u32),
+
+    /// Output {0} pays to an external address which the wallet has already paid to.
+    ExternalAddressReuse(u32),
+
+    /// Payment output {payment} has a round amount, revealing output {change} as the change.
+    RoundAmountChange { payment: u32, change: u32 },
+
+    /// Change output {vout} uses {change} script, while payments use {payment} script, revealing
+    /// the change.
+    ChangeScriptMismatch {
+        vout: u32,
+        change: ScriptType,
+        payment: ScriptType,
+    },
+
+    /// Inputs spend outputs of different script types ({0:?}), fingerprinting the wallet.
+    MixedInputTypes(BTreeSet<ScriptType>),
+
+    /// Inputs merge coins with different labels ({0:?}), linking them onchain.
+    ClusterMerge(BTreeSet<String>),
+}
+
+impl PrivacyIssue {
+    pub fn severity(&self) -> PrivacySeverity {
+        match self {
+            PrivacyIssue::OwnAddressReuse(_) | PrivacyIssue::ClusterMerge(_) => {
+                PrivacySeverity::High
+            }
+            PrivacyIssue::ExternalAddressReuse(_)
+            | PrivacyIssue::RoundAmountChange { .. }
+            | PrivacyIssue::ChangeScriptMismatch { .. } => PrivacySeverity::Medium,
+            PrivacyIssue::MixedInputTypes(_) => PrivacySeverity::Low,
+        }
+    }
+}
+
+/// Structured privacy report for a transaction draft.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PrivacyReport {
+    pub issues: Vec<PrivacyIssue>,
+}
+
+impl PrivacyReport {
+    pub fn is_clean(&self) -> bool { self.issues.is_empty() }
+
+    /// Highest severity among the detected issues.
+    pub fn severity(&self) -> Option<PrivacySeverity> {
+        self.issues.iter().map(PrivacyIssue::severity).max()
+    }
+}
+
+impl Wallet {
+    /// Detects whether a scriptPubkey was already used by any of the wallet transactions.
+    pub(crate) fn is_script_used(&self, script: &Script) -> bool {
+        self.history()
+            .iter()
+            .flat_map(|entry| &entry.tx.output)
+            .any(|txout| &txout.script_pubkey == script)
+    }
+
+    /// Analyzes privacy of a transaction draft before it gets signed. Outputs paying to the
+    /// wallet scripts are treated as change; previous outputs are taken from the PSBT inputs.
+    pub fn privacy_report(&self, psbt: &PartiallySignedTransaction) -> PrivacyReport {
+        let tx = &psbt.unsigned_tx;
+        let mut issues = vec![];
+
+        let (change, payments): (Vec<_>, Vec<_>) = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, txout)| (vout as u32, txout))
+            .partition(|(_, txout)| self.is_mine(&txout.script_pubkey).is_some());
+
+        issues.extend(
+            change
+                .iter()
+                .filter(|(_, txout)| self.is_script_used(&txout.script_pubkey))
+                .map(|(vout, _)| PrivacyIssue::OwnAddressReuse(*vout)),
+        );
+        issues.extend(
+            payments
+                .iter()
+                .filter(|(_, txout)| self.is_script_used(&txout.script_pubkey))
+                .map(|(vout, _)| PrivacyIssue::ExternalAddressReuse(*vout)),
+        );
+
+        if let [(change_vout, change_out)] = change[..] {
+            if change_out.value % ROUND_AMOUNT_UNIT != 0 {
+                issues.extend(
+                    payments
+                        .iter()
+                        .filter(|(_, txout)| txout.value % ROUND_AMOUNT_UNIT == 0)
+                        .map(|(vout, _)| PrivacyIssue::RoundAmountChange {
+                            payment: *vout,
+                            change: change_vout,
+                        }),
+                );
+            }
+        }
+
+        let payment_types = payments
+            .iter()
+            .map(|(_, txout)| ScriptType::from(&txout.script_pubkey))
+            .collect::<BTreeSet<_>>();
+        if let Some(payment) = payment_types
+            .iter()
+            .next()
+            .filter(|_| payment_types.len() == 1)
+        {
+            issues.extend(change.iter().filter_map(|(vout, txout)| {
+                let change = ScriptType::from(&txout.script_pubkey);
+                (change != *payment).then_some(PrivacyIssue::ChangeScriptMismatch {
+                    vout: *vout,
+                    change,
+                    payment: *payment,
+                })
+            }));
+        }
+
+        let input_types = psbt
+            .inputs
+            .iter()
+            .zip(&tx.input)
+            .filter_map(|(input, txin)| {
+                input.witness_utxo.clone().or_else(|| {
+                    input.non_witness_utxo.as_ref().and_then(|prev_tx| {
+                        prev_tx
+                            .output
+                            .get(txin.previous_output.vout as usize)
+                            .cloned()
+                    })
+                })
+            })
+            .map(|txout: TxOut| ScriptType::from(&txout.script_pubkey))
+            .collect::<BTreeSet<_>>();
+        if input_types.len() > 1 {
+            issues.push(PrivacyIssue::MixedInputTypes(input_types));
+        }
+
+        let labels = tx
+            .input
+            .iter()
+            .filter_map(|txin| self.coin_label(txin.previous_output))
+            .collect::<BTreeSet<_>>();
+        if labels.len() > 1 {
+            issues.push(PrivacyIssue::ClusterMerge(labels));
+        }
+
+        PrivacyReport { issues }
+    }
+}
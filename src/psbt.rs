@@ -9,10 +9,17 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::psbt::raw::ProprietaryKey;
+use bitcoin::psbt::PartiallySignedTransaction;
 use bitcoin::util::bip32::Fingerprint;
+use bitcoin::Txid;
 use wallet::psbt::Psbt;
 
+use crate::Wallet;
+
 pub const MC_PSBT_GLOBAL_SIGNER_NAME: u8 = 0;
 
 pub trait McKeys {
@@ -47,3 +54,136 @@ impl McKeys for Psbt {
         *entry = name.as_bytes().to_vec();
     }
 }
+
+/// Prefix for the proprietary PSBT keys used by opret commitments.
+pub const PSBT_OPRET_PREFIX: &[u8] = b"OPRET";
+/// Proprietary output key marking the output which will host opret commitment.
+pub const PSBT_OUT_OPRET_HOST: u8 = 0x00;
+/// Proprietary output key containing the opret commitment message.
+pub const PSBT_OUT_OPRET_COMMITMENT: u8 = 0x01;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum OpretError {
+    /// Transaction has no output {0}.
+    NoOutput(usize),
+
+    /// Output {0} can't host opret commitment since it is not a zero-value OP_RETURN output.
+    InvalidHost(usize),
+
+    /// Transaction has no output marked as opret commitment host.
+    NoHost,
+
+    /// Transaction already contains opret commitment.
+    AlreadyCommitted,
+
+    /// PSBT inputs are already signed; adding commitment will invalidate the signatures.
+    Signed,
+}
+
+/// Opret (OP_RETURN-based) commitments in PSBT, used by RGB on non-taproot wallets.
+pub trait OpretCommit {
+    /// Index of the output which hosts or will host opret commitment.
+    fn opret_host(&self) -> Option<usize>;
+
+    /// Marks zero-value OP_RETURN output as a host for the future opret commitment.
+    fn set_opret_host(&mut self, vout: usize) -> Result<(), OpretError>;
+
+    /// Commits to the message by placing it into the host output, returning the output number.
+    fn opret_commit(&mut self, commitment: sha256::Hash) -> Result<usize, OpretError>;
+
+    /// Commitment message, if the transaction was committed to.
+    fn opret_commitment(&self) -> Option<sha256::Hash>;
+}
+
+fn opret_key(subtype: u8) -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PSBT_OPRET_PREFIX.to_vec(),
+        subtype,
+        key: vec![],
+    }
+}
+
+impl OpretCommit for PartiallySignedTransaction {
+    fn opret_host(&self) -> Option<usize> {
+        self.outputs.iter().position(|output| {
+            output
+                .proprietary
+                .contains_key(&opret_key(PSBT_OUT_OPRET_HOST))
+        })
+    }
+
+    fn set_opret_host(&mut self, vout: usize) -> Result<(), OpretError> {
+        let txout = self
+            .unsigned_tx
+            .output
+            .get(vout)
+            .ok_or(OpretError::NoOutput(vout))?;
+        if !txout.script_pubkey.is_op_return() || txout.value != 0 {
+            return Err(OpretError::InvalidHost(vout));
+        }
+        if self.opret_commitment().is_some() {
+            return Err(OpretError::AlreadyCommitted);
+        }
+        for output in &mut self.outputs {
+            output.proprietary.remove(&opret_key(PSBT_OUT_OPRET_HOST));
+        }
+        self.outputs[vout]
+            .proprietary
+            .insert(opret_key(PSBT_OUT_OPRET_HOST), vec![]);
+        Ok(())
+    }
+
+    fn opret_commit(&mut self, commitment: sha256::Hash) -> Result<usize, OpretError> {
+        if self.opret_commitment().is_some() {
+            return Err(OpretError::AlreadyCommitted);
+        }
+        if self.inputs.iter().any(|input| {
+            !input.partial_sigs.is_empty()
+                || input.tap_key_sig.is_some()
+                || !input.tap_script_sigs.is_empty()
+                || input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+        }) {
+            return Err(OpretError::Signed);
+        }
+        let vout = self.opret_host().ok_or(OpretError::NoHost)?;
+        self.unsigned_tx.output[vout].script_pubkey = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&commitment[..])
+            .into_script();
+        self.outputs[vout].proprietary.insert(
+            opret_key(PSBT_OUT_OPRET_COMMITMENT),
+            commitment[..].to_vec(),
+        );
+        Ok(vout)
+    }
+
+    fn opret_commitment(&self) -> Option<sha256::Hash> {
+        self.outputs.iter().find_map(|output| {
+            output
+                .proprietary
+                .get(&opret_key(PSBT_OUT_OPRET_COMMITMENT))
+                .and_then(|data| sha256::Hash::from_slice(data).ok())
+        })
+    }
+}
+
+impl Wallet {
+    /// Registers opret commitment of a wallet transaction, such that the commitment can be
+    /// later recognized and preserved. Returns the transaction id, which is changed by the
+    /// commitment.
+    pub fn register_opret(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Txid, OpretError> {
+        let commitment = psbt.opret_commitment().ok_or(OpretError::NoHost)?;
+        let txid = psbt.unsigned_tx.txid();
+        self.opret_commitments_mut().insert(txid, commitment);
+        Ok(txid)
+    }
+
+    pub fn opret_commitment(&self, txid: Txid) -> Option<sha256::Hash> {
+        self.opret_commitments().get(&txid).copied()
+    }
+}
@@ -0,0 +1,355 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Fiat exchange rates from the public price APIs.
+
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+#[cfg(feature = "serde")]
+use serde_crate::de::DeserializeOwned;
+
+use crate::{HistoryEntry, Wallet};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum RateError {
+    /// Unable to fetch exchange rate from {0}. Details: {1}
+    Transport(String, String),
+
+    /// Exchange rate provider {0} does not support {1} currency.
+    UnsupportedFiat(String, String),
+
+    /// Exchange rate provider {0} returned invalid response.
+    InvalidResponse(String),
+
+    /// {0} is not a valid ISO 4217 currency code.
+    InvalidFiat(String),
+}
+
+/// Transport fetching data from the price APIs, usually over HTTPS or Tor.
+pub trait HttpGet {
+    type Error: StdError;
+
+    fn get(&self, url: &str) -> Result<String, Self::Error>;
+}
+
+/// Exchange rate of bitcoin to a fiat currency.
+#[derive(Clone, PartialEq, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct ExchangeRate {
+    /// Fiat currency code, in upper case.
+    pub fiat: String,
+    /// Price of a single bitcoin in the fiat currency.
+    pub rate: f64,
+    /// Name of the provider the rate was taken from.
+    pub source: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl ExchangeRate {
+    /// Converts amount in sats into the fiat currency.
+    pub fn convert(&self, sats: i64) -> f64 { sats as f64 / 100_000_000.0 * self.rate }
+
+    pub fn is_stale(&self, max_age: Duration) -> bool { Utc::now() - self.timestamp > max_age }
+}
+
+/// Source of the fiat exchange rates.
+pub trait RateProvider {
+    fn name(&self) -> &str;
+
+    fn rate(&self, fiat: &str) -> Result<ExchangeRate, RateError>;
+}
+
+/// Source of the past daily prices of bitcoin.
+pub trait HistoricalRateProvider: RateProvider {
+    fn historical_rate(&self, fiat: &str, date: NaiveDate) -> Result<f64, RateError>;
+}
+
+/// Daily prices of bitcoin in a fiat currency.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct PriceHistory {
+    /// Prices by the midnight (UTC) of the day.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    prices: BTreeMap<DateTime<Utc>, f64>,
+}
+
+impl PriceHistory {
+    fn key(date: NaiveDate) -> DateTime<Utc> {
+        DateTime::<Utc>::from_utc(date.and_hms_opt(0, 0, 0).expect("midnight"), Utc)
+    }
+
+    pub fn price(&self, date: NaiveDate) -> Option<f64> {
+        self.prices.get(&Self::key(date)).copied()
+    }
+
+    /// Records price for the day, returning the previously known one.
+    pub fn insert(&mut self, date: NaiveDate, price: f64) -> Option<f64> {
+        self.prices.insert(Self::key(date), price)
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool { self.prices.contains_key(&Self::key(date)) }
+
+    pub fn len(&self) -> usize { self.prices.len() }
+
+    pub fn is_empty(&self) -> bool { self.prices.is_empty() }
+}
+
+/// Converts fiat currency code into the upper case, checking that it consists of three latin
+/// letters (as ISO 4217 codes do), so it can be safely put into the API request URLs.
+#[cfg(feature = "serde")]
+fn fiat_code(fiat: &str) -> Result<String, RateError> {
+    if fiat.len() != 3 || !fiat.bytes().all(|c| c.is_ascii_alphabetic()) {
+        return Err(RateError::InvalidFiat(fiat.to_owned()));
+    }
+    Ok(fiat.to_ascii_uppercase())
+}
+
+/// Fetches JSON document and parses it into the response type of the provider API.
+#[cfg(feature = "serde")]
+fn get_json<T: DeserializeOwned>(
+    transport: &impl HttpGet,
+    provider: &str,
+    url: &str,
+) -> Result<T, RateError> {
+    let body = transport
+        .get(url)
+        .map_err(|err| RateError::Transport(provider.to_owned(), err.to_string()))?;
+    serde_json::from_str(&body).map_err(|_| RateError::InvalidResponse(provider.to_owned()))
+}
+
+/// Response of CoinGecko simple price API.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct CoinGeckoPrice {
+    /// Prices by the lower-case fiat currency code.
+    bitcoin: BTreeMap<String, f64>,
+}
+
+/// Response of CoinGecko coin history API.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct CoinGeckoHistory {
+    /// Absent for the dates before the price data coverage.
+    market_data: Option<CoinGeckoMarketData>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct CoinGeckoMarketData {
+    /// Prices by the lower-case fiat currency code.
+    current_price: BTreeMap<String, f64>,
+}
+
+/// Exchange rates from CoinGecko simple price API.
+#[cfg(feature = "serde")]
+pub struct CoinGecko<T: HttpGet> {
+    transport: T,
+}
+
+#[cfg(feature = "serde")]
+impl<T: HttpGet> CoinGecko<T> {
+    pub fn with(transport: T) -> Self { CoinGecko { transport } }
+}
+
+#[cfg(feature = "serde")]
+impl<T: HttpGet> RateProvider for CoinGecko<T> {
+    fn name(&self) -> &str { "coingecko.com" }
+
+    fn rate(&self, fiat: &str) -> Result<ExchangeRate, RateError> {
+        let fiat = fiat_code(fiat)?.to_lowercase();
+        let url = format!(
+            "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies={}",
+            fiat
+        );
+        let response: CoinGeckoPrice = get_json(&self.transport, self.name(), &url)?;
+        let rate = response.bitcoin.get(&fiat).copied().ok_or_else(|| {
+            RateError::UnsupportedFiat(self.name().to_owned(), fiat.to_uppercase())
+        })?;
+        Ok(ExchangeRate {
+            fiat: fiat.to_uppercase(),
+            rate,
+            source: self.name().to_owned(),
+            timestamp: Utc::now(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: HttpGet> HistoricalRateProvider for CoinGecko<T> {
+    fn historical_rate(&self, fiat: &str, date: NaiveDate) -> Result<f64, RateError> {
+        let fiat = fiat_code(fiat)?.to_lowercase();
+        let url = format!(
+            "https://api.coingecko.com/api/v3/coins/bitcoin/history?date={}&localization=false",
+            date.format("%d-%m-%Y")
+        );
+        let response: CoinGeckoHistory = get_json(&self.transport, self.name(), &url)?;
+        response
+            .market_data
+            .and_then(|market_data| market_data.current_price.get(&fiat).copied())
+            .ok_or_else(|| RateError::UnsupportedFiat(self.name().to_owned(), fiat.to_uppercase()))
+    }
+}
+
+/// Ticker of a single fiat currency in the blockchain.info API response.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct BlockchainInfoTicker {
+    last: f64,
+}
+
+/// Exchange rates from blockchain.info ticker API.
+#[cfg(feature = "serde")]
+pub struct BlockchainInfo<T: HttpGet> {
+    transport: T,
+}
+
+#[cfg(feature = "serde")]
+impl<T: HttpGet> BlockchainInfo<T> {
+    pub fn with(transport: T) -> Self { BlockchainInfo { transport } }
+}
+
+#[cfg(feature = "serde")]
+impl<T: HttpGet> RateProvider for BlockchainInfo<T> {
+    fn name(&self) -> &str { "blockchain.info" }
+
+    fn rate(&self, fiat: &str) -> Result<ExchangeRate, RateError> {
+        let fiat = fiat_code(fiat)?;
+        let tickers: BTreeMap<String, BlockchainInfoTicker> = get_json(
+            &self.transport,
+            self.name(),
+            "https://blockchain.info/ticker",
+        )?;
+        let rate = tickers
+            .get(&fiat)
+            .map(|ticker| ticker.last)
+            .ok_or_else(|| RateError::UnsupportedFiat(self.name().to_owned(), fiat.clone()))?;
+        Ok(ExchangeRate {
+            fiat,
+            rate,
+            source: self.name().to_owned(),
+            timestamp: Utc::now(),
+        })
+    }
+}
+
+impl Wallet {
+    /// Fetches exchange rate for the wallet fiat currency from the provider, caching it.
+    pub fn update_exchange_rate(
+        &mut self,
+        provider: &impl RateProvider,
+    ) -> Result<ExchangeRate, RateError> {
+        let rate = provider.rate(&self.ephemerals().fiat)?;
+        let ephemerals = self.ephemerals_mut();
+        ephemerals.exchange_rate = rate.rate;
+        ephemerals.rates.insert(rate.fiat.clone(), rate.clone());
+        Ok(rate)
+    }
+
+    /// Selects fiat currency used for the wallet, returning whether it has changed. Cached rate
+    /// for the currency, if any, becomes the current exchange rate.
+    pub fn set_fiat(&mut self, fiat: &str) -> bool {
+        let fiat = fiat.to_uppercase();
+        let ephemerals = self.ephemerals_mut();
+        if ephemerals.fiat == fiat {
+            return false;
+        }
+        ephemerals.exchange_rate = ephemerals
+            .rates
+            .get(&fiat)
+            .map(|rate| rate.rate)
+            .unwrap_or_default();
+        ephemerals.fiat = fiat;
+        true
+    }
+
+    /// Cached exchange rate for the wallet fiat currency.
+    pub fn exchange_rate(&self) -> Option<&ExchangeRate> {
+        let ephemerals = self.ephemerals();
+        ephemerals.rates.get(&ephemerals.fiat)
+    }
+
+    /// Wallet balance in the fiat currency at the cached exchange rate.
+    pub fn fiat_balance(&self) -> Option<f64> {
+        self.exchange_rate()
+            .map(|rate| rate.convert(self.state().balance as i64))
+    }
+
+    /// Change of the wallet balance by the transaction in the fiat currency, at the cached
+    /// (current, not historical) exchange rate.
+    pub fn fiat_value(&self, entry: &HistoryEntry) -> Option<f64> {
+        self.exchange_rate()
+            .map(|rate| rate.convert(entry.balance()))
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use std::cell::RefCell;
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Transport returning the same response for all requests and recording their URLs.
+    struct Mock {
+        response: &'static str,
+        urls: RefCell<Vec<String>>,
+    }
+
+    impl Mock {
+        fn new(response: &'static str) -> Self {
+            Mock {
+                response,
+                urls: empty!(),
+            }
+        }
+    }
+
+    impl HttpGet for &Mock {
+        type Error = Infallible;
+
+        fn get(&self, url: &str) -> Result<String, Self::Error> {
+            self.urls.borrow_mut().push(url.to_owned());
+            Ok(self.response.to_owned())
+        }
+    }
+
+    #[test]
+    fn coingecko_fiat_code() {
+        let mock = Mock::new(r#"{"bitcoin":{"eur":25000.5}}"#);
+        let provider = CoinGecko::with(&mock);
+        let rate = provider.rate("Eur").unwrap();
+        assert_eq!(rate.fiat, "EUR");
+        assert_eq!(rate.rate, 25000.5);
+        assert_eq!(mock.urls.borrow().as_slice(), [
+            "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=eur"
+        ]);
+
+        for fiat in ["usd&ids=ethereum", "eu", "€ur", ""] {
+            assert_eq!(
+                provider.rate(fiat),
+                Err(RateError::InvalidFiat(fiat.to_owned()))
+            );
+        }
+        assert_eq!(mock.urls.borrow().len(), 1);
+    }
+}
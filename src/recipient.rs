@@ -0,0 +1,135 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Validation of the payment destinations entered by the user.
+
+use std::str::FromStr;
+
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::util::address::{Payload, WitnessVersion};
+use bitcoin::Address;
+use wallet::onchain::PublicNetwork;
+
+use crate::{PaymentUri, ScriptType, Wallet};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum RecipientError {
+    /// Destination address is not provided.
+    Empty,
+
+    /// Destination is not a valid bitcoin address or payment URI. Details: {0}
+    InvalidAddress(String),
+
+    /// Address is intended for {0} network, while the wallet operates on {1}.
+    NetworkMismatch(bitcoin::Network, PublicNetwork),
+
+    /// Address pays to a provably unspendable script; the funds will be burned.
+    Burn,
+}
+
+/// Warnings about a valid destination, which should be confirmed by the user.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum RecipientWarning {
+    /// Address uses witness version {0}, which is not yet defined by consensus rules; funds
+    /// sent to it can be taken by anybody until a soft fork defines its spending rules.
+    FutureWitnessVersion(u8),
+
+    /// Address belongs to this wallet.
+    OwnAddress,
+
+    /// Address was already paid by this wallet; its reuse damages privacy.
+    ReusedAddress,
+}
+
+/// Structured result of a destination validation.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RecipientCheck {
+    pub address: Address,
+    pub script_type: ScriptType,
+    /// Payment URI, if the destination was given as one.
+    pub uri: Option<PaymentUri>,
+    pub warnings: Vec<RecipientWarning>,
+}
+
+impl RecipientCheck {
+    pub fn is_clean(&self) -> bool { self.warnings.is_empty() }
+}
+
+/// Detects payloads which are obviously unspendable: all-zero or all-one hashes and taproot
+/// output keys which are not valid curve points.
+fn is_burn(payload: &Payload) -> bool {
+    let is_pattern = |data: &[u8]| data.iter().all(|b| *b == 0) || data.iter().all(|b| *b == 0xFF);
+    match payload {
+        Payload::PubkeyHash(hash) => is_pattern(&hash[..]),
+        Payload::ScriptHash(hash) => is_pattern(&hash[..]),
+        Payload::WitnessProgram { version, program } => {
+            is_pattern(program)
+                || (*version == WitnessVersion::V1
+                    && program.len() == 32
+                    && XOnlyPublicKey::from_slice(program).is_err())
+        }
+    }
+}
+
+impl Wallet {
+    /// Validates destination pasted by the user, which may be an address or a BIP-21 payment
+    /// URI.
+    pub fn validate_recipient(&self, destination: &str) -> Result<RecipientCheck, RecipientError> {
+        let destination = destination.trim();
+        if destination.is_empty() {
+            return Err(RecipientError::Empty);
+        }
+        let (address, uri) = if destination.to_lowercase().starts_with("bitcoin:") {
+            let uri = PaymentUri::from_str(destination)
+                .map_err(|err| RecipientError::InvalidAddress(err.to_string()))?;
+            (uri.address.clone(), Some(uri))
+        } else {
+            let address = Address::from_str(destination)
+                .map_err(|err| RecipientError::InvalidAddress(err.to_string()))?;
+            (address, None)
+        };
+
+        let network = self.as_settings().network();
+        if !address.is_valid_for_network(bitcoin::Network::from(network)) {
+            return Err(RecipientError::NetworkMismatch(address.network, network));
+        }
+        if is_burn(&address.payload) {
+            return Err(RecipientError::Burn);
+        }
+
+        let mut warnings = vec![];
+        if let Payload::WitnessProgram { version, program } = &address.payload {
+            let is_defined = match version {
+                WitnessVersion::V0 => true,
+                WitnessVersion::V1 => program.len() == 32,
+                _ => false,
+            };
+            if !is_defined {
+                warnings.push(RecipientWarning::FutureWitnessVersion(version.to_num()));
+            }
+        }
+        let script = address.script_pubkey();
+        if self.is_mine(&script).is_some() {
+            warnings.push(RecipientWarning::OwnAddress);
+        } else if self.is_script_used(&script) {
+            warnings.push(RecipientWarning::ReusedAddress);
+        }
+
+        Ok(RecipientCheck {
+            script_type: ScriptType::from(&script),
+            address,
+            uri,
+            warnings,
+        })
+    }
+}
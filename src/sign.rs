@@ -9,12 +9,21 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
-use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey, SECP256K1};
-use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, Fingerprint};
+use std::collections::BTreeMap;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{schnorr, Parity, PublicKey, Scalar, Secp256k1, SecretKey, SECP256K1};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint};
+use bitcoin::util::taproot::{TapBranchHash, TapTweakHash};
 use bitcoin::{secp256k1, KeyPair, XOnlyPublicKey};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
 use miniscript::ToPublicKey;
+use wallet::hd::UnhardenedIndex;
 use wallet::psbt::sign::{SecretProvider, SecretProviderError};
 
+use crate::Signer;
+
 #[derive(Debug)]
 pub struct XprivSigner {
     pub xpriv: ExtendedPrivKey,
@@ -75,3 +84,688 @@ impl SecretProvider<secp256k1::All> for XprivSigner {
 
     fn use_musig(&self) -> bool { false }
 }
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MuSigError {
+    /// MuSig2 requires at least two distinct participant keys.
+    NotEnoughKeys,
+
+    /// Key {0} does not participate in the MuSig2 session.
+    UnknownParticipant(PublicKey),
+
+    /// Nonce of participant {0} was already received.
+    DuplicateNonce(PublicKey),
+
+    /// Not all nonces were received; {0} are still missing.
+    MissingNonces(usize),
+
+    /// Partial signature of participant {0} was already received.
+    DuplicatePartialSig(PublicKey),
+
+    /// Not all partial signatures were received; {0} are still missing.
+    MissingPartialSigs(usize),
+
+    /// Partial signature of participant {0} is invalid.
+    InvalidPartialSig(PublicKey),
+
+    /// Secret nonce does not belong to participant {0} or does not match the nonce registered
+    /// in the session.
+    NonceMismatch(PublicKey),
+
+    /// Cryptographic operation produced an invalid value; a new session must be started.
+    InvalidValue,
+}
+
+impl From<secp256k1::Error> for MuSigError {
+    fn from(_: secp256k1::Error) -> Self { MuSigError::InvalidValue }
+}
+
+pub(crate) fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for item in data {
+        engine.input(item);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+pub(crate) fn hash_scalar(tag: &[u8], data: &[&[u8]]) -> Result<SecretKey, secp256k1::Error> {
+    SecretKey::from_slice(&tagged_hash(tag, data)[..])
+}
+
+pub(crate) fn is_odd(key: PublicKey) -> bool { key.x_only_public_key().1 == Parity::Odd }
+
+pub(crate) fn x_bytes(key: PublicKey) -> [u8; 32] { key.x_only_public_key().0.serialize() }
+
+/// MuSig2 (BIP-327) aggregation of N-of-N participant keys into a single key, which can be
+/// used as a taproot internal key for the key-path spending.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MuSigKeyAgg {
+    /// Participant keys, sorted.
+    keys: Vec<PublicKey>,
+    list_hash: sha256::Hash,
+    second_key: Option<PublicKey>,
+    internal_key: PublicKey,
+    output_key: PublicKey,
+    /// Whether accumulated sign of the tweaks is negative.
+    gacc_neg: bool,
+    /// Accumulated tweak; `None` for zero.
+    tacc: Option<[u8; 32]>,
+}
+
+impl MuSigKeyAgg {
+    /// Aggregates distinct participant keys, sorting them first, such that the aggregated key
+    /// does not depend on the order in which the participants are listed.
+    pub fn with(keys: impl IntoIterator<Item = PublicKey>) -> Result<Self, MuSigError> {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_by_key(PublicKey::serialize);
+        keys.dedup();
+        if keys.len() < 2 {
+            return Err(MuSigError::NotEnoughKeys);
+        }
+        MuSigKeyAgg::with_order(keys)
+    }
+
+    /// Aggregates participant keys in the given order (BIP-327 `KeyAgg` algorithm).
+    pub fn with_order(keys: Vec<PublicKey>) -> Result<Self, MuSigError> {
+        let first_key = *keys.first().ok_or(MuSigError::NotEnoughKeys)?;
+        let data = keys.iter().map(PublicKey::serialize).collect::<Vec<_>>();
+        let list_hash = tagged_hash(
+            b"KeyAgg list",
+            &data.iter().map(|pk| &pk[..]).collect::<Vec<_>>(),
+        );
+        let second_key = keys.iter().find(|key| **key != first_key).copied();
+        let terms = keys
+            .iter()
+            .map(
+                |pk| match key_agg_coefficient(list_hash, second_key, *pk)? {
+                    None => Ok(*pk),
+                    Some(coef) => Ok(pk.mul_tweak(SECP256K1, &Scalar::from(coef))?),
+                },
+            )
+            .collect::<Result<Vec<_>, MuSigError>>()?;
+        let internal_key = PublicKey::combine_keys(&terms.iter().collect::<Vec<_>>())?;
+        Ok(MuSigKeyAgg {
+            keys,
+            list_hash,
+            second_key,
+            internal_key,
+            output_key: internal_key,
+            gacc_neg: false,
+            tacc: None,
+        })
+    }
+
+    /// Aggregates keys and applies BIP-341 taproot tweak with a given script tree root.
+    pub fn with_taproot(
+        keys: impl IntoIterator<Item = PublicKey>,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<Self, MuSigError> {
+        let mut agg = MuSigKeyAgg::with(keys)?;
+        let tweak =
+            TapTweakHash::from_key_and_tweak(agg.internal_key.x_only_public_key().0, merkle_root);
+        let tweak = SecretKey::from_slice(&tweak[..])?;
+        let negate = is_odd(agg.output_key);
+        let key = if negate { agg.output_key.negate(SECP256K1) } else { agg.output_key };
+        agg.output_key = key.add_exp_tweak(SECP256K1, &Scalar::from(tweak))?;
+        agg.gacc_neg ^= negate;
+        let tacc = match agg
+            .tacc
+            .map(|tacc| SecretKey::from_slice(&tacc))
+            .transpose()?
+        {
+            Some(tacc) if negate => tacc.negate().add_tweak(&Scalar::from(tweak))?,
+            Some(tacc) => tacc.add_tweak(&Scalar::from(tweak))?,
+            None => tweak,
+        };
+        agg.tacc = Some(tacc.secret_bytes());
+        Ok(agg)
+    }
+
+    /// Aggregates keys of the wallet signers derived for a given terminal, as selected by
+    /// [`crate::ConditionCompiler::musig_signers`].
+    pub fn derive(
+        signers: &[&Signer],
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<Self, MuSigError> {
+        let terminal = [ChildNumber::from(change), ChildNumber::from(index)];
+        let keys = signers
+            .iter()
+            .map(|signer| {
+                signer
+                    .xpub
+                    .derive_pub(SECP256K1, &terminal)
+                    .map(|xpub| xpub.public_key)
+                    .map_err(|_| MuSigError::InvalidValue)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        MuSigKeyAgg::with_taproot(keys, merkle_root)
+    }
+
+    pub fn keys(&self) -> &[PublicKey] { &self.keys }
+
+    /// Aggregated key before the taproot tweak, used as the taproot internal key.
+    pub fn internal_key(&self) -> XOnlyPublicKey { self.internal_key.x_only_public_key().0 }
+
+    /// Aggregated key after the taproot tweak, for which the final signature is valid.
+    pub fn output_key(&self) -> XOnlyPublicKey { self.output_key.x_only_public_key().0 }
+
+    /// Key aggregation coefficient; `None` stands for one.
+    fn coefficient(&self, key: PublicKey) -> Result<Option<SecretKey>, MuSigError> {
+        if !self.keys.contains(&key) {
+            return Err(MuSigError::UnknownParticipant(key));
+        }
+        key_agg_coefficient(self.list_hash, self.second_key, key)
+    }
+}
+
+fn key_agg_coefficient(
+    list_hash: sha256::Hash,
+    second_key: Option<PublicKey>,
+    key: PublicKey,
+) -> Result<Option<SecretKey>, MuSigError> {
+    if Some(key) == second_key {
+        return Ok(None);
+    }
+    Ok(Some(hash_scalar(b"KeyAgg coefficient", &[
+        &list_hash[..],
+        &key.serialize(),
+    ])?))
+}
+
+/// Secret nonce of a MuSig2 participant. It is consumed by signing and must never be reused.
+#[derive(Debug)]
+pub struct MuSigSecNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+    key: PublicKey,
+}
+
+impl MuSigSecNonce {
+    /// Public nonce corresponding to the secret one.
+    pub fn public(&self) -> MuSigPubNonce {
+        MuSigPubNonce {
+            r1: self.k1.public_key(SECP256K1),
+            r2: self.k2.public_key(SECP256K1),
+        }
+    }
+}
+
+/// Generates secret nonce from the random data and the optional signing context (BIP-327
+/// `NonceGen` algorithm).
+fn nonce_gen(
+    rand: [u8; 32],
+    sk: Option<&SecretKey>,
+    key: PublicKey,
+    aggpk: Option<[u8; 32]>,
+    msg: Option<&[u8]>,
+    extra_in: &[u8],
+) -> Result<MuSigSecNonce, MuSigError> {
+    let rand = match sk {
+        Some(sk) => {
+            let aux = tagged_hash(b"MuSig/aux", &[&rand]);
+            let mut data = sk.secret_bytes();
+            data.iter_mut()
+                .zip(&aux[..])
+                .for_each(|(byte, aux)| *byte ^= aux);
+            data
+        }
+        None => rand,
+    };
+    let pk = key.serialize();
+    let aggpk = aggpk.map(|key| key.to_vec()).unwrap_or_default();
+    let msg_prefixed = match msg {
+        Some(msg) => {
+            let mut data = vec![1u8];
+            data.extend((msg.len() as u64).to_be_bytes());
+            data.extend(msg);
+            data
+        }
+        None => vec![0u8],
+    };
+    let nonce = |no: u8| {
+        hash_scalar(b"MuSig/nonce", &[
+            &rand,
+            &[pk.len() as u8],
+            &pk,
+            &[aggpk.len() as u8],
+            &aggpk,
+            &msg_prefixed,
+            &(extra_in.len() as u32).to_be_bytes(),
+            extra_in,
+            &[no],
+        ])
+    };
+    Ok(MuSigSecNonce {
+        k1: nonce(0)?,
+        k2: nonce(1)?,
+        key,
+    })
+}
+
+/// Public nonce of a MuSig2 participant, shared with other participants in the first round.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MuSigPubNonce {
+    pub r1: PublicKey,
+    pub r2: PublicKey,
+}
+
+impl MuSigPubNonce {
+    pub fn from_slice(data: &[u8]) -> Result<Self, MuSigError> {
+        if data.len() != 66 {
+            return Err(MuSigError::InvalidValue);
+        }
+        Ok(MuSigPubNonce {
+            r1: PublicKey::from_slice(&data[..33])?,
+            r2: PublicKey::from_slice(&data[33..])?,
+        })
+    }
+
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut data = [0u8; 66];
+        data[..33].copy_from_slice(&self.r1.serialize());
+        data[33..].copy_from_slice(&self.r2.serialize());
+        data
+    }
+}
+
+/// Partial signature of a MuSig2 participant, shared in the second round.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MuSigPartialSig([u8; 32]);
+
+impl MuSigPartialSig {
+    pub fn from_slice(data: &[u8]) -> Result<Self, MuSigError> {
+        Ok(MuSigPartialSig(SecretKey::from_slice(data)?.secret_bytes()))
+    }
+
+    pub fn serialize(&self) -> [u8; 32] { self.0 }
+
+    fn scalar(&self) -> SecretKey {
+        SecretKey::from_slice(&self.0).expect("partial signature is always a valid scalar")
+    }
+}
+
+/// Round of the MuSig2 signing session.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum MuSigRound {
+    /// Participants exchange public nonces.
+    #[display("nonces")]
+    Nonces,
+
+    /// Participants exchange partial signatures.
+    #[display("partial signatures")]
+    PartialSigs,
+
+    /// All partial signatures are collected and the final signature can be produced.
+    #[display("complete")]
+    Complete,
+}
+
+/// Two-round MuSig2 signing session for a single message (like taproot key-path sighash),
+/// collecting nonces and partial signatures of all participants.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MuSigSession {
+    key_agg: MuSigKeyAgg,
+    msg: [u8; 32],
+    nonces: BTreeMap<PublicKey, MuSigPubNonce>,
+    partial_sigs: BTreeMap<PublicKey, MuSigPartialSig>,
+}
+
+/// Values shared by all participants once all nonces are known.
+struct MuSigContext {
+    r: PublicKey,
+    b: SecretKey,
+    e: SecretKey,
+}
+
+impl MuSigSession {
+    pub fn new(key_agg: MuSigKeyAgg, msg: [u8; 32]) -> Self {
+        MuSigSession {
+            key_agg,
+            msg,
+            nonces: empty!(),
+            partial_sigs: empty!(),
+        }
+    }
+
+    pub fn key_agg(&self) -> &MuSigKeyAgg { &self.key_agg }
+
+    pub fn message(&self) -> [u8; 32] { self.msg }
+
+    pub fn round(&self) -> MuSigRound {
+        if self.nonces.len() < self.key_agg.keys.len() {
+            MuSigRound::Nonces
+        } else if self.partial_sigs.len() < self.key_agg.keys.len() {
+            MuSigRound::PartialSigs
+        } else {
+            MuSigRound::Complete
+        }
+    }
+
+    pub fn nonces(&self) -> &BTreeMap<PublicKey, MuSigPubNonce> { &self.nonces }
+
+    pub fn partial_sigs(&self) -> &BTreeMap<PublicKey, MuSigPartialSig> { &self.partial_sigs }
+
+    /// Generates fresh nonce for the participant owning the secret key, registering its
+    /// public part in the session. The secret nonce must be kept until the second round.
+    pub fn generate_nonce(&mut self, sk: &SecretKey) -> Result<MuSigSecNonce, MuSigError> {
+        let mut rand = [0u8; 32];
+        OsRng.fill_bytes(&mut rand);
+        let secnonce = nonce_gen(
+            rand,
+            Some(sk),
+            sk.public_key(SECP256K1),
+            Some(self.key_agg.output_key().serialize()),
+            Some(&self.msg),
+            &[],
+        )?;
+        self.add_nonce(secnonce.key, secnonce.public())?;
+        Ok(secnonce)
+    }
+
+    /// Registers public nonce received from another participant.
+    pub fn add_nonce(&mut self, key: PublicKey, nonce: MuSigPubNonce) -> Result<(), MuSigError> {
+        if !self.key_agg.keys.contains(&key) {
+            return Err(MuSigError::UnknownParticipant(key));
+        }
+        if self.nonces.contains_key(&key) {
+            return Err(MuSigError::DuplicateNonce(key));
+        }
+        self.nonces.insert(key, nonce);
+        Ok(())
+    }
+
+    fn context(&self) -> Result<MuSigContext, MuSigError> {
+        let missing = self.key_agg.keys.len() - self.nonces.len();
+        if missing > 0 {
+            return Err(MuSigError::MissingNonces(missing));
+        }
+        let r1 = PublicKey::combine_keys(&self.nonces.values().map(|n| &n.r1).collect::<Vec<_>>())?;
+        let r2 = PublicKey::combine_keys(&self.nonces.values().map(|n| &n.r2).collect::<Vec<_>>())?;
+        let output_key = self.key_agg.output_key().serialize();
+        let b = hash_scalar(b"MuSig/noncecoef", &[
+            &r1.serialize(),
+            &r2.serialize(),
+            &output_key,
+            &self.msg,
+        ])?;
+        let r = r1.combine(&r2.mul_tweak(SECP256K1, &Scalar::from(b))?)?;
+        let e = hash_scalar(b"BIP0340/challenge", &[&x_bytes(r), &output_key, &self.msg])?;
+        Ok(MuSigContext { r, b, e })
+    }
+
+    /// Whether the participant secret key has to be negated to match the aggregated key parity.
+    fn negate_key(&self) -> bool { is_odd(self.key_agg.output_key) ^ self.key_agg.gacc_neg }
+
+    /// Produces partial signature with the participant secret key and its secret nonce,
+    /// registering it in the session.
+    pub fn sign(
+        &mut self,
+        secnonce: MuSigSecNonce,
+        sk: &SecretKey,
+    ) -> Result<MuSigPartialSig, MuSigError> {
+        let key = sk.public_key(SECP256K1);
+        let pubnonce = self
+            .nonces
+            .get(&key)
+            .ok_or(MuSigError::NonceMismatch(key))?;
+        if secnonce.key != key
+            || pubnonce.r1 != secnonce.k1.public_key(SECP256K1)
+            || pubnonce.r2 != secnonce.k2.public_key(SECP256K1)
+        {
+            return Err(MuSigError::NonceMismatch(key));
+        }
+        let ctx = self.context()?;
+        let (k1, k2) = if is_odd(ctx.r) {
+            (secnonce.k1.negate(), secnonce.k2.negate())
+        } else {
+            (secnonce.k1, secnonce.k2)
+        };
+        let d = if self.negate_key() { sk.negate() } else { *sk };
+        let ead = match self.key_agg.coefficient(key)? {
+            Some(a) => d.mul_tweak(&Scalar::from(a))?,
+            None => d,
+        }
+        .mul_tweak(&Scalar::from(ctx.e))?;
+        let s = k2
+            .mul_tweak(&Scalar::from(ctx.b))?
+            .add_tweak(&Scalar::from(k1))?
+            .add_tweak(&Scalar::from(ead))?;
+        let sig = MuSigPartialSig(s.secret_bytes());
+        self.add_partial_sig(key, sig)?;
+        Ok(sig)
+    }
+
+    /// Verifies and registers partial signature received from another participant.
+    pub fn add_partial_sig(
+        &mut self,
+        key: PublicKey,
+        sig: MuSigPartialSig,
+    ) -> Result<(), MuSigError> {
+        if self.partial_sigs.contains_key(&key) {
+            return Err(MuSigError::DuplicatePartialSig(key));
+        }
+        let nonce = self
+            .nonces
+            .get(&key)
+            .ok_or(MuSigError::UnknownParticipant(key))?;
+        let ctx = self.context()?;
+        let re = nonce
+            .r1
+            .combine(&nonce.r2.mul_tweak(SECP256K1, &Scalar::from(ctx.b))?)?;
+        let re = if is_odd(ctx.r) { re.negate(SECP256K1) } else { re };
+        let p = if self.negate_key() { key.negate(SECP256K1) } else { key };
+        let ap = match self.key_agg.coefficient(key)? {
+            Some(a) => p.mul_tweak(SECP256K1, &Scalar::from(a))?,
+            None => p,
+        };
+        let expected = re.combine(&ap.mul_tweak(SECP256K1, &Scalar::from(ctx.e))?)?;
+        if sig.scalar().public_key(SECP256K1) != expected {
+            return Err(MuSigError::InvalidPartialSig(key));
+        }
+        self.partial_sigs.insert(key, sig);
+        Ok(())
+    }
+
+    /// Aggregates partial signatures into the final BIP-340 signature valid for the output key.
+    pub fn signature(&self) -> Result<schnorr::Signature, MuSigError> {
+        let missing = self.key_agg.keys.len() - self.partial_sigs.len();
+        if missing > 0 {
+            return Err(MuSigError::MissingPartialSigs(missing));
+        }
+        let ctx = self.context()?;
+        let mut sigs = self.partial_sigs.values().map(MuSigPartialSig::scalar);
+        let first = sigs.next().ok_or(MuSigError::MissingPartialSigs(0))?;
+        let mut s = sigs.try_fold(first, |acc, sig| acc.add_tweak(&Scalar::from(sig)))?;
+        if let Some(tacc) = self.key_agg.tacc {
+            let etacc = SecretKey::from_slice(&tacc)?.mul_tweak(&Scalar::from(ctx.e))?;
+            let etacc = if is_odd(self.key_agg.output_key) { etacc.negate() } else { etacc };
+            s = s.add_tweak(&Scalar::from(etacc))?;
+        }
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(&x_bytes(ctx.r));
+        data[32..].copy_from_slice(&s.secret_bytes());
+        let sig = schnorr::Signature::from_slice(&data)?;
+        let msg = secp256k1::Message::from_slice(&self.msg)?;
+        SECP256K1
+            .verify_schnorr(&sig, &msg, &self.key_agg.output_key())
+            .map_err(|_| MuSigError::InvalidValue)?;
+        Ok(sig)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    fn pk(hex: &str) -> PublicKey { PublicKey::from_str(hex).unwrap() }
+
+    fn sk(hex: &str) -> SecretKey { SecretKey::from_str(hex).unwrap() }
+
+    fn bytes32(hex: &str) -> [u8; 32] {
+        let mut data = [0u8; 32];
+        data.copy_from_slice(&Vec::<u8>::from_hex(hex).unwrap());
+        data
+    }
+
+    fn key_agg_keys() -> [PublicKey; 3] {
+        [
+            pk("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            pk("03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+            pk("023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66"),
+        ]
+    }
+
+    // BIP-327 `key_agg_vectors.json`
+    #[test]
+    fn key_agg_vectors() {
+        let keys = key_agg_keys();
+        for (indices, expected) in [
+            (
+                &[0, 1, 2][..],
+                "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c",
+            ),
+            (
+                &[2, 1, 0][..],
+                "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b",
+            ),
+            (
+                &[0, 0, 0][..],
+                "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935",
+            ),
+            (
+                &[0, 0, 1, 1][..],
+                "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e",
+            ),
+        ] {
+            let key_agg =
+                MuSigKeyAgg::with_order(indices.iter().map(|i| keys[*i]).collect()).unwrap();
+            assert_eq!(key_agg.internal_key().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn key_agg_sorted() {
+        let keys = key_agg_keys();
+        let sorted = MuSigKeyAgg::with(keys).unwrap();
+        let reversed = MuSigKeyAgg::with(keys.into_iter().rev()).unwrap();
+        assert_eq!(sorted, reversed);
+        assert_eq!(
+            MuSigKeyAgg::with([keys[0], keys[0]]),
+            Err(MuSigError::NotEnoughKeys)
+        );
+    }
+
+    // BIP-327 `nonce_gen_vectors.json`
+    #[test]
+    fn nonce_gen_vector() {
+        let secnonce = nonce_gen(
+            [0x0F; 32],
+            Some(&SecretKey::from_slice(&[0x02; 32]).unwrap()),
+            pk("024D4B6CD1361032CA9BD2AEB9D900AA4D45D9EAD80AC9423374C451A7254D0766"),
+            Some([0x07; 32]),
+            Some(&[0x01; 32]),
+            &[0x08; 32],
+        )
+        .unwrap();
+        assert_eq!(
+            secnonce.k1.secret_bytes().to_hex(),
+            "b114e502beaa4e301dd08a50264172c84e41650e6cb726b410c0694d59effb64"
+        );
+        assert_eq!(
+            secnonce.k2.secret_bytes().to_hex(),
+            "95b5caf28d045b973d63e3c99a44b807bde375fd6cb39e46dc4a511708d0e9d2"
+        );
+    }
+
+    // BIP-327 `sign_verify_vectors.json`
+    #[test]
+    fn sign_vectors() {
+        let secret = sk("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671");
+        let keys = [
+            pk("03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9"),
+            pk("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            pk("02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661"),
+        ];
+        let pubnonces = [
+            "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+        ]
+        .map(|hex| MuSigPubNonce::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap());
+        let msg = bytes32("F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF");
+        assert_eq!(secret.public_key(SECP256K1), keys[0]);
+
+        for (indices, expected) in [
+            (
+                [0, 1, 2],
+                "012abbcb52b3016ac03ad82395a1a415c48b93def78718e62a7a90052fe224fb",
+            ),
+            (
+                [1, 0, 2],
+                "9ff2f7aaa856150cc8819254218d3adeeb0535269051897724f9db3789513a52",
+            ),
+            (
+                [1, 2, 0],
+                "fa23c359f6fac4e7796bb93bc9f0532a95468c539ba20ff86d7c76ed92227900",
+            ),
+        ] {
+            let key_agg =
+                MuSigKeyAgg::with_order(indices.iter().map(|i| keys[*i]).collect()).unwrap();
+            let mut session = MuSigSession::new(key_agg, msg);
+            for i in indices {
+                session.add_nonce(keys[i], pubnonces[i]).unwrap();
+            }
+            let secnonce = MuSigSecNonce {
+                k1: sk("508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61"),
+                k2: sk("FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7"),
+                key: keys[0],
+            };
+            assert_eq!(secnonce.public(), pubnonces[0]);
+            let sig = session.sign(secnonce, &secret).unwrap();
+            assert_eq!(sig.serialize().to_hex(), expected);
+        }
+    }
+
+    #[test]
+    fn taproot_session() {
+        let secrets =
+            [[0x11; 32], [0x22; 32], [0x33; 32]].map(|data| SecretKey::from_slice(&data).unwrap());
+        let merkle_root = TapBranchHash::from_inner([0x44; 32]);
+        let key_agg = MuSigKeyAgg::with_taproot(
+            secrets.iter().map(|sk| sk.public_key(SECP256K1)),
+            Some(merkle_root),
+        )
+        .unwrap();
+        let tweak = TapTweakHash::from_key_and_tweak(key_agg.internal_key(), Some(merkle_root));
+        let (output_key, _) = key_agg
+            .internal_key()
+            .add_tweak(
+                SECP256K1,
+                &Scalar::from_be_bytes(tweak.into_inner()).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(key_agg.output_key(), output_key);
+
+        let mut session = MuSigSession::new(key_agg, [0x55; 32]);
+        let secnonces = secrets
+            .iter()
+            .map(|sk| session.generate_nonce(sk).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(session.round(), MuSigRound::PartialSigs);
+        for (secnonce, sk) in secnonces.into_iter().zip(&secrets) {
+            session.sign(secnonce, sk).unwrap();
+        }
+        assert_eq!(session.round(), MuSigRound::Complete);
+        assert!(session.signature().is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Custom signets (BIP-325), run by teams for their own testing.
+
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::Script;
+use wallet::onchain::PublicNetwork;
+
+use crate::{ElectrumServer, WalletSettings};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SignetError {
+    /// Custom signet can be used only by the wallets operating on signet, while the wallet uses
+    /// {0}.
+    NotSignet(PublicNetwork),
+
+    /// Custom signet must have at least one dedicated Electrum server.
+    NoElectrum,
+
+    /// Signet challenge script must not be empty.
+    EmptyChallenge,
+}
+
+/// Custom signet, defined by its block signing challenge.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct CustomSignet {
+    /// Script which must be satisfied by the block signatures.
+    pub challenge: Script,
+    /// Electrum servers indexing the signet; the first one is used by default.
+    pub electrum: Vec<ElectrumServer>,
+}
+
+impl CustomSignet {
+    pub fn with(
+        challenge: Script,
+        electrum: impl IntoIterator<Item = ElectrumServer>,
+    ) -> Result<CustomSignet, SignetError> {
+        let signet = CustomSignet {
+            challenge,
+            electrum: electrum.into_iter().collect(),
+        };
+        if signet.challenge.is_empty() {
+            return Err(SignetError::EmptyChallenge);
+        }
+        if signet.electrum.is_empty() {
+            return Err(SignetError::NoElectrum);
+        }
+        Ok(signet)
+    }
+
+    /// Network magic of the signet, made of the first four bytes of the double SHA256 hash of
+    /// the serialized challenge.
+    pub fn magic(&self) -> u32 {
+        let hash = sha256d::Hash::hash(&serialize(&self.challenge));
+        u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+    }
+}
+
+impl WalletSettings {
+    /// Makes signet wallet operate on a custom signet, switching it to the first of the signet
+    /// Electrum servers. Passing `None` returns the wallet to the default signet.
+    pub fn set_custom_signet(&mut self, signet: Option<CustomSignet>) -> Result<bool, SignetError> {
+        if self.network() != PublicNetwork::Signet {
+            return Err(SignetError::NotSignet(self.network()));
+        }
+        if self.signet() == &signet {
+            return Ok(false);
+        }
+        if let Some(electrum) = signet.as_ref().and_then(|signet| signet.electrum.first()) {
+            self.update_electrum(electrum.clone());
+        }
+        self.signet = signet;
+        Ok(true)
+    }
+
+    /// Network magic used by the wallet peers.
+    pub fn network_magic(&self) -> u32 {
+        match self.signet() {
+            Some(signet) => signet.magic(),
+            None => bitcoin::Network::from(self.network()).magic(),
+        }
+    }
+
+    /// Electrum servers which can be used by the wallet: the dedicated servers of the custom
+    /// signet, or the configured server otherwise.
+    pub fn electrum_servers(&self) -> Vec<ElectrumServer> {
+        match self.signet() {
+            Some(signet) => signet.electrum.clone(),
+            None => vec![self.electrum().clone()],
+        }
+    }
+}
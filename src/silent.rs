@@ -0,0 +1,722 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Silent payments (BIP-352): reusable addresses derived from the wallet signer keys, scanning
+//! of transactions for the taproot outputs paying to them and computation of the output keys
+//! for paying to silent payment addresses.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bitcoin::bech32::{self, u5, FromBase32, ToBase32, Variant};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::{hash160, sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, Scalar, SecretKey, XOnlyPublicKey, SECP256K1};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, Fingerprint};
+use bitcoin::{OutPoint, Transaction, TxIn, TxOut, Txid};
+use bitcoin_scripts::address::AddressCompat;
+use bitcoin_scripts::PubkeyScript;
+use wallet::hd::{HardenedIndex, SegmentIndexes, UnhardenedIndex};
+use wallet::onchain::{PublicNetwork, ResolveTx};
+
+use crate::sign::{tagged_hash, x_bytes};
+use crate::{AddressSource, OnchainTxid, UtxoTxid, Wallet, XprivSigner};
+
+/// Derivation branch used in the synthetic terminals of the UTXOs received with silent
+/// payments, which are not derived from the wallet descriptor.
+pub const SILENT_PAYMENT_BRANCH: u16 = 352;
+
+/// Silent payment address version supported by the wallet.
+const SILENT_PAYMENT_VERSION: u8 = 0;
+
+/// X coordinate of the BIP-341 NUMS point; taproot inputs spent by script path with this
+/// internal key are not eligible for silent payments.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SilentPaymentError {
+    /// Signer with master fingerprint {0} is not part of the wallet.
+    UnknownSigner(Fingerprint),
+
+    /// Silent payment keys can be derived only from the master private key of signer {0}.
+    MasterKeyRequired(Fingerprint),
+
+    /// Invalid silent payment address. Details: {0}
+    InvalidAddress(String),
+
+    /// Silent payment address version {0} is not supported.
+    UnsupportedVersion(u8),
+
+    /// Silent payment address is intended for a different network than the wallet.
+    NetworkMismatch,
+
+    /// Unable to resolve transaction {0} spent by the scanned transaction.
+    Resolver(Txid),
+
+    /// Transaction inputs do not allow creating silent payment outputs.
+    IneligibleInputs,
+}
+
+/// Reusable silent payment address, consisting of the scan and spend public keys.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SilentPaymentAddress {
+    pub testnet: bool,
+    pub scan_key: PublicKey,
+    pub spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    fn hrp(testnet: bool) -> &'static str {
+        match testnet {
+            false => "sp",
+            true => "tsp",
+        }
+    }
+}
+
+impl Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut payload = self.scan_key.serialize().to_vec();
+        payload.extend(self.spend_key.serialize());
+        let data = [u5::try_from_u8(SILENT_PAYMENT_VERSION).expect("version fits 5 bits")]
+            .into_iter()
+            .chain(payload.to_base32())
+            .collect::<Vec<_>>();
+        let s = bech32::encode(Self::hrp(self.testnet), data, Variant::Bech32m)
+            .map_err(|_| fmt::Error)?;
+        f.write_str(&s)
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data, variant) =
+            bech32::decode(s).map_err(|err| SilentPaymentError::InvalidAddress(err.to_string()))?;
+        if variant != Variant::Bech32m {
+            return Err(SilentPaymentError::InvalidAddress(s!(
+                "address must use bech32m encoding"
+            )));
+        }
+        let testnet = match hrp.as_str() {
+            "sp" => false,
+            "tsp" => true,
+            _ => {
+                return Err(SilentPaymentError::InvalidAddress(format!(
+                    "unknown address prefix `{}`",
+                    hrp
+                )))
+            }
+        };
+        let version = data
+            .first()
+            .ok_or_else(|| SilentPaymentError::InvalidAddress(s!("empty address payload")))?
+            .to_u8();
+        if version != SILENT_PAYMENT_VERSION {
+            return Err(SilentPaymentError::UnsupportedVersion(version));
+        }
+        let payload = Vec::<u8>::from_base32(&data[1..])
+            .map_err(|err| SilentPaymentError::InvalidAddress(err.to_string()))?;
+        if payload.len() != 66 {
+            return Err(SilentPaymentError::InvalidAddress(format!(
+                "invalid payload length {}",
+                payload.len()
+            )));
+        }
+        let key = |slice: &[u8]| {
+            PublicKey::from_slice(slice)
+                .map_err(|err| SilentPaymentError::InvalidAddress(err.to_string()))
+        };
+        Ok(SilentPaymentAddress {
+            testnet,
+            scan_key: key(&payload[..33])?,
+            spend_key: key(&payload[33..])?,
+        })
+    }
+}
+
+/// Computes taproot output keys paying to the silent payment addresses from a transaction
+/// spending the given outpoints. Private keys must be provided for all eligible inputs, each with
+/// a flag whether the input is a taproot one. Output keys are returned in the order of the
+/// recipients; recipients sharing the same scan key are paid with increasing `k`.
+pub fn silent_payment_outputs(
+    outpoints: &[OutPoint],
+    input_secrets: &[(SecretKey, bool)],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<XOnlyPublicKey>, SilentPaymentError> {
+    let mut input_secret: Option<SecretKey> = None;
+    for (secret, taproot) in input_secrets {
+        let secret = match *taproot && secret.public_key(SECP256K1).serialize()[0] == 0x03 {
+            true => secret.negate(),
+            false => *secret,
+        };
+        input_secret = Some(match input_secret {
+            None => secret,
+            Some(sum) => sum
+                .add_tweak(&Scalar::from(secret))
+                .map_err(|_| SilentPaymentError::IneligibleInputs)?,
+        });
+    }
+    let input_secret = input_secret.ok_or(SilentPaymentError::IneligibleInputs)?;
+    let smallest_outpoint = outpoints
+        .iter()
+        .map(serialize)
+        .min()
+        .ok_or(SilentPaymentError::IneligibleInputs)?;
+    let input_hash = input_hash(&smallest_outpoint, input_secret.public_key(SECP256K1))
+        .ok_or(SilentPaymentError::IneligibleInputs)?;
+    let tweak = input_secret
+        .mul_tweak(&input_hash)
+        .map_err(|_| SilentPaymentError::IneligibleInputs)?;
+
+    let mut counters = BTreeMap::<PublicKey, u32>::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let k = counters.entry(recipient.scan_key).or_default();
+            let output = recipient
+                .scan_key
+                .mul_tweak(SECP256K1, &Scalar::from(tweak))
+                .ok()
+                .and_then(|shared_secret| output_key(shared_secret, recipient.spend_key, *k))
+                .map(|(_, key)| key.x_only_public_key().0)
+                .ok_or(SilentPaymentError::IneligibleInputs)?;
+            *k += 1;
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Computes BIP-352 input hash from the smallest serialized outpoint of the transaction and the
+/// sum of the public keys of its eligible inputs.
+fn input_hash(smallest_outpoint: &[u8], input_sum: PublicKey) -> Option<Scalar> {
+    let hash = tagged_hash(b"BIP0352/Inputs", &[
+        smallest_outpoint,
+        &input_sum.serialize(),
+    ]);
+    Scalar::from_be_bytes(hash.into_inner()).ok()
+}
+
+/// Computes the tweak and the output key for the `k`-th output paying to the spend key.
+fn output_key(
+    shared_secret: PublicKey,
+    spend_key: PublicKey,
+    k: u32,
+) -> Option<(sha256::Hash, PublicKey)> {
+    let tweak = tagged_hash(b"BIP0352/SharedSecret", &[
+        &shared_secret.serialize(),
+        &k.to_be_bytes(),
+    ]);
+    let scalar = Scalar::from_be_bytes(tweak.into_inner()).ok()?;
+    let key = spend_key.add_exp_tweak(SECP256K1, &scalar).ok()?;
+    Some((tweak, key))
+}
+
+/// Keys for receiving silent payments: the scan private key, required for detecting incoming
+/// payments, and the spend public key together with its origin.
+#[derive(Getters, Clone, PartialEq, Eq, Debug)]
+pub struct SilentPaymentKeys {
+    #[getter(as_copy)]
+    testnet: bool,
+    #[getter(as_copy)]
+    scan_secret: SecretKey,
+    #[getter(as_copy)]
+    spend_key: PublicKey,
+    #[getter(as_copy)]
+    master_fp: Fingerprint,
+    spend_path: DerivationPath,
+}
+
+/// Wallet output received with a silent payment, with the information required to spend it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct SilentPaymentOutput {
+    /// Number of the output among the outputs of the same transaction paying to the address.
+    pub k: u16,
+    /// Tweak added to the spend key, producing the output key.
+    pub tweak: sha256::Hash,
+    pub master_fp: Fingerprint,
+    /// Derivation of the spend key from the signer master key.
+    pub spend_path: DerivationPath,
+}
+
+impl SilentPaymentOutput {
+    /// Computes private key for the key-path spending of the output.
+    pub fn secret_key(&self, signer: &XprivSigner) -> Result<SecretKey, SilentPaymentError> {
+        if signer.master_fp != self.master_fp
+            || signer.xpriv.fingerprint(SECP256K1) != self.master_fp
+        {
+            return Err(SilentPaymentError::MasterKeyRequired(self.master_fp));
+        }
+        let spend_secret = signer
+            .xpriv
+            .derive_priv(SECP256K1, &self.spend_path)
+            .expect("xpriv derivation does not fail")
+            .private_key;
+        let tweak = Scalar::from_be_bytes(self.tweak.into_inner())
+            .expect("tweak was validated during scanning");
+        Ok(spend_secret
+            .add_tweak(&tweak)
+            .expect("negligible probability of zero key"))
+    }
+}
+
+impl SilentPaymentKeys {
+    /// Derives silent payment keys from the signer master key using BIP-352 derivation paths
+    /// `m/352'/coin'/account'/1'/0` (scan key) and `m/352'/coin'/account'/0'/0` (spend key).
+    pub fn derive(
+        signer: &XprivSigner,
+        testnet: bool,
+        account: HardenedIndex,
+    ) -> Result<Self, SilentPaymentError> {
+        if signer.xpriv.fingerprint(SECP256K1) != signer.master_fp {
+            return Err(SilentPaymentError::MasterKeyRequired(signer.master_fp));
+        }
+        let path = |branch: u32| -> DerivationPath {
+            vec![
+                ChildNumber::Hardened { index: 352 },
+                ChildNumber::Hardened {
+                    index: testnet as u32,
+                },
+                ChildNumber::from(account),
+                ChildNumber::Hardened { index: branch },
+                ChildNumber::Normal { index: 0 },
+            ]
+            .into()
+        };
+        let derive = |path: &DerivationPath| {
+            signer
+                .xpriv
+                .derive_priv(SECP256K1, path)
+                .expect("xpriv derivation does not fail")
+                .private_key
+        };
+        let spend_path = path(0);
+        Ok(SilentPaymentKeys {
+            testnet,
+            scan_secret: derive(&path(1)),
+            spend_key: derive(&spend_path).public_key(SECP256K1),
+            master_fp: signer.master_fp,
+            spend_path,
+        })
+    }
+
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress {
+            testnet: self.testnet,
+            scan_key: self.scan_secret.public_key(SECP256K1),
+            spend_key: self.spend_key,
+        }
+    }
+
+    /// Detects transaction outputs paying to the silent payment address. Previous outputs must
+    /// be provided for all transaction inputs, in the order of the inputs.
+    pub fn scan_tx(&self, tx: &Transaction, prevouts: &[TxOut]) -> Vec<(u32, SilentPaymentOutput)> {
+        let mut candidates = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txout)| txout.script_pubkey.is_v1_p2tr())
+            .map(|(vout, txout)| (vout as u32, &txout.script_pubkey.as_bytes()[2..34]))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() || tx.is_coin_base() || prevouts.len() != tx.input.len() {
+            return vec![];
+        }
+
+        let input_keys = tx
+            .input
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(txin, prevout)| input_pubkey(txin, prevout))
+            .collect::<Vec<_>>();
+        if input_keys.is_empty() {
+            return vec![];
+        }
+        let input_sum = match PublicKey::combine_keys(&input_keys.iter().collect::<Vec<_>>()) {
+            Ok(key) => key,
+            Err(_) => return vec![],
+        };
+        let smallest_outpoint = tx
+            .input
+            .iter()
+            .map(|txin| serialize(&txin.previous_output))
+            .min()
+            .expect("non-coinbase transaction has inputs");
+        let shared_secret = match input_hash(&smallest_outpoint, input_sum)
+            .and_then(|input_hash| self.scan_secret.mul_tweak(&input_hash).ok())
+            .and_then(|tweak| input_sum.mul_tweak(SECP256K1, &Scalar::from(tweak)).ok())
+        {
+            Some(key) => key,
+            None => return vec![],
+        };
+
+        let mut found = vec![];
+        for k in 0..=u16::MAX {
+            let (tweak, output_key) = match output_key(shared_secret, self.spend_key, k as u32) {
+                Some((tweak, key)) => (tweak, x_bytes(key)),
+                None => break,
+            };
+            let pos = match candidates.iter().position(|(_, x)| *x == &output_key[..]) {
+                Some(pos) => pos,
+                None => break,
+            };
+            let (vout, _) = candidates.remove(pos);
+            found.push((vout, SilentPaymentOutput {
+                k,
+                tweak,
+                master_fp: self.master_fp,
+                spend_path: self.spend_path.clone(),
+            }));
+        }
+        found
+    }
+}
+
+/// Extracts public key contributing to the silent payment shared secret from a transaction
+/// input, if the input is eligible.
+fn input_pubkey(txin: &TxIn, prevout: &TxOut) -> Option<PublicKey> {
+    let script_pubkey = &prevout.script_pubkey;
+    let compressed_key = |data: &[u8]| -> Option<PublicKey> {
+        (data.len() == 33).then_some(())?;
+        PublicKey::from_slice(data).ok()
+    };
+
+    if script_pubkey.is_v1_p2tr() {
+        let mut stack = txin.witness.to_vec();
+        if stack.len() > 1 && stack.last().and_then(|item| item.first()) == Some(&0x50) {
+            stack.pop();
+        }
+        if stack.len() > 1 {
+            let control_block = stack.last()?;
+            if control_block.get(1..33) == Some(&NUMS_H[..]) {
+                return None;
+            }
+        }
+        let mut key = [2u8; 33];
+        key[1..].copy_from_slice(&script_pubkey.as_bytes()[2..34]);
+        return PublicKey::from_slice(&key).ok();
+    }
+
+    if script_pubkey.is_v0_p2wpkh() {
+        return compressed_key(txin.witness.last()?);
+    }
+
+    if script_pubkey.is_p2sh() {
+        let redeem_script = txin.script_sig.as_bytes();
+        let is_nested_wpkh =
+            redeem_script.len() == 23 && redeem_script[0] == 22 && redeem_script[1..3] == [0, 20];
+        return is_nested_wpkh
+            .then_some(())
+            .and_then(|_| compressed_key(txin.witness.last()?));
+    }
+
+    if script_pubkey.is_p2pkh() {
+        let pubkey_hash = &script_pubkey.as_bytes()[3..23];
+        return txin
+            .script_sig
+            .instructions()
+            .filter_map(Result::ok)
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(data) => Some(data),
+                Instruction::Op(_) => None,
+            })
+            .find(|data| data.len() == 33 && &hash160::Hash::hash(data)[..] == pubkey_hash)
+            .and_then(compressed_key);
+    }
+
+    None
+}
+
+impl Wallet {
+    /// Derives silent payment keys for the wallet account of the signer, which must be one of
+    /// the wallet signers with the known master private key.
+    pub fn silent_payment_keys(
+        &self,
+        signer: &XprivSigner,
+    ) -> Result<SilentPaymentKeys, SilentPaymentError> {
+        let account = self
+            .as_settings()
+            .signers()
+            .iter()
+            .find(|s| s.master_fp == signer.master_fp)
+            .ok_or(SilentPaymentError::UnknownSigner(signer.master_fp))?
+            .account
+            .unwrap_or_else(HardenedIndex::zero);
+        let testnet = self.as_settings().network() != PublicNetwork::Mainnet;
+        SilentPaymentKeys::derive(signer, testnet, account)
+    }
+
+    /// Scans transactions for the outputs paying to the silent payment address, using the
+    /// resolver for retrieving previous outputs spent by the transactions. Detected outputs are
+    /// added to the wallet UTXOs under synthetic `/352/k` derivation terminal and returned.
+    pub fn scan_silent_payments(
+        &mut self,
+        keys: &SilentPaymentKeys,
+        txs: impl IntoIterator<Item = (OnchainTxid, Transaction)>,
+        resolver: &impl ResolveTx,
+    ) -> Result<Vec<UtxoTxid>, SilentPaymentError> {
+        if keys.testnet() != (self.as_settings().network() != PublicNetwork::Mainnet) {
+            return Err(SilentPaymentError::NetworkMismatch);
+        }
+        let network = bitcoin::Network::from(self.as_settings().network());
+
+        let mut received = vec![];
+        for (onchain, tx) in txs {
+            if tx.is_coin_base()
+                || !tx
+                    .output
+                    .iter()
+                    .any(|txout| txout.script_pubkey.is_v1_p2tr())
+            {
+                continue;
+            }
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|txin| {
+                    let prev_txid = txin.previous_output.txid;
+                    resolver
+                        .resolve_tx(prev_txid)
+                        .ok()
+                        .and_then(|prev_tx| {
+                            prev_tx
+                                .output
+                                .get(txin.previous_output.vout as usize)
+                                .cloned()
+                        })
+                        .ok_or(SilentPaymentError::Resolver(prev_txid))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (vout, output) in keys.scan_tx(&tx, &prevouts) {
+                let txout = &tx.output[vout as usize];
+                let script = PubkeyScript::from(txout.script_pubkey.clone());
+                let utxo = UtxoTxid {
+                    onchain,
+                    value: txout.value,
+                    vout,
+                    addr_src: AddressSource {
+                        address: AddressCompat::from_script(&script, network.into())
+                            .expect("taproot output always has an address"),
+                        change: UnhardenedIndex::from(SILENT_PAYMENT_BRANCH),
+                        index: UnhardenedIndex::from(output.k),
+                    },
+                    coinbase: false,
+                };
+                self.add_silent_payment(utxo, output);
+                received.push(utxo);
+            }
+        }
+        Ok(received)
+    }
+
+    /// Script pubkeys of the outputs received with silent payments, which should be watched by
+    /// the wallet synchronization alongside the descriptor-derived ones.
+    pub fn silent_payment_script_pubkeys(&self) -> BTreeSet<PubkeyScript> {
+        self.utxos()
+            .iter()
+            .filter(|utxo| self.silent_payments().contains_key(&utxo.outpoint()))
+            .map(|utxo| utxo.addr_src.address.script_pubkey())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::{PackedLockTime, Script, Sequence, Witness};
+
+    use super::*;
+
+    // Keys, outpoints and the address of the BIP-352 "Simple send: two inputs" vector.
+    const INPUT_SECRETS: [&str; 2] = [
+        "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+        "93f5ed907ad5b2bdbbdcb6d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+    ];
+    const OUTPOINTS: [&str; 2] = [
+        "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+        "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:0",
+    ];
+    const SCAN_SECRET: &str = "0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c";
+    const SPEND_SECRET: &str = "9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3";
+    const ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+    const OUTPUTS: [&str; 2] = [
+        "eee78f4383ed1a7147f7beb5bcaf4762d8c11708b77f9df1bbe54eb98c2a8e50",
+        "7b24f76105198f28322b190fb6736dc3cae9fbf5743aee9eb113498041dbc801",
+    ];
+    const TWEAKS: [&str; 2] = [
+        "21e75bf27024547f32bca7613d9edb02a8ce3e81712ded1bc9a8bede713b0602",
+        "20a7f05daa4d51ab608ab83f388764b944b2f5d21f5e3bab15a2d1626ca2f389",
+    ];
+
+    fn secret(hex: &str) -> SecretKey { SecretKey::from_str(hex).unwrap() }
+
+    fn outpoints() -> Vec<OutPoint> {
+        OUTPOINTS
+            .iter()
+            .map(|s| OutPoint::from_str(s).unwrap())
+            .collect()
+    }
+
+    fn receiver() -> SilentPaymentKeys {
+        SilentPaymentKeys {
+            testnet: false,
+            scan_secret: secret(SCAN_SECRET),
+            spend_key: secret(SPEND_SECRET).public_key(SECP256K1),
+            master_fp: Fingerprint::default(),
+            spend_path: DerivationPath::master(),
+        }
+    }
+
+    fn output_script(x: &str) -> Script {
+        let mut script = vec![0x51, 0x20];
+        script.extend(Vec::<u8>::from_hex(x).unwrap());
+        Script::from(script)
+    }
+
+    fn p2wpkh_spend(outpoint: OutPoint, secret: SecretKey) -> (TxIn, TxOut) {
+        let pubkey = bitcoin::PublicKey::new(secret.public_key(SECP256K1));
+        let txin = TxIn {
+            previous_output: outpoint,
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_vec(vec![vec![0u8; 71], pubkey.to_bytes()]),
+        };
+        let prevout = TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+        };
+        (txin, prevout)
+    }
+
+    fn p2tr_spend(outpoint: OutPoint, secret: SecretKey) -> (TxIn, TxOut) {
+        let txin = TxIn {
+            previous_output: outpoint,
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_vec(vec![vec![0u8; 64]]),
+        };
+        let x = secret.x_only_public_key(SECP256K1).0.serialize();
+        let mut script = vec![0x51, 0x20];
+        script.extend(x);
+        let prevout = TxOut {
+            value: 100_000,
+            script_pubkey: Script::from(script),
+        };
+        (txin, prevout)
+    }
+
+    fn tx(inputs: Vec<(TxIn, TxOut)>, outputs: &[&str]) -> (Transaction, Vec<TxOut>) {
+        let (input, prevouts) = inputs.into_iter().unzip();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input,
+            output: outputs
+                .iter()
+                .map(|x| TxOut {
+                    value: 50_000,
+                    script_pubkey: output_script(x),
+                })
+                .collect(),
+        };
+        (tx, prevouts)
+    }
+
+    #[test]
+    fn address() {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        assert_eq!(address, receiver().address());
+        assert_eq!(address.to_string(), ADDRESS);
+    }
+
+    #[test]
+    fn send() {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        let secrets = INPUT_SECRETS
+            .iter()
+            .map(|hex| (secret(hex), false))
+            .collect::<Vec<_>>();
+        let outputs = silent_payment_outputs(&outpoints(), &secrets, &[address, address]).unwrap();
+        assert_eq!(
+            outputs
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>(),
+            OUTPUTS
+        );
+    }
+
+    #[test]
+    fn send_taproot_odd_key() {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        let secrets = [(secret(INPUT_SECRETS[0]), true), (secret(INPUT_SECRETS[1]).negate(), true)];
+        let outputs = silent_payment_outputs(&outpoints(), &secrets, &[address]).unwrap();
+        assert_eq!(outputs[0].to_string(), OUTPUTS[0]);
+    }
+
+    #[test]
+    fn receive() {
+        let inputs = outpoints()
+            .into_iter()
+            .zip(INPUT_SECRETS)
+            .map(|(outpoint, hex)| p2wpkh_spend(outpoint, secret(hex)))
+            .collect();
+        let (tx, prevouts) = tx(inputs, &OUTPUTS);
+        let found = receiver().scan_tx(&tx, &prevouts);
+        assert_eq!(found.len(), 2);
+        for (k, (vout, output)) in found.into_iter().enumerate() {
+            assert_eq!(vout, k as u32);
+            assert_eq!(output.k, k as u16);
+            assert_eq!(output.tweak.to_string(), TWEAKS[k]);
+            let tweak = Scalar::from_be_bytes(output.tweak.into_inner()).unwrap();
+            let spend_secret = secret(SPEND_SECRET).add_tweak(&tweak).unwrap();
+            assert_eq!(
+                spend_secret.x_only_public_key(SECP256K1).0.to_string(),
+                OUTPUTS[k]
+            );
+        }
+    }
+
+    #[test]
+    fn receive_taproot() {
+        let inputs = outpoints()
+            .into_iter()
+            .zip(INPUT_SECRETS)
+            .map(|(outpoint, hex)| p2tr_spend(outpoint, secret(hex)))
+            .collect();
+        let (tx, prevouts) = tx(inputs, &OUTPUTS[..1]);
+        let found = receiver().scan_tx(&tx, &prevouts);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.tweak.to_string(), TWEAKS[0]);
+    }
+
+    #[test]
+    fn receive_other_address() {
+        let inputs = outpoints()
+            .into_iter()
+            .zip(INPUT_SECRETS)
+            .map(|(outpoint, hex)| p2wpkh_spend(outpoint, secret(hex)))
+            .collect();
+        let (tx, prevouts) = tx(inputs, &OUTPUTS);
+        let mut keys = receiver();
+        keys.scan_secret = secret(SPEND_SECRET);
+        assert!(keys.scan_tx(&tx, &prevouts).is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Sweeping funds controlled by standalone private keys (paper wallets, gift keys) into the
+//! wallet.
+
+use bitcoin::blockdata::script::Builder;
+use bitcoin::secp256k1::{Message, SECP256K1};
+use bitcoin::util::schnorr::TapTweak;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{
+    Address, EcdsaSig, EcdsaSighashType, KeyPair, Network, OutPoint, PackedLockTime, PrivateKey,
+    SchnorrSighashType, Script, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+#[cfg(feature = "electrum")]
+use electrum_client::ElectrumApi;
+use wallet::onchain::PublicNetwork;
+
+use crate::Wallet;
+
+/// Outputs below this value are not relayed by the network nodes.
+pub const DUST_LIMIT: u64 = 546;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SweepError {
+    /// Invalid WIF private key. Details: {0}
+    InvalidKey(String),
+
+    /// The private key is intended for {0} network, while the wallet operates on {1}.
+    NetworkMismatch(Network, PublicNetwork),
+
+    /// Uncompressed private keys can't be used with {0} outputs.
+    UncompressedKey(SweepScript),
+
+    /// No funds controlled by the private key were found.
+    NoFunds,
+
+    /// Swept funds ({0} sats) are insufficient to pay the transaction fee ({1} sats).
+    InsufficientFunds(u64, u64),
+
+    /// Unable to sign the sweep transaction. Details: {0}
+    Signing(String),
+
+    /// Unable to discover the private key funds. Details: {0}
+    Resolver(String),
+}
+
+/// Types of the scriptPubkeys which may be controlled by a single private key.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum SweepScript {
+    #[display("P2PKH")]
+    P2pkh,
+
+    #[display("P2SH-P2WPKH")]
+    P2shP2wpkh,
+
+    #[display("P2WPKH")]
+    P2wpkh,
+
+    #[display("P2TR")]
+    P2tr,
+}
+
+impl SweepScript {
+    pub fn all() -> &'static [SweepScript] {
+        &[SweepScript::P2pkh, SweepScript::P2shP2wpkh, SweepScript::P2wpkh, SweepScript::P2tr]
+    }
+
+    pub fn requires_compressed(self) -> bool { self != SweepScript::P2pkh }
+
+    /// Virtual size of the transaction input spending the script.
+    fn input_vsize(self) -> u64 {
+        match self {
+            SweepScript::P2pkh => 148,
+            SweepScript::P2shP2wpkh => 91,
+            SweepScript::P2wpkh => 68,
+            SweepScript::P2tr => 58,
+        }
+    }
+}
+
+/// Private key to be swept, together with the script types which should be checked for funds.
+#[derive(Clone, Debug)]
+pub struct SweepKey {
+    key: PrivateKey,
+    scripts: Vec<SweepScript>,
+}
+
+/// Unspent output controlled by the [`SweepKey`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SweepUtxo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub script: SweepScript,
+    pub script_pubkey: Script,
+}
+
+impl SweepKey {
+    /// Parses WIF-encoded private key. If no script type hints are given, all script types
+    /// applicable for the key are used.
+    pub fn from_wif(wif: &str, hints: Option<&[SweepScript]>) -> Result<Self, SweepError> {
+        let key = PrivateKey::from_wif(wif.trim())
+            .map_err(|err| SweepError::InvalidKey(err.to_string()))?;
+        let scripts = match hints {
+            Some(hints) => {
+                if let Some(script) = hints
+                    .iter()
+                    .find(|script| script.requires_compressed() && !key.compressed)
+                {
+                    return Err(SweepError::UncompressedKey(*script));
+                }
+                hints.to_vec()
+            }
+            None => SweepScript::all()
+                .iter()
+                .copied()
+                .filter(|script| key.compressed || !script.requires_compressed())
+                .collect(),
+        };
+        Ok(SweepKey { key, scripts })
+    }
+
+    pub fn network(&self) -> Network { self.key.network }
+
+    pub fn scripts(&self) -> &[SweepScript] { &self.scripts }
+
+    pub fn script_pubkey(&self, script: SweepScript) -> Script {
+        let pubkey = self.key.public_key(SECP256K1);
+        match script {
+            SweepScript::P2pkh => Script::new_p2pkh(&pubkey.pubkey_hash()),
+            SweepScript::P2wpkh => Script::new_v0_p2wpkh(
+                &pubkey
+                    .wpubkey_hash()
+                    .expect("uncompressed keys are filtered on construction"),
+            ),
+            SweepScript::P2shP2wpkh => Script::new_p2sh(&self.redeem_script().script_hash()),
+            SweepScript::P2tr => {
+                let keypair = KeyPair::from_secret_key(SECP256K1, &self.key.inner);
+                Script::new_v1_p2tr(SECP256K1, keypair.x_only_public_key().0, None)
+            }
+        }
+    }
+
+    pub fn script_pubkeys(&self) -> Vec<(SweepScript, Script)> {
+        self.scripts
+            .iter()
+            .map(|script| (*script, self.script_pubkey(*script)))
+            .collect()
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.script_pubkeys()
+            .into_iter()
+            .filter_map(|(_, spk)| Address::from_script(&spk, self.key.network).ok())
+            .collect()
+    }
+
+    fn redeem_script(&self) -> Script {
+        let pubkey = self.key.public_key(SECP256K1);
+        Script::new_v0_p2wpkh(
+            &pubkey
+                .wpubkey_hash()
+                .expect("uncompressed keys are filtered on construction"),
+        )
+    }
+
+    /// Discovers UTXOs controlled by the key using Electrum server.
+    #[cfg(feature = "electrum")]
+    pub fn discover_utxos(&self, client: &impl ElectrumApi) -> Result<Vec<SweepUtxo>, SweepError> {
+        let mut utxos = vec![];
+        for (script, script_pubkey) in self.script_pubkeys() {
+            let unspent = client
+                .script_list_unspent(&script_pubkey)
+                .map_err(|err| SweepError::Resolver(err.to_string()))?;
+            utxos.extend(unspent.into_iter().map(|res| SweepUtxo {
+                outpoint: OutPoint::new(res.tx_hash, res.tx_pos as u32),
+                value: res.value,
+                script,
+                script_pubkey: script_pubkey.clone(),
+            }));
+        }
+        Ok(utxos)
+    }
+
+    /// Composes and signs transaction spending all provided UTXOs into a single output.
+    ///
+    /// The fee is computed from `fee_rate` (in sats per vbyte) and deducted from the swept value.
+    pub fn sweep(
+        &self,
+        utxos: &[SweepUtxo],
+        destination: Script,
+        fee_rate: f32,
+    ) -> Result<Transaction, SweepError> {
+        if utxos.is_empty() {
+            return Err(SweepError::NoFunds);
+        }
+
+        let total = utxos.iter().map(|utxo| utxo.value).sum::<u64>();
+        let vsize = 11
+            + utxos
+                .iter()
+                .map(|utxo| utxo.script.input_vsize())
+                .sum::<u64>()
+            + 9
+            + destination.len() as u64;
+        let fee = (vsize as f32 * fee_rate).ceil() as u64;
+        if total < fee + DUST_LIMIT {
+            return Err(SweepError::InsufficientFunds(total, fee));
+        }
+
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: total - fee,
+                script_pubkey: destination,
+            }],
+        };
+
+        let prevouts = utxos
+            .iter()
+            .map(|utxo| TxOut {
+                value: utxo.value,
+                script_pubkey: utxo.script_pubkey.clone(),
+            })
+            .collect::<Vec<_>>();
+        let signing_err = |err: bitcoin::util::sighash::Error| SweepError::Signing(err.to_string());
+
+        let pubkey = self.key.public_key(SECP256K1);
+        let mut satisfactions = Vec::with_capacity(utxos.len());
+        let mut cache = SighashCache::new(&tx);
+        for (index, utxo) in utxos.iter().enumerate() {
+            let ecdsa_sig = |sighash: &[u8]| -> Vec<u8> {
+                let msg = Message::from_slice(sighash).expect("sighash is always 32 bytes");
+                EcdsaSig {
+                    sig: SECP256K1.sign_ecdsa(&msg, &self.key.inner),
+                    hash_ty: EcdsaSighashType::All,
+                }
+                .to_vec()
+            };
+            let satisfaction = match utxo.script {
+                SweepScript::P2pkh => {
+                    let sighash = cache
+                        .legacy_signature_hash(
+                            index,
+                            &utxo.script_pubkey,
+                            EcdsaSighashType::All.to_u32(),
+                        )
+                        .map_err(signing_err)?;
+                    let script_sig = Builder::new()
+                        .push_slice(&ecdsa_sig(&sighash[..]))
+                        .push_key(&pubkey)
+                        .into_script();
+                    (script_sig, Witness::new())
+                }
+                SweepScript::P2wpkh | SweepScript::P2shP2wpkh => {
+                    let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+                    let sighash = cache
+                        .segwit_signature_hash(
+                            index,
+                            &script_code,
+                            utxo.value,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(signing_err)?;
+                    let witness =
+                        Witness::from_vec(vec![ecdsa_sig(&sighash[..]), pubkey.to_bytes()]);
+                    let script_sig = match utxo.script {
+                        SweepScript::P2shP2wpkh => Builder::new()
+                            .push_slice(self.redeem_script().as_bytes())
+                            .into_script(),
+                        _ => Script::new(),
+                    };
+                    (script_sig, witness)
+                }
+                SweepScript::P2tr => {
+                    let sighash = cache
+                        .taproot_key_spend_signature_hash(
+                            index,
+                            &Prevouts::All(&prevouts),
+                            SchnorrSighashType::Default,
+                        )
+                        .map_err(signing_err)?;
+                    let msg =
+                        Message::from_slice(&sighash[..]).expect("sighash is always 32 bytes");
+                    let keypair = KeyPair::from_secret_key(SECP256K1, &self.key.inner)
+                        .tap_tweak(SECP256K1, None)
+                        .to_inner();
+                    let sig = SECP256K1.sign_schnorr_no_aux_rand(&msg, &keypair);
+                    (
+                        Script::new(),
+                        Witness::from_vec(vec![sig.as_ref().to_vec()]),
+                    )
+                }
+            };
+            satisfactions.push(satisfaction);
+        }
+
+        for (txin, (script_sig, witness)) in tx.input.iter_mut().zip(satisfactions) {
+            txin.script_sig = script_sig;
+            txin.witness = witness;
+        }
+        Ok(tx)
+    }
+}
+
+impl Wallet {
+    /// Composes and signs transaction sweeping funds from the private key UTXOs into the next
+    /// unused wallet address.
+    pub fn sweep_key(
+        &self,
+        key: &SweepKey,
+        utxos: &[SweepUtxo],
+        fee_rate: f32,
+    ) -> Result<Transaction, SweepError> {
+        let network = self.as_settings().network();
+        if (key.network() == Network::Bitcoin) != (network == PublicNetwork::Mainnet) {
+            return Err(SweepError::NetworkMismatch(key.network(), network));
+        }
+        key.sweep(utxos, self.next_address().script_pubkey(), fee_rate)
+    }
+
+    /// Discovers funds controlled by WIF-encoded private key using Electrum server and composes
+    /// signed transaction sweeping them into the next unused wallet address.
+    #[cfg(feature = "electrum")]
+    pub fn sweep_wif(
+        &self,
+        client: &impl ElectrumApi,
+        wif: &str,
+        hints: Option<&[SweepScript]>,
+        fee_rate: f32,
+    ) -> Result<Transaction, SweepError> {
+        let key = SweepKey::from_wif(wif, hints)?;
+        let utxos = key.discover_utxos(client)?;
+        self.sweep_key(&key, &utxos, fee_rate)
+    }
+}
@@ -0,0 +1,278 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Tapret commitments (LNPBP-12), tweaking taproot outputs of the wallet with an additional
+//! script leaf. The wallet must remember the commitment for each tweaked derivation terminal
+//! to be able to recognize and spend such outputs.
+
+use bitcoin::blockdata::opcodes::all::{OP_RESERVED, OP_RETURN};
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::psbt::TapTree;
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{PublicKey, Script};
+use miniscript::descriptor::Tr;
+use miniscript::ToPublicKey;
+use wallet::hd::UnhardenedIndex;
+
+use crate::Wallet;
+
+/// Number of `OP_RESERVED` opcodes prefixing tapret commitment leaf script.
+const TAPRET_PREFIX_LEN: usize = 29;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TapretError {
+    /// Wallet does not use taproot descriptor for the terminal {0}/{1}.
+    NotTaproot(UnhardenedIndex, UnhardenedIndex),
+
+    /// Unable to construct tapret-tweaked script tree. Details: {0}
+    Tree(String),
+
+    /// Tapret commitment with nonce {0} does not take the right-most position in the script
+    /// tree.
+    NotRightmost(u8),
+
+    /// None of the nonce values places tapret commitment at the right-most position in the
+    /// script tree.
+    NonceExhausted,
+}
+
+/// Tapret commitment tweaking taproot output of the wallet.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct TapretTweak {
+    /// Commitment message (like RGB bundle commitment).
+    pub commitment: sha256::Hash,
+    /// Nonce used to place commitment leaf at the right-most tree position.
+    pub nonce: u8,
+}
+
+impl TapretTweak {
+    /// Constructs tapret commitment for a derived descriptor, searching for the nonce which
+    /// places the commitment leaf at the right-most position of the script tree.
+    pub fn new(commitment: sha256::Hash, tr: &Tr<PublicKey>) -> Result<Self, TapretError> {
+        (0..=u8::MAX)
+            .map(|nonce| TapretTweak { commitment, nonce })
+            .find(|tweak| tweak.verify(tr).is_ok())
+            .ok_or(TapretError::NonceExhausted)
+    }
+
+    /// Script of the tapret commitment leaf.
+    pub fn leaf_script(&self) -> Script {
+        let mut builder = Builder::new();
+        for _ in 0..TAPRET_PREFIX_LEN {
+            builder = builder.push_opcode(OP_RESERVED);
+        }
+        let mut data = self.commitment[..].to_vec();
+        data.push(self.nonce);
+        builder
+            .push_opcode(OP_RETURN)
+            .push_slice(&data)
+            .into_script()
+    }
+
+    /// Checks that the commitment leaf takes the right-most position of the script tree, as
+    /// required by LNPBP-12: the leaf placed next to the root of the existing script tree must
+    /// have hash lexicographically larger than the existing tree root.
+    pub fn verify(&self, tr: &Tr<PublicKey>) -> Result<(), TapretError> {
+        let root = match tr.spend_info().merkle_root() {
+            None => return Ok(()),
+            Some(root) => root,
+        };
+        let leaf_hash = TapLeafHash::from_script(&self.leaf_script(), LeafVersion::TapScript);
+        if leaf_hash[..] > root[..] {
+            Ok(())
+        } else {
+            Err(TapretError::NotRightmost(self.nonce))
+        }
+    }
+
+    /// Builds script tree of a derived descriptor with the commitment leaf added next to the
+    /// root of the existing script tree.
+    fn builder(&self, tr: &Tr<PublicKey>) -> Result<TaprootBuilder, TapretError> {
+        let leaf = self.leaf_script();
+        match tr.taptree() {
+            None => TaprootBuilder::new().add_leaf(0, leaf),
+            Some(_) => tr
+                .iter_scripts()
+                .try_fold(TaprootBuilder::new(), |builder, (depth, ms)| {
+                    builder.add_leaf(depth + 1, ms.encode())
+                })
+                .and_then(|builder| builder.add_leaf(1, leaf)),
+        }
+        .map_err(|err| TapretError::Tree(err.to_string()))
+    }
+
+    /// Constructs taproot spending information for a derived descriptor with the commitment
+    /// leaf added next to the root of the existing script tree.
+    pub fn spend_info(&self, tr: &Tr<PublicKey>) -> Result<TaprootSpendInfo, TapretError> {
+        self.builder(tr)?
+            .finalize(SECP256K1, tr.internal_key().to_x_only_pubkey())
+            .map_err(|_| TapretError::Tree(s!("incomplete script tree")))
+    }
+
+    /// Complete script tree of the tweaked output, including the commitment leaf, which can be
+    /// used as PSBT output `PSBT_OUT_TAP_TREE` value.
+    pub fn tap_tree(&self, tr: &Tr<PublicKey>) -> Result<TapTree, TapretError> {
+        TapTree::try_from(self.builder(tr)?)
+            .map_err(|_| TapretError::Tree(s!("incomplete script tree")))
+    }
+
+    /// ScriptPubkey of the tweaked output.
+    pub fn script_pubkey(&self, tr: &Tr<PublicKey>) -> Result<Script, TapretError> {
+        let info = self.spend_info(tr)?;
+        Ok(Script::new_v1_p2tr_tweaked(info.output_key()))
+    }
+}
+
+impl Wallet {
+    /// Registers tapret commitment tweaking wallet output at a given derivation terminal,
+    /// returning the tweaked scriptPubkey which must be tracked by the wallet. Fails if the
+    /// commitment nonce does not place the commitment at the right-most tree position.
+    pub fn add_tapret_tweak(
+        &mut self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+        tweak: TapretTweak,
+    ) -> Result<Script, TapretError> {
+        let tr = self.derive_tr(change, index)?;
+        tweak.verify(&tr)?;
+        let script_pubkey = tweak.script_pubkey(&tr)?;
+        self.tapret_tweaks_mut().insert((change, index), tweak);
+        Ok(script_pubkey)
+    }
+
+    /// Commits to a message by tweaking wallet output at a given derivation terminal, selecting
+    /// the commitment nonce. Returns the registered tweak and the tweaked scriptPubkey.
+    pub fn add_tapret_commitment(
+        &mut self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+        commitment: sha256::Hash,
+    ) -> Result<(TapretTweak, Script), TapretError> {
+        let tr = self.derive_tr(change, index)?;
+        let tweak = TapretTweak::new(commitment, &tr)?;
+        let script_pubkey = self.add_tapret_tweak(change, index, tweak)?;
+        Ok((tweak, script_pubkey))
+    }
+
+    pub fn tapret_tweak(
+        &self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Option<TapretTweak> {
+        self.tapret_tweaks().get(&(change, index)).copied()
+    }
+
+    /// Tweaked scriptPubkeys of all registered tapret commitments, which have to be tracked
+    /// together with the regular wallet scriptPubkeys.
+    pub fn tapret_script_pubkeys(&self) -> Vec<(UnhardenedIndex, UnhardenedIndex, Script)> {
+        self.tapret_tweaks()
+            .iter()
+            .filter_map(|((change, index), tweak)| {
+                let tr = self.derive_tr(*change, *index).ok()?;
+                let script_pubkey = tweak.script_pubkey(&tr).ok()?;
+                Some((*change, *index, script_pubkey))
+            })
+            .collect()
+    }
+
+    /// Derives taproot descriptor of the wallet for a given terminal.
+    pub(crate) fn derive_tr(
+        &self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Result<Tr<PublicKey>, TapretError> {
+        let (descriptor, others) = self
+            .as_settings()
+            .descriptors_all()
+            .map_err(|_| TapretError::NotTaproot(change, index))?;
+        std::iter::once(descriptor)
+            .chain(others)
+            .filter_map(|descriptor| self.as_settings().derive(&descriptor, change, index).ok())
+            .find_map(|derived| match derived {
+                miniscript::Descriptor::Tr(tr) => Some(tr),
+                _ => None,
+            })
+            .ok_or(TapretError::NotTaproot(change, index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    const KEY_A: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_B: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY_C: &str = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    fn tr(desc: &str) -> Tr<PublicKey> { Tr::from_str(desc).unwrap() }
+
+    #[test]
+    fn rightmost_nonce() {
+        let tr = tr(&format!("tr({},pk({}))", KEY_A, KEY_B));
+        let root = tr.spend_info().merkle_root().unwrap();
+        for byte in 0..16u8 {
+            let commitment = sha256::Hash::hash(&[byte]);
+            let tweak = TapretTweak::new(commitment, &tr).unwrap();
+            let leaf_hash = TapLeafHash::from_script(&tweak.leaf_script(), LeafVersion::TapScript);
+            assert!(leaf_hash[..] > root[..]);
+            assert!(tweak.verify(&tr).is_ok());
+            if tweak.nonce > 0 {
+                let invalid = TapretTweak {
+                    commitment,
+                    nonce: 0,
+                };
+                assert_eq!(invalid.verify(&tr), Err(TapretError::NotRightmost(0)));
+            }
+        }
+    }
+
+    #[test]
+    fn key_only_tree() {
+        let tr = tr(&format!("tr({})", KEY_A));
+        let tweak = TapretTweak::new(sha256::Hash::hash(b"commitment"), &tr).unwrap();
+        assert_eq!(tweak.nonce, 0);
+        let info = tweak.spend_info(&tr).unwrap();
+        assert_eq!(
+            info.merkle_root().unwrap()[..],
+            TapLeafHash::from_script(&tweak.leaf_script(), LeafVersion::TapScript)[..]
+        );
+    }
+
+    #[test]
+    fn tap_tree_matches_spend_info() {
+        let tr = tr(&format!("tr({},{{pk({}),pk({})}})", KEY_A, KEY_B, KEY_C));
+        let tweak = TapretTweak::new(sha256::Hash::hash(b"commitment"), &tr).unwrap();
+        let info = tweak.spend_info(&tr).unwrap();
+        let tap_tree = tweak.tap_tree(&tr).unwrap();
+        let rebuilt = tap_tree
+            .into_builder()
+            .finalize(SECP256K1, tr.internal_key().to_x_only_pubkey())
+            .unwrap();
+        assert_eq!(rebuilt.output_key(), info.output_key());
+        assert!(info
+            .control_block(&(tweak.leaf_script(), LeafVersion::TapScript))
+            .is_some());
+        for (_, ms) in tr.iter_scripts() {
+            assert!(info
+                .control_block(&(ms.encode(), LeafVersion::TapScript))
+                .is_some());
+        }
+    }
+}
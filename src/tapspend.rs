@@ -0,0 +1,338 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Taproot script-path spending: control blocks for the wallet script leaves, selection of the
+//! cheapest satisfiable leaf and assembly of the script-path witness when finalizing PSBT.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::psbt::{self, PartiallySignedTransaction};
+use bitcoin::secp256k1::schnorr;
+use bitcoin::util::bip32::KeySource;
+use bitcoin::util::psbt::TapTree;
+use bitcoin::util::sighash::SchnorrSighashType;
+use bitcoin::util::taproot::{
+    ControlBlock, LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo,
+};
+use bitcoin::{LockTime, PublicKey, SchnorrSig, Script, Sequence, Witness, XOnlyPublicKey};
+use miniscript::descriptor::Tr;
+use miniscript::psbt::PsbtInputSatisfier;
+use miniscript::{Miniscript, Satisfier, Tap, ToPublicKey};
+
+use crate::migration::{add_musig_origins, key_origins};
+use crate::Wallet;
+
+/// Witness size of the key-path spend with the default sighash type.
+const KEY_PATH_WITNESS_SIZE: usize = 1 + 1 + 64;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TapSpendError {
+    /// PSBT has no input {0}.
+    NoInput(usize),
+
+    /// Input {0} does not spend taproot output or has no information about the spent output.
+    NotTaproot(usize),
+
+    /// Input {0} can't be finalized: there is neither key-path signature nor a script leaf
+    /// satisfiable with the present signatures and transaction timelocks.
+    Unsatisfiable(usize),
+}
+
+/// Path selected for spending taproot output.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum TapSpendPath {
+    /// Key-path spending with the internal key.
+    KeyPath,
+
+    /// Script-path spending with a given leaf.
+    ScriptPath {
+        leaf_hash: TapLeafHash,
+        script: Script,
+        control_block: ControlBlock,
+    },
+}
+
+/// Spending plan for a taproot output.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TapSpendPlan {
+    pub path: TapSpendPath,
+    /// Estimated size of the spending witness, in weight units.
+    pub witness_size: usize,
+}
+
+/// Satisfier used for planning, which pretends to have signatures of the available keys and
+/// checks timelocks against values the spending transaction can use.
+struct PlanningSatisfier<'keys> {
+    keys: &'keys BTreeSet<XOnlyPublicKey>,
+    lock_time: LockTime,
+    sequence: Sequence,
+}
+
+impl<'keys, Pk> Satisfier<Pk> for PlanningSatisfier<'keys>
+where Pk: ToPublicKey
+{
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, _: &TapLeafHash) -> Option<SchnorrSig> {
+        if !self.keys.contains(&pk.to_x_only_pubkey()) {
+            return None;
+        }
+        Some(SchnorrSig {
+            sig: schnorr::Signature::from_slice(&[1u8; 64]).expect("fixed signature length"),
+            hash_ty: SchnorrSighashType::Default,
+        })
+    }
+
+    fn check_older(&self, older: Sequence) -> bool {
+        older.is_relative_lock_time()
+            && self.sequence.is_relative_lock_time()
+            && older.is_height_locked() == self.sequence.is_height_locked()
+            && (older.0 & 0xFFFF) <= (self.sequence.0 & 0xFFFF)
+    }
+
+    fn check_after(&self, after: LockTime) -> bool {
+        let (after, lock_time) = (after.to_consensus_u32(), self.lock_time.to_consensus_u32());
+        (after < 500_000_000) == (lock_time < 500_000_000) && after <= lock_time
+    }
+}
+
+fn witness_size(stack: &[Vec<u8>]) -> usize {
+    1 + stack.iter().map(|item| 1 + item.len()).sum::<usize>()
+}
+
+/// Fills PSBT input with the control blocks for all wallet script leaves, such that signers and
+/// finalizer can use script-path spending. Spending info must be for the same descriptor,
+/// optionally extended with other (like tapret) leaves.
+pub fn fill_tap_scripts(
+    input: &mut psbt::Input,
+    tr: &Tr<PublicKey>,
+    spend_info: &TaprootSpendInfo,
+) {
+    for (_, ms) in tr.iter_scripts() {
+        let script = ms.encode();
+        if let Some(control_block) =
+            spend_info.control_block(&(script.clone(), LeafVersion::TapScript))
+        {
+            input
+                .tap_scripts
+                .insert(control_block, (script, LeafVersion::TapScript));
+        }
+    }
+}
+
+/// Taproot key origins for the keys used in the descriptor, together with the hashes of the
+/// script leaves using each of the keys.
+pub fn tap_key_origins(
+    tr: &Tr<PublicKey>,
+    origins: impl IntoIterator<Item = (PublicKey, KeySource)>,
+) -> BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)> {
+    let internal_key = tr.internal_key().to_x_only_pubkey();
+    origins
+        .into_iter()
+        .filter_map(|(pk, source)| {
+            let xonly = pk.to_x_only_pubkey();
+            let leaves = tr
+                .iter_scripts()
+                .filter(|(_, ms)| ms.iter_pk().any(|key| key.to_x_only_pubkey() == xonly))
+                .map(|(_, ms)| TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript))
+                .collect::<Vec<_>>();
+            (!leaves.is_empty() || xonly == internal_key).then_some((xonly, (leaves, source)))
+        })
+        .collect()
+}
+
+/// Fills PSBT output paying to a taproot descriptor with the internal key, the full script
+/// tree (`PSBT_OUT_TAP_TREE`) and key origins, such that hardware wallets can verify that
+/// script-path outputs belong to the wallet.
+pub fn fill_tap_output(
+    output: &mut psbt::Output,
+    tr: &Tr<PublicKey>,
+    origins: impl IntoIterator<Item = (PublicKey, KeySource)>,
+) {
+    output.tap_internal_key = Some(tr.internal_key().to_x_only_pubkey());
+    output.tap_tree = if tr.taptree().is_some() {
+        let builder = tr
+            .iter_scripts()
+            .fold(TaprootBuilder::new(), |builder, (depth, ms)| {
+                builder
+                    .add_leaf(depth, ms.encode())
+                    .expect("descriptor script tree depth is always valid")
+            });
+        Some(TapTree::try_from(builder).expect("descriptor script tree is always complete"))
+    } else {
+        None
+    };
+    output.tap_key_origins = tap_key_origins(tr, origins);
+}
+
+/// Selects the cheapest way of spending taproot output with the given keys, if the spending
+/// transaction uses the provided lock time and input sequence number. Key path is preferred
+/// whenever the internal key is available.
+pub fn plan_tap_spend(
+    tr: &Tr<PublicKey>,
+    spend_info: &TaprootSpendInfo,
+    keys: &BTreeSet<XOnlyPublicKey>,
+    lock_time: LockTime,
+    sequence: Sequence,
+) -> Option<TapSpendPlan> {
+    if keys.contains(&tr.internal_key().to_x_only_pubkey()) {
+        return Some(TapSpendPlan {
+            path: TapSpendPath::KeyPath,
+            witness_size: KEY_PATH_WITNESS_SIZE,
+        });
+    }
+    let satisfier = PlanningSatisfier {
+        keys,
+        lock_time,
+        sequence,
+    };
+    tr.iter_scripts()
+        .filter_map(|(_, ms)| {
+            let stack = ms.satisfy(&satisfier).ok()?;
+            let script = ms.encode();
+            let control_block =
+                spend_info.control_block(&(script.clone(), LeafVersion::TapScript))?;
+            let witness_size = witness_size(&stack) + 1 + script.len() + 1 + control_block.size();
+            Some(TapSpendPlan {
+                path: TapSpendPath::ScriptPath {
+                    leaf_hash: TapLeafHash::from_script(&script, LeafVersion::TapScript),
+                    script,
+                    control_block,
+                },
+                witness_size,
+            })
+        })
+        .min_by_key(|plan| plan.witness_size)
+}
+
+/// Finalizer for taproot PSBT inputs, supporting both key-path and script-path spending.
+pub trait TapFinalize {
+    /// Finalizes taproot input, using key-path signature if present, or the cheapest script
+    /// leaf satisfiable with the present signatures and transaction timelocks otherwise.
+    fn finalize_tap_input(&mut self, index: usize) -> Result<(), TapSpendError>;
+
+    /// Finalizes all taproot inputs which are not yet finalized, returning their number.
+    /// Non-taproot inputs are left untouched.
+    fn finalize_tap_inputs(&mut self) -> Result<usize, TapSpendError>;
+}
+
+impl TapFinalize for PartiallySignedTransaction {
+    fn finalize_tap_input(&mut self, index: usize) -> Result<(), TapSpendError> {
+        let input = self
+            .inputs
+            .get(index)
+            .ok_or(TapSpendError::NoInput(index))?;
+        if !is_taproot(input) {
+            return Err(TapSpendError::NotTaproot(index));
+        }
+        if input.final_script_witness.is_some() {
+            return Ok(());
+        }
+
+        let witness = if let Some(sig) = input.tap_key_sig {
+            vec![sig.to_vec()]
+        } else {
+            let satisfier = PsbtInputSatisfier::new(self, index);
+            input
+                .tap_scripts
+                .iter()
+                .filter(|(_, (_, version))| *version == LeafVersion::TapScript)
+                .filter_map(|(control_block, (script, _))| {
+                    let ms = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script).ok()?;
+                    let mut stack = ms.satisfy(&satisfier).ok()?;
+                    stack.push(script.to_bytes());
+                    stack.push(control_block.serialize());
+                    Some(stack)
+                })
+                .min_by_key(|stack| witness_size(stack))
+                .ok_or(TapSpendError::Unsatisfiable(index))?
+        };
+
+        let input = &mut self.inputs[index];
+        input.final_script_witness = Some(Witness::from_vec(witness));
+        input.tap_key_sig = None;
+        input.tap_script_sigs.clear();
+        input.tap_scripts.clear();
+        input.tap_key_origins.clear();
+        input.tap_internal_key = None;
+        input.tap_merkle_root = None;
+        Ok(())
+    }
+
+    fn finalize_tap_inputs(&mut self) -> Result<usize, TapSpendError> {
+        let pending = self
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| is_taproot(input) && input.final_script_witness.is_none())
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        for index in &pending {
+            self.finalize_tap_input(*index)?;
+        }
+        Ok(pending.len())
+    }
+}
+
+fn is_taproot(input: &psbt::Input) -> bool {
+    input
+        .witness_utxo
+        .as_ref()
+        .map(|txout| txout.script_pubkey.is_v1_p2tr())
+        .unwrap_or_default()
+}
+
+impl Wallet {
+    /// Fills PSBT output paying to a wallet taproot address (like change or receive output of a
+    /// transfer between wallet accounts) with the script tree and key origins. For outputs
+    /// tweaked with tapret commitment the script tree includes the commitment leaf. Returns
+    /// `false` if the output does not belong to the wallet or is not a taproot output.
+    pub fn fill_tap_output(&self, output: &mut psbt::Output, script_pubkey: &Script) -> bool {
+        let (change, index) = match self.is_mine(script_pubkey).as_deref().map(Vec::as_slice) {
+            Some([change, index]) => (*change, *index),
+            _ => return false,
+        };
+        let tr = match self.derive_tr(change, index) {
+            Ok(tr) => tr,
+            Err(_) => return false,
+        };
+        // Tapret-tweaked outputs commit to the script tree extended with the commitment leaf,
+        // which must be provided instead of the descriptor tree
+        let tapret = self
+            .tapret_tweak(change, index)
+            .and_then(|tweak| tweak.spend_info(&tr).ok().map(|info| (tweak, info)))
+            .filter(|(_, info)| Script::new_v1_p2tr_tweaked(info.output_key()) == *script_pubkey);
+        let tap_tree = match tapret {
+            Some((tweak, _)) => match tweak.tap_tree(&tr) {
+                Ok(tap_tree) => Some(tap_tree),
+                Err(_) => return false,
+            },
+            None if Script::new_v1_p2tr_tweaked(tr.spend_info().output_key()) == *script_pubkey => {
+                None
+            }
+            None => return false,
+        };
+        fill_tap_output(
+            output,
+            &tr,
+            key_origins(self.as_settings().signers(), change, index),
+        );
+        add_musig_origins(
+            &mut output.tap_key_origins,
+            self.as_settings(),
+            change,
+            index,
+        );
+        if tap_tree.is_some() {
+            output.tap_tree = tap_tree;
+        }
+        true
+    }
+}
@@ -11,9 +11,17 @@
 
 use std::sync::Arc;
 
+use bitcoin::util::taproot::{LeafVersion, TaprootBuilder, TAPROOT_CONTROL_MAX_NODE_COUNT};
+use bitcoin::{PublicKey, Script};
 use miniscript::descriptor::TapTree;
+use miniscript::policy::Concrete;
 use miniscript::{Miniscript, MiniscriptKey, Tap};
 
+use crate::compiler::huffman;
+
+/// Fixed-point precision of the branch probabilities used for the tree layout.
+const PROBABILITY_SCALE: f64 = (1u64 << 32) as f64;
+
 // TODO: Move to descriptor wallet library
 pub trait ToTapTree<Pk>
 where Pk: MiniscriptKey
@@ -59,3 +67,150 @@ where Pk: MiniscriptKey
             .ok_or_else(ms_err)
     }
 }
+
+/// Builds tap tree from a concrete miniscript policy (for instance, authored by external
+/// software). Top-level alternatives (`or` and `thresh(1, ...)`) become separate script leaves,
+/// which are packed according to the alternative probabilities, such that the most likely
+/// leaves get the shortest control blocks.
+impl<Pk> ToTapTree<Pk> for Concrete<Pk>
+where Pk: MiniscriptKey
+{
+    fn to_tap_tree(self) -> Result<TapTree<Pk>, miniscript::Error> {
+        let mut alternatives = vec![];
+        split_alternatives(self, 1.0, &mut alternatives);
+        let leaves = alternatives
+            .into_iter()
+            .map(|(probability, policy)| {
+                let weight = ((probability * PROBABILITY_SCALE) as usize).max(1);
+                Ok((weight, TapTree::Leaf(Arc::new(policy.compile::<Tap>()?))))
+            })
+            .collect::<Result<Vec<_>, miniscript::Error>>()?;
+        huffman(leaves, |(_, left), (_, right)| {
+            TapTree::Tree(Arc::new(left), Arc::new(right))
+        })
+        .ok_or_else(|| miniscript::Error::Unexpected(s!("policy has no satisfiable alternatives")))
+    }
+}
+
+fn split_alternatives<Pk>(
+    policy: Concrete<Pk>,
+    probability: f64,
+    alternatives: &mut Vec<(f64, Concrete<Pk>)>,
+) where
+    Pk: MiniscriptKey,
+{
+    match policy {
+        Concrete::Unsatisfiable => {}
+        Concrete::Or(subs) => {
+            let total = subs.iter().map(|(weight, _)| *weight).sum::<usize>().max(1) as f64;
+            for (weight, sub) in subs {
+                split_alternatives(sub, probability * weight as f64 / total, alternatives);
+            }
+        }
+        Concrete::Threshold(1, subs) if subs.len() > 1 => {
+            let count = subs.len() as f64;
+            for sub in subs {
+                split_alternatives(sub, probability / count, alternatives);
+            }
+        }
+        policy => alternatives.push((probability, policy)),
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TapLeafError {
+    /// Leaf version {0:#04x} is not a valid taproot leaf version.
+    InvalidVersion(u8),
+
+    /// Leaf depth {0} exceeds the maximum taproot tree depth.
+    DepthTooLarge(u8),
+
+    /// Tapscript leaf contains malformed script.
+    MalformedScript,
+
+    /// Unable to construct taproot tree from the provided leaves. Details: {0}
+    Tree(String),
+}
+
+/// Raw tapscript leaf which is not produced from the wallet spending conditions (like a
+/// hash-lock or a vendor-specific script).
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RawTapLeaf {
+    script: Script,
+    version: LeafVersion,
+}
+
+impl RawTapLeaf {
+    /// Constructs raw leaf validating the leaf version. Scripts with the tapscript leaf version
+    /// must consist of well-formed instructions; scripts with future leaf versions are not
+    /// validated.
+    pub fn with(script: Script, version: u8) -> Result<Self, TapLeafError> {
+        let version = LeafVersion::from_consensus(version)
+            .map_err(|_| TapLeafError::InvalidVersion(version))?;
+        if version == LeafVersion::TapScript
+            && script
+                .instructions()
+                .any(|instruction| instruction.is_err())
+        {
+            return Err(TapLeafError::MalformedScript);
+        }
+        Ok(RawTapLeaf { script, version })
+    }
+
+    pub fn tapscript(script: Script) -> Result<Self, TapLeafError> {
+        RawTapLeaf::with(script, LeafVersion::TapScript.to_consensus())
+    }
+
+    pub fn script(&self) -> &Script { &self.script }
+
+    pub fn version(&self) -> LeafVersion { self.version }
+}
+
+/// Leaf of a taproot script tree, which may be either miniscript or a raw script.
+#[derive(Clone, PartialEq, Eq, Debug, From)]
+#[allow(clippy::large_enum_variant)]
+pub enum TapLeaf {
+    #[from]
+    Miniscript(Miniscript<PublicKey, Tap>),
+
+    #[from]
+    Raw(RawTapLeaf),
+}
+
+impl TapLeaf {
+    fn into_script(self) -> (Script, LeafVersion) {
+        match self {
+            TapLeaf::Miniscript(ms) => (ms.encode(), LeafVersion::TapScript),
+            TapLeaf::Raw(leaf) => (leaf.script, leaf.version),
+        }
+    }
+}
+
+/// Constructs taproot tree builder from the leaves provided in DFS order together with their
+/// depths, allowing raw script leaves next to the miniscript ones. The builder can be finalized
+/// with the internal key into the taproot spending info.
+pub fn tap_tree_builder(
+    leaves: impl IntoIterator<Item = (u8, TapLeaf)>,
+) -> Result<TaprootBuilder, TapLeafError> {
+    leaves
+        .into_iter()
+        .try_fold(TaprootBuilder::new(), |builder, (depth, leaf)| {
+            if depth as usize > TAPROOT_CONTROL_MAX_NODE_COUNT {
+                return Err(TapLeafError::DepthTooLarge(depth));
+            }
+            let (script, version) = leaf.into_script();
+            builder
+                .add_leaf_with_ver(depth, script, version)
+                .map_err(|err| TapLeafError::Tree(err.to_string()))
+        })
+        .and_then(|builder| {
+            if builder.is_finalizable() {
+                Ok(builder)
+            } else {
+                Err(TapLeafError::Tree(s!(
+                    "leaf depths do not form a complete tree"
+                )))
+            }
+        })
+}
@@ -0,0 +1,345 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Cost basis accounting of the wallet history and capital gains reporting.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use bitcoin::Txid;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::{
+    AmountFormat, Denomination, HistoricalRateProvider, HistoryEntry, OnchainStatus, PriceHistory,
+    RateError, Wallet,
+};
+
+const SATS_PER_BTC: f64 = 100_000_000.0;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TaxError {
+    /// Price of bitcoin in {0} on {1} is unknown; the price history must be updated first.
+    NoPrice(String, NaiveDate),
+
+    /// Transaction {0} spends more funds than the wallet has acquired according to its history.
+    UnknownBasis(Txid),
+}
+
+/// Method of matching disposed funds with the acquisition lots.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+pub enum CostBasisMethod {
+    /// First in, first out: the oldest lots are disposed first.
+    #[default]
+    #[display("FIFO")]
+    Fifo,
+
+    /// Last in, first out: the most recent lots are disposed first.
+    #[display("LIFO")]
+    Lifo,
+
+    /// Specific identification: lots are the ones actually spent by the transaction inputs,
+    /// traced through the wallet change outputs. Amounts which can't be traced are matched as
+    /// with FIFO.
+    #[display("specific lot")]
+    SpecificLot,
+}
+
+/// Funds acquired by a single incoming transaction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TaxLot {
+    pub txid: Txid,
+    pub acquired: DateTime<Utc>,
+    /// Amount of the lot not yet disposed, in sats.
+    pub amount: u64,
+    /// Cost basis of the amount not yet disposed, in fiat.
+    pub cost: f64,
+}
+
+/// Disposal of (a part of) a single lot.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Disposal {
+    /// Transaction disposing the funds.
+    pub txid: Txid,
+    /// Transaction acquiring the lot.
+    pub lot: Txid,
+    pub acquired: DateTime<Utc>,
+    pub disposed: DateTime<Utc>,
+    /// Disposed amount, in sats.
+    pub amount: u64,
+    /// Proceeds in fiat. Transaction fees are deducted from the proceeds.
+    pub proceeds: f64,
+    /// Cost basis in fiat.
+    pub cost: f64,
+}
+
+impl Disposal {
+    pub fn gain(&self) -> f64 { self.proceeds - self.cost }
+
+    pub fn holding_period(&self) -> Duration { self.disposed - self.acquired }
+
+    /// Whether the lot was held for more than a year.
+    pub fn is_long_term(&self) -> bool { self.holding_period() > Duration::days(365) }
+}
+
+/// Layout of the exported capital gains records.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+pub enum TaxFormat {
+    /// All disposal details with ISO 8601 dates.
+    #[default]
+    #[display("generic")]
+    Generic,
+
+    /// Columns of the US IRS Form 8949, with the form part (I for short-term and II for
+    /// long-term) in the first column.
+    #[display("Form 8949")]
+    Form8949,
+}
+
+/// Capital gains of the wallet disposals.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TaxReport {
+    pub fiat: String,
+    pub method: CostBasisMethod,
+    pub disposals: Vec<Disposal>,
+    /// Lots which are not yet fully disposed.
+    pub open_lots: Vec<TaxLot>,
+}
+
+impl TaxReport {
+    pub fn total_gain(&self) -> f64 { self.disposals.iter().map(Disposal::gain).sum() }
+
+    pub fn short_term_gain(&self) -> f64 {
+        self.disposals
+            .iter()
+            .filter(|disposal| !disposal.is_long_term())
+            .map(Disposal::gain)
+            .sum()
+    }
+
+    pub fn long_term_gain(&self) -> f64 {
+        self.disposals
+            .iter()
+            .filter(|disposal| disposal.is_long_term())
+            .map(Disposal::gain)
+            .sum()
+    }
+
+    /// Total gains by the year of disposal.
+    pub fn gains_by_year(&self) -> BTreeMap<i32, f64> {
+        let mut gains = BTreeMap::<i32, f64>::new();
+        for disposal in &self.disposals {
+            *gains.entry(disposal.disposed.year()).or_default() += disposal.gain();
+        }
+        gains
+    }
+
+    /// Cost basis of the funds remaining in the wallet.
+    pub fn unrealized_cost(&self) -> f64 { self.open_lots.iter().map(|lot| lot.cost).sum() }
+
+    /// Writes disposals of the given year (or all disposals) as CSV with a header line.
+    pub fn export_csv(
+        &self,
+        format: TaxFormat,
+        year: Option<i32>,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let disposals = self.disposals.iter().filter(|disposal| {
+            year.map(|year| disposal.disposed.year() == year)
+                .unwrap_or(true)
+        });
+        let amount = |sats: u64| Denomination::Btc.format(sats, AmountFormat::PLAIN);
+        match format {
+            TaxFormat::Generic => {
+                writeln!(
+                    writer,
+                    "txid,lot,acquired,disposed,amount_btc,proceeds_{fiat},cost_{fiat},gain_{fiat},\
+                     term",
+                    fiat = self.fiat.to_lowercase()
+                )?;
+                for disposal in disposals {
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{},{:.2},{:.2},{:.2},{}",
+                        disposal.txid,
+                        disposal.lot,
+                        disposal.acquired.to_rfc3339(),
+                        disposal.disposed.to_rfc3339(),
+                        amount(disposal.amount),
+                        disposal.proceeds,
+                        disposal.cost,
+                        disposal.gain(),
+                        if disposal.is_long_term() { "long" } else { "short" }
+                    )?;
+                }
+            }
+            TaxFormat::Form8949 => {
+                writeln!(
+                    writer,
+                    "part,description,date_acquired,date_sold,proceeds,cost_basis,gain_or_loss"
+                )?;
+                for disposal in disposals {
+                    writeln!(
+                        writer,
+                        "{},{} BTC,{},{},{:.2},{:.2},{:.2}",
+                        if disposal.is_long_term() { "II" } else { "I" },
+                        amount(disposal.amount),
+                        disposal.acquired.format("%m/%d/%Y"),
+                        disposal.disposed.format("%m/%d/%Y"),
+                        disposal.proceeds,
+                        disposal.cost,
+                        disposal.gain()
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// Mined transactions changing the wallet balance, in the chronological order.
+    fn taxable_entries(&self) -> Vec<(&HistoryEntry, DateTime<Utc>)> {
+        let mut entries = self
+            .history()
+            .iter()
+            .filter(|entry| entry.onchain.status != OnchainStatus::Mempool && entry.balance() != 0)
+            .map(|entry| (entry, entry.date_time_est().with_timezone(&Utc)))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(entry, date_time)| (*date_time, entry.onchain));
+        entries
+    }
+
+    /// Cached daily prices of bitcoin in the wallet fiat currency.
+    pub fn fiat_price_history(&self) -> Option<&PriceHistory> {
+        self.price_history()
+            .get(&self.ephemerals().fiat.to_uppercase())
+    }
+
+    /// Records price of bitcoin in the fiat currency for the day, for instance imported from
+    /// the records of an exchange. Returns the previously known price.
+    pub fn set_historical_price(&mut self, fiat: &str, date: NaiveDate, price: f64) -> Option<f64> {
+        self.price_history_mut(fiat).insert(date, price)
+    }
+
+    /// Fetches the wallet fiat currency prices for the days of the wallet transactions missing
+    /// from the price history. Returns the number of fetched prices.
+    pub fn update_price_history(
+        &mut self,
+        provider: &impl HistoricalRateProvider,
+    ) -> Result<usize, RateError> {
+        let fiat = self.ephemerals().fiat.clone();
+        let dates = self
+            .taxable_entries()
+            .into_iter()
+            .map(|(_, date_time)| date_time.naive_utc().date())
+            .filter(|date| {
+                self.fiat_price_history()
+                    .map(|history| !history.contains(*date))
+                    .unwrap_or(true)
+            })
+            .collect::<BTreeSet<_>>();
+        for date in &dates {
+            let price = provider.historical_rate(&fiat, *date)?;
+            self.price_history_mut(&fiat).insert(*date, price);
+        }
+        Ok(dates.len())
+    }
+
+    /// Computes capital gains of the wallet transactions in the wallet fiat currency. Incoming
+    /// transactions acquire lots at the price of their day; outgoing transactions (including
+    /// transfers between own addresses, which dispose their fee) dispose lots chosen with the
+    /// given method.
+    pub fn tax_report(&self, method: CostBasisMethod) -> Result<TaxReport, TaxError> {
+        let fiat = self.ephemerals().fiat.clone();
+        let empty = PriceHistory::default();
+        let prices = self.fiat_price_history().unwrap_or(&empty);
+
+        let mut lots = Vec::<TaxLot>::new();
+        let mut disposals = Vec::<Disposal>::new();
+        // Lots whose funds may have ended up in the outputs of each transaction.
+        let mut origins = BTreeMap::<Txid, BTreeSet<Txid>>::new();
+
+        for (entry, date_time) in self.taxable_entries() {
+            let txid = entry.onchain.txid;
+            let date = date_time.naive_utc().date();
+            let price = prices
+                .price(date)
+                .ok_or_else(|| TaxError::NoPrice(fiat.clone(), date))?;
+            let mut sources = entry
+                .tx
+                .input
+                .iter()
+                .filter_map(|txin| origins.get(&txin.previous_output.txid))
+                .flatten()
+                .copied()
+                .collect::<BTreeSet<_>>();
+
+            let balance = entry.balance();
+            if balance > 0 {
+                lots.push(TaxLot {
+                    txid,
+                    acquired: date_time,
+                    amount: balance as u64,
+                    cost: balance as f64 / SATS_PER_BTC * price,
+                });
+                sources.insert(txid);
+            } else {
+                let amount = balance.unsigned_abs();
+                let fee = entry.fee.unwrap_or_default().min(amount);
+                let proceeds = (amount - fee) as f64 / SATS_PER_BTC * price;
+
+                let mut order = (0..lots.len()).collect::<Vec<_>>();
+                match method {
+                    CostBasisMethod::Fifo => {}
+                    CostBasisMethod::Lifo => order.reverse(),
+                    CostBasisMethod::SpecificLot => {
+                        order.sort_by_key(|no| !sources.contains(&lots[*no].txid))
+                    }
+                }
+                let mut remaining = amount;
+                for no in order {
+                    if remaining == 0 {
+                        break;
+                    }
+                    let lot = &mut lots[no];
+                    let taken = remaining.min(lot.amount);
+                    let cost = lot.cost * taken as f64 / lot.amount as f64;
+                    lot.amount -= taken;
+                    lot.cost -= cost;
+                    remaining -= taken;
+                    sources.insert(lot.txid);
+                    disposals.push(Disposal {
+                        txid,
+                        lot: lot.txid,
+                        acquired: lot.acquired,
+                        disposed: date_time,
+                        amount: taken,
+                        proceeds: proceeds * taken as f64 / amount as f64,
+                        cost,
+                    });
+                }
+                if remaining > 0 {
+                    return Err(TaxError::UnknownBasis(txid));
+                }
+                lots.retain(|lot| lot.amount > 0);
+            }
+            origins.insert(txid, sources);
+        }
+
+        Ok(TaxReport {
+            fiat,
+            method,
+            disposals,
+            open_lots: lots,
+        })
+    }
+}
@@ -9,16 +9,24 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
-use std::collections::BTreeSet;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
 
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::Network;
 use chrono::prelude::*;
 use wallet::descriptors::DescriptorClass;
 use wallet::hd::{Bip43, HardenedIndex, SegmentIndexes};
 use wallet::onchain::PublicNetwork;
 
-use crate::{DerivationType, SigsReq, SpendingCondition};
+use crate::{
+    DerivationStandardExt, DerivationType, DescriptorError, ElectrumServer, MultisigOrder, Signer,
+    SignerRef, SigsReq, SpendingCondition, TimelockDuration, TimelockReq, TimelockedSigs,
+    WalletSettings,
+};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub enum Requirement {
     #[default]
     Allow,
@@ -26,10 +34,80 @@ pub enum Requirement {
     Deny,
 }
 
+/// Errors detected during [`WalletTemplateBuilder::build`] validation.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TemplateError {
+    /// Template must require at least one signer.
+    NoSigners,
+
+    /// Minimal number of signers ({0}) exceeds maximal number of signers ({1}).
+    SignerBounds(u16, u16),
+
+    /// Template must contain at least one spending condition.
+    NoConditions,
+
+    /// Spending condition "{1}" at depth {0} requires zero signatures.
+    ZeroThreshold(u8, SpendingCondition),
+
+    /// Spending condition "{1}" at depth {0} requires more signatures than the maximal number of
+    /// signers ({2}).
+    InsufficientSigners(u8, SpendingCondition, u16),
+
+    /// Spending condition "{1}" at depth {0} references specific signers, which is not possible
+    /// in a template.
+    SpecificSigners(u8, SpendingCondition),
+
+    /// Degrading condition "{1}" at depth {0} must have strictly decreasing non-zero signature
+    /// thresholds and strictly increasing timelocks of the same kind.
+    InconsistentDegrading(u8, SpendingCondition),
+
+    /// Single signer can't be both a hardware and a watch-only one.
+    RequirementConflict,
+
+    /// Derivation standard {0} is not compatible with {1:?} descriptors.
+    DerivationMismatch(DerivationType, DescriptorClass),
+}
+
+/// Violation of the wallet template requirements by a set of signers, detected by
+/// [`WalletTemplate::validate`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum TemplateViolation {
+    /// Template requires at least {required} signers, while only {present} are provided.
+    TooFewSigners { required: u16, present: usize },
+
+    /// Template allows at most {allowed} signers, while {present} are provided.
+    TooManySigners { allowed: u16, present: usize },
+
+    /// Signer {0} is present more than once.
+    DuplicateSigner(Fingerprint),
+
+    /// Signer {0} uses extended public key for a different network.
+    NetworkMismatch(Fingerprint),
+
+    /// Template requires at least one hardware signer.
+    HardwareRequired,
+
+    /// Signer {0} is a hardware signer, which is not allowed by the template.
+    HardwareDenied(Fingerprint),
+
+    /// Template requires at least one watch-only signer.
+    WatchOnlyRequired,
+
+    /// Signer {0} is a watch-only signer, which is not allowed by the template.
+    WatchOnlyDenied(Fingerprint),
+
+    /// Spending condition "{1}" at depth {0} can't be satisfied by the provided signers.
+    InfeasibleCondition(u8, SpendingCondition),
+}
+
 /// Wallet template is a way to define constrained version of a wallet descriptor, but unlike
 /// [`super::WalletDescriptor`] not having restrains on the internal consistency between amount of
 /// signatures already present and condition parameters.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub struct WalletTemplate {
     pub default_derivation: DerivationType,
     pub descriptor_class: DescriptorClass,
@@ -40,9 +118,19 @@ pub struct WalletTemplate {
     pub conditions: BTreeSet<(u8, SpendingCondition)>,
     pub network: PublicNetwork,
     pub use_rgb: bool,
+    pub multisig_order: MultisigOrder,
+    /// Require taproot wallets to use provably unspendable (NUMS) internal key.
+    pub nums_internal_key: bool,
 }
 
 impl WalletTemplate {
+    pub fn builder(
+        network: PublicNetwork,
+        descriptor_class: DescriptorClass,
+    ) -> WalletTemplateBuilder {
+        WalletTemplateBuilder::new(network, descriptor_class)
+    }
+
     pub fn taproot_singlesig_rgb(network: PublicNetwork, require_hardware: bool) -> WalletTemplate {
         let hardware_req = match require_hardware {
             true => Requirement::Require,
@@ -62,6 +150,8 @@ impl WalletTemplate {
             conditions: bset![(0, SpendingCondition::default())],
             network,
             use_rgb: true,
+            multisig_order: MultisigOrder::Auto,
+            nums_internal_key: false,
         }
     }
 
@@ -90,6 +180,8 @@ impl WalletTemplate {
             conditions: bset![(0, SpendingCondition::default())],
             network,
             use_rgb,
+            multisig_order: MultisigOrder::Auto,
+            nums_internal_key: false,
         }
     }
 
@@ -124,6 +216,8 @@ impl WalletTemplate {
             conditions,
             network,
             use_rgb: false,
+            multisig_order: MultisigOrder::Auto,
+            nums_internal_key: false,
         }
     }
 
@@ -187,6 +281,99 @@ impl WalletTemplate {
             conditions,
             network,
             use_rgb: false,
+            multisig_order: MultisigOrder::Auto,
+            nums_internal_key: false,
+        }
+    }
+
+    /// Checks whether the signers satisfy template requirements, returning list of all detected
+    /// violations. Hardware signers are the ones with a known device; all other signers are
+    /// considered watch-only.
+    pub fn validate(&self, signers: &[Signer]) -> Result<(), Vec<TemplateViolation>> {
+        let mut violations = vec![];
+
+        let present = signers.len();
+        if present < self.min_signer_count as usize {
+            violations.push(TemplateViolation::TooFewSigners {
+                required: self.min_signer_count,
+                present,
+            });
+        }
+        if let Some(allowed) = self.max_signer_count {
+            if present > allowed as usize {
+                violations.push(TemplateViolation::TooManySigners { allowed, present });
+            }
+        }
+
+        let mut fingerprints = BTreeSet::new();
+        for signer in signers {
+            let fingerprint = signer.fingerprint();
+            if !fingerprints.insert(fingerprint) {
+                violations.push(TemplateViolation::DuplicateSigner(fingerprint));
+            }
+            if (signer.xpub.network == Network::Bitcoin) == self.network.is_testnet() {
+                violations.push(TemplateViolation::NetworkMismatch(fingerprint));
+            }
+        }
+
+        let (hardware, watch_only): (Vec<_>, Vec<_>) =
+            signers.iter().partition(|signer| signer.device.is_some());
+        match self.hardware_req {
+            Requirement::Require if hardware.is_empty() => {
+                violations.push(TemplateViolation::HardwareRequired)
+            }
+            Requirement::Deny => violations.extend(
+                hardware
+                    .iter()
+                    .map(|signer| TemplateViolation::HardwareDenied(signer.fingerprint())),
+            ),
+            _ => {}
+        }
+        match self.watch_only_req {
+            Requirement::Require if watch_only.is_empty() => {
+                violations.push(TemplateViolation::WatchOnlyRequired)
+            }
+            Requirement::Deny => violations.extend(
+                watch_only
+                    .iter()
+                    .map(|signer| TemplateViolation::WatchOnlyDenied(signer.fingerprint())),
+            ),
+            _ => {}
+        }
+
+        for (depth, condition) in &self.conditions {
+            let feasible = condition
+                .branches()
+                .iter()
+                .all(|branch| match &branch.sigs {
+                    SigsReq::All | SigsReq::Any => present > 0,
+                    SigsReq::AtLeast(count) => present >= *count as usize,
+                    SigsReq::Specific(count, required) => {
+                        required
+                            .iter()
+                            .all(|fp| fingerprints.contains(&fp.fingerprint()))
+                            && required.len() >= *count as usize
+                    }
+                    SigsReq::AccountBased(count, account) => {
+                        signers
+                            .iter()
+                            .filter(|signer| signer.account == Some(*account))
+                            .count()
+                            >= *count as usize
+                    }
+                });
+            if !feasible {
+                violations.push(TemplateViolation::InfeasibleCondition(
+                    *depth,
+                    condition.clone(),
+                ));
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
         }
     }
 
@@ -197,3 +384,479 @@ impl WalletTemplate {
         })
     }
 }
+
+/// Fluent builder for custom [`WalletTemplate`]s not covered by the predefined constructors.
+/// Template consistency is validated only once, in [`WalletTemplateBuilder::build`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct WalletTemplateBuilder {
+    network: PublicNetwork,
+    descriptor_class: DescriptorClass,
+    default_derivation: Option<DerivationType>,
+    min_signer_count: u16,
+    max_signer_count: Option<u16>,
+    hardware_req: Requirement,
+    watch_only_req: Requirement,
+    conditions: BTreeSet<(u8, SpendingCondition)>,
+    use_rgb: bool,
+    multisig_order: MultisigOrder,
+    nums_internal_key: bool,
+}
+
+impl WalletTemplateBuilder {
+    pub fn new(network: PublicNetwork, descriptor_class: DescriptorClass) -> WalletTemplateBuilder {
+        WalletTemplateBuilder {
+            network,
+            descriptor_class,
+            default_derivation: None,
+            min_signer_count: 1,
+            max_signer_count: None,
+            hardware_req: Requirement::Allow,
+            watch_only_req: Requirement::Allow,
+            conditions: empty!(),
+            use_rgb: false,
+            multisig_order: MultisigOrder::Auto,
+            nums_internal_key: false,
+        }
+    }
+
+    /// Sets derivation standard used by default. If not provided, the standard is deduced from
+    /// the descriptor class and the minimal number of signers.
+    pub fn derivation(mut self, derivation: impl Into<DerivationType>) -> Self {
+        self.default_derivation = Some(derivation.into());
+        self
+    }
+
+    pub fn descriptor_class(mut self, descriptor_class: DescriptorClass) -> Self {
+        self.descriptor_class = descriptor_class;
+        self
+    }
+
+    pub fn multisig_order(mut self, order: MultisigOrder) -> Self {
+        self.multisig_order = order;
+        self
+    }
+
+    pub fn nums_internal_key(mut self, nums: bool) -> Self {
+        self.nums_internal_key = nums;
+        self
+    }
+
+    pub fn signer_count(mut self, min: u16, max: Option<u16>) -> Self {
+        self.min_signer_count = min;
+        self.max_signer_count = max;
+        self
+    }
+
+    pub fn min_signers(mut self, min: u16) -> Self {
+        self.min_signer_count = min;
+        self
+    }
+
+    pub fn max_signers(mut self, max: u16) -> Self {
+        self.max_signer_count = Some(max);
+        self
+    }
+
+    pub fn hardware(mut self, req: Requirement) -> Self {
+        self.hardware_req = req;
+        self
+    }
+
+    pub fn watch_only(mut self, req: Requirement) -> Self {
+        self.watch_only_req = req;
+        self
+    }
+
+    /// Adds spending condition at a given depth of the condition tree. Depth defines the order
+    /// in which conditions are tried; conditions of the same depth are alternatives.
+    pub fn condition(mut self, depth: u8, condition: impl Into<SpendingCondition>) -> Self {
+        self.conditions.insert((depth, condition.into()));
+        self
+    }
+
+    pub fn conditions(
+        mut self,
+        conditions: impl IntoIterator<Item = (u8, SpendingCondition)>,
+    ) -> Self {
+        self.conditions.extend(conditions);
+        self
+    }
+
+    pub fn rgb(mut self, use_rgb: bool) -> Self {
+        self.use_rgb = use_rgb;
+        self
+    }
+
+    pub fn build(self) -> Result<WalletTemplate, TemplateError> {
+        if self.min_signer_count == 0 {
+            return Err(TemplateError::NoSigners);
+        }
+        if let Some(max) = self.max_signer_count {
+            if max < self.min_signer_count {
+                return Err(TemplateError::SignerBounds(self.min_signer_count, max));
+            }
+            if max == 1
+                && self.hardware_req == Requirement::Require
+                && self.watch_only_req == Requirement::Require
+            {
+                return Err(TemplateError::RequirementConflict);
+            }
+        }
+        if self.conditions.is_empty() {
+            return Err(TemplateError::NoConditions);
+        }
+        for (depth, condition) in &self.conditions {
+            if let SpendingCondition::Degrading(degrading) = condition {
+                if !degrading.is_consistent() {
+                    return Err(TemplateError::InconsistentDegrading(
+                        *depth,
+                        condition.clone(),
+                    ));
+                }
+            }
+            for TimelockedSigs { sigs, .. } in condition.branches() {
+                let required = match sigs {
+                    SigsReq::Specific(..) => {
+                        return Err(TemplateError::SpecificSigners(*depth, condition.clone()))
+                    }
+                    sigs => sigs.required_sigs_count(),
+                };
+                match (required, self.max_signer_count) {
+                    (Some(0), _) => {
+                        return Err(TemplateError::ZeroThreshold(*depth, condition.clone()))
+                    }
+                    (Some(required), Some(max)) if required > max => {
+                        return Err(TemplateError::InsufficientSigners(
+                            *depth,
+                            condition.clone(),
+                            max,
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let default_derivation = match self.default_derivation {
+            Some(derivation) => {
+                match derivation
+                    .bip43()
+                    .as_ref()
+                    .and_then(Bip43::descriptor_class)
+                {
+                    Some(class) if class != self.descriptor_class => {
+                        return Err(TemplateError::DerivationMismatch(
+                            derivation,
+                            self.descriptor_class,
+                        ))
+                    }
+                    _ => derivation,
+                }
+            }
+            None => self
+                .descriptor_class
+                .bip43(self.min_signer_count as usize)
+                .into(),
+        };
+
+        Ok(WalletTemplate {
+            default_derivation,
+            descriptor_class: self.descriptor_class,
+            min_signer_count: self.min_signer_count,
+            max_signer_count: self.max_signer_count,
+            hardware_req: self.hardware_req,
+            watch_only_req: self.watch_only_req,
+            conditions: self.conditions,
+            network: self.network,
+            use_rgb: self.use_rgb,
+            multisig_order: self.multisig_order,
+            nums_internal_key: self.nums_internal_key,
+        })
+    }
+}
+
+/// Named collection of approved wallet templates, which can be distributed to the users as a
+/// file (see [`crate::FileDocument`]) and loaded programmatically.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct TemplateCatalog {
+    pub name: String,
+    pub description: String,
+    templates: BTreeMap<String, WalletTemplate>,
+}
+
+impl TemplateCatalog {
+    pub fn new(name: impl ToString) -> TemplateCatalog {
+        TemplateCatalog {
+            name: name.to_string(),
+            description: s!(""),
+            templates: empty!(),
+        }
+    }
+
+    pub fn with(
+        name: impl ToString,
+        templates: impl IntoIterator<Item = (String, WalletTemplate)>,
+    ) -> TemplateCatalog {
+        TemplateCatalog {
+            name: name.to_string(),
+            description: s!(""),
+            templates: templates.into_iter().collect(),
+        }
+    }
+
+    /// Adds template to the catalog, returning previous template with the same name, if any.
+    pub fn insert(
+        &mut self,
+        name: impl ToString,
+        template: WalletTemplate,
+    ) -> Option<WalletTemplate> {
+        self.templates.insert(name.to_string(), template)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<WalletTemplate> { self.templates.remove(name) }
+
+    pub fn get(&self, name: &str) -> Option<&WalletTemplate> { self.templates.get(name) }
+
+    pub fn contains(&self, name: &str) -> bool { self.templates.contains_key(name) }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> { self.templates.keys().map(String::as_str) }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, WalletTemplate> { self.templates.iter() }
+
+    pub fn len(&self) -> usize { self.templates.len() }
+
+    pub fn is_empty(&self) -> bool { self.templates.is_empty() }
+
+    /// Templates from the catalog applicable to the given network.
+    pub fn for_network(
+        &self,
+        network: PublicNetwork,
+    ) -> impl Iterator<Item = (&String, &WalletTemplate)> {
+        self.templates
+            .iter()
+            .filter(move |(_, template)| template.network == network)
+    }
+}
+
+impl<'catalog> IntoIterator for &'catalog TemplateCatalog {
+    type Item = (&'catalog String, &'catalog WalletTemplate);
+    type IntoIter = btree_map::Iter<'catalog, String, WalletTemplate>;
+
+    fn into_iter(self) -> Self::IntoIter { self.templates.iter() }
+}
+
+/// Maximal number of alternative signer sets a single role-based condition may expand into.
+pub const MAX_ROLE_COMBINATIONS: usize = 64;
+
+/// Errors happening when role-based template is instantiated into wallet settings.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum RoleError {
+    /// Signer {0} is tagged with role "{1}" unknown to the template.
+    UnknownRole(Fingerprint, String),
+
+    /// Signer {0} has no roles assigned.
+    NoRoles(Fingerprint),
+
+    /// Spending condition at depth {0} requires {1} signatures from roles {2:?}, while only {3}
+    /// signers have these roles.
+    InsufficientRoleSigners(u8, u16, BTreeSet<String>, usize),
+
+    /// Spending condition at depth {0} expands into too many alternative signer sets.
+    TooManyCombinations(u8),
+
+    #[display(inner)]
+    #[from]
+    Descriptor(DescriptorError),
+}
+
+/// Requirement of a number of signatures from signers having any of the given roles.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct RoleSigs {
+    pub roles: BTreeSet<String>,
+    pub count: u16,
+}
+
+impl RoleSigs {
+    pub fn any_of(count: u16, roles: impl IntoIterator<Item = impl ToString>) -> RoleSigs {
+        RoleSigs {
+            roles: roles.into_iter().map(|role| role.to_string()).collect(),
+            count,
+        }
+    }
+}
+
+/// Spending condition defined in terms of signer roles instead of specific signers. All role
+/// requirements must be satisfied simultaneously, each by a distinct signer.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct RoleCondition {
+    pub sigs: Vec<RoleSigs>,
+    pub timelock: TimelockReq,
+}
+
+/// Template for wallets where signers are tagged with organizational roles and spending
+/// conditions reference roles. Roles are compiled down to [`SigsReq::Specific`] signer sets
+/// when the wallet is instantiated with [`RoleTemplate::instantiate`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RoleTemplate {
+    pub descriptor_class: DescriptorClass,
+    pub network: PublicNetwork,
+    pub hardware_req: Requirement,
+    pub watch_only_req: Requirement,
+    pub roles: BTreeSet<String>,
+    pub conditions: BTreeSet<(u8, RoleCondition)>,
+}
+
+impl RoleTemplate {
+    /// Corporate treasury, where any two of the company officers (CEO, CFO, COO) can spend,
+    /// or any officer together with the auditor after six months.
+    pub fn corporate_treasury(
+        descriptor_class: DescriptorClass,
+        network: PublicNetwork,
+        hardware_req: Requirement,
+    ) -> RoleTemplate {
+        let officers = ["CEO", "CFO", "COO"];
+        RoleTemplate {
+            descriptor_class,
+            network,
+            hardware_req,
+            watch_only_req: Requirement::Allow,
+            roles: ["CEO", "CFO", "COO", "auditor"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            conditions: bset![
+                (1, RoleCondition {
+                    sigs: vec![RoleSigs::any_of(2, officers)],
+                    timelock: TimelockReq::Anytime,
+                }),
+                (2, RoleCondition {
+                    sigs: vec![RoleSigs::any_of(1, officers), RoleSigs::any_of(1, ["auditor"])],
+                    timelock: TimelockReq::AfterPeriod(TimelockDuration::Months(6)),
+                })
+            ],
+        }
+    }
+
+    /// Compiles role-based conditions into spending conditions over the provided signers.
+    pub fn compile(
+        &self,
+        signers: &[(Signer, BTreeSet<String>)],
+    ) -> Result<BTreeSet<(u8, SpendingCondition)>, RoleError> {
+        for (signer, roles) in signers {
+            if roles.is_empty() {
+                return Err(RoleError::NoRoles(signer.fingerprint()));
+            }
+            if let Some(role) = roles.iter().find(|role| !self.roles.contains(*role)) {
+                return Err(RoleError::UnknownRole(signer.fingerprint(), role.clone()));
+            }
+        }
+
+        let mut conditions = bset![];
+        for (depth, condition) in &self.conditions {
+            let required = condition.sigs.iter().map(|req| req.count).sum::<u16>();
+            // Each element is a set of signers satisfying all role requirements processed so far
+            let mut alternatives: Vec<BTreeSet<Fingerprint>> = vec![empty!()];
+            for req in &condition.sigs {
+                let candidates = signers
+                    .iter()
+                    .filter(|(_, roles)| !roles.is_disjoint(&req.roles))
+                    .map(|(signer, _)| signer.fingerprint())
+                    .collect::<Vec<_>>();
+                if candidates.len() < req.count as usize {
+                    return Err(RoleError::InsufficientRoleSigners(
+                        *depth,
+                        req.count,
+                        req.roles.clone(),
+                        candidates.len(),
+                    ));
+                }
+                // Requirement on a single group of roles is expressed as a threshold
+                if condition.sigs.len() == 1 {
+                    alternatives = vec![candidates.into_iter().collect()];
+                    break;
+                }
+                let mut next = Vec::new();
+                for prev in &alternatives {
+                    let available = candidates
+                        .iter()
+                        .filter(|fp| !prev.contains(fp))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for subset in combinations(&available, req.count as usize) {
+                        let mut set = prev.clone();
+                        set.extend(subset);
+                        if !next.contains(&set) {
+                            next.push(set);
+                        }
+                    }
+                    if next.len() > MAX_ROLE_COMBINATIONS {
+                        return Err(RoleError::TooManyCombinations(*depth));
+                    }
+                }
+                if next.is_empty() {
+                    return Err(RoleError::InsufficientRoleSigners(
+                        *depth,
+                        required,
+                        condition
+                            .sigs
+                            .iter()
+                            .flat_map(|req| req.roles.iter().cloned())
+                            .collect(),
+                        0,
+                    ));
+                }
+                alternatives = next;
+            }
+            for set in alternatives {
+                conditions.insert((
+                    *depth,
+                    SpendingCondition::Sigs(TimelockedSigs {
+                        sigs: SigsReq::Specific(
+                            required,
+                            set.into_iter().map(SignerRef::from).collect(),
+                        ),
+                        timelock: condition.timelock,
+                    }),
+                ));
+            }
+        }
+        Ok(conditions)
+    }
+
+    /// Instantiates wallet settings from the signers tagged with roles.
+    pub fn instantiate(
+        &self,
+        signers: impl IntoIterator<Item = (Signer, BTreeSet<String>)>,
+        electrum: ElectrumServer,
+    ) -> Result<WalletSettings, RoleError> {
+        let signers = signers.into_iter().collect::<Vec<_>>();
+        let conditions = self.compile(&signers)?;
+        WalletSettings::new_btc(
+            signers.into_iter().map(|(signer, _)| signer),
+            conditions,
+            self.descriptor_class,
+            self.network,
+            electrum,
+        )
+        .map_err(RoleError::from)
+    }
+}
+
+fn combinations(items: &[Fingerprint], k: usize) -> Vec<BTreeSet<Fingerprint>> {
+    if k == 0 {
+        return vec![empty!()];
+    }
+    if items.len() < k {
+        return vec![];
+    }
+    let (first, rest) = items.split_first().expect("non-empty items");
+    let mut with_first = combinations(rest, k - 1);
+    for set in &mut with_first {
+        set.insert(*first);
+    }
+    with_first.extend(combinations(rest, k));
+    with_first
+}
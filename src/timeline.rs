@@ -0,0 +1,107 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Time series of the wallet balance for drawing charts.
+
+use chrono::{DateTime, Datelike, Utc};
+
+use crate::{OnchainStatus, Wallet};
+
+/// Period aggregated into a single point of the balance timeline.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Default)]
+pub enum TimelineResolution {
+    #[display("block")]
+    Block,
+
+    /// Calendar day in UTC.
+    #[default]
+    #[display("day")]
+    Day,
+}
+
+/// Wallet balance at the end of a period with wallet transactions.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BalancePoint {
+    /// Height of the last block in the period with wallet transactions; `None` for the
+    /// unconfirmed transactions.
+    pub height: Option<u32>,
+    /// Block time for the block resolution, midnight for the day resolution and the current
+    /// time for the unconfirmed transactions.
+    pub date_time: DateTime<Utc>,
+    pub balance: u64,
+    /// Change of the balance during the period.
+    pub change: i64,
+    pub tx_count: u32,
+}
+
+impl Wallet {
+    /// Wallet balance after each period containing wallet transactions, from the oldest to the
+    /// newest. Periods without transactions are omitted, so the balance is a step function
+    /// between the points. Unconfirmed transactions, if included, form the last point.
+    pub fn balance_timeline(
+        &self,
+        resolution: TimelineResolution,
+        include_mempool: bool,
+    ) -> Vec<BalancePoint> {
+        let mut entries = self
+            .history()
+            .iter()
+            .filter(|entry| include_mempool || entry.onchain.status != OnchainStatus::Mempool)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| (entry.onchain.status, entry.date_time_est()));
+
+        let mut points = Vec::<BalancePoint>::new();
+        let mut last_period = None;
+        let mut balance = 0i64;
+        for entry in entries {
+            let (height, date_time) = match entry.onchain.status {
+                OnchainStatus::Blockchain(height) => {
+                    (Some(height), entry.date_time_est().with_timezone(&Utc))
+                }
+                OnchainStatus::Mempool => (None, Utc::now()),
+            };
+            let (period, date_time) = match (resolution, height) {
+                (_, None) => (None, date_time),
+                (TimelineResolution::Block, Some(height)) => (Some(height as i64), date_time),
+                (TimelineResolution::Day, Some(_)) => {
+                    let midnight = date_time
+                        .naive_utc()
+                        .date()
+                        .and_hms_opt(0, 0, 0)
+                        .expect("midnight");
+                    (
+                        Some(date_time.num_days_from_ce() as i64),
+                        DateTime::<Utc>::from_utc(midnight, Utc),
+                    )
+                }
+            };
+            let change = entry.balance();
+            balance += change;
+            match points.last_mut() {
+                Some(point) if last_period == Some(period) => {
+                    point.height = height;
+                    point.balance = balance.max(0) as u64;
+                    point.change += change;
+                    point.tx_count += 1;
+                }
+                _ => points.push(BalancePoint {
+                    height,
+                    date_time,
+                    balance: balance.max(0) as u64,
+                    change,
+                    tx_count: 1,
+                }),
+            }
+            last_period = Some(period);
+        }
+        points
+    }
+}
@@ -13,9 +13,12 @@ use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
+use bitcoin::hashes::hex::FromHex;
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
-use chrono::{DateTime, Utc};
+use bitcoin::{LockTime, Sequence, Transaction};
+use chrono::{DateTime, TimeZone, Utc};
 use hwi::types::HWIDevice;
 use hwi::HWIClient;
 use wallet::hd::standards::DerivationBlockchain;
@@ -24,6 +27,10 @@ use wallet::hd::{
     SegmentIndexes, TerminalStep, XpubRef, XpubkeyCore,
 };
 use wallet::onchain::PublicNetwork;
+use wallet::slip132::FromSlip132;
+
+/// Mask for the value of relative timelock encoded in the sequence number.
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000FFFF;
 
 // TODO: Move to descriptor wallet or BPro
 
@@ -174,6 +181,53 @@ impl Display for OriginFormat {
     }
 }
 
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum OriginParseError {
+    /// Invalid derivation path `{0}`.
+    InvalidPath(String),
+
+    /// Derivation path is too deep ({0} steps).
+    TooDeep(usize),
+
+    /// Coin type {0} in the derivation path does not correspond to bitcoin mainnet or testnet.
+    UnknownCoinType(ChildNumber),
+}
+
+impl FromStr for OriginFormat {
+    type Err = OriginParseError;
+
+    /// Parses derivation path in `m/86'/1'/0'` format, accepting both `'` and `h` as hardened
+    /// index markers and tolerating missing `m/` prefix. Network for the standard derivation
+    /// schemes is detected from the coin type.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().replace(['H', '’'], "'").replace('h', "'");
+        let normalized = normalized.trim_end_matches('/');
+        let normalized = match normalized.strip_prefix('m') {
+            Some(rest) => rest.trim_start_matches('/').to_owned(),
+            None => normalized.trim_start_matches('/').to_owned(),
+        };
+        let path = match normalized.as_str() {
+            "" => DerivationPath::master(),
+            path => DerivationPath::from_str(&format!("m/{path}"))
+                .map_err(|_| OriginParseError::InvalidPath(s.to_owned()))?,
+        };
+        let depth = u8::try_from(path.as_ref().len())
+            .map_err(|_| OriginParseError::TooDeep(path.as_ref().len()))?;
+
+        let coin_type = Bip43::deduce(&path)
+            .and_then(|bip43| bip43.coin_type_depth())
+            .and_then(|coin_depth| path.as_ref().get(coin_depth as usize).copied());
+        let network = match coin_type {
+            None | Some(ChildNumber::Hardened { index: 0 }) => PublicNetwork::Mainnet,
+            Some(ChildNumber::Hardened { index: 1 }) => PublicNetwork::Testnet,
+            Some(child) => return Err(OriginParseError::UnknownCoinType(child)),
+        };
+
+        Ok(OriginFormat::with_account(&path, depth, network))
+    }
+}
+
 impl OriginFormat {
     pub fn with_account(path: &DerivationPath, depth: u8, network: PublicNetwork) -> OriginFormat {
         let bip43 = Bip43::deduce(path);
@@ -224,6 +278,44 @@ impl OriginFormat {
      */
 }
 
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum KeyOriginError {
+    /// Key origin must be provided in square brackets before the extended public key, like
+    /// `[f1d2c3b4/84'/0'/0']xpub...`.
+    NoOrigin,
+
+    /// Invalid master key fingerprint `{0}` in the key origin.
+    InvalidFingerprint(String),
+
+    /// Invalid derivation path `{0}` in the key origin.
+    InvalidPath(String),
+
+    /// Invalid extended public key. Details: {0}
+    InvalidXpub(String),
+
+    /// Key origin has {0} derivation steps, while the extended public key has depth {1}.
+    DepthMismatch(usize, u8),
+
+    /// Key origin ends with derivation step {0}, while the extended public key was derived with
+    /// {1}.
+    ChildMismatch(ChildNumber, ChildNumber),
+
+    /// Master key fingerprint {0} does not match the fingerprint {1} of the provided master
+    /// extended public key.
+    MasterMismatch(Fingerprint, Fingerprint),
+
+    /// Key origin {0} corresponds to {1} derivation scheme, while the wallet uses {2}.
+    SchemeMismatch(DerivationPath, Bip43, Bip43),
+
+    /// Key origin {0} has depth {1}, which is not enough to include account index required by
+    /// {2} derivation scheme at position {3}.
+    AccountDepthMismatch(DerivationPath, usize, Bip43, u8),
+
+    /// Extended public key is intended for a different network than {0}.
+    NetworkMismatch(PublicNetwork),
+}
+
 #[derive(Clone, Debug)]
 #[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
@@ -320,6 +412,89 @@ impl Signer {
         }
     }
 
+    /// Constructs signer from the key-origin notation `[f1d2c3b4/84'/0'/0']xpub...`, checking
+    /// that the origin is consistent with the extended public key and the wallet derivation
+    /// scheme.
+    pub fn with_key_origin(
+        s: &str,
+        schema: &Bip43,
+        network: PublicNetwork,
+    ) -> Result<Self, KeyOriginError> {
+        let (origin, xpub) = s
+            .trim()
+            .strip_prefix('[')
+            .and_then(|s| s.split_once(']'))
+            .ok_or(KeyOriginError::NoOrigin)?;
+        let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+
+        let master_fp = Vec::<u8>::from_hex(fingerprint)
+            .ok()
+            .filter(|data| data.len() == 4)
+            .map(|data| Fingerprint::from(&data[..]))
+            .ok_or_else(|| KeyOriginError::InvalidFingerprint(fingerprint.to_owned()))?;
+        let origin = match path {
+            "" => DerivationPath::master(),
+            path => DerivationPath::from_str(&format!("m/{}", path.replace('H', "h")))
+                .map_err(|_| KeyOriginError::InvalidPath(path.to_owned()))?,
+        };
+        let xpub = ExtendedPubKey::from_slip132_str(xpub.trim())
+            .map_err(|err| KeyOriginError::InvalidXpub(err.to_string()))?;
+
+        if (xpub.network == bitcoin::Network::Bitcoin) != (network == PublicNetwork::Mainnet) {
+            return Err(KeyOriginError::NetworkMismatch(network));
+        }
+        let steps = origin.as_ref();
+        if steps.len() != xpub.depth as usize {
+            return Err(KeyOriginError::DepthMismatch(steps.len(), xpub.depth));
+        }
+        match steps.last() {
+            None if master_fp != xpub.fingerprint() => {
+                return Err(KeyOriginError::MasterMismatch(
+                    master_fp,
+                    xpub.fingerprint(),
+                ));
+            }
+            Some(child) if *child != xpub.child_number => {
+                return Err(KeyOriginError::ChildMismatch(*child, xpub.child_number));
+            }
+            _ => {}
+        }
+        if let Some(found) = Bip43::deduce(&origin) {
+            if found != *schema {
+                return Err(KeyOriginError::SchemeMismatch(origin, found, *schema));
+            }
+        }
+        if let Some(account_depth) = schema.account_depth() {
+            if steps.len() <= account_depth as usize {
+                return Err(KeyOriginError::AccountDepthMismatch(
+                    origin.clone(),
+                    steps.len(),
+                    *schema,
+                    account_depth,
+                ));
+            }
+        }
+
+        let account = schema
+            .extract_account_index(&origin)
+            .and_then(Result::ok)
+            .or_else(|| {
+                steps
+                    .last()
+                    .copied()
+                    .and_then(|child| HardenedIndex::try_from(child).ok())
+            });
+        Ok(Signer {
+            master_fp,
+            origin,
+            account,
+            xpub,
+            device: None,
+            name: "".to_string(),
+            ownership: Ownership::External,
+        })
+    }
+
     pub fn is_master_known(&self) -> bool { self.master_fp != zero!() }
 
     pub fn account_string(&self) -> String {
@@ -364,26 +539,106 @@ impl Signer {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Default)]
+/// Reference to a wallet signer by the fingerprint of its account extended public key.
+#[derive(Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, From)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[display(inner)]
+pub struct SignerRef(Fingerprint);
+
+impl SignerRef {
+    pub fn fingerprint(self) -> Fingerprint { self.0 }
+
+    /// Finds referenced signer among the provided ones.
+    pub fn resolve(self, signers: &[Signer]) -> Option<&Signer> {
+        signers.iter().find(|signer| signer.fingerprint() == self.0)
+    }
+
+    /// Name of the referenced signer, or the fingerprint if the signer is not known or unnamed.
+    pub fn name(self, signers: &[Signer]) -> String {
+        self.resolve(signers)
+            .map(|signer| signer.name.clone())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| self.0.to_string())
+    }
+}
+
+impl From<&Signer> for SignerRef {
+    fn from(signer: &Signer) -> Self { SignerRef(signer.fingerprint()) }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
 #[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub enum SigsReq {
     #[default]
-    #[display("all signatures")]
     All,
-    #[display("at least {0} signatures")]
     AtLeast(u16),
-    /// A set of account xpub fingerprints
-    // TODO: Do custom Display implementation
-    #[display("set of signatures")]
-    Specific(u16, Vec<Fingerprint>),
-    #[display("any signature")]
+    /// A set of signers referenced by their account xpub fingerprints
+    Specific(u16, Vec<SignerRef>),
     Any,
-    #[display("at least {0} signatures from account {1}")]
     AccountBased(u16, HardenedIndex),
 }
 
+impl Display for SigsReq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, |signer| signer.to_string())
+    }
+}
+
+/// Displays [`SigsReq`] with signers referenced by their names; constructed with
+/// [`SigsReq::named`].
+#[derive(Copy, Clone, Debug)]
+pub struct NamedSigsReq<'req> {
+    sigs: &'req SigsReq,
+    signers: &'req [Signer],
+}
+
+impl<'req> Display for NamedSigsReq<'req> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.sigs.fmt_with(f, |signer| signer.name(self.signers))
+    }
+}
+
 impl SigsReq {
+    /// Wraps the requirement into a type displaying names of the specific signers, like
+    /// "2 of {Alice's Ledger, Bob's Trezor, Treasury cold}".
+    pub fn named<'req>(&'req self, signers: &'req [Signer]) -> NamedSigsReq<'req> {
+        NamedSigsReq {
+            sigs: self,
+            signers,
+        }
+    }
+
+    fn fmt_with(
+        &self,
+        f: &mut Formatter<'_>,
+        signer_name: impl Fn(SignerRef) -> String,
+    ) -> fmt::Result {
+        match self {
+            SigsReq::All => f.write_str("all signatures"),
+            SigsReq::AtLeast(count) => write!(f, "at least {} signatures", count),
+            SigsReq::Specific(count, signers) => {
+                write!(f, "{} of {{", count)?;
+                for (no, signer) in signers.iter().enumerate() {
+                    if no > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(&signer_name(*signer))?;
+                }
+                f.write_str("}")
+            }
+            SigsReq::Any => f.write_str("any signature"),
+            SigsReq::AccountBased(count, account) => {
+                write!(f, "at least {} signatures from account {}", count, account)
+            }
+        }
+    }
+
     pub fn required_sigs_count(&self) -> Option<u16> {
         match self {
             SigsReq::All => None,
@@ -410,20 +665,71 @@ pub enum TimelockDuration {
 
     #[display("{0} years")]
     Years(u8),
+
+    /// Raw number of 512-second intervals, as used by `OP_CHECKSEQUENCEVERIFY`.
+    #[display("{0} × 512 seconds")]
+    Intervals(u16),
+
+    /// Number of blocks mined after the output.
+    #[display("{0} blocks")]
+    Blocks(u16),
 }
 
 impl TimelockDuration {
-    pub fn intervals(self) -> u16 {
-        const DAY: u32 = 24 * 60 * 60;
-        const WEEK: u32 = DAY * 7;
-        const MONTH: u32 = DAY * 30;
-        const YEAR: u32 = DAY * 365;
-        (match self {
-            TimelockDuration::Days(days) => days as u32 * DAY,
-            TimelockDuration::Weeks(weeks) => weeks as u32 * WEEK,
-            TimelockDuration::Months(months) => months as u32 * MONTH,
-            TimelockDuration::Years(years) => years as u32 * YEAR,
-        } / 512) as u16
+    /// Maximal duration representable with a relative timelock, in seconds.
+    pub const MAX_SECONDS: u64 = u16::MAX as u64 * 512;
+
+    /// Duration in seconds, or `None` for the block-based durations.
+    pub fn seconds(self) -> Option<u64> {
+        const DAY: u64 = 24 * 60 * 60;
+        const WEEK: u64 = DAY * 7;
+        const MONTH: u64 = DAY * 30;
+        const YEAR: u64 = DAY * 365;
+        Some(match self {
+            TimelockDuration::Days(days) => days as u64 * DAY,
+            TimelockDuration::Weeks(weeks) => weeks as u64 * WEEK,
+            TimelockDuration::Months(months) => months as u64 * MONTH,
+            TimelockDuration::Years(years) => years as u64 * YEAR,
+            TimelockDuration::Intervals(intervals) => intervals as u64 * 512,
+            TimelockDuration::Blocks(_) => return None,
+        })
+    }
+
+    /// Number of whole 512-second intervals in the duration (the remainder is rounded down).
+    ///
+    /// # Errors
+    ///
+    /// If the duration is block-based or exceeds the maximal relative timelock
+    /// ([`TimelockDuration::MAX_SECONDS`]).
+    pub fn intervals(self) -> Result<u16, TimelockError> {
+        let seconds = self
+            .seconds()
+            .ok_or(TimelockError::BlockBasedPeriod(self))?;
+        u16::try_from(seconds / 512).map_err(|_| TimelockError::PeriodOverflow(self))
+    }
+
+    /// Sequence value encoding the duration as a relative timelock.
+    pub fn sequence(self) -> Result<Sequence, TimelockError> {
+        match self {
+            TimelockDuration::Blocks(blocks) => Ok(Sequence::from_height(blocks)),
+            duration => duration
+                .intervals()
+                .map(Sequence::from_512_second_intervals),
+        }
+    }
+
+    /// Finds duration expressed in the largest whole units which corresponds to the given number
+    /// of 512-second intervals, falling back to [`TimelockDuration::Intervals`].
+    pub fn from_intervals(intervals: u16) -> TimelockDuration {
+        // Relative timelocks can't exceed 65535 intervals of 512 seconds (~388 days)
+        let mut candidates = (1..=1)
+            .map(TimelockDuration::Years)
+            .chain((1..=12).map(TimelockDuration::Months))
+            .chain((1..=55).map(TimelockDuration::Weeks))
+            .chain((1..=u8::MAX).map(TimelockDuration::Days));
+        candidates
+            .find(|duration| duration.intervals() == Ok(intervals))
+            .unwrap_or(TimelockDuration::Intervals(intervals))
     }
 }
 
@@ -444,6 +750,196 @@ pub enum TimelockReq {
     AfterHeight(u32),
 }
 
+impl TimelockReq {
+    /// Checks whether the timelock is satisfied for an output at a given blockchain height and
+    /// time. `mined` provides height and time of the block containing the output, if it is mined.
+    pub fn is_satisfied(
+        &self,
+        height: u32,
+        now: DateTime<Utc>,
+        mined: Option<(u32, DateTime<Utc>)>,
+    ) -> bool {
+        match (self, mined) {
+            (TimelockReq::Anytime, _) => true,
+            (TimelockReq::AfterDate(date), _) => now >= *date,
+            (TimelockReq::AfterHeight(block), _) => height >= *block,
+            (TimelockReq::AfterBlock(blocks), Some((mined_height, _))) => {
+                height.saturating_sub(mined_height) + 1 >= *blocks as u32
+            }
+            (
+                TimelockReq::AfterPeriod(TimelockDuration::Blocks(blocks)),
+                Some((mined_height, _)),
+            ) => height.saturating_sub(mined_height) + 1 >= *blocks as u32,
+            (TimelockReq::AfterPeriod(duration), Some((_, mined_time))) => {
+                let seconds = duration.seconds().unwrap_or(u64::MAX).min(i64::MAX as u64);
+                (now - mined_time).num_seconds() >= seconds as i64
+            }
+            (TimelockReq::AfterBlock(_) | TimelockReq::AfterPeriod(_), None) => false,
+        }
+    }
+
+    /// Converts the requirement into the value used by `OP_CHECKLOCKTIMEVERIFY` or
+    /// `OP_CHECKSEQUENCEVERIFY` script terms. Returns `None` for [`TimelockReq::Anytime`].
+    ///
+    /// Absolute dates are compared by the consensus rules against the median time past of the
+    /// last 11 blocks, which usually lags behind the wall clock by about an hour; relative
+    /// periods are rounded down to whole 512-second intervals.
+    pub fn script_timelock(&self) -> Result<Option<ScriptTimelock>, TimelockError> {
+        Ok(Some(match self {
+            TimelockReq::Anytime => return Ok(None),
+            TimelockReq::AfterDate(date) => {
+                let timestamp = u32::try_from(date.timestamp())
+                    .map_err(|_| TimelockError::DateOutOfRange(*date))?;
+                ScriptTimelock::Absolute(
+                    LockTime::from_time(timestamp)
+                        .map_err(|_| TimelockError::DateOutOfRange(*date))?,
+                )
+            }
+            TimelockReq::AfterHeight(height) => ScriptTimelock::Absolute(
+                LockTime::from_height(*height)
+                    .map_err(|_| TimelockError::HeightOutOfRange(*height))?,
+            ),
+            TimelockReq::AfterPeriod(duration) => ScriptTimelock::Relative(duration.sequence()?),
+            TimelockReq::AfterBlock(blocks) => {
+                ScriptTimelock::Relative(Sequence::from_height(*blocks))
+            }
+        }))
+    }
+}
+
+/// Errors converting between [`TimelockReq`] and script timelock values.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TimelockError {
+    /// Date {0} can't be represented as an absolute timelock.
+    DateOutOfRange(DateTime<Utc>),
+
+    /// Block height {0} can't be represented as an absolute timelock.
+    HeightOutOfRange(u32),
+
+    /// Duration of {0} exceeds maximal relative timelock of 65535 × 512 seconds (~388 days).
+    PeriodOverflow(TimelockDuration),
+
+    /// Duration of {0} is measured in blocks and can't be converted into time intervals.
+    BlockBasedPeriod(TimelockDuration),
+
+    /// Sequence value {0:#010x} doesn't encode a relative timelock.
+    NotRelativeTimelock(u32),
+}
+
+/// Timelock in the form it is committed to in the script.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ScriptTimelock {
+    /// Absolute timelock checked with `OP_CHECKLOCKTIMEVERIFY`.
+    Absolute(LockTime),
+
+    /// Relative timelock checked with `OP_CHECKSEQUENCEVERIFY`.
+    Relative(Sequence),
+}
+
+impl ScriptTimelock {
+    /// Consensus value of the timelock as it is put into the script.
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            ScriptTimelock::Absolute(lock_time) => lock_time.to_consensus_u32(),
+            ScriptTimelock::Relative(sequence) => sequence.to_consensus_u32(),
+        }
+    }
+}
+
+impl ScriptTimelock {
+    /// Checks whether the lock time of the transaction and sequence of its input satisfy the
+    /// timelock, such that the input may spend a script branch protected by it.
+    pub fn is_enabled_by(self, tx: &Transaction, vin: usize) -> bool {
+        let sequence = match tx.input.get(vin) {
+            Some(txin) => txin.sequence,
+            None => return false,
+        };
+        match (self, LockTime::from_consensus(tx.lock_time.0)) {
+            (ScriptTimelock::Absolute(_), _) if sequence == Sequence::MAX => false,
+            (ScriptTimelock::Absolute(LockTime::Blocks(req)), LockTime::Blocks(height)) => {
+                height.to_consensus_u32() >= req.to_consensus_u32()
+            }
+            (ScriptTimelock::Absolute(LockTime::Seconds(req)), LockTime::Seconds(time)) => {
+                time.to_consensus_u32() >= req.to_consensus_u32()
+            }
+            (ScriptTimelock::Absolute(_), _) => false,
+            (ScriptTimelock::Relative(req), _) => {
+                tx.version >= 2
+                    && sequence.is_relative_lock_time()
+                    && sequence.is_time_locked() == req.is_time_locked()
+                    && sequence.0 & SEQUENCE_LOCKTIME_MASK >= req.0 & SEQUENCE_LOCKTIME_MASK
+            }
+        }
+    }
+}
+
+impl Display for ScriptTimelock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptTimelock::Absolute(LockTime::Blocks(height)) => {
+                write!(f, "after block {}", height.to_consensus_u32())
+            }
+            ScriptTimelock::Absolute(LockTime::Seconds(time)) => {
+                let timestamp = time.to_consensus_u32();
+                let date = Utc
+                    .timestamp_opt(timestamp as i64, 0)
+                    .single()
+                    .expect("32-bit timestamps are always valid");
+                write!(f, "after {} (median time past)", date)
+            }
+            ScriptTimelock::Relative(sequence) if sequence.is_time_locked() => {
+                let intervals = sequence.0 & SEQUENCE_LOCKTIME_MASK;
+                let days = (intervals * 512) as f32 / (24 * 60 * 60) as f32;
+                write!(
+                    f,
+                    "{} × 512 seconds (~{:.1} days) after confirmation",
+                    intervals, days
+                )
+            }
+            ScriptTimelock::Relative(sequence) => {
+                write!(
+                    f,
+                    "{} blocks after confirmation",
+                    sequence.0 & SEQUENCE_LOCKTIME_MASK
+                )
+            }
+        }
+    }
+}
+
+impl TryFrom<ScriptTimelock> for TimelockReq {
+    type Error = TimelockError;
+
+    fn try_from(timelock: ScriptTimelock) -> Result<Self, Self::Error> {
+        Ok(match timelock {
+            ScriptTimelock::Absolute(LockTime::Blocks(height)) => {
+                TimelockReq::AfterHeight(height.to_consensus_u32())
+            }
+            ScriptTimelock::Absolute(LockTime::Seconds(time)) => {
+                let timestamp = time.to_consensus_u32();
+                TimelockReq::AfterDate(
+                    Utc.timestamp_opt(timestamp as i64, 0)
+                        .single()
+                        .expect("32-bit timestamps are always valid"),
+                )
+            }
+            ScriptTimelock::Relative(sequence) if !sequence.is_relative_lock_time() => {
+                return Err(TimelockError::NotRelativeTimelock(
+                    sequence.to_consensus_u32(),
+                ))
+            }
+            ScriptTimelock::Relative(sequence) if sequence.is_time_locked() => {
+                let intervals = (sequence.0 & SEQUENCE_LOCKTIME_MASK) as u16;
+                TimelockReq::AfterPeriod(TimelockDuration::from_intervals(intervals))
+            }
+            ScriptTimelock::Relative(sequence) => {
+                TimelockReq::AfterBlock((sequence.0 & SEQUENCE_LOCKTIME_MASK) as u16)
+            }
+        })
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default, Display)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 #[derive(StrictEncode, StrictDecode)]
@@ -452,3 +948,92 @@ pub struct TimelockedSigs {
     pub sigs: SigsReq,
     pub timelock: TimelockReq,
 }
+
+impl TimelockedSigs {
+    /// Describes condition using names of the specific signers (see [`SigsReq::named`]).
+    pub fn describe(&self, signers: &[Signer]) -> String {
+        format!("{} {}", self.sigs.named(signers), self.timelock)
+    }
+}
+
+/// Threshold of signatures from all wallet signers which decreases over time, like 3-of-5 at
+/// any time, 2-of-5 after a year and 1-of-5 after two years.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct DegradingSigs {
+    /// Number of signatures required at any time.
+    pub initial: u16,
+    /// Decreased number of required signatures after each of the timelocks.
+    pub steps: Vec<(TimelockReq, u16)>,
+}
+
+impl Display for DegradingSigs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "at least {} signatures", self.initial)?;
+        for (timelock, sigs) in &self.steps {
+            write!(f, ", {} {}", sigs, timelock)?;
+        }
+        Ok(())
+    }
+}
+
+impl DegradingSigs {
+    /// Checks that the required number of signatures strictly decreases with each step, never
+    /// reaching zero, and that the timelocks are of the same kind and strictly increase.
+    pub fn is_consistent(&self) -> bool {
+        if self.steps.is_empty() || self.initial == 0 {
+            return false;
+        }
+        let mut prev_sigs = self.initial;
+        let mut prev_lock: Option<ScriptTimelock> = None;
+        for (timelock, sigs) in &self.steps {
+            if *sigs == 0 || *sigs >= prev_sigs {
+                return false;
+            }
+            let lock = match timelock.script_timelock() {
+                Ok(Some(lock)) => lock,
+                _ => return false,
+            };
+            let ordered = match (prev_lock, lock) {
+                (None, _) => true,
+                (
+                    Some(ScriptTimelock::Absolute(LockTime::Blocks(_))),
+                    ScriptTimelock::Absolute(LockTime::Blocks(_)),
+                )
+                | (
+                    Some(ScriptTimelock::Absolute(LockTime::Seconds(_))),
+                    ScriptTimelock::Absolute(LockTime::Seconds(_)),
+                ) => {
+                    prev_lock.map(ScriptTimelock::to_consensus_u32) < Some(lock.to_consensus_u32())
+                }
+                (Some(ScriptTimelock::Relative(prev)), ScriptTimelock::Relative(next))
+                    if prev.is_time_locked() == next.is_time_locked() =>
+                {
+                    prev.to_consensus_u32() < next.to_consensus_u32()
+                }
+                _ => false,
+            };
+            if !ordered {
+                return false;
+            }
+            prev_sigs = *sigs;
+            prev_lock = Some(lock);
+        }
+        true
+    }
+
+    /// Expands the condition into the equivalent set of alternative timelocked signature
+    /// requirements.
+    pub fn branches(&self) -> Vec<TimelockedSigs> {
+        let mut branches = vec![TimelockedSigs {
+            sigs: SigsReq::AtLeast(self.initial),
+            timelock: TimelockReq::Anytime,
+        }];
+        branches.extend(self.steps.iter().map(|(timelock, sigs)| TimelockedSigs {
+            sigs: SigsReq::AtLeast(*sigs),
+            timelock: *timelock,
+        }));
+        branches
+    }
+}
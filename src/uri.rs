@@ -0,0 +1,185 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! BIP-21 payment URIs, including payjoin (BIP-78) parameters.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bitcoin::util::amount::Denomination;
+use bitcoin::{Address, Amount};
+
+const URI_SCHEME: &str = "bitcoin:";
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum UriError {
+    /// Payment URI must start with `bitcoin:` scheme.
+    InvalidScheme,
+
+    /// Invalid payment URI address. Details: {0}
+    InvalidAddress(String),
+
+    /// Invalid payment URI amount `{0}`.
+    InvalidAmount(String),
+
+    /// Payment URI parameter `{0}` has invalid percent encoding.
+    InvalidEncoding(String),
+
+    /// Payment URI parameter `{0}` is present more than once.
+    DuplicateParam(String),
+
+    /// Payment URI requires support of unknown parameter `{0}`.
+    UnsupportedRequirement(String),
+}
+
+/// BIP-21 payment request.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PaymentUri {
+    pub address: Address,
+    /// Requested amount, in sats.
+    pub amount: Option<u64>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+    /// Payjoin (BIP-78) endpoint of the receiver (`pj` parameter).
+    pub payjoin: Option<String>,
+    /// Whether the receiver allows substitution of the payment output during payjoin (absent
+    /// or non-zero `pjos` parameter).
+    pub output_substitution: bool,
+    /// Other parameters, which are not known to the wallet and are not required.
+    pub params: BTreeMap<String, String>,
+}
+
+impl PaymentUri {
+    pub fn with(address: Address) -> PaymentUri {
+        PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            payjoin: None,
+            output_substitution: true,
+            params: empty!(),
+        }
+    }
+
+    pub fn is_payjoin(&self) -> bool { self.payjoin.is_some() }
+}
+
+impl Display for PaymentUri {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", URI_SCHEME, self.address)?;
+        let mut params = vec![];
+        if let Some(amount) = self.amount {
+            params.push((
+                s!("amount"),
+                Amount::from_sat(amount).to_string_in(Denomination::Bitcoin),
+            ));
+        }
+        if let Some(label) = &self.label {
+            params.push((s!("label"), percent_encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push((s!("message"), percent_encode(message)));
+        }
+        if let Some(endpoint) = &self.payjoin {
+            params.push((s!("pj"), percent_encode(endpoint)));
+            if !self.output_substitution {
+                params.push((s!("pjos"), s!("0")));
+            }
+        }
+        params.extend(
+            self.params
+                .iter()
+                .map(|(key, value)| (key.clone(), percent_encode(value))),
+        );
+        for (no, (key, value)) in params.iter().enumerate() {
+            let sep = if no == 0 { '?' } else { '&' };
+            write!(f, "{}{}={}", sep, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = UriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() < URI_SCHEME.len() || !s[..URI_SCHEME.len()].eq_ignore_ascii_case(URI_SCHEME) {
+            return Err(UriError::InvalidScheme);
+        }
+        let (address, query) = match s[URI_SCHEME.len()..].split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (&s[URI_SCHEME.len()..], None),
+        };
+        let address =
+            Address::from_str(address).map_err(|err| UriError::InvalidAddress(err.to_string()))?;
+
+        let mut uri = PaymentUri::with(address);
+        let mut seen = bset![];
+        for param in query.into_iter().flat_map(|query| query.split('&')) {
+            if param.is_empty() {
+                continue;
+            }
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            if !seen.insert(key.to_owned()) {
+                return Err(UriError::DuplicateParam(key.to_owned()));
+            }
+            let decoded =
+                percent_decode(value).ok_or_else(|| UriError::InvalidEncoding(key.to_owned()))?;
+            match key {
+                "amount" => {
+                    let amount = Amount::from_str_in(value, Denomination::Bitcoin)
+                        .map_err(|_| UriError::InvalidAmount(value.to_owned()))?;
+                    uri.amount = Some(amount.to_sat());
+                }
+                "label" => uri.label = Some(decoded),
+                "message" => uri.message = Some(decoded),
+                "pj" => uri.payjoin = Some(decoded),
+                "pjos" => uri.output_substitution = decoded != "0",
+                key if key.starts_with("req-") => {
+                    return Err(UriError::UnsupportedRequirement(key.to_owned()))
+                }
+                key => {
+                    uri.params.insert(key.to_owned(), decoded);
+                }
+            }
+        }
+        Ok(uri)
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [iter.next()?, iter.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,330 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Vaults protecting funds with pre-signed transactions: an "unvault" transaction moving funds
+//! into an output spendable by the regular keys only after a relative timelock, and "emergency
+//! sweep" transactions moving funds to a cold emergency destination, which can be broadcast at
+//! any moment before the unvault timelock expires.
+
+use bitcoin::{OutPoint, Script, Transaction, Txid};
+#[cfg(feature = "electrum")]
+use electrum_client::ElectrumApi;
+
+use crate::{OnchainStatus, Wallet};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum VaultError {
+    /// Pre-signed {0} transaction does not spend the output it is supposed to spend.
+    InvalidSpending(&'static str),
+
+    /// Pre-signed {0} transaction is not signed.
+    NotSigned(&'static str),
+
+    /// Unvault transaction has no output {0}.
+    NoUnvaultOutput(u32),
+
+    /// Vault for output {0} is already present in the wallet.
+    DuplicateVault(OutPoint),
+
+    /// Wallet has no vault for output {0}.
+    UnknownVault(OutPoint),
+
+    /// Unable to retrieve vault transactions. Details: {0}
+    Resolver(String),
+}
+
+/// State of the vault, as observed on chain.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum VaultState {
+    /// Funds are in the vault output.
+    #[display("vaulted")]
+    Vaulted,
+
+    /// Unvault transaction was broadcast; contains the height at which it was mined, if any.
+    /// Emergency sweep must be broadcast before the timelock expires if the unvault was not
+    /// authorized.
+    #[display("unvaulting")]
+    Unvaulting(Option<u32>),
+
+    /// Unvault timelock has expired and funds can be spent with the regular keys.
+    #[display("unvaulted")]
+    Unvaulted,
+
+    /// Unvaulted funds were spent by the regular keys.
+    #[display("spent by {0}")]
+    Spent(Txid),
+
+    /// Funds were moved to the emergency destination.
+    #[display("swept by {0}")]
+    Swept(Txid),
+
+    /// Vault output was spent by a transaction which is neither unvault nor emergency sweep.
+    #[display("breached by {0}")]
+    Breached(Txid),
+}
+
+impl VaultState {
+    /// Detects whether funds have left the vault setup.
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            VaultState::Spent(_) | VaultState::Swept(_) | VaultState::Breached(_)
+        )
+    }
+
+    fn progress(self) -> u8 {
+        match self {
+            VaultState::Vaulted => 0,
+            VaultState::Unvaulting(None) => 1,
+            VaultState::Unvaulting(Some(_)) => 2,
+            VaultState::Unvaulted => 3,
+            _ => 4,
+        }
+    }
+}
+
+/// Vault state change requiring user attention.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("vault {outpoint} is {state}")]
+pub struct VaultAlert {
+    pub outpoint: OutPoint,
+    pub state: VaultState,
+}
+
+/// Vault with its pre-signed transactions, persisted in the wallet file.
+#[derive(Getters, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct Vault {
+    #[getter(as_copy)]
+    outpoint: OutPoint,
+    #[getter(as_copy)]
+    amount: u64,
+    /// ScriptPubkey of the vault output.
+    script_pubkey: Script,
+    unvault: Transaction,
+    /// Number of the unvault transaction output locked with the relative timelock.
+    #[getter(as_copy)]
+    unvault_vout: u32,
+    /// Relative timelock (in blocks) of the unvault output.
+    #[getter(as_copy)]
+    unvault_delay: u16,
+    /// Emergency sweep spending the vault output.
+    emergency: Transaction,
+    /// Emergency sweep spending the unvault output.
+    unvault_emergency: Transaction,
+    label: String,
+    #[getter(as_copy)]
+    state: VaultState,
+}
+
+impl Vault {
+    #[allow(clippy::too_many_arguments)]
+    pub fn with(
+        outpoint: OutPoint,
+        amount: u64,
+        script_pubkey: Script,
+        unvault: Transaction,
+        unvault_vout: u32,
+        unvault_delay: u16,
+        emergency: Transaction,
+        unvault_emergency: Transaction,
+        label: impl ToString,
+    ) -> Result<Vault, VaultError> {
+        if unvault.output.len() <= unvault_vout as usize {
+            return Err(VaultError::NoUnvaultOutput(unvault_vout));
+        }
+        let unvault_outpoint = OutPoint::new(unvault.txid(), unvault_vout);
+        for (name, tx, prevout) in [
+            ("unvault", &unvault, outpoint),
+            ("emergency", &emergency, outpoint),
+            ("unvault emergency", &unvault_emergency, unvault_outpoint),
+        ] {
+            if !spends(tx, prevout) {
+                return Err(VaultError::InvalidSpending(name));
+            }
+            if tx
+                .input
+                .iter()
+                .any(|txin| txin.script_sig.is_empty() && txin.witness.is_empty())
+            {
+                return Err(VaultError::NotSigned(name));
+            }
+        }
+        Ok(Vault {
+            outpoint,
+            amount,
+            script_pubkey,
+            unvault,
+            unvault_vout,
+            unvault_delay,
+            emergency,
+            unvault_emergency,
+            label: label.to_string(),
+            state: VaultState::Vaulted,
+        })
+    }
+
+    pub fn unvault_outpoint(&self) -> OutPoint {
+        OutPoint::new(self.unvault.txid(), self.unvault_vout)
+    }
+
+    pub fn unvault_script_pubkey(&self) -> &Script {
+        &self.unvault.output[self.unvault_vout as usize].script_pubkey
+    }
+
+    /// Emergency sweep transaction which has to be broadcast in the current vault state, if any.
+    pub fn emergency_tx(&self) -> Option<&Transaction> {
+        match self.state {
+            VaultState::Vaulted => Some(&self.emergency),
+            VaultState::Unvaulting(_) | VaultState::Unvaulted => Some(&self.unvault_emergency),
+            _ => None,
+        }
+    }
+
+    /// Block height at which the unvaulted funds become spendable by the regular keys.
+    pub fn matures_at(&self) -> Option<u32> {
+        match self.state {
+            VaultState::Unvaulting(Some(height)) => Some(height + self.unvault_delay as u32),
+            _ => None,
+        }
+    }
+
+    /// Updates vault state with an observed transaction, returning whether the state has
+    /// changed.
+    pub fn observe(&mut self, tx: &Transaction, status: OnchainStatus) -> bool {
+        let txid = tx.txid();
+        let state = if spends(tx, self.outpoint) {
+            if txid == self.unvault.txid() {
+                match status {
+                    OnchainStatus::Blockchain(height) => VaultState::Unvaulting(Some(height)),
+                    OnchainStatus::Mempool => VaultState::Unvaulting(None),
+                }
+            } else if txid == self.emergency.txid() {
+                VaultState::Swept(txid)
+            } else {
+                VaultState::Breached(txid)
+            }
+        } else if spends(tx, self.unvault_outpoint()) {
+            if txid == self.unvault_emergency.txid() {
+                VaultState::Swept(txid)
+            } else {
+                VaultState::Spent(txid)
+            }
+        } else {
+            return false;
+        };
+        self.update_state(state)
+    }
+
+    /// Checks whether unvault timelock has expired at the given height, returning whether the
+    /// state has changed.
+    pub fn check_maturity(&mut self, height: u32) -> bool {
+        match self.matures_at() {
+            Some(matures_at) if height + 1 >= matures_at => {
+                self.update_state(VaultState::Unvaulted)
+            }
+            _ => false,
+        }
+    }
+
+    fn update_state(&mut self, state: VaultState) -> bool {
+        if self.state.progress() >= state.progress() {
+            return false;
+        }
+        self.state = state;
+        true
+    }
+}
+
+fn spends(tx: &Transaction, outpoint: OutPoint) -> bool {
+    tx.input.iter().any(|txin| txin.previous_output == outpoint)
+}
+
+impl Wallet {
+    pub fn add_vault(&mut self, vault: Vault) -> Result<(), VaultError> {
+        if self.vaults().contains_key(&vault.outpoint) {
+            return Err(VaultError::DuplicateVault(vault.outpoint));
+        }
+        self.vaults_mut().insert(vault.outpoint, vault);
+        Ok(())
+    }
+
+    pub fn remove_vault(&mut self, outpoint: OutPoint) -> Result<Vault, VaultError> {
+        self.vaults_mut()
+            .remove(&outpoint)
+            .ok_or(VaultError::UnknownVault(outpoint))
+    }
+
+    /// Updates vault states from the wallet transaction history and current height, returning
+    /// alerts for the changed vaults.
+    pub fn refresh_vaults(&mut self) -> Vec<VaultAlert> {
+        let observed = self
+            .history()
+            .iter()
+            .map(|entry| (entry.tx.clone(), entry.onchain.status))
+            .collect::<Vec<_>>();
+        self.observe_vaults(&observed)
+    }
+
+    /// Updates vault states with the transactions spending vault and unvault outputs, as
+    /// reported by the Electrum server, returning alerts for the changed vaults.
+    #[cfg(feature = "electrum")]
+    pub fn monitor_vaults(
+        &mut self,
+        client: &impl ElectrumApi,
+    ) -> Result<Vec<VaultAlert>, VaultError> {
+        let mut observed = vec![];
+        for vault in self
+            .vaults()
+            .values()
+            .filter(|vault| !vault.state.is_final())
+        {
+            for script in [&vault.script_pubkey, vault.unvault_script_pubkey()] {
+                let history = client
+                    .script_get_history(script)
+                    .map_err(|err| VaultError::Resolver(err.to_string()))?;
+                for item in history {
+                    let tx = client
+                        .transaction_get(&item.tx_hash)
+                        .map_err(|err| VaultError::Resolver(err.to_string()))?;
+                    observed.push((tx, OnchainStatus::from_i32(item.height)));
+                }
+            }
+        }
+        Ok(self.observe_vaults(&observed))
+    }
+
+    fn observe_vaults(&mut self, observed: &[(Transaction, OnchainStatus)]) -> Vec<VaultAlert> {
+        let height = self.height();
+        self.vaults_mut()
+            .values_mut()
+            .filter_map(|vault| {
+                let mut changed = false;
+                for (tx, status) in observed {
+                    changed |= vault.observe(tx, *status);
+                }
+                changed |= vault.check_maturity(height);
+                changed.then_some(VaultAlert {
+                    outpoint: vault.outpoint,
+                    state: vault.state,
+                })
+            })
+            .collect()
+    }
+}
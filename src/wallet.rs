@@ -9,28 +9,30 @@
 // a copy of the AGPL-3.0 License along with this software. If not, see
 // <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
 
+mod ext;
+mod legacy;
+
+use std::cmp::Reverse;
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Display, Formatter};
 use std::io::{Read, Write};
-use std::ops::{Deref, RangeInclusive};
+use std::iter;
+use std::ops::{Bound, Deref, RangeInclusive};
 
 use amplify::Wrapper;
-use bitcoin::hashes::Hash;
-use bitcoin::secp256k1::SECP256K1;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{Parity, SECP256K1};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, Fingerprint};
-use bitcoin::{
-    Address, BlockHash, LockTime, Network, PublicKey, Script, Sequence, Transaction, TxOut, Txid,
-};
+use bitcoin::{Address, BlockHash, Network, OutPoint, PublicKey, Script, Transaction, TxOut, Txid};
 use bitcoin_scripts::address::AddressCompat;
 use bitcoin_scripts::PubkeyScript;
 use chrono::{DateTime, Utc};
 #[cfg(feature = "electrum")]
 use electrum_client::HeaderNotification;
-use miniscript::descriptor::{DescriptorType, Sh, Wsh};
-use miniscript::policy::compiler::CompilerError;
-use miniscript::policy::concrete::{Policy, PolicyError};
-use miniscript::{Descriptor, Legacy, Segwitv0, Tap};
+use miniscript::descriptor::DescriptorType;
+use miniscript::policy::concrete::Policy;
+use miniscript::Descriptor;
 use strict_encoding::{StrictDecode, StrictEncode};
 use wallet::descriptors::derive::DeriveDescriptor;
 use wallet::descriptors::{DescrVariants, DescriptorClass};
@@ -38,15 +40,23 @@ use wallet::hd::standards::DerivationBlockchain;
 use wallet::hd::{
     Bip43, DerivationAccount, DerivationStandard, DerivationSubpath, HardenedIndex,
     HardenedIndexExpected, IndexRange, IndexRangeList, SegmentIndexes, TerminalStep,
-    UnhardenedIndex, UnsatisfiableKey, XpubkeyCore,
+    UnhardenedIndex, XpubkeyCore,
 };
 use wallet::onchain::{PublicNetwork, ResolveTx, TxResolverError};
 use wallet::slip132::KeyApplication;
 
-use crate::onchain::Comment;
+use self::ext::{ExtReader, ExtWriter};
+pub(crate) use self::legacy::{WalletSettingsV1, WalletV1};
+use crate::onchain::{is_change_terminal, Comment};
 use crate::{
-    AddressSource, AddressSummary, AddressValue, ElectrumServer, HistoryEntry, Prevout, Signer,
-    SigsReq, TimelockReq, TimelockedSigs, ToTapTree, TxidMeta, UtxoTxid,
+    AddressDetails, AddressIndex, AddressSource, AddressSummary, AddressValue, AmountFormat,
+    AuditEvent, AuditLog, CoinLabel, ConditionCompiler, CosignerEnrollment, CursorDirection,
+    CustomSignet, DegradingSigs, Denomination, DustPolicy, ElectrumServer, ExchangeRate,
+    ExplorerLinks, FrostGroup, HistoryCursor, HistoryEntry, HistoryPage, Invoice, MigrationPlan,
+    MuSigKeyAgg, MultisigOrder, OnchainStatus, OnchainTxid, Ownership, PaymentTemplate, Prevout,
+    PriceHistory, ScriptLayout, ScriptTimelock, ScriptType, Signer, SignerRef, SignetError,
+    SigsReq, SilentPaymentOutput, SpendingPolicy, TapretTweak, TimelockError, TimelockReq,
+    TimelockedSigs, TxFilter, TxOrder, TxOrdering, TxPage, TxidMeta, UtxoTxid, Vault,
 };
 
 #[derive(Getters, Clone, Debug)]
@@ -68,6 +78,81 @@ pub struct Wallet {
 
     utxos: BTreeSet<UtxoTxid>,
     history: BTreeSet<HistoryEntry>,
+
+    meta: WalletMeta,
+    frozen: BTreeSet<OutPoint>,
+    /// Last address indexes given out to users, which may not yet have been used onchain.
+    advertised_indexes: BTreeMap<UnhardenedIndex, UnhardenedIndex>,
+    /// Additional accounts derived from the same signers under different hardened account
+    /// indexes. Each account tracks its own addresses, UTXOs and history.
+    #[getter(skip)]
+    accounts: WalletAccounts,
+    spending_policy: SpendingPolicy,
+    /// Identifiers of the spending policy override tokens which were already used.
+    used_overrides: BTreeSet<sha256::Hash>,
+    /// Migration of the wallet funds to a successor wallet descriptor, if in progress.
+    migration: Option<MigrationPlan>,
+    /// Append-only log of the security-relevant events.
+    audit_log: AuditLog,
+    /// Co-signing services registered for some of the wallet signers.
+    cosigners: BTreeMap<Fingerprint, CosignerEnrollment>,
+    /// Vaults with pre-signed unvault and emergency sweep transactions.
+    vaults: BTreeMap<OutPoint, Vault>,
+    /// Recurring and scheduled payments.
+    payment_templates: BTreeMap<u32, PaymentTemplate>,
+    /// Tapret commitments tweaking wallet taproot outputs, by derivation terminal.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    tapret_tweaks: BTreeMap<(UnhardenedIndex, UnhardenedIndex), TapretTweak>,
+    /// Opret commitments made by wallet transactions.
+    opret_commitments: BTreeMap<Txid, sha256::Hash>,
+    /// Outputs received with silent payments, which can't be derived from the wallet descriptor.
+    silent_payments: BTreeMap<OutPoint, SilentPaymentOutput>,
+    /// Ordering of inputs and outputs in the transactions composed by the wallet.
+    #[getter(as_copy)]
+    tx_ordering: TxOrdering,
+    /// Whether coin selection must not merge coins from different clusters.
+    #[getter(as_copy)]
+    isolate_clusters: bool,
+    /// Whether coin selection must spend all coins sent to the same address together.
+    #[getter(as_copy)]
+    avoid_reuse: bool,
+    /// Whether change outputs must use the script type of the payment outputs, when the wallet
+    /// has a descriptor of the matching class.
+    #[getter(as_copy)]
+    match_change_type: bool,
+    #[getter(as_copy)]
+    dust_policy: DustPolicy,
+    /// Payments expected to the wallet receive addresses.
+    invoices: BTreeMap<u32, Invoice>,
+    /// Names of the counterparties by their scriptPubkeys.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    address_book: BTreeMap<Script, String>,
+    /// Denomination used for displaying and entering amounts.
+    #[getter(as_copy)]
+    denomination: Denomination,
+    #[getter(as_copy)]
+    amount_format: AmountFormat,
+    /// Outputs spent by the wallet transactions which are not part of the wallet history,
+    /// cached for computing transaction fees.
+    prevouts: BTreeMap<OutPoint, TxOut>,
+    /// Daily bitcoin prices by fiat currency, used for the cost basis accounting.
+    price_history: BTreeMap<String, PriceHistory>,
+    /// Use statistics of the wallet addresses, updated on each sync.
+    address_index: AddressIndex,
+    /// Labels assigned by the user to the wallet addresses.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    address_labels: BTreeMap<Script, Comment>,
+    /// Labels and memos assigned by the user to the wallet coins.
+    coin_labels: BTreeMap<OutPoint, CoinLabel>,
 }
 
 impl From<WalletSettings> for Wallet {
@@ -81,6 +166,34 @@ impl From<WalletSettings> for Wallet {
             ephemerals: zero!(),
             utxos: bset![],
             history: bset![],
+            meta: WalletMeta::new(),
+            frozen: empty!(),
+            advertised_indexes: empty!(),
+            accounts: empty!(),
+            spending_policy: default!(),
+            used_overrides: empty!(),
+            migration: None,
+            audit_log: default!(),
+            cosigners: empty!(),
+            vaults: empty!(),
+            payment_templates: empty!(),
+            tapret_tweaks: empty!(),
+            opret_commitments: empty!(),
+            silent_payments: empty!(),
+            tx_ordering: default!(),
+            isolate_clusters: false,
+            avoid_reuse: false,
+            match_change_type: false,
+            dust_policy: default!(),
+            invoices: empty!(),
+            address_book: empty!(),
+            denomination: default!(),
+            amount_format: default!(),
+            prevouts: empty!(),
+            price_history: empty!(),
+            address_index: default!(),
+            address_labels: empty!(),
+            coin_labels: empty!(),
         }
     }
 }
@@ -118,87 +231,412 @@ impl Wallet {
             .as_settings()
             .descriptors_all()
             .expect("invalid wallet descriptor");
-        let d = DeriveDescriptor::<PublicKey>::derive_descriptor(&descriptor, SECP256K1, [
-            UnhardenedIndex::zero(),
-            index,
-        ])
-        .expect("unable to derive address for the wallet descriptor");
+        let d = self
+            .as_settings()
+            .derive(&descriptor, UnhardenedIndex::zero(), index)
+            .expect("unable to derive address for the wallet descriptor");
         d.address(self.settings.network.into())
             .expect("unable to derive address for the wallet descriptor")
     }
 
     pub fn next_address(&self) -> Address { self.indexed_address(self.next_default_index()) }
 
+    /// Indexes of the addresses under a given change index which have onchain history.
+    pub fn used_indexes(&self, change: UnhardenedIndex) -> BTreeSet<UnhardenedIndex> {
+        self.history
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .credit
+                    .values()
+                    .map(|addr| addr.addr_src)
+                    .chain(entry.debit.values().copied())
+            })
+            .chain(self.utxos.iter().map(|utxo| utxo.addr_src))
+            .filter(|addr_src| addr_src.change == change)
+            .map(|addr_src| addr_src.index)
+            .collect()
+    }
+
+    /// Returns the lowest-index receive address which has no onchain history. The address may
+    /// have already been given out before; use [`Wallet::fresh_address`] to avoid this.
+    pub fn next_unused_address(&self) -> (UnhardenedIndex, Address) {
+        let used = self.used_indexes(UnhardenedIndex::zero());
+        let mut index = UnhardenedIndex::zero();
+        while used.contains(&index) {
+            index = index
+                .checked_inc()
+                .expect("wallet has exhausted all receive addresses");
+        }
+        (index, self.indexed_address(index))
+    }
+
+    /// Returns receive address which was neither used onchain nor given out before, and records
+    /// it as given out, such that it will not be returned again even after the wallet is
+    /// re-opened.
+    pub fn fresh_address(&mut self) -> (UnhardenedIndex, Address) {
+        let next_default = self.next_default_index();
+        let index = self
+            .advertised_indexes
+            .get(&UnhardenedIndex::zero())
+            .and_then(UnhardenedIndex::checked_inc)
+            .map(|index| index.max(next_default))
+            .unwrap_or(next_default);
+        self.advertised_indexes
+            .insert(UnhardenedIndex::zero(), index);
+        (index, self.indexed_address(index))
+    }
+
+    /// Last receive address index given out with [`Wallet::fresh_address`].
+    pub fn last_advertised_index(&self) -> Option<UnhardenedIndex> {
+        self.advertised_indexes
+            .get(&UnhardenedIndex::zero())
+            .copied()
+    }
+
     // TODO: Implement multiple coinselect algorithms
+    /// Selects coins for spending the given value. If cluster isolation is enabled, all
+    /// selected coins come from a single cluster, and toxic change is never spent together with
+    /// other coins. If address reuse avoidance is enabled, coins sent to the same address are
+    /// always spent together.
     pub fn coinselect(&self, value: u64) -> Option<(BTreeSet<Prevout>, u64)> {
-        let mut prevouts = self.utxos.iter().map(Prevout::from).collect::<Vec<_>>();
-        prevouts.sort_by_key(|p| p.amount);
-        let mut acc = 0u64;
-        let mut take_next = true;
-        #[allow(clippy::needless_collect)]
-        let prevouts = prevouts
+        self.coinselect_filtered(value, |_| true)
+    }
+
+    /// Selects coins for spending the given value only among the coins with the given label,
+    /// own or inherited from the transaction which created them. See [`Wallet::coinselect`].
+    pub fn coinselect_labeled(&self, value: u64, label: &str) -> Option<(BTreeSet<Prevout>, u64)> {
+        self.coinselect_filtered(value, |utxo| self.is_coin_labeled(utxo.outpoint(), label))
+    }
+
+    fn coinselect_filtered(
+        &self,
+        value: u64,
+        filter: impl Fn(&UtxoTxid) -> bool,
+    ) -> Option<(BTreeSet<Prevout>, u64)> {
+        let threshold = self.economic_threshold();
+        let spendable = |utxo: &&UtxoTxid| {
+            filter(utxo)
+                && !self.is_frozen(utxo.outpoint())
+                && !self.is_immature(utxo)
+                && !self.silent_payments.contains_key(&utxo.outpoint())
+                && threshold
+                    .map(|threshold| utxo.value > threshold)
+                    .unwrap_or(true)
+        };
+        let candidates = if self.isolate_clusters {
+            self.coin_clusters()
+                .into_iter()
+                .flat_map(|cluster| {
+                    let coins = self
+                        .utxos
+                        .iter()
+                        .filter(spendable)
+                        .filter(|utxo| cluster.coins.contains(&utxo.outpoint()));
+                    let (toxic, clean): (Vec<&UtxoTxid>, Vec<&UtxoTxid>) =
+                        coins.partition(|utxo| cluster.toxic.contains(&utxo.outpoint()));
+                    iter::once(clean)
+                        .chain(toxic.into_iter().map(|utxo| vec![utxo]))
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        } else {
+            vec![self.utxos.iter().filter(spendable).collect::<Vec<_>>()]
+        };
+        candidates
             .into_iter()
-            .take_while(|p| {
-                let take_this = take_next;
-                acc += p.amount;
-                take_next = acc < value;
-                take_this
+            .map(|coins| self.address_groups(coins))
+            .filter_map(|groups| select_prevouts(groups, value))
+            .min_by_key(|(_, acc)| *acc)
+    }
+
+    /// Groups coins which must be spent together: with address reuse avoidance all coins sent
+    /// to the same address form a single group; otherwise each coin is a group on its own.
+    fn address_groups(&self, coins: Vec<&UtxoTxid>) -> Vec<Vec<Prevout>> {
+        if !self.avoid_reuse {
+            return coins
+                .into_iter()
+                .map(|utxo| vec![Prevout::from(utxo)])
+                .collect();
+        }
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        for utxo in coins {
+            groups
+                .entry((utxo.addr_src.change, utxo.addr_src.index))
+                .or_default()
+                .push(Prevout::from(utxo));
+        }
+        groups.into_values().collect()
+    }
+
+    /// Detects whether the scriptPubkey belongs to the wallet, returning its derivation terminal.
+    ///
+    /// Scripts which were already used are found from the wallet history; other scripts are
+    /// looked up within the [`ADDRESS_GAP_LIMIT`] after the last used index.
+    pub fn is_mine(&self, script: &Script) -> Option<DerivationSubpath<UnhardenedIndex>> {
+        let known = self
+            .history
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .credit
+                    .values()
+                    .map(|addr| addr.addr_src)
+                    .chain(entry.debit.values().copied())
             })
-            .collect::<Vec<_>>();
-        // Going from back to remove small inputs if larger inputs are enough
-        let mut acc = 0u64;
-        let mut take_next = true;
-        let prevouts = prevouts
+            .chain(self.utxos.iter().map(|utxo| utxo.addr_src))
+            .find(|addr_src| addr_src.address.script_pubkey().as_inner() == script);
+        if let Some(addr_src) = known {
+            return Some(DerivationSubpath::from(
+                &[addr_src.change, addr_src.index][..],
+            ));
+        }
+        if let Some((change, index, _)) = self
+            .tapret_script_pubkeys()
+            .into_iter()
+            .find(|(_, _, spk)| spk == script)
+        {
+            return Some(DerivationSubpath::from(&[change, index][..]));
+        }
+
+        [UnhardenedIndex::zero(), UnhardenedIndex::one()]
             .into_iter()
-            .rev()
-            .take_while(|p| {
-                let take_this = take_next;
-                acc += p.amount;
-                take_next = acc < value;
-                take_this
+            .find_map(|change| {
+                let last = self
+                    .last_indexes
+                    .get(&change)
+                    .map(UnhardenedIndex::first_index)
+                    .unwrap_or_default();
+                let max = last.saturating_add(ADDRESS_GAP_LIMIT).min(u16::MAX as u32) as u16;
+                self.settings
+                    .script_pubkeys_all(change == UnhardenedIndex::one(), 0..=max)
+                    .ok()?
+                    .into_values()
+                    .flatten()
+                    .find(|(_, spk)| spk.as_inner() == script)
+                    .map(|(index, _)| DerivationSubpath::from(&[change, index][..]))
             })
-            .collect();
-        if acc < value {
-            None
-        } else {
-            Some((prevouts, acc))
+    }
+
+    /// Detects whether the address belongs to the wallet, returning its derivation terminal.
+    pub fn owns_address(&self, address: &Address) -> Option<DerivationSubpath<UnhardenedIndex>> {
+        if bitcoin::Network::from(self.settings.network) != address.network {
+            return None;
         }
+        self.is_mine(&address.script_pubkey())
     }
 
-    pub fn address_info(&self, include_empty: bool) -> Vec<AddressSummary> {
-        let mut addresses = self
+    /// Collects information about the use of a wallet address. Returns `None` if the address does
+    /// not belong to the wallet.
+    pub fn address_details(&self, address: &Address) -> Option<AddressDetails> {
+        let terminal = self.owns_address(address)?;
+        let script = address.script_pubkey();
+        let is_ours =
+            |addr_src: &AddressSource| addr_src.address.script_pubkey().as_inner() == &script;
+
+        let summary = self.address_index.get(&script);
+        let label = self
+            .address_labels
+            .get(&script)
+            .map(|comment| comment.label.clone());
+        let label = label.or_else(|| {
+            self.history.iter().find_map(|entry| {
+                entry
+                    .debit
+                    .iter()
+                    .filter(|(_, addr_src)| is_ours(addr_src))
+                    .find_map(|(vout, _)| entry.beneficiaries.get(vout))
+                    .cloned()
+            })
+        });
+        let details = AddressDetails {
+            is_change: is_change_terminal(&terminal),
+            terminal,
+            first_use: summary.and_then(|summary| summary.first_activity),
+            last_use: summary.and_then(|summary| summary.last_activity),
+            tx_count: summary.map(|summary| summary.tx_count).unwrap_or_default(),
+            total_received: summary.map(|summary| summary.received).unwrap_or_default(),
+            balance: summary.map(|summary| summary.balance).unwrap_or_default(),
+            label,
+        };
+        Some(details)
+    }
+
+    /// Selects page of wallet transactions matching the filter, sorted in a given order.
+    pub fn transactions(
+        &self,
+        filter: &TxFilter,
+        order: TxOrder,
+        offset: usize,
+        limit: usize,
+    ) -> TxPage<'_> {
+        let mut entries = self
             .history
             .iter()
-            .flat_map(|item| item.address_summaries())
-            .fold(
-                BTreeMap::<AddressCompat, AddressSummary>::new(),
-                |mut list, info| {
-                    match list.entry(info.addr_src.address) {
-                        Entry::Vacant(entry) => {
-                            entry.insert(info);
-                        }
-                        Entry::Occupied(entry) => {
-                            entry.into_mut().merge(info);
-                        }
-                    };
-                    list
-                },
-            );
+            .filter(|entry| filter.matches(entry))
+            .collect::<Vec<_>>();
+        match order {
+            TxOrder::NewestFirst => entries.sort_by_key(|entry| Reverse(entry.date_time_est())),
+            TxOrder::OldestFirst => entries.sort_by_key(|entry| entry.date_time_est()),
+            TxOrder::LargestFirst => {
+                entries.sort_by_key(|entry| Reverse(entry.balance().unsigned_abs()))
+            }
+            TxOrder::SmallestFirst => entries.sort_by_key(|entry| entry.balance().unsigned_abs()),
+        }
+        let total = entries.len();
+        TxPage {
+            entries: entries.into_iter().skip(offset).take(limit).collect(),
+            total,
+            offset,
+        }
+    }
+
+    /// Retrieves up to `limit` history entries following the cursor (or from the start of the
+    /// history if no cursor is given) in a given direction, ordered by their onchain status.
+    pub fn history_page(
+        &self,
+        cursor: Option<HistoryCursor>,
+        direction: CursorDirection,
+        limit: usize,
+    ) -> HistoryPage<'_> {
+        let range = match (direction, cursor) {
+            (_, None) => (Bound::Unbounded, Bound::Unbounded),
+            (CursorDirection::Forward, Some(HistoryCursor(onchain))) => {
+                (Bound::Excluded(onchain), Bound::Unbounded)
+            }
+            (CursorDirection::Backward, Some(HistoryCursor(onchain))) => {
+                (Bound::Unbounded, Bound::Excluded(onchain))
+            }
+        };
+        let iter = self.history.range::<OnchainTxid, _>(range);
+        let mut entries: Vec<_> = match direction {
+            CursorDirection::Forward => iter.take(limit + 1).collect(),
+            CursorDirection::Backward => iter.rev().take(limit + 1).collect(),
+        };
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|entry| entry.cursor())
+        } else {
+            None
+        };
+        HistoryPage { entries, next }
+    }
+
+    /// Excludes the output from the coin selection. Returns `false` if the output was already
+    /// frozen.
+    pub fn freeze(&mut self, outpoint: OutPoint) -> bool { self.frozen.insert(outpoint) }
+
+    pub fn unfreeze(&mut self, outpoint: OutPoint) -> bool { self.frozen.remove(&outpoint) }
+
+    pub fn is_frozen(&self, outpoint: OutPoint) -> bool { self.frozen.contains(&outpoint) }
+
+    /// Detects whether the UTXO comes from a coinbase transaction which has not yet reached
+    /// maturity at the current wallet height.
+    pub fn is_immature(&self, utxo: &UtxoTxid) -> bool {
+        match utxo.onchain.status {
+            _ if !utxo.coinbase => false,
+            OnchainStatus::Mempool => true,
+            OnchainStatus::Blockchain(height) => {
+                self.height.saturating_sub(height) + 1 < COINBASE_MATURITY
+            }
+        }
+    }
 
+    /// Breaks down wallet balance according to the confirmation status and spendability of the
+    /// UTXOs.
+    pub fn balance(&self) -> Balance {
+        let own_spendings = self
+            .history
+            .iter()
+            .filter(|entry| !entry.credit.is_empty())
+            .map(|entry| entry.onchain.txid)
+            .collect::<BTreeSet<_>>();
+        let threshold = self.economic_threshold();
+        let mut balance = Balance::default();
         for utxo in &self.utxos {
-            match addresses.entry(utxo.addr_src.address) {
-                Entry::Vacant(entry) => {
-                    entry.insert(AddressSummary {
-                        addr_src: utxo.addr_src,
-                        balance: utxo.value,
-                        volume: 0,
-                        tx_count: 1,
-                    });
-                }
-                Entry::Occupied(mut entry) => {
-                    let item = entry.get_mut();
-                    item.balance = utxo.value;
+            let value = utxo.value;
+            if self.is_frozen(utxo.outpoint()) {
+                balance.frozen += value;
+            } else if self.is_immature(utxo) {
+                balance.immature += value;
+            } else if threshold
+                .map(|threshold| value <= threshold)
+                .unwrap_or_default()
+            {
+                balance.uneconomic += value;
+            } else if utxo.onchain.status.is_mined() {
+                balance.confirmed += value;
+            } else if own_spendings.contains(&utxo.onchain.txid) {
+                balance.pending_change += value;
+            } else {
+                balance.pending_incoming += value;
+            }
+        }
+        balance
+    }
+
+    /// Reports which part of the wallet funds can be spent under each of the spending conditions
+    /// at the current wallet height and time. Frozen and immature outputs are not accounted.
+    pub fn condition_balances(&self) -> Vec<ConditionBalance> {
+        let now = Utc::now();
+        let utxos = self
+            .utxos
+            .iter()
+            .filter(|utxo| !self.is_frozen(utxo.outpoint()) && !self.is_immature(utxo))
+            .collect::<Vec<_>>();
+        self.settings
+            .spending_conditions()
+            .iter()
+            .map(|(depth, condition)| {
+                let timelock = match condition {
+                    SpendingCondition::Sigs(TimelockedSigs { timelock, .. }) => *timelock,
+                    // Initial threshold of degrading condition can be used at any time
+                    SpendingCondition::Degrading(_) => TimelockReq::Anytime,
+                };
+                let (spendable, locked) =
+                    utxos
+                        .iter()
+                        .fold((0u64, 0u64), |(spendable, locked), utxo| {
+                            let mined = match utxo.onchain.status {
+                                OnchainStatus::Mempool => None,
+                                OnchainStatus::Blockchain(height) => {
+                                    Some((height, utxo.onchain.date_time_est().with_timezone(&Utc)))
+                                }
+                            };
+                            match timelock.is_satisfied(self.height, now, mined) {
+                                true => (spendable + utxo.value, locked),
+                                false => (spendable, locked + utxo.value),
+                            }
+                        });
+                ConditionBalance {
+                    depth: *depth,
+                    condition: condition.clone(),
+                    spendable,
+                    locked,
                 }
+            })
+            .collect()
+    }
+
+    pub fn address_info(&self, include_empty: bool) -> Vec<AddressSummary> {
+        let mut addresses = self
+            .address_index
+            .summaries()
+            .map(|summary| (summary.addr_src.address, *summary))
+            .collect::<BTreeMap<AddressCompat, AddressSummary>>();
+
+        // Outputs not known from the history, like silent payments
+        for utxo in &self.utxos {
+            if let Entry::Vacant(entry) = addresses.entry(utxo.addr_src.address) {
+                entry.insert(AddressSummary {
+                    addr_src: utxo.addr_src,
+                    balance: utxo.value,
+                    received: utxo.value,
+                    sent: 0,
+                    tx_count: 1,
+                    first_activity: Some(utxo.onchain),
+                    last_activity: Some(utxo.onchain),
+                });
             }
         }
 
@@ -223,8 +661,11 @@ impl Wallet {
                         index,
                     },
                     balance: 0,
-                    volume: 0,
+                    received: 0,
+                    sent: 0,
                     tx_count: 0,
+                    first_activity: None,
+                    last_activity: None,
                 });
             }
         }
@@ -236,17 +677,397 @@ impl Wallet {
         &mut self,
         signers: impl IntoIterator<Item = Signer>,
     ) -> Result<u16, DescriptorError> {
-        self.settings.update_signers(signers)
+        let signers = signers.into_iter().collect::<Vec<_>>();
+        let fingerprints = signers.iter().map(Signer::fingerprint).collect::<Vec<_>>();
+        let count = self.settings.update_signers(signers)?;
+        for fingerprint in fingerprints {
+            self.record_audit(AuditEvent::SignerUpdated(fingerprint));
+        }
+        Ok(count)
     }
 
+    /// Adds descriptor class to the wallet, such that the wallet tracks funds on the addresses
+    /// of several script types sharing the same signers.
     pub fn add_descriptor_class(&mut self, descriptor_class: DescriptorClass) -> bool {
-        self.settings.add_descriptor_class(descriptor_class)
+        if !self.settings.add_descriptor_class(descriptor_class) {
+            return false;
+        }
+        self.record_audit(AuditEvent::descriptor_changed(&self.settings.core));
+        true
+    }
+
+    /// Directs new addresses to a given descriptor class of a multi-descriptor wallet.
+    pub fn set_preferred_class(&mut self, class: DescriptorClass) -> Result<bool, DescriptorError> {
+        if !self.settings.set_preferred_class(class)? {
+            return Ok(false);
+        }
+        self.record_audit(AuditEvent::SettingsChanged(s!(
+            "preferred descriptor class"
+        )));
+        Ok(true)
+    }
+
+    /// Records security-relevant event happening outside of the wallet, like signing of a PSBT
+    /// or export of the wallet data, into the wallet audit log.
+    pub fn record_audit(&mut self, event: AuditEvent) { self.audit_log.append(event); }
+
+    pub fn set_name(&mut self, name: impl ToString) -> bool {
+        let name = name.to_string();
+        if self.meta.name == name {
+            return false;
+        }
+        self.meta.name = name;
+        true
+    }
+
+    pub fn set_description(&mut self, description: impl ToString) -> bool {
+        let description = description.to_string();
+        if self.meta.description == description {
+            return false;
+        }
+        self.meta.description = description;
+        true
+    }
+
+    pub fn add_tag(&mut self, tag: impl ToString) -> bool { self.meta.tags.insert(tag.to_string()) }
+
+    pub fn remove_tag(&mut self, tag: &str) -> bool { self.meta.tags.remove(tag) }
+
+    /// Copy of the wallet without UTXO set and transaction history, which are persisted
+    /// separately by the storage backends supporting incremental updates.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn to_core(&self) -> Wallet {
+        Wallet {
+            utxos: empty!(),
+            history: empty!(),
+            ..self.clone()
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn set_records(
+        &mut self,
+        utxos: BTreeSet<UtxoTxid>,
+        history: BTreeSet<HistoryEntry>,
+    ) {
+        self.utxos = utxos;
+        self.history = history;
+        self.refresh_address_index();
+    }
+
+    pub fn set_spending_policy(&mut self, policy: SpendingPolicy) {
+        if self.spending_policy != policy {
+            self.spending_policy = policy;
+            self.record_audit(AuditEvent::SpendingPolicyChanged);
+        }
+    }
+
+    pub fn set_tx_ordering(&mut self, ordering: TxOrdering) -> bool {
+        if self.tx_ordering == ordering {
+            return false;
+        }
+        self.tx_ordering = ordering;
+        true
+    }
+
+    pub fn set_isolate_clusters(&mut self, isolate: bool) -> bool {
+        if self.isolate_clusters == isolate {
+            return false;
+        }
+        self.isolate_clusters = isolate;
+        true
+    }
+
+    pub fn set_avoid_reuse(&mut self, avoid: bool) -> bool {
+        if self.avoid_reuse == avoid {
+            return false;
+        }
+        self.avoid_reuse = avoid;
+        true
+    }
+
+    pub fn set_match_change_type(&mut self, match_type: bool) -> bool {
+        if self.match_change_type == match_type {
+            return false;
+        }
+        self.match_change_type = match_type;
+        true
+    }
+
+    pub fn set_denomination(&mut self, denomination: Denomination) -> bool {
+        if self.denomination == denomination {
+            return false;
+        }
+        self.denomination = denomination;
+        true
+    }
+
+    pub fn set_amount_format(&mut self, format: AmountFormat) -> bool {
+        if self.amount_format == format {
+            return false;
+        }
+        self.amount_format = format;
+        true
+    }
+
+    pub fn set_dust_policy(&mut self, policy: DustPolicy) -> bool {
+        if self.dust_policy == policy {
+            return false;
+        }
+        self.dust_policy = policy;
+        true
+    }
+
+    /// Descriptor for deriving change of a transaction paying to the given scripts. If change
+    /// type matching is enabled and all payments use the same script type, the wallet
+    /// descriptor of the matching class is used; otherwise the primary descriptor.
+    pub fn change_descriptor(
+        &self,
+        payments: &[Script],
+    ) -> Result<Descriptor<DerivationAccount>, miniscript::Error> {
+        let settings = self.as_settings();
+        let types = payments
+            .iter()
+            .map(ScriptType::from)
+            .collect::<BTreeSet<_>>();
+        let class = types
+            .iter()
+            .next()
+            .filter(|_| self.match_change_type && types.len() == 1)
+            .and_then(|ty| ty.descriptor_class())
+            .filter(|class| settings.descriptor_classes().contains(class))
+            .unwrap_or_else(|| settings.primary_class());
+        settings.descriptor_for_class(class)
+    }
+
+    pub(crate) fn mark_override_used(&mut self, id: sha256::Hash) {
+        self.used_overrides.insert(id);
+        self.record_audit(AuditEvent::PolicyOverridden(id));
+    }
+
+    pub(crate) fn insert_cosigner(&mut self, enrollment: CosignerEnrollment) {
+        self.cosigners.insert(enrollment.fingerprint(), enrollment);
+    }
+
+    pub(crate) fn vaults_mut(&mut self) -> &mut BTreeMap<OutPoint, Vault> { &mut self.vaults }
+
+    pub(crate) fn payment_templates_mut(&mut self) -> &mut BTreeMap<u32, PaymentTemplate> {
+        &mut self.payment_templates
+    }
+
+    pub(crate) fn ephemerals_mut(&mut self) -> &mut WalletEphemerals { &mut self.ephemerals }
+
+    pub(crate) fn prevouts_mut(&mut self) -> &mut BTreeMap<OutPoint, TxOut> { &mut self.prevouts }
+
+    pub(crate) fn price_history_mut(&mut self, fiat: &str) -> &mut PriceHistory {
+        self.price_history.entry(fiat.to_uppercase()).or_default()
+    }
+
+    pub(crate) fn address_labels_mut(&mut self) -> &mut BTreeMap<Script, Comment> {
+        &mut self.address_labels
+    }
+
+    pub(crate) fn coin_labels_mut(&mut self) -> &mut BTreeMap<OutPoint, CoinLabel> {
+        &mut self.coin_labels
+    }
+
+    pub(crate) fn address_book_mut(&mut self) -> &mut BTreeMap<Script, String> {
+        &mut self.address_book
+    }
+
+    /// Reflects history entries which are new or have changed their status in the address
+    /// index, returning the number of such entries.
+    pub(crate) fn refresh_address_index(&mut self) -> usize {
+        let index = &mut self.address_index;
+        self.history
+            .iter()
+            .filter(|entry| index.apply(entry))
+            .count()
+    }
+
+    /// Rebuilds address index from the whole wallet history.
+    pub fn reindex_addresses(&mut self) {
+        self.address_index = default!();
+        self.refresh_address_index();
+    }
+
+    /// Applies modification to each of the wallet history entries.
+    pub(crate) fn map_history(&mut self, mut f: impl FnMut(&mut HistoryEntry)) {
+        self.history = std::mem::take(&mut self.history)
+            .into_iter()
+            .map(|mut entry| {
+                f(&mut entry);
+                entry
+            })
+            .collect();
+    }
+
+    pub(crate) fn invoices_mut(&mut self) -> &mut BTreeMap<u32, Invoice> { &mut self.invoices }
+
+    pub(crate) fn tapret_tweaks_mut(
+        &mut self,
+    ) -> &mut BTreeMap<(UnhardenedIndex, UnhardenedIndex), TapretTweak> {
+        &mut self.tapret_tweaks
+    }
+
+    pub(crate) fn opret_commitments_mut(&mut self) -> &mut BTreeMap<Txid, sha256::Hash> {
+        &mut self.opret_commitments
+    }
+
+    pub(crate) fn migration_mut(&mut self) -> Option<&mut MigrationPlan> { self.migration.as_mut() }
+
+    pub(crate) fn set_migration(&mut self, plan: Option<MigrationPlan>) -> Option<MigrationPlan> {
+        std::mem::replace(&mut self.migration, plan)
+    }
+
+    /// Hardened account index used by the wallet signers, if all of them use the same one.
+    pub fn account_index(&self) -> Option<HardenedIndex> {
+        let mut accounts = self.settings.signers.iter().map(|signer| signer.account);
+        let first = accounts.next()??;
+        accounts
+            .all(|account| account == Some(first))
+            .then_some(first)
+    }
+
+    /// Adds account under a different hardened account index. Account xpubs must be provided for
+    /// every wallet signer (matched by the master key fingerprint), since hardened derivation
+    /// requires private keys.
+    pub fn add_account(
+        &mut self,
+        index: HardenedIndex,
+        name: impl ToString,
+        signers: impl IntoIterator<Item = Signer>,
+    ) -> Result<&mut Wallet, DescriptorError> {
+        if self.account_index() == Some(index) || self.accounts.0.contains_key(&index) {
+            return Err(DescriptorError::DuplicateAccount(index));
+        }
+        let settings = self.settings.with_account_signers(index, signers)?;
+        self.record_audit(AuditEvent::AccountAdded(index));
+        let mut account = Wallet::from(settings);
+        account.meta.name = name.to_string();
+        account.height = self.height;
+        account.last_block = self.last_block;
+        Ok(self.accounts.0.entry(index).or_insert(account))
+    }
+
+    /// Additional wallet accounts by their hardened account indexes.
+    pub fn accounts(&self) -> &BTreeMap<HardenedIndex, Wallet> { &self.accounts.0 }
+
+    pub fn remove_account(&mut self, index: HardenedIndex) -> Option<Wallet> {
+        let account = self.accounts.0.remove(&index)?;
+        self.record_audit(AuditEvent::AccountRemoved(index));
+        Some(account)
+    }
+
+    pub fn account(&self, index: HardenedIndex) -> Option<&Wallet> {
+        match self.account_index() {
+            Some(primary) if primary == index => Some(self),
+            _ => self.accounts.0.get(&index),
+        }
+    }
+
+    pub fn account_mut(&mut self, index: HardenedIndex) -> Option<&mut Wallet> {
+        match self.account_index() {
+            Some(primary) if primary == index => Some(self),
+            _ => self.accounts.0.get_mut(&index),
+        }
+    }
+
+    /// Balances of the primary and all additional wallet accounts.
+    pub fn account_balances(&self) -> BTreeMap<Option<HardenedIndex>, Balance> {
+        let mut balances = bmap! { self.account_index() => self.balance() };
+        balances.extend(
+            self.accounts
+                .0
+                .iter()
+                .map(|(index, account)| (Some(*index), account.balance())),
+        );
+        balances
+    }
+
+    /// Detects whether none of the wallet signers is owned by the current user, i.e. the wallet
+    /// can only track funds, but not sign transactions.
+    pub fn is_watch_only(&self) -> bool { self.settings.is_watch_only() }
+
+    /// Produces copy of the wallet which is safe to be moved to an online machine: it keeps
+    /// descriptors, transaction history and labels, but all signers are marked as external and
+    /// hardware device information is removed.
+    pub fn export_watch_only(&self) -> Wallet {
+        let mut wallet = self.clone();
+        wallet.settings = self.settings.to_watch_only();
+        wallet
     }
 
     #[cfg(feature = "electrum")]
     pub fn update_last_block(&mut self, last_block: &HeaderNotification) {
-        self.last_block = last_block.header.block_hash();
-        self.height = last_block.height as u32;
+        self.update_tip(last_block.height as u32, last_block.header.block_hash());
+    }
+
+    /// Updates chain tip known to the wallet, returning whether it has changed.
+    pub fn update_tip(&mut self, height: u32, block: BlockHash) -> bool {
+        if self.height == height && self.last_block == block {
+            return false;
+        }
+        self.height = height;
+        self.last_block = block;
+        true
+    }
+
+    /// Number of confirmations of a wallet transaction at the current chain tip.
+    pub fn confirmations(&self, txid: Txid) -> Option<u32> {
+        self.history
+            .iter()
+            .find(|entry| entry.onchain.txid == txid)
+            .map(|entry| entry.confirmations(self.height))
+    }
+
+    /// Updates mining status of a wallet transaction in its history entry and UTXOs, like when
+    /// a mempool transaction gets mined or a block gets reorganized. Returns whether anything
+    /// was changed.
+    pub fn update_tx_status(
+        &mut self,
+        txid: Txid,
+        status: OnchainStatus,
+        date_time: Option<DateTime<Utc>>,
+    ) -> bool {
+        let onchain = OnchainTxid {
+            txid,
+            status,
+            date_time,
+        };
+        let known = self
+            .history
+            .iter()
+            .any(|entry| entry.onchain.txid == txid && entry.onchain != onchain)
+            || self
+                .utxos
+                .iter()
+                .any(|utxo| utxo.onchain.txid == txid && utxo.onchain != onchain);
+        if !known {
+            return false;
+        }
+        self.map_history(|entry| {
+            if entry.onchain.txid == txid {
+                entry.onchain = onchain;
+            }
+        });
+        self.utxos = self
+            .utxos
+            .iter()
+            .map(
+                |utxo| {
+                    if utxo.onchain.txid == txid {
+                        UtxoTxid { onchain, ..*utxo }
+                    } else {
+                        *utxo
+                    }
+                },
+            )
+            .collect();
+        self.refresh_migration();
+        self.refresh_invoices();
+        self.refresh_address_index();
+        true
     }
 
     pub fn update_fees(&mut self, f0: f64, f1: f64, f2: f64) {
@@ -259,7 +1080,44 @@ impl Wallet {
 
     pub fn clear_utxos(&mut self) { self.utxos = bset![]; }
 
-    pub fn update_utxos(&mut self, batch: BTreeSet<UtxoTxid>) { self.utxos.extend(batch); }
+    /// Adds UTXO received with silent payment, replacing information about the same output
+    /// known before (like when a mempool transaction gets mined).
+    pub(crate) fn add_silent_payment(&mut self, utxo: UtxoTxid, output: SilentPaymentOutput) {
+        let outpoint = utxo.outpoint();
+        let known = self.utxos.len();
+        self.utxos.retain(|u| u.outpoint() != outpoint);
+        if self.utxos.len() == known {
+            self.state.balance += utxo.value;
+        }
+        self.utxos.insert(utxo);
+        self.silent_payments.insert(outpoint, output);
+    }
+
+    pub fn update_utxos(&mut self, batch: BTreeSet<UtxoTxid>) {
+        self.utxos.extend(batch);
+        self.mark_coinbase_utxos();
+    }
+
+    /// Marks UTXOs created by coinbase transactions known from the wallet history.
+    fn mark_coinbase_utxos(&mut self) {
+        let coinbase_txids = self
+            .history
+            .iter()
+            .filter(|entry| entry.tx.is_coin_base())
+            .map(|entry| entry.onchain.txid)
+            .collect::<BTreeSet<_>>();
+        if coinbase_txids.is_empty() {
+            return;
+        }
+        self.utxos = self
+            .utxos
+            .iter()
+            .map(|utxo| UtxoTxid {
+                coinbase: utxo.coinbase || coinbase_txids.contains(&utxo.onchain.txid),
+                ..*utxo
+            })
+            .collect();
+    }
 
     pub fn update_complete(
         &mut self,
@@ -361,10 +1219,37 @@ impl Wallet {
                 }
             }
         }
+
+        self.mark_coinbase_utxos();
+        self.refresh_migration();
+        self.refresh_fees();
+        self.refresh_invoices();
+        self.populate_counterparties(&empty!());
+        self.refresh_address_index();
     }
 
     pub fn update_electrum(&mut self, electrum: ElectrumServer) -> bool {
-        self.settings.update_electrum(electrum)
+        if !self.settings.update_electrum(electrum) {
+            return false;
+        }
+        self.record_audit(AuditEvent::SettingsChanged(s!("electrum server")));
+        true
+    }
+
+    pub fn update_explorer(&mut self, explorer: ExplorerLinks) -> bool {
+        if !self.settings.update_explorer(explorer) {
+            return false;
+        }
+        self.record_audit(AuditEvent::SettingsChanged(s!("block explorer")));
+        true
+    }
+
+    pub fn set_custom_signet(&mut self, signet: Option<CustomSignet>) -> Result<bool, SignetError> {
+        if !self.settings.set_custom_signet(signet)? {
+            return Ok(false);
+        }
+        self.record_audit(AuditEvent::SettingsChanged(s!("custom signet")));
+        Ok(true)
     }
 
     #[allow(clippy::result_unit_err)]
@@ -414,10 +1299,28 @@ pub enum DescriptorError {
     DuplicateSigner(String, Fingerprint),
     /// Insufficient number of signers ({0}) to support spending condition "{1}" requirement.
     InsufficientSignerCount(usize, SpendingCondition),
+    /// Spending condition "{0}" uses invalid timelock. Details: {1}
+    InvalidTimelock(SpendingCondition, TimelockError),
+    /// Degrading condition "{0}" must have strictly decreasing non-zero signature thresholds
+    /// and strictly increasing timelocks of the same kind.
+    InconsistentDegrading(SpendingCondition),
+    /// Account {0} is already present in the wallet.
+    DuplicateAccount(HardenedIndex),
+    /// No account xpub is provided for signer with master key fingerprint {0}.
+    MissedAccountSigner(Fingerprint),
+    /// Account signer {0} does not match any of the wallet signers by the master key fingerprint
+    /// or uses account index other than {1}.
+    AccountSignerMismatch(Fingerprint, HardenedIndex),
+    /// Wallet does not use descriptor class {0:?}.
+    UnknownDescriptorClass(DescriptorClass),
+    /// Spending condition "{0}" is not part of the wallet descriptor.
+    UnknownCondition(SpendingCondition),
 }
 
+/// Wallet settings. Fields added after the first version of the settings encoding are
+/// strict-encoded in an extension section following the original fields, which is read with the
+/// default values when absent.
 #[derive(Getters, Clone, PartialEq, Eq, Hash, Debug)]
-#[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub struct WalletSettings {
     #[getter(as_copy)]
@@ -425,6 +1328,13 @@ pub struct WalletSettings {
     core: WalletDescriptor,
     signers: Vec<Signer>,
     electrum: ElectrumServer,
+    /// Descriptor class used for new addresses in multi-descriptor wallets.
+    #[getter(as_copy)]
+    preferred_class: Option<DescriptorClass>,
+    /// Custom signet the wallet operates on, if it is not the default signet.
+    pub(crate) signet: Option<CustomSignet>,
+    /// Block explorer used for the transaction and address links.
+    explorer: ExplorerLinks,
 }
 
 impl Deref for WalletSettings {
@@ -433,14 +1343,46 @@ impl Deref for WalletSettings {
     fn deref(&self) -> &Self::Target { &self.core }
 }
 
+impl StrictEncode for WalletSettings {
+    fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let len = strict_encode_list!(e; self.network, self.core, self.signers, self.electrum);
+        let ext = ExtWriter::new()
+            .field(&self.preferred_class)?
+            .field(&self.signet)?
+            .field(&self.explorer)?;
+        Ok(len + ext.finish(e)?)
+    }
+}
+
+impl StrictDecode for WalletSettings {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let network = PublicNetwork::strict_decode(&mut d)?;
+        let core = WalletDescriptor::strict_decode(&mut d)?;
+        let signers = StrictDecode::strict_decode(&mut d)?;
+        let electrum = ElectrumServer::strict_decode(&mut d)?;
+        let mut ext = ExtReader::read(&mut d)?;
+        Ok(WalletSettings {
+            network,
+            core,
+            signers,
+            electrum,
+            preferred_class: ext.field(|| None)?,
+            signet: ext.field(|| None)?,
+            explorer: ext.field(ExplorerLinks::default)?,
+        })
+    }
+}
+
 /// Wallet descriptor defines a deterministic part of the wallet. It fully controls how different
 /// scriptPubkeys can be formed and satisfied. Change in any of wallet descriptor parameters will
 /// create a different set of addresses/scriptPubkeys/satisfactions, thus changing the wallet.
 ///
 /// Tagged hash of strict-encoded wallet descriptor data operates as a globally unique wallet
-/// descriptor.
+/// descriptor (see [`WalletDescriptor::id`]).
+///
+/// Fields added after the first version of the descriptor encoding are strict-encoded in an
+/// extension section following the original fields.
 #[derive(Getters, Clone, PartialEq, Eq, Hash, Debug)]
-#[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub struct WalletDescriptor {
     /// We commit to the information whether
@@ -467,6 +1409,85 @@ pub struct WalletDescriptor {
     pub(self) signing_keys: Vec<XpubkeyCore>,
     /// DFS-ordered alternative spending conditions.
     pub(self) spending_conditions: BTreeSet<(u8, SpendingCondition)>,
+    /// Ordering of keys in multi-signature scripts.
+    #[getter(as_copy)]
+    pub(self) multisig_order: MultisigOrder,
+    /// Relative spending probabilities assigned to the conditions. If present, taproot script
+    /// tree is laid out with the most likely conditions having the shortest control blocks.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "::serde_with::As::<Vec<(::serde_with::Same, ::serde_with::Same)>>")
+    )]
+    pub(self) spend_weights: BTreeMap<SpendingCondition, u16>,
+    /// Whether taproot descriptors use provably unspendable (NUMS) internal key, leaving only
+    /// script-path spending.
+    #[getter(as_copy)]
+    pub(self) nums_internal_key: bool,
+    /// Whether taproot descriptors satisfy timelock-free N-of-N condition with the key-path
+    /// spending using MuSig2-aggregated key of the signers.
+    #[getter(as_copy)]
+    pub(self) musig_key_path: bool,
+    /// FROST group whose per-terminal group key is used as the internal key of the taproot
+    /// descriptors, allowing t-of-n key-path spending.
+    pub(self) frost_group: Option<FrostGroup>,
+}
+
+impl WalletDescriptor {
+    /// Globally unique identifier of the wallet descriptor: hash of the descriptor fields of the
+    /// first encoding version. Fields from the extension section are not committed to, such
+    /// that the identifiers of the existing wallets do not change.
+    pub fn id(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        self.strict_encode_original(&mut engine)
+            .expect("wallet descriptor encoding");
+        sha256::Hash::from_engine(engine)
+    }
+
+    fn strict_encode_original(&self, mut e: impl Write) -> Result<usize, strict_encoding::Error> {
+        Ok(strict_encode_list!(e;
+            self.testnet,
+            self.descriptor_classes,
+            self.terminal,
+            self.signing_keys,
+            self.spending_conditions
+        ))
+    }
+}
+
+impl StrictEncode for WalletDescriptor {
+    fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let len = self.strict_encode_original(&mut e)?;
+        let ext = ExtWriter::new()
+            .field(&self.multisig_order)?
+            .field(&self.spend_weights)?
+            .field(&self.nums_internal_key)?
+            .field(&self.musig_key_path)?
+            .field(&self.frost_group)?;
+        Ok(len + ext.finish(e)?)
+    }
+}
+
+impl StrictDecode for WalletDescriptor {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let testnet = bool::strict_decode(&mut d)?;
+        let descriptor_classes = StrictDecode::strict_decode(&mut d)?;
+        let terminal = StrictDecode::strict_decode(&mut d)?;
+        let signing_keys = StrictDecode::strict_decode(&mut d)?;
+        let spending_conditions = StrictDecode::strict_decode(&mut d)?;
+        let mut ext = ExtReader::read(&mut d)?;
+        Ok(WalletDescriptor {
+            testnet,
+            descriptor_classes,
+            terminal,
+            signing_keys,
+            spending_conditions,
+            multisig_order: ext.field(MultisigOrder::default)?,
+            spend_weights: ext.field(BTreeMap::new)?,
+            nums_internal_key: ext.field(bool::default)?,
+            musig_key_path: ext.field(bool::default)?,
+            frost_group: ext.field(Option::default)?,
+        })
+    }
 }
 
 impl Display for WalletDescriptor {
@@ -499,6 +1520,10 @@ impl Display for WalletDescriptor {
                         n
                     )?;
                 }
+            } else if let Some((_, SpendingCondition::Degrading(_))) =
+                self.spending_conditions.first()
+            {
+                f.write_str("degrading multi-sig")?;
             } else {
                 unreachable!("empty spending conditions");
             }
@@ -567,12 +1592,20 @@ impl WalletSettings {
             signers: empty!(),
             network,
             electrum,
+            preferred_class: None,
+            signet: None,
+            explorer: default!(),
             core: WalletDescriptor {
                 testnet: network.is_testnet(),
                 descriptor_classes: empty!(),
                 terminal,
                 signing_keys: empty!(),
                 spending_conditions: empty!(),
+                multisig_order: MultisigOrder::Auto,
+                spend_weights: empty!(),
+                nums_internal_key: false,
+                musig_key_path: false,
+                frost_group: None,
             },
         };
 
@@ -626,8 +1659,28 @@ impl WalletSettings {
         {
             return Err(DescriptorError::DuplicateCondition(depth, condition));
         }
+        for TimelockedSigs { timelock, .. } in condition.branches() {
+            if let Err(err) = timelock.script_timelock() {
+                return Err(DescriptorError::InvalidTimelock(condition, err));
+            }
+        }
         let signer_count = self.signers.len();
         match &condition {
+            SpendingCondition::Degrading(degrading) if !degrading.is_consistent() => {
+                Err(DescriptorError::InconsistentDegrading(condition))
+            }
+            SpendingCondition::Degrading(degrading)
+                if degrading.initial as usize > signer_count =>
+            {
+                Err(DescriptorError::InsufficientSignerCount(
+                    signer_count,
+                    condition,
+                ))
+            }
+            SpendingCondition::Degrading(_) => {
+                self.core.spending_conditions.insert((depth, condition));
+                Ok(())
+            }
             SpendingCondition::Sigs(ts) => match &ts.sigs {
                 SigsReq::AtLeast(n) if (*n as usize) > signer_count => Err(
                     DescriptorError::InsufficientSignerCount(signer_count, condition),
@@ -638,7 +1691,7 @@ impl WalletSettings {
                         .iter()
                         .map(|signer| signer.xpub.fingerprint())
                         .collect::<BTreeSet<_>>()
-                        .intersection(&signers.iter().copied().collect::<BTreeSet<_>>())
+                        .intersection(&signers.iter().map(|fp| fp.fingerprint()).collect())
                         .count()
                         == signers.len() =>
                 {
@@ -706,6 +1759,241 @@ impl WalletSettings {
         }
     }
 
+    /// Constructs settings for a different account of the same signers, replacing account xpubs
+    /// and re-mapping spending conditions referencing specific signers.
+    pub fn with_account_signers(
+        &self,
+        index: HardenedIndex,
+        signers: impl IntoIterator<Item = Signer>,
+    ) -> Result<WalletSettings, DescriptorError> {
+        let mut account_signers = signers
+            .into_iter()
+            .map(|signer| {
+                if !signer.is_master_known() || signer.account != Some(index) {
+                    return Err(DescriptorError::AccountSignerMismatch(
+                        signer.fingerprint(),
+                        index,
+                    ));
+                }
+                Ok((signer.master_fp, signer))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+        let mut remap = BTreeMap::new();
+        let mut settings = WalletSettings {
+            network: self.network,
+            core: WalletDescriptor {
+                signing_keys: empty!(),
+                spending_conditions: empty!(),
+                spend_weights: empty!(),
+                ..self.core.clone()
+            },
+            signers: empty!(),
+            electrum: self.electrum.clone(),
+            preferred_class: self.preferred_class,
+            signet: self.signet.clone(),
+            explorer: self.explorer.clone(),
+        };
+        for signer in &self.signers {
+            let account_signer = account_signers
+                .remove(&signer.master_fp)
+                .filter(|_| signer.is_master_known())
+                .ok_or(DescriptorError::MissedAccountSigner(signer.master_fp))?;
+            remap.insert(signer.fingerprint(), account_signer.fingerprint());
+            settings.add_signer(account_signer)?;
+        }
+        if let Some(signer) = account_signers.into_values().next() {
+            return Err(DescriptorError::AccountSignerMismatch(
+                signer.fingerprint(),
+                index,
+            ));
+        }
+
+        self.remap_conditions(&mut settings, &remap, Some(index))?;
+
+        Ok(settings)
+    }
+
+    /// Constructs successor settings where some of the signers are replaced with new ones, for
+    /// instance after a signer key got compromised or a device was lost. Signers are replaced by
+    /// their xpub fingerprint; spending conditions referencing specific signers are re-mapped.
+    ///
+    /// The successor settings produce a different wallet descriptor, and the funds must be moved
+    /// to it with a migration plan, see [`Wallet::plan_migration`].
+    pub fn rekey(
+        &self,
+        replacements: impl IntoIterator<Item = (Fingerprint, Signer)>,
+    ) -> Result<WalletSettings, DescriptorError> {
+        let mut replacements = replacements.into_iter().collect::<BTreeMap<_, _>>();
+
+        let mut remap = BTreeMap::new();
+        let mut settings = WalletSettings {
+            network: self.network,
+            core: WalletDescriptor {
+                signing_keys: empty!(),
+                spending_conditions: empty!(),
+                spend_weights: empty!(),
+                ..self.core.clone()
+            },
+            signers: empty!(),
+            electrum: self.electrum.clone(),
+            preferred_class: self.preferred_class,
+            signet: self.signet.clone(),
+            explorer: self.explorer.clone(),
+        };
+        for signer in &self.signers {
+            let signer = match replacements.remove(&signer.fingerprint()) {
+                Some(replacement) => {
+                    remap.insert(signer.fingerprint(), replacement.fingerprint());
+                    replacement
+                }
+                None => signer.clone(),
+            };
+            settings.add_signer(signer)?;
+        }
+        if let Some(fingerprint) = replacements.into_keys().next() {
+            return Err(DescriptorError::UnknownSigner(fingerprint));
+        }
+
+        self.remap_conditions(&mut settings, &remap, None)?;
+
+        Ok(settings)
+    }
+
+    /// Selects ordering of keys in multi-signature scripts. Since this changes the wallet
+    /// descriptor, it must be done before the wallet gets used.
+    pub fn with_multisig_order(mut self, order: MultisigOrder) -> WalletSettings {
+        self.core.multisig_order = order;
+        self
+    }
+
+    /// Makes taproot descriptors use provably unspendable (NUMS) internal key, such that the
+    /// outputs can be spent only with script paths. Since this changes the wallet descriptor,
+    /// it must be done before the wallet gets used.
+    pub fn with_nums_internal_key(mut self, nums: bool) -> WalletSettings {
+        self.core.nums_internal_key = nums;
+        self
+    }
+
+    /// Makes taproot descriptors satisfy timelock-free N-of-N condition with the key-path
+    /// spending using MuSig2-aggregated key of the signers instead of a script leaf. Since this
+    /// changes the wallet descriptor, it must be done before the wallet gets used.
+    pub fn with_musig_key_path(mut self, musig: bool) -> WalletSettings {
+        self.core.musig_key_path = musig;
+        self
+    }
+
+    /// Makes taproot descriptors use the group key of a FROST group (derived for each terminal)
+    /// as the internal key, such that the outputs can be spent with a key path by any threshold
+    /// of the group participants. Script paths of the spending conditions remain available. The
+    /// group takes precedence over MuSig2 key-path spending. Since this changes the wallet
+    /// descriptor, it must be done before the wallet gets used.
+    pub fn with_frost_group(mut self, group: FrostGroup) -> WalletSettings {
+        self.core.frost_group = Some(group);
+        self
+    }
+
+    /// Assigns relative spending probability to one of the wallet spending conditions; the
+    /// conditions without assigned probability get the lowest weight of 1. Once any probability
+    /// is assigned, taproot script tree gets laid out according to them, so this changes the
+    /// wallet descriptor and must be done before the wallet gets used.
+    pub fn with_spend_weight(
+        mut self,
+        condition: &SpendingCondition,
+        weight: u16,
+    ) -> Result<WalletSettings, DescriptorError> {
+        if !self
+            .core
+            .spending_conditions
+            .iter()
+            .any(|(_, cond)| cond == condition)
+        {
+            return Err(DescriptorError::UnknownCondition(condition.clone()));
+        }
+        self.core.spend_weights.insert(condition.clone(), weight);
+        Ok(self)
+    }
+
+    /// Constructs successor settings with the same signers and spending conditions, but using
+    /// a different descriptor class, for instance to upgrade a legacy wallet to taproot.
+    pub fn with_descriptor_class(&self, class: DescriptorClass) -> WalletSettings {
+        let mut settings = self.clone();
+        settings.core.descriptor_classes = bset![class];
+        settings.preferred_class = None;
+        settings
+    }
+
+    /// Copies spending conditions into the new settings, re-mapping references to specific
+    /// signers and, if provided, account index.
+    fn remap_conditions(
+        &self,
+        settings: &mut WalletSettings,
+        remap: &BTreeMap<Fingerprint, Fingerprint>,
+        account: Option<HardenedIndex>,
+    ) -> Result<(), DescriptorError> {
+        for (depth, condition) in &self.core.spending_conditions {
+            let (sigs, timelock) = match condition {
+                SpendingCondition::Sigs(TimelockedSigs { sigs, timelock }) => (sigs, timelock),
+                SpendingCondition::Degrading(_) => {
+                    settings.add_condition(*depth, condition.clone())?;
+                    if let Some(weight) = self.core.spend_weights.get(condition) {
+                        settings
+                            .core
+                            .spend_weights
+                            .insert(condition.clone(), *weight);
+                    }
+                    continue;
+                }
+            };
+            let sigs = match sigs {
+                SigsReq::Specific(count, fingerprints) => SigsReq::Specific(
+                    *count,
+                    fingerprints
+                        .iter()
+                        .map(|fp| {
+                            remap
+                                .get(&fp.fingerprint())
+                                .copied()
+                                .map(SignerRef::from)
+                                .unwrap_or(*fp)
+                        })
+                        .collect(),
+                ),
+                SigsReq::AccountBased(count, prev) => {
+                    SigsReq::AccountBased(*count, account.unwrap_or(*prev))
+                }
+                sigs => sigs.clone(),
+            };
+            let remapped = SpendingCondition::Sigs(TimelockedSigs {
+                sigs,
+                timelock: *timelock,
+            });
+            if let Some(weight) = self.core.spend_weights.get(condition) {
+                settings
+                    .core
+                    .spend_weights
+                    .insert(remapped.clone(), *weight);
+            }
+            settings.add_condition(*depth, remapped)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_watch_only(&self) -> bool {
+        self.signers
+            .iter()
+            .all(|signer| signer.ownership == Ownership::External)
+    }
+
+    pub fn to_watch_only(&self) -> WalletSettings {
+        let mut settings = self.clone();
+        for signer in &mut settings.signers {
+            signer.ownership = Ownership::External;
+            signer.device = None;
+        }
+        settings
+    }
+
     pub fn update_electrum(&mut self, electrum: ElectrumServer) -> bool {
         if self.electrum != electrum {
             self.electrum = electrum;
@@ -715,6 +2003,15 @@ impl WalletSettings {
         }
     }
 
+    pub fn update_explorer(&mut self, explorer: ExplorerLinks) -> bool {
+        if self.explorer != explorer {
+            self.explorer = explorer;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn descriptors_all(
         &self,
     ) -> Result<
@@ -724,10 +2021,15 @@ impl WalletSettings {
         ),
         miniscript::Error,
     > {
-        let mut descriptors = self
-            .descriptor_classes
-            .iter()
-            .map(|class| self.descriptor_for_class(*class));
+        let primary = self.primary_class();
+        let mut descriptors = iter::once(primary)
+            .chain(
+                self.descriptor_classes
+                    .iter()
+                    .copied()
+                    .filter(|class| *class != primary),
+            )
+            .map(|class| self.descriptor_for_class(class));
         Ok((
             descriptors
                 .next()
@@ -740,143 +2042,158 @@ impl WalletSettings {
         &self,
         class: DescriptorClass,
     ) -> Result<Descriptor<DerivationAccount>, miniscript::Error> {
-        if self.signers.len() <= 1 {
-            let first_key = self
-                .signers
-                .first()
-                .ok_or_else(|| {
-                    miniscript::Error::Unexpected(s!("wallet core does not contain any signers"))
-                })?
-                .to_tracking_account(self.terminal.clone());
-
-            return Ok(match class {
-                DescriptorClass::PreSegwit => Descriptor::new_pk(first_key),
-                DescriptorClass::SegwitV0 => Descriptor::new_wpkh(first_key)?,
-                DescriptorClass::NestedV0 => Descriptor::new_sh_wpkh(first_key)?,
-                DescriptorClass::TaprootC0 => Descriptor::new_tr(first_key, None)?,
-            });
+        let layout = if self.spend_weights.is_empty() {
+            ScriptLayout::DepthOrdered
+        } else {
+            ScriptLayout::CostOptimized
+        };
+        self.descriptor_with_layout(class, layout)
+    }
+
+    /// Constructs descriptor of a given class using specific layout of the spending conditions.
+    /// Layouts other than [`ScriptLayout::DepthOrdered`] produce different addresses and can't
+    /// be used with already existing wallets.
+    pub fn descriptor_with_layout(
+        &self,
+        class: DescriptorClass,
+        layout: ScriptLayout,
+    ) -> Result<Descriptor<DerivationAccount>, miniscript::Error> {
+        ConditionCompiler::new(&self.signers, &self.terminal, self.network.is_testnet())
+            .with_layout(layout)
+            .with_multisig_order(self.multisig_order)
+            .with_spend_weights(&self.spend_weights)
+            .with_nums_internal_key(self.nums_internal_key || self.frost_group.is_some())
+            .with_musig_key_path(self.musig_key_path)
+            .compile(class, &self.spending_conditions)
+    }
+
+    /// Signers whose keys are aggregated with MuSig2 into the internal key of the taproot
+    /// descriptor, if the wallet uses MuSig2 key-path spending.
+    pub fn musig_signers(&self) -> Option<Vec<&Signer>> {
+        if !self.musig_key_path
+            || self.nums_internal_key
+            || self.frost_group.is_some()
+            || !self
+                .descriptor_classes
+                .contains(&DescriptorClass::TaprootC0)
+        {
+            return None;
         }
+        ConditionCompiler::new(&self.signers, &self.terminal, self.network.is_testnet())
+            .musig_signers(&self.spending_conditions)
+    }
 
-        // Construct policy fragments
-        let mut dfs_tree = self
-            .spending_conditions
-            .iter()
-            .map(|(depth, cond)| (depth, cond.policy(&self.signers, &self.terminal)));
-
-        // Pack miniscript fragments according to the descriptor class
-        if class == DescriptorClass::TaprootC0 {
-            let tree = dfs_tree.try_fold::<_, _, Result<_, miniscript::Error>>(
-                Vec::new(),
-                |mut acc, (depth, policy)| {
-                    acc.push((*depth, policy.compile::<Tap>()?));
-                    Ok(acc)
-                },
-            )?;
-
-            return Descriptor::new_tr(
-                DerivationAccount::unsatisfiable_key((
-                    self.network.is_testnet(),
-                    self.terminal.clone(),
-                )),
-                Some(tree.to_tap_tree()?),
-            );
-        }
-
-        // Pack the tree into a linear structure
-        let (policy, remnant) = dfs_tree.rfold(
-            (None, None)
-                as (
-                    Option<Policy<DerivationAccount>>,
-                    Option<Policy<DerivationAccount>>,
-                ),
-            |(acc, prev), (index, pol)| match (acc, prev) {
-                (None, None) if index % 2 == 1 => (None, Some(pol)),
-                (None, None) => (Some(pol), None),
-                (None, Some(prev)) => (
-                    Some(Policy::Or(vec![
-                        (*index as usize, pol),
-                        (*index as usize + 1, prev),
-                    ])),
-                    None,
-                ),
-                (Some(acc), None) => (
-                    Some(Policy::Or(vec![
-                        (*index as usize, pol),
-                        (*index as usize + 1, acc),
-                    ])),
-                    None,
-                ),
-                _ => unreachable!(),
-            },
-        );
-        let policy = policy.or(remnant).ok_or_else(|| {
-            miniscript::Error::Unexpected(s!("zero signing accounts must be filtered"))
-        })?;
-
-        let err_mapper = |err| match err {
-            CompilerError::PolicyError(PolicyError::DuplicatePubKeys) => {
-                miniscript::Error::Unexpected(s!(
-                    "Multiple spending conditions re-using the same keys require Taproot"
-                ))
-            }
-            err => miniscript::Error::CompilerError(err),
+    /// Derives wallet descriptor for a given terminal. Taproot descriptors of the wallets using
+    /// FROST group or MuSig2 key-path spending get the internal key replaced with the group key
+    /// or the aggregated key of the signers derived for the terminal.
+    pub fn derive(
+        &self,
+        descriptor: &Descriptor<DerivationAccount>,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Result<Descriptor<PublicKey>, miniscript::Error> {
+        let derived = DeriveDescriptor::<PublicKey>::derive_descriptor(descriptor, SECP256K1, [
+            change, index,
+        ])
+        .map_err(|_| miniscript::Error::BadDescriptor(s!("unable to derive descriptor")))?;
+        let tr = match derived {
+            Descriptor::Tr(tr) => tr,
+            derived => return Ok(derived),
+        };
+        let internal_key = if let Some(group) = &self.frost_group {
+            group
+                .derive(change, index)
+                .map_err(|err| miniscript::Error::Unexpected(err.to_string()))?
+                .internal_key()
+        } else if let Some(signers) = self.musig_signers() {
+            MuSigKeyAgg::derive(&signers, change, index, None)
+                .map_err(|err| miniscript::Error::Unexpected(err.to_string()))?
+                .internal_key()
+        } else {
+            return Ok(Descriptor::Tr(tr));
         };
+        let internal_key = PublicKey::new(internal_key.public_key(Parity::Even));
+        Descriptor::new_tr(internal_key, tr.taptree().clone())
+    }
 
-        if class.is_segwit_v0() {
-            let mut min_sigs = 0usize;
-            let mut sorted_multi = vec![];
-            if let Policy::Threshold(k, ref thresh) = policy {
-                min_sigs = k;
-                let sigs =
-                    thresh
-                        .iter()
-                        .filter_map(|pol| {
-                            if let Policy::Key(key) = pol {
-                                Some(key.clone())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                if sigs.len() == thresh.len() {
-                    sorted_multi = sigs
-                }
-            };
-            let descr = if !sorted_multi.is_empty() {
-                Wsh::new_sortedmulti(min_sigs, sorted_multi)?
-            } else {
-                let ms_witscript = policy.compile::<Segwitv0>().map_err(err_mapper)?;
-                Wsh::new(ms_witscript)?
-            };
-            return Ok(match class {
-                DescriptorClass::SegwitV0 => Descriptor::Wsh(descr),
-                DescriptorClass::NestedV0 => Descriptor::Sh(Sh::new_with_wsh(descr)),
-                _ => unreachable!(),
-            });
+    /// Descriptor class used for new receive and change addresses: the preferred class, if set,
+    /// or the first of the wallet descriptor classes otherwise.
+    pub fn primary_class(&self) -> DescriptorClass {
+        self.preferred_class
+            .filter(|class| self.descriptor_classes.contains(class))
+            .or_else(|| self.descriptor_classes.first().copied())
+            .expect("wallet core without descriptor class")
+    }
+
+    /// Selects descriptor class used for new addresses in a multi-descriptor wallet. Funds on
+    /// the addresses of all other descriptor classes remain tracked by the wallet.
+    pub fn set_preferred_class(&mut self, class: DescriptorClass) -> Result<bool, DescriptorError> {
+        if !self.descriptor_classes.contains(&class) {
+            return Err(DescriptorError::UnknownDescriptorClass(class));
+        }
+        if self.preferred_class == Some(class) {
+            return Ok(false);
         }
+        self.preferred_class = Some(class);
+        Ok(true)
+    }
 
-        let ms = policy.compile::<Legacy>().map_err(err_mapper)?;
-        Ok(Descriptor::Sh(Sh::new(ms)?))
+    /// Finds descriptor of the wallet descriptor classes producing given scriptPubkey at the
+    /// given derivation terminal.
+    pub fn descriptor_for_script(
+        &self,
+        change: UnhardenedIndex,
+        index: UnhardenedIndex,
+        script: &Script,
+    ) -> Option<Descriptor<DerivationAccount>> {
+        self.descriptor_classes
+            .iter()
+            .filter_map(|class| self.descriptor_for_class(*class).ok())
+            .find(|descriptor| {
+                self.derive(descriptor, change, index)
+                    .map(|d| &d.script_pubkey() == script)
+                    .unwrap_or_default()
+            })
     }
 
+    /// ScriptPubkeys of the primary descriptor class; see [`WalletSettings::primary_class`].
     pub fn script_pubkeys(
         &self,
         change: bool,
         range: RangeInclusive<u16>,
     ) -> Result<BTreeMap<UnhardenedIndex, PubkeyScript>, miniscript::Error> {
-        let (descriptor, _) = self.descriptors_all()?;
-        let len = 2; // TODO: Replace this hardcoded value
-        let mut pat = vec![UnhardenedIndex::zero(); len];
-        pat[len - 2] = if change { UnhardenedIndex::one() } else { UnhardenedIndex::zero() };
+        self.script_pubkeys_for_class(self.primary_class(), change, range)
+    }
+
+    /// ScriptPubkeys of all wallet descriptor classes, which must be all tracked to discover
+    /// the wallet funds.
+    pub fn script_pubkeys_all(
+        &self,
+        change: bool,
+        range: RangeInclusive<u16>,
+    ) -> Result<BTreeMap<DescriptorClass, BTreeMap<UnhardenedIndex, PubkeyScript>>, miniscript::Error>
+    {
+        self.descriptor_classes
+            .iter()
+            .map(|class| {
+                self.script_pubkeys_for_class(*class, change, range.clone())
+                    .map(|spks| (*class, spks))
+            })
+            .collect()
+    }
+
+    pub fn script_pubkeys_for_class(
+        &self,
+        class: DescriptorClass,
+        change: bool,
+        range: RangeInclusive<u16>,
+    ) -> Result<BTreeMap<UnhardenedIndex, PubkeyScript>, miniscript::Error> {
+        let descriptor = self.descriptor_for_class(class)?;
+        let change = if change { UnhardenedIndex::one() } else { UnhardenedIndex::zero() };
         range
             .map(UnhardenedIndex::from)
             .map(|index| -> Result<_, _> {
-                pat[len - 1] = index;
-                let d =
-                    DeriveDescriptor::<PublicKey>::derive_descriptor(&descriptor, SECP256K1, &pat)
-                        .map_err(|_| {
-                            miniscript::Error::BadDescriptor(s!("unable to derive script pubkey"))
-                        })?;
+                let d = self.derive(&descriptor, change, index)?;
                 Ok((index, d.script_pubkey().into()))
             })
             .collect()
@@ -886,9 +2203,37 @@ impl WalletSettings {
         &self,
         change: bool,
         range: RangeInclusive<u16>,
+    ) -> Result<BTreeMap<UnhardenedIndex, AddressCompat>, miniscript::Error> {
+        self.addresses_for_class(self.primary_class(), change, range)
+    }
+
+    /// Addresses of all wallet descriptor classes, which must be all tracked to discover the
+    /// wallet funds.
+    pub fn addresses_all(
+        &self,
+        change: bool,
+        range: RangeInclusive<u16>,
+    ) -> Result<
+        BTreeMap<DescriptorClass, BTreeMap<UnhardenedIndex, AddressCompat>>,
+        miniscript::Error,
+    > {
+        self.descriptor_classes
+            .iter()
+            .map(|class| {
+                self.addresses_for_class(*class, change, range.clone())
+                    .map(|addrs| (*class, addrs))
+            })
+            .collect()
+    }
+
+    pub fn addresses_for_class(
+        &self,
+        class: DescriptorClass,
+        change: bool,
+        range: RangeInclusive<u16>,
     ) -> Result<BTreeMap<UnhardenedIndex, AddressCompat>, miniscript::Error> {
         let network = bitcoin::Network::from(self.network);
-        self.script_pubkeys(change, range)?
+        self.script_pubkeys_for_class(class, change, range)?
             .into_iter()
             .map(|(index, spk)| -> Result<_, _> {
                 Ok((
@@ -909,6 +2254,8 @@ impl WalletSettings {
 pub enum SpendingCondition {
     #[from]
     Sigs(TimelockedSigs),
+    #[from]
+    Degrading(DegradingSigs),
     // In a future we may add custom script types
 }
 
@@ -917,6 +2264,33 @@ impl Default for SpendingCondition {
 }
 
 impl SpendingCondition {
+    /// Describes condition using names of the specific signers (see [`SigsReq::named`]).
+    pub fn describe(&self, signers: &[Signer]) -> String {
+        match self {
+            SpendingCondition::Sigs(sigs) => sigs.describe(signers),
+            SpendingCondition::Degrading(degrading) => degrading.to_string(),
+        }
+    }
+
+    /// Threshold of signatures from all signers decreasing after each of the timelocks.
+    pub fn degrading(
+        initial: u16,
+        steps: impl IntoIterator<Item = (TimelockReq, u16)>,
+    ) -> SpendingCondition {
+        SpendingCondition::Degrading(DegradingSigs {
+            initial,
+            steps: steps.into_iter().collect(),
+        })
+    }
+
+    /// Timelocked signature requirements, alternatives of which form the condition.
+    pub fn branches(&self) -> Vec<TimelockedSigs> {
+        match self {
+            SpendingCondition::Sigs(sigs) => vec![sigs.clone()],
+            SpendingCondition::Degrading(degrading) => degrading.branches(),
+        }
+    }
+
     pub fn all() -> SpendingCondition {
         SpendingCondition::Sigs(TimelockedSigs {
             sigs: SigsReq::All,
@@ -961,39 +2335,57 @@ impl SpendingCondition {
             .collect::<BTreeMap<Fingerprint, DerivationAccount>>();
 
         let count = accounts.len();
-        let key_policies = accounts.values().cloned().map(Policy::Key).collect();
-        let sigs = match self {
-            SpendingCondition::Sigs(TimelockedSigs {
+        let key_policies: Vec<_> = accounts.values().cloned().map(Policy::Key).collect();
+        let condition = match self {
+            SpendingCondition::Sigs(sigs) => sigs,
+            SpendingCondition::Degrading(degrading) => {
+                // Timelocks substitute signatures which are no longer required after each step,
+                // such that all steps are packed into a single threshold using each key once
+                let mut subs = key_policies;
+                let mut prev_sigs = degrading.initial;
+                for (timelock, sigs) in &degrading.steps {
+                    if let Some(timelock) = timelock_policy(timelock) {
+                        subs.extend(
+                            iter::repeat(timelock).take(prev_sigs.saturating_sub(*sigs) as usize),
+                        );
+                    }
+                    prev_sigs = *sigs;
+                }
+                return Policy::Threshold(degrading.initial as usize, subs);
+            }
+        };
+        let sigs = match condition {
+            TimelockedSigs {
                 sigs: SigsReq::All, ..
-            }) => Policy::Threshold(count, key_policies),
-            SpendingCondition::Sigs(TimelockedSigs {
+            } => Policy::Threshold(count, key_policies),
+            TimelockedSigs {
                 sigs: SigsReq::Any, ..
-            }) => Policy::Threshold(1, key_policies),
-            SpendingCondition::Sigs(TimelockedSigs {
+            } => Policy::Threshold(1, key_policies),
+            TimelockedSigs {
                 sigs: SigsReq::AtLeast(k),
                 ..
-            }) => Policy::Threshold(*k as usize, key_policies),
-            SpendingCondition::Sigs(TimelockedSigs {
+            } => Policy::Threshold(*k as usize, key_policies),
+            TimelockedSigs {
                 sigs: SigsReq::Specific(at_least, signers),
                 ..
-            }) => Policy::Threshold(
+            } => Policy::Threshold(
                 *at_least as usize,
                 signers
                     .iter()
                     .map(|fp| {
                         Policy::Key(
                             accounts
-                                .get(fp)
+                                .get(&fp.fingerprint())
                                 .expect("fingerprint is absent from the accounts")
                                 .clone(),
                         )
                     })
                     .collect(),
             ),
-            SpendingCondition::Sigs(TimelockedSigs {
+            TimelockedSigs {
                 sigs: SigsReq::AccountBased(at_least, account_no),
                 ..
-            }) => Policy::Threshold(
+            } => Policy::Threshold(
                 *at_least as usize,
                 signers
                     .iter()
@@ -1014,38 +2406,7 @@ impl SpendingCondition {
                     .collect(),
             ),
         };
-        let timelock = match self {
-            SpendingCondition::Sigs(TimelockedSigs {
-                timelock: TimelockReq::Anytime,
-                ..
-            }) => None,
-            // TODO: Check that this is correct
-            SpendingCondition::Sigs(TimelockedSigs {
-                timelock: TimelockReq::AfterDate(datetime),
-                ..
-            }) => Some(Policy::After(
-                LockTime::from_time(datetime.timestamp() as u32)
-                    .unwrap()
-                    .into(),
-            )),
-            // TODO: Check that this is correct
-            SpendingCondition::Sigs(TimelockedSigs {
-                timelock: TimelockReq::AfterHeight(block),
-                ..
-            }) => Some(Policy::After(LockTime::from_height(*block).unwrap().into())),
-            // TODO: Check that this is correct
-            SpendingCondition::Sigs(TimelockedSigs {
-                timelock: TimelockReq::AfterPeriod(duration),
-                ..
-            }) => Some(Policy::Older(Sequence::from_512_second_intervals(
-                duration.intervals(),
-            ))),
-            // TODO: Check that this is correct
-            SpendingCondition::Sigs(TimelockedSigs {
-                timelock: TimelockReq::AfterBlock(block),
-                ..
-            }) => Some(Policy::Older(Sequence::from_height(*block))),
-        };
+        let timelock = timelock_policy(&condition.timelock);
 
         timelock
             .map(|timelock| Policy::And(vec![sigs.clone(), timelock]))
@@ -1053,9 +2414,20 @@ impl SpendingCondition {
     }
 }
 
+fn timelock_policy(timelock: &TimelockReq) -> Option<Policy<DerivationAccount>> {
+    timelock
+        .script_timelock()
+        .expect("timelock is outside of the consensus range")
+        .map(|timelock| match timelock {
+            ScriptTimelock::Absolute(lock_time) => Policy::After(lock_time.into()),
+            ScriptTimelock::Relative(sequence) => Policy::Older(sequence),
+        })
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display, From)]
 #[derive(StrictEncode, StrictDecode)]
 #[display(inner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
 pub enum DerivationType {
     #[from]
     LnpBp(DescrVariants),
@@ -1224,10 +2596,94 @@ impl WalletState {
     pub fn volume_btc(self) -> f64 { self.volume as f64 / 100_000_000.0 }
 }
 
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+/// Funds which can be spent under a specific wallet spending condition, in satoshis.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{condition}: {spendable} sats")]
+pub struct ConditionBalance {
+    pub depth: u8,
+    pub condition: SpendingCondition,
+    /// Funds which can be spent under the condition at the current height and time.
+    pub spendable: u64,
+    /// Funds which will become spendable under the condition once its timelock expires.
+    pub locked: u64,
+}
+
+/// Number of unused addresses after the last used one which are checked when looking for the
+/// wallet scripts.
+pub const ADDRESS_GAP_LIMIT: u32 = 20;
+
+/// Number of blocks after which coinbase outputs can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Wallet balance broken down by the state of the UTXOs, in satoshis. Each UTXO is accounted in
+/// exactly one of the components.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
+pub struct Balance {
+    /// Confirmed spendable funds.
+    pub confirmed: u64,
+    /// Unconfirmed funds received from other parties.
+    pub pending_incoming: u64,
+    /// Unconfirmed change from the wallet's own transactions.
+    pub pending_change: u64,
+    /// Coinbase outputs which have not yet reached maturity.
+    pub immature: u64,
+    /// Funds explicitly excluded from spending by the user.
+    pub frozen: u64,
+    /// Outputs which cost more to spend than their value, if excluded by the dust policy.
+    pub uneconomic: u64,
+}
+
+impl Balance {
+    pub fn total(self) -> u64 {
+        self.confirmed
+            + self.pending_incoming
+            + self.pending_change
+            + self.immature
+            + self.frozen
+            + self.uneconomic
+    }
+
+    /// Funds which can be spent now, including unconfirmed change, which can be trusted since
+    /// it originates from the wallet itself.
+    pub fn spendable(self) -> u64 { self.confirmed + self.pending_change }
+
+    pub fn pending(self) -> u64 { self.pending_incoming + self.pending_change }
+}
+
+/// User-facing wallet information which does not affect wallet descriptors.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 #[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
-pub struct Sats(u64);
+pub struct WalletMeta {
+    pub name: String,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+    pub tags: BTreeSet<String>,
+}
+
+impl Default for WalletMeta {
+    fn default() -> Self { WalletMeta::new() }
+}
+
+impl WalletMeta {
+    pub fn new() -> WalletMeta {
+        WalletMeta {
+            name: empty!(),
+            description: empty!(),
+            created_at: Utc::now(),
+            tags: empty!(),
+        }
+    }
+
+    pub fn with(name: impl ToString, description: impl ToString) -> WalletMeta {
+        WalletMeta {
+            name: name.to_string(),
+            description: description.to_string(),
+            ..WalletMeta::new()
+        }
+    }
+}
 
 #[derive(Clone, PartialEq, Debug, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(crate = "serde_crate"))]
@@ -1235,12 +2691,14 @@ pub struct WalletEphemerals {
     pub fees: (f32, f32, f32),
     pub fiat: String,
     pub exchange_rate: f64,
+    /// Exchange rates fetched from the rate providers, by fiat currency.
+    pub rates: BTreeMap<String, ExchangeRate>,
 }
 
 impl StrictEncode for WalletEphemerals {
     fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
         Ok(
-            strict_encode_list!(e; self.fees.0, self.fees.1, self.fees.2, self.fiat, self.exchange_rate),
+            strict_encode_list!(e; self.fees.0, self.fees.1, self.fees.2, self.fiat, self.exchange_rate, self.rates),
         )
     }
 }
@@ -1255,6 +2713,68 @@ impl StrictDecode for WalletEphemerals {
             ),
             fiat: String::strict_decode(&mut d)?,
             exchange_rate: f64::strict_decode(&mut d)?,
+            rates: StrictDecode::strict_decode(&mut d)?,
+        })
+    }
+}
+
+/// Additional accounts of a wallet. Since each account is a wallet on its own, the accounts are
+/// strict-encoded through a type-erased stream: otherwise each nesting level would instantiate
+/// the wallet encoding for a new stream type without a bound.
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+struct WalletAccounts(BTreeMap<HardenedIndex, Wallet>);
+
+impl StrictEncode for WalletAccounts {
+    fn strict_encode<E: Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let e: &mut dyn Write = &mut e;
+        self.0.strict_encode(e)
+    }
+}
+
+impl StrictDecode for WalletAccounts {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let d: &mut dyn Read = &mut d;
+        StrictDecode::strict_decode(d).map(WalletAccounts)
+    }
+}
+
+fn select_prevouts(mut groups: Vec<Vec<Prevout>>, value: u64) -> Option<(BTreeSet<Prevout>, u64)> {
+    let amount = |group: &Vec<Prevout>| group.iter().map(|p| p.amount).sum::<u64>();
+    groups.sort_by_key(amount);
+    let mut acc = 0u64;
+    let mut take_next = true;
+    #[allow(clippy::needless_collect)]
+    let groups = groups
+        .into_iter()
+        .take_while(|group| {
+            let take_this = take_next;
+            acc += amount(group);
+            take_next = acc < value;
+            take_this
+        })
+        .collect::<Vec<_>>();
+    // Going from back to remove small inputs if larger inputs are enough
+    let mut acc = 0u64;
+    let mut take_next = true;
+    let prevouts = groups
+        .into_iter()
+        .rev()
+        .take_while(|group| {
+            let take_this = take_next;
+            acc += amount(group);
+            take_next = acc < value;
+            take_this
         })
+        .flatten()
+        .collect();
+    if acc < value {
+        None
+    } else {
+        Some((prevouts, acc))
     }
 }
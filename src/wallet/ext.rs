@@ -0,0 +1,80 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Extension sections of the strict-encoded structures.
+//!
+//! Fields added to a structure after the first version of its encoding are written into a
+//! length-prefixed section following the original fields. Readers take the fields they know
+//! from the section, using defaults for the fields missing from the data written by the older
+//! versions and skipping the fields appended by the newer ones. A section missing at the end of
+//! the data is read as an empty one.
+
+use std::io::{self, Cursor, Read, Write};
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+pub(super) struct ExtWriter(Vec<u8>);
+
+impl ExtWriter {
+    pub fn new() -> ExtWriter { ExtWriter(vec![]) }
+
+    pub fn field(mut self, value: &impl StrictEncode) -> Result<Self, strict_encoding::Error> {
+        value.strict_encode(&mut self.0)?;
+        Ok(self)
+    }
+
+    pub fn finish(self, mut e: impl Write) -> Result<usize, strict_encoding::Error> {
+        let len = u32::try_from(self.0.len())
+            .map_err(|_| strict_encoding::Error::ExceedMaxItems(self.0.len()))?;
+        let count = len.strict_encode(&mut e)?;
+        e.write_all(&self.0)?;
+        Ok(count + self.0.len())
+    }
+}
+
+pub(super) struct ExtReader(Cursor<Vec<u8>>);
+
+impl ExtReader {
+    pub fn read(mut d: impl Read) -> Result<ExtReader, strict_encoding::Error> {
+        let mut len = [0u8; 4];
+        let mut pos = 0;
+        while pos < len.len() {
+            match d.read(&mut len[pos..]) {
+                Ok(0) => break,
+                Ok(count) => pos += count,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let len = match pos {
+            0 => return Ok(ExtReader(Cursor::new(vec![]))),
+            4 => u32::from_le_bytes(len),
+            _ => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        let mut data = Vec::new();
+        d.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(ExtReader(Cursor::new(data)))
+    }
+
+    /// Reads next field of the section, or returns the default if the section has ended.
+    pub fn field<T: StrictDecode>(
+        &mut self,
+        default: impl FnOnce() -> T,
+    ) -> Result<T, strict_encoding::Error> {
+        if self.0.position() >= self.0.get_ref().len() as u64 {
+            return Ok(default());
+        }
+        T::strict_decode(&mut self.0)
+    }
+}
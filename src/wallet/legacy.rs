@@ -0,0 +1,158 @@
+// Rust bitcoin wallet library for professional use.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// Copyright (C) 2022 by Pandora Prime SA, Switzerland.
+//
+// This software is distributed without any warranty. You should have received
+// a copy of the AGPL-3.0 License along with this software. If not, see
+// <https://www.gnu.org/licenses/agpl-3.0-standalone.html>.
+
+//! Wallet data in the layout of the first version of the wallet documents, which is read for
+//! migrating existing documents into the current version.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use bitcoin::BlockHash;
+use strict_encoding::StrictDecode;
+use wallet::descriptors::DescriptorClass;
+use wallet::hd::{DerivationSubpath, TerminalStep, UnhardenedIndex, XpubkeyCore};
+use wallet::onchain::PublicNetwork;
+
+use super::{
+    SpendingCondition, Wallet, WalletDescriptor, WalletEphemerals, WalletSettings, WalletState,
+};
+use crate::{AddressSource, ElectrumServer, HistoryEntry, OnchainTxid, Signer, UtxoTxid};
+
+#[derive(StrictDecode)]
+pub(crate) struct WalletV1 {
+    settings: WalletSettingsV1,
+    last_indexes: BTreeMap<UnhardenedIndex, UnhardenedIndex>,
+    last_block: BlockHash,
+    height: u32,
+    state: WalletState,
+    ephemerals: WalletEphemeralsV1,
+    utxos: BTreeSet<UtxoTxidV1>,
+    history: BTreeSet<HistoryEntry>,
+}
+
+#[derive(StrictDecode)]
+pub(crate) struct WalletSettingsV1 {
+    network: PublicNetwork,
+    core: WalletDescriptorV1,
+    signers: Vec<Signer>,
+    electrum: ElectrumServer,
+}
+
+#[derive(StrictDecode)]
+struct WalletDescriptorV1 {
+    testnet: bool,
+    descriptor_classes: BTreeSet<DescriptorClass>,
+    terminal: DerivationSubpath<TerminalStep>,
+    signing_keys: Vec<XpubkeyCore>,
+    spending_conditions: BTreeSet<(u8, SpendingCondition)>,
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+#[derive(StrictDecode)]
+struct UtxoTxidV1 {
+    onchain: OnchainTxid,
+    value: u64,
+    vout: u32,
+    addr_src: AddressSource,
+}
+
+struct WalletEphemeralsV1 {
+    fees: (f32, f32, f32),
+    fiat: String,
+    exchange_rate: f64,
+}
+
+impl StrictDecode for WalletEphemeralsV1 {
+    fn strict_decode<D: Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        Ok(WalletEphemeralsV1 {
+            fees: (
+                f32::strict_decode(&mut d)?,
+                f32::strict_decode(&mut d)?,
+                f32::strict_decode(&mut d)?,
+            ),
+            fiat: String::strict_decode(&mut d)?,
+            exchange_rate: f64::strict_decode(&mut d)?,
+        })
+    }
+}
+
+impl From<WalletDescriptorV1> for WalletDescriptor {
+    fn from(v1: WalletDescriptorV1) -> Self {
+        WalletDescriptor {
+            testnet: v1.testnet,
+            descriptor_classes: v1.descriptor_classes,
+            terminal: v1.terminal,
+            signing_keys: v1.signing_keys,
+            spending_conditions: v1.spending_conditions,
+            multisig_order: default!(),
+            spend_weights: empty!(),
+            nums_internal_key: false,
+            musig_key_path: false,
+            frost_group: None,
+        }
+    }
+}
+
+impl From<WalletSettingsV1> for WalletSettings {
+    fn from(v1: WalletSettingsV1) -> Self {
+        WalletSettings {
+            network: v1.network,
+            core: v1.core.into(),
+            signers: v1.signers,
+            electrum: v1.electrum,
+            preferred_class: None,
+            signet: None,
+            explorer: default!(),
+        }
+    }
+}
+
+impl From<UtxoTxidV1> for UtxoTxid {
+    fn from(v1: UtxoTxidV1) -> Self {
+        UtxoTxid {
+            onchain: v1.onchain,
+            value: v1.value,
+            vout: v1.vout,
+            addr_src: v1.addr_src,
+            // Restored from the wallet history during the migration
+            coinbase: false,
+        }
+    }
+}
+
+impl From<WalletEphemeralsV1> for WalletEphemerals {
+    fn from(v1: WalletEphemeralsV1) -> Self {
+        WalletEphemerals {
+            fees: v1.fees,
+            fiat: v1.fiat,
+            exchange_rate: v1.exchange_rate,
+            rates: empty!(),
+        }
+    }
+}
+
+impl From<WalletV1> for Wallet {
+    /// Migrates wallet into the current version. Fields which did not exist in the first version
+    /// get their defaults, and data derived from the transaction history are rebuilt.
+    fn from(v1: WalletV1) -> Self {
+        let mut wallet = Wallet::from(WalletSettings::from(v1.settings));
+        wallet.last_indexes = v1.last_indexes;
+        wallet.last_block = v1.last_block;
+        wallet.height = v1.height;
+        wallet.state = v1.state;
+        wallet.ephemerals = v1.ephemerals.into();
+        wallet.utxos = v1.utxos.into_iter().map(UtxoTxid::from).collect();
+        wallet.history = v1.history;
+        wallet.mark_coinbase_utxos();
+        wallet.refresh_address_index();
+        wallet
+    }
+}